@@ -0,0 +1,71 @@
+// What the server's input path pays for log calls whose category is off: the same calls as
+// broadcast_inputs and handle_message, timed and with every allocation counted. There must be none.
+// cargo bench --bench logging
+use std::{
+    alloc::{ GlobalAlloc, Layout, System },
+    hint::black_box,
+    net::SocketAddr,
+    sync::atomic::{ AtomicUsize, Ordering },
+    time::{ Duration, Instant },
+};
+
+use unlockrs::types::{
+    BufferedNetworkedPlayerInputs,
+    LogConfig,
+    Logger,
+    NetworkedPlayerInput,
+    PlayerInput,
+};
+
+const CALLS: u32 = 1_000_000;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// time per call and allocations over all calls
+fn measure(mut call: impl FnMut()) -> (Duration, usize) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..CALLS {
+        call();
+    }
+    (start.elapsed() / CALLS, ALLOCATIONS.load(Ordering::Relaxed) - allocations)
+}
+
+fn main() {
+    let logger = Logger::new(LogConfig { message_handling: false, ..LogConfig::default() });
+    let src = SocketAddr::from(([127, 0, 0, 1], 8080));
+    let inputs = BufferedNetworkedPlayerInputs {
+        buffered_inputs: (1..=8).map(|frame| NetworkedPlayerInput::new(vec![PlayerInput::Left], frame)).collect(),
+    };
+
+    let (lazy, lazy_allocations) = measure(|| {
+        let (src, inputs) = black_box((&src, &inputs));
+        logger.player_input(|| format!("Processing player inputs from {:?}: {:?}", src, inputs));
+        logger.message(|| format!("Received message from {:?}", src));
+        logger.debug(|| "Handling msg now!");
+    });
+    // what every one of these calls cost when the message was built before the category was checked
+    let (eager, eager_allocations) = measure(|| {
+        let (src, inputs) = black_box((&src, &inputs));
+        black_box(format!("Processing player inputs from {:?}: {:?}", src, inputs));
+        black_box(format!("Received message from {:?}", src));
+    });
+    println!("categories off: {:>8.1?} per call, {} allocations", lazy, lazy_allocations);
+    println!("eager format!:  {:>8.1?} per call, {} allocations", eager, eager_allocations);
+    assert_eq!(lazy_allocations, 0, "a disabled log category allocated");
+}
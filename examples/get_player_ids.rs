@@ -0,0 +1,46 @@
+// Asks a running server which hosts can be joined, with nothing but the unlockrs library.
+// cargo run --example get_player_ids -- 127.0.0.1:8080
+use std::{ net::UdpSocket, time::Duration };
+
+use unlockrs::{
+    type_impl::parse_datagram,
+    types::{
+        DeserializedMessageType,
+        Endpoint,
+        NetworkMessage,
+        NetworkMessageType,
+        SeqNum,
+        SerializedMessageType,
+    },
+};
+
+fn send(socket: &UdpSocket, msg: NetworkMessage, msg_type: NetworkMessageType) -> std::io::Result<()> {
+    let SerializedMessageType::NonChunked(datagram) = msg.serialize(msg_type) else {
+        unreachable!("requests and acks fit one datagram");
+    };
+    socket.send(&datagram.bytes).map(|_| ())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let server_addr = std::env::args().nth(1).unwrap_or_else(|| "127.0.0.1:8080".to_string());
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(&server_addr)?;
+    socket.set_read_timeout(Some(Duration::from_secs(2)))?;
+
+    send(&socket, NetworkMessage::GetServerPlayerIDs, NetworkMessageType::ResendUntilAck(SeqNum(0)))?;
+    let mut buffer = [0; 1024];
+    loop {
+        let amt = socket.recv(&mut buffer)?;
+        let Ok(DeserializedMessageType::NonChunked(reply)) = parse_datagram(&buffer[..amt], Endpoint::Client) else {
+            continue;
+        };
+        // the server resends what we don't ack
+        if let Some(seq_num) = reply.seq_num {
+            send(&socket, NetworkMessage::ClientSideAck(SeqNum(seq_num)), NetworkMessageType::SendOnce)?;
+        }
+        if let NetworkMessage::ServerSentPlayerIDs(ids, other_layout) = reply.msg {
+            println!("hosts: {:?}, {} more with another world layout", ids, other_layout);
+            return Ok(());
+        }
+    }
+}
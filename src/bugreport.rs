@@ -0,0 +1,145 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    fs,
+    io,
+    path::{ Path, PathBuf },
+    time::{ SystemTime, UNIX_EPOCH },
+};
+
+use crate::types::PlayerInput;
+
+pub const BUG_REPORT_ROOT: &str = "bugreports";
+pub const RECENT_LOG_LINES: usize = 500;
+const JOURNAL_FRAMES: usize = 60 * 60 * 10; // ten minutes at the default tick rate
+
+// the last RECENT_LOG_LINES things the game reported, oldest first
+#[derive(Default)]
+pub struct RecentLog {
+    lines: VecDeque<String>,
+}
+
+impl RecentLog {
+    pub fn push(&mut self, line: impl Into<String>) {
+        if self.lines.len() == RECENT_LOG_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line.into());
+    }
+}
+
+// the local inputs of every frame the game sent, oldest first, enough to replay what we did
+#[derive(Default)]
+pub struct InputJournal {
+    frames: VecDeque<(u32, Vec<PlayerInput>)>,
+}
+
+impl InputJournal {
+    pub fn record(&mut self, frame: u32, inputs: &[PlayerInput]) {
+        if self.frames.len() == JOURNAL_FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back((frame, inputs.to_vec()));
+    }
+
+    // what we played in `from_frame..=to_frame`, for a peer that never got those frames
+    pub fn frames_in(&self, from_frame: u32, to_frame: u32) -> impl Iterator<Item = &(u32, Vec<PlayerInput>)> {
+        self.frames.iter().filter(move |(frame, _)| (from_frame..=to_frame).contains(frame))
+    }
+}
+
+// what the game collects when F12 is pressed, every piece is written to a file of its own
+pub struct BugReport<'a> {
+    pub input_buffer: String,
+    pub state_diff: String,
+    pub net_stats: String,
+    pub messages: String, // the connection's recent datagrams
+    pub log: &'a RecentLog,
+    pub journal: &'a InputJournal,
+}
+
+// writes the report to a new directory below `root` named after the current time and returns it
+pub fn export(report: &BugReport, root: &Path) -> io::Result<PathBuf> {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let dir = root.join(format!("bugreport-{}", millis));
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("input_buffer.txt"), &report.input_buffer)?;
+    fs::write(dir.join("state_diff.txt"), &report.state_diff)?;
+    fs::write(dir.join("net_stats.txt"), &report.net_stats)?;
+    fs::write(dir.join("messages.txt"), &report.messages)?;
+    let log: Vec<&str> = report.log.lines.iter().map(String::as_str).collect();
+    fs::write(dir.join("log.txt"), log.join("\n"))?;
+    let mut journal = String::new();
+    for (frame, inputs) in &report.journal.frames {
+        writeln!(journal, "{} {:?}", frame, inputs).unwrap();
+    }
+    fs::write(dir.join("input_journal.txt"), journal)?;
+    Ok(dir)
+}
+
+// the bytes where the predicted state left the verified one, one line per page that differs
+pub fn state_diff(verified: &[u8], predicted: &[u8], page_size: usize) -> String {
+    if verified.len() != predicted.len() {
+        return format!("sizes differ: verified {} bytes, predicted {} bytes", verified.len(), predicted.len());
+    }
+    let mut diff = String::new();
+    for (page, (verified, predicted)) in verified
+        .chunks(page_size)
+        .zip(predicted.chunks(page_size))
+        .enumerate() {
+        let differing: Vec<usize> = (0..verified.len()).filter(|i| verified[*i] != predicted[*i]).collect();
+        if let (Some(first), Some(last)) = (differing.first(), differing.last()) {
+            writeln!(
+                diff,
+                "page {}: {} bytes differ between offsets {} and {}",
+                page,
+                differing.len(),
+                first,
+                last
+            ).unwrap();
+        }
+    }
+    if diff.is_empty() {
+        diff.push_str("identical\n");
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_writes_every_piece() {
+        let mut log = RecentLog::default();
+        for line in 0..RECENT_LOG_LINES + 3 {
+            log.push(format!("line {}", line));
+        }
+        let mut journal = InputJournal::default();
+        journal.record(1, &[PlayerInput::Left]);
+        journal.record(2, &[PlayerInput::Right, PlayerInput::Shoot]);
+        let report = BugReport {
+            input_buffer: "input buffer".to_string(),
+            state_diff: state_diff(&[0, 0, 0, 0, 1, 2], &[0, 0, 0, 0, 1, 9], 2),
+            net_stats: "net stats".to_string(),
+            messages: "messages".to_string(),
+            log: &log,
+            journal: &journal,
+        };
+        let root = std::env::temp_dir().join(format!("unlockrs-bugreport-{}", std::process::id()));
+        let dir = export(&report, &root).unwrap();
+        assert!(dir.starts_with(&root));
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("input_buffer.txt"), "input buffer");
+        assert_eq!(read("state_diff.txt"), "page 2: 1 bytes differ between offsets 1 and 1\n");
+        assert_eq!(read("net_stats.txt"), "net stats");
+        assert_eq!(read("messages.txt"), "messages");
+        let log = read("log.txt");
+        // only the newest lines are kept
+        assert_eq!(log.lines().count(), RECENT_LOG_LINES);
+        assert_eq!(log.lines().next(), Some("line 3"));
+        assert_eq!(read("input_journal.txt"), "1 [Left]\n2 [Right, Shoot]\n");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
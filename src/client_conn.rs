@@ -1,9 +1,10 @@
-use core::panic;
 use std::{
-    collections::HashMap,
-    net::UdpSocket,
+    collections::{ HashSet, VecDeque },
+    io,
+    net::{ Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket },
+    panic::{ self, AssertUnwindSafe },
     process::exit,
-    sync::{ mpsc, Arc, Mutex },
+    sync::{ atomic::{ AtomicBool, Ordering }, mpsc, Arc, Mutex, PoisonError },
     thread::{ self },
     time::{ Duration, Instant },
 };
@@ -14,48 +15,346 @@ use crate::types::{
     ChunkedMessageCollector,
     GameMessage,
     GameRequestToNetwork,
+    InputGapStats,
     MsgBuffer,
+    NetError,
+    NetworkEvent,
     NetworkLogger,
     NetworkMessage,
     NetworkMessageType,
     NetworkedPlayerInput,
-    SendInputsError,
+    ProtocolInfo,
+    RetryClass,
     SeqNum,
     SeqNumGenerator,
     SerializedNetworkMessage,
     ServerPlayerID,
+    SessionId,
+    MAX_ACKS_PER_DATAGRAM,
+    MAX_CHUNKS_PER_MESSAGE,
     MAX_UDP_PAYLOAD_DATA_LENGTH,
+    MAX_UDP_PAYLOAD_LEN,
     SEQ_NUM_BYTE_POS,
 };
+use crate::input_ack::InputAcks;
+use crate::link_loss::LinkLoss;
+use crate::message_history::{ Direction, MessageHistory, MessageRecord };
+use crate::ping::PingTracker;
+use crate::retransmit::{ RetransmitTable, RetryPolicy };
+use crate::type_impl::panic_reason;
+use crate::timing::{ RollingWindow, TimingSummary, TIMING_WINDOW };
+use crate::world::WORLD_LAYOUT_HASH;
+
+const CONTROL_RETRY: RetryPolicy = RetryPolicy::fixed(Duration::from_millis(250), 8);
+// a world upload keeps going for about 20s on a link that loses most of it
+const BULK_RETRY: RetryPolicy = RetryPolicy {
+    timeout: Duration::from_millis(250),
+    backoff: 2,
+    max_timeout: Duration::from_secs(2),
+    max_retries: 12,
+};
+const MAX_CHUNKED_PAYLOAD_LEN: usize = MAX_CHUNKS_PER_MESSAGE * MAX_UDP_PAYLOAD_DATA_LENGTH;
+const NET_STATS_PUBLISH_INTERVAL: Duration = Duration::from_millis(250);
+const RECEIVE_POLL_INTERVAL: Duration = Duration::from_millis(100); // how quickly a replaced receive thread notices
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500); // a server that is up answers the probe's ping in this
+
+// what the network thread shares with the game for the diagnostics overlay and bug reports, the game adds
+// what rollbacks cost it
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NetStats {
+    pub loop_iteration: TimingSummary,
+    pub remote_inputs: InputGapStats, // totals over every ServerSentPlayerInputs so far
+    pub rtt: Option<Duration>, // smoothed over the pongs so far, None until the first one
+    pub reliable_loss: Option<f32>, // share of reliable sends that had to be resent, over LOSS_WINDOW
+    pub input_loss: Option<f32>, // share of input datagrams the server never acked, over LOSS_WINDOW
+    pub repeated_input_frames: u32, // inputs for a frame that already went out, dropped instead of sent
+    pub predicted_steps_per_second: f32, // set by the game, rollbacks make it exceed the tick rate
+}
+// when input datagrams go out, frames sampled in between ride along in the next one since every
+// datagram carries all unacked inputs anyway
+#[derive(Debug, Default)]
+pub struct InputSendSchedule {
+    interval: Option<Duration>, // None sends one datagram per frame
+    next_send: Option<Instant>,
+}
+
+impl InputSendSchedule {
+    // `rate_hz` datagrams per second, 0 sends one per frame
+    pub fn new(rate_hz: u32) -> Self {
+        Self {
+            interval: (rate_hz > 0).then(|| Duration::from_secs(1) / rate_hz),
+            next_send: None,
+        }
+    }
+    // called once per input frame, sends are spaced from the last due time so they keep the rate
+    // on average instead of drifting later by however late each frame came
+    pub fn is_due(&mut self, now: Instant) -> bool {
+        let Some(interval) = self.interval else {
+            return true;
+        };
+        if self.next_send.is_some_and(|next| now < next) {
+            return false;
+        }
+        self.next_send = Some(match self.next_send {
+            Some(next) if now < next + interval => next + interval,
+            _ => now + interval, // after a pause we don't send a burst to catch up
+        });
+        true
+    }
+}
+
+// how often ConnectionServer::connect probes the server before reporting it unreachable
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectRetry {
+    pub attempts: u32, // 0 still tries once
+    pub backoff: Duration, // waited after the first failed attempt, doubled after each further one
+}
+
+impl Default for ConnectRetry {
+    fn default() -> Self {
+        Self { attempts: 5, backoff: Duration::from_millis(200) }
+    }
+}
+
+impl ConnectRetry {
+    // only an unreachable server is tried again, any other error is returned right away
+    pub fn run<T>(
+        &self,
+        mut attempt: impl FnMut() -> Result<T, NetError>,
+        mut sleep: impl FnMut(Duration)
+    ) -> Result<T, NetError> {
+        let mut backoff = self.backoff;
+        let mut tries = 1;
+        loop {
+            match attempt() {
+                Err(NetError::NotConnected) if tries < self.attempts => {
+                    sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                    tries += 1;
+                }
+                result => {
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+pub type ConnectionHandles = (Arc<Mutex<ConnectionServer>>, RequestSender, mpsc::Receiver<NetworkEvent>);
+
+// what the game asks of the network thread besides sending, answered on the channel it comes with
+#[derive(Debug)]
+pub enum NetworkControl {
+    DumpMessages(mpsc::Sender<String>), // the datagrams each way, oldest first
+}
+
+// the game's end of the network thread's diagnostics, nothing in here locks what that thread works on
+#[derive(Debug)]
+pub struct NetDiagnostics {
+    stats: mpsc::Receiver<NetStats>,
+    latest: NetStats,
+    control: mpsc::Sender<NetworkControl>,
+}
+
+impl NetDiagnostics {
+    // the newest stats the network thread published, default until the first arrive
+    pub fn stats(&mut self) -> NetStats {
+        if let Some(stats) = self.stats.try_iter().last() {
+            self.latest = stats;
+        }
+        self.latest
+    }
+    // None if the network thread doesn't answer within `timeout`, e.g. because it is gone
+    pub fn dump_messages(&self, timeout: Duration) -> Option<String> {
+        let (reply, answer) = mpsc::channel();
+        self.control.send(NetworkControl::DumpMessages(reply)).ok()?;
+        answer.recv_timeout(timeout).ok()
+    }
+}
+
+// counts the game's sessions, every request carries the one it was sent in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SessionEpoch(pub u32);
+
+pub struct TaggedRequest {
+    pub epoch: SessionEpoch,
+    pub request: GameRequestToNetwork,
+}
+
+// the game's end of the request channel. A clone keeps the epoch it was made in, so a callback
+// left over from an ended session can't send into the next one
+#[derive(Clone)]
+pub struct RequestSender {
+    sender: mpsc::Sender<TaggedRequest>,
+    epoch: SessionEpoch,
+}
+
+impl RequestSender {
+    pub fn new(sender: mpsc::Sender<TaggedRequest>) -> Self {
+        Self { sender, epoch: SessionEpoch::default() }
+    }
+    pub fn send(&self, request: GameRequestToNetwork) -> Result<(), mpsc::SendError<GameRequestToNetwork>> {
+        self.sender
+            .send(TaggedRequest { epoch: self.epoch, request })
+            .map_err(|e| mpsc::SendError(e.0.request))
+    }
+    // everything sent from here on belongs to a new session
+    pub fn begin_session(&mut self) -> Result<(), mpsc::SendError<GameRequestToNetwork>> {
+        self.epoch = SessionEpoch(self.epoch.0 + 1);
+        self.send(GameRequestToNetwork::BeginSession)
+    }
+    // whatever the session still has queued or sends later is dropped by the network thread
+    pub fn end_session(&self) -> Result<(), mpsc::SendError<GameRequestToNetwork>> {
+        self.send(GameRequestToNetwork::EndSession)
+    }
+}
+
+// what this build tells the server in its Hello, with the layout of the world it would host or join
+fn local_protocol() -> ProtocolInfo {
+    ProtocolInfo { world_layout: WORLD_LAYOUT_HASH, ..ProtocolInfo::local() }
+}
+
+fn retry_policy(class: RetryClass) -> RetryPolicy {
+    match class {
+        RetryClass::Control => CONTROL_RETRY,
+        RetryClass::Bulk => BULK_RETRY,
+    }
+}
+
+// send errors we get when the interface our socket was bound to went away
+fn looks_like_address_change(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::AddrNotAvailable |
+            io::ErrorKind::NetworkUnreachable |
+            io::ErrorKind::NetworkDown |
+            io::ErrorKind::HostUnreachable
+    )
+}
 
-const MAX_RETRIES: u32 = 8;
-const RETRY_TIMEOUT: Duration = Duration::from_millis(250);
 pub struct ConnectionServer {
     socket: Arc<UdpSocket>,
+    server_addr: SocketAddr,
+    session: SessionId,
+    receive_thread: Option<(Arc<AtomicBool>, thread::JoinHandle<()>)>, // stop flag of the thread reading `socket`
     sequence_number: SeqNumGenerator,
-    pending_acks: HashMap<SeqNum, (Instant, SerializedNetworkMessage)>,
-    network_to_game: mpsc::Sender<NetworkMessage>,
-    client_request_receiver: mpsc::Receiver<GameRequestToNetwork>,
+    next_transfer_id: u16, // of the next chunked message, the server only assembles the newest
+    pending_acks: RetransmitTable<SeqNum, SerializedNetworkMessage>,
+    network_to_game: mpsc::Sender<NetworkEvent>,
+    client_request_receiver: mpsc::Receiver<TaggedRequest>,
+    queued_requests: VecDeque<TaggedRequest>, // taken off the channel but not handled yet
+    session_epoch: SessionEpoch, // requests from an older one are dropped
+    stale_requests_dropped: usize,
     ack_sender: mpsc::Sender<SeqNum>,
     ack_receiver: mpsc::Receiver<SeqNum>,
     network_msg_receiver: mpsc::Receiver<NetworkMessage>,
     network_msg_sender: mpsc::Sender<NetworkMessage>,
     chunked_msg_collector: Arc<Mutex<ChunkedMessageCollector>>,
     unack_input_buffer: BufferedNetworkedPlayerInputs,
-    unack_input_seq_nums_to_frame: HashMap<SeqNum, u32>, // Hashmaps from seq_num to u32 could also be rewritten as vecs / depending on seq_num_size as static arrays
+    newest_sent_input: Option<u32>, // frames up to here went out already, a second input for one is dropped
+    repeated_input_frames: u32,
+    input_sends: InputSendSchedule,
+    compact_held_inputs: bool, // runs of equal inputs go out as ClientSentPlayerInputRuns when that is shorter
+    remote_input_acks: InputAcks, // how far the relayed inputs arrived, acked back to the server
+    world_upload_seq_nums: HashSet<SeqNum>, // chunks of the last world upload the server hasn't acked yet
+    loop_timing: RollingWindow,
+    remote_input_stats: InputGapStats,
+    ping: PingTracker,
+    link_loss: LinkLoss,
+    stats_sender: Option<mpsc::Sender<NetStats>>, // set by diagnostics(), sent every NET_STATS_PUBLISH_INTERVAL
+    control_receiver: Option<mpsc::Receiver<NetworkControl>>, // set by diagnostics()
+    message_history: Arc<Mutex<MessageHistory>>, // shared with the receive thread
+    shutting_down: bool, // set once the game dropped its request sender
+    incompatible_server: Option<ProtocolInfo>, // set when the HelloAck didn't match our build, nothing is sent after that
+    fatal_sender: Option<mpsc::Sender<NetworkEvent>>, // set by start(), lives outside the lock so a panic can't poison it
+    #[cfg(test)]
+    inject_panic: bool,
 }
 
 impl ConnectionServer {
-    pub fn new() -> Result<
-        (
-            Arc<Mutex<ConnectionServer>>,
-            mpsc::Sender<GameRequestToNetwork>,
-            mpsc::Receiver<NetworkMessage>,
-        ),
-        std::io::Error
-    > {
-        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0")?);
-        socket.connect("127.0.0.1:8080")?;
+    // a UDP connect only sets the peer, the server is probed with a ping so an unreachable one is
+    // reported as NetError::NotConnected instead of every request silently timing out later.
+    // every attempt goes out from the same socket, the server sees one client and not one per attempt
+    pub fn connect<A: ToSocketAddrs>(server_addr: A, retry: ConnectRetry) -> Result<ConnectionHandles, NetError> {
+        let socket = Self::bind_towards(server_addr)?;
+        retry.run(|| Self::probe(&socket), thread::sleep)?;
+        socket.set_read_timeout(Some(RECEIVE_POLL_INTERVAL))?;
+        Self::with_socket(socket)
+    }
+    // connect on a thread of its own, the caller keeps drawing while the probes wait for the server
+    pub fn connect_in_background(
+        server_addr: String,
+        retry: ConnectRetry
+    ) -> mpsc::Receiver<Result<ConnectionHandles, NetError>> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(Self::connect(server_addr.as_str(), retry));
+        });
+        receiver
+    }
+    // tests talk to a plain socket that never answers the probe
+    #[cfg(test)]
+    pub fn with_server_addr<A: ToSocketAddrs>(
+        server_addr: A
+    ) -> Result<ConnectionHandles, NetError> {
+        let socket = Self::bind_towards(server_addr)?;
+        socket.set_read_timeout(Some(RECEIVE_POLL_INTERVAL))?;
+        Self::with_socket(socket)
+    }
+    // bound to the unspecified address of the server's family, the OS picks the interface that reaches
+    // it. a socket bound to loopback can't even connect to any other address
+    fn bind_towards<A: ToSocketAddrs>(server_addr: A) -> io::Result<UdpSocket> {
+        let server_addr = server_addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "server address resolves to nothing"))?;
+        let local: SocketAddr = match server_addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(server_addr)?;
+        Ok(socket)
+    }
+    // Ok once the server answered a ping, no answer within PROBE_TIMEOUT or a refused send is an
+    // unreachable server. a late answer to an earlier attempt counts too
+    fn probe(socket: &UdpSocket) -> Result<(), NetError> {
+        let crate::types::SerializedMessageType::NonChunked(ping) = (NetworkMessage::Ping {
+            nonce: 0,
+            send_time_micros: 0,
+        }).serialize(NetworkMessageType::SendOnce) else {
+            unreachable!("a ping fits one datagram");
+        };
+        socket.send(&ping.bytes)?;
+        let deadline = Instant::now() + PROBE_TIMEOUT;
+        let mut buffer = MsgBuffer::default();
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(NetError::NotConnected);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            let amt = match socket.recv(&mut buffer.0) {
+                Ok(amt) => amt,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => {
+                    return Err(NetError::NotConnected);
+                }
+                Err(e) => {
+                    return Err(e.into());
+                }
+            };
+            if
+                let Ok(crate::types::DeserializedMessageType::NonChunked(msg)) = buffer.parse_on_client(amt)
+            {
+                if matches!(msg.msg, NetworkMessage::Pong { nonce: 0, .. }) {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    fn with_socket(socket: UdpSocket) -> Result<ConnectionHandles, NetError> {
+        let socket = Arc::new(socket);
+        let server_addr = socket.peer_addr()?;
 
         let (response_sender, response_receiver) = mpsc::channel();
         let (request_sender, request_receiver) = mpsc::channel();
@@ -64,12 +363,19 @@ impl ConnectionServer {
         let connection_server = Arc::new(
             Mutex::new(ConnectionServer {
                 socket,
+                server_addr,
+                session: SessionId::random(),
+                receive_thread: None,
                 sequence_number: SeqNumGenerator {
                     seq_num: SeqNum(0),
                 },
-                pending_acks: HashMap::new(),
+                next_transfer_id: 0,
+                pending_acks: RetransmitTable::new(CONTROL_RETRY.timeout, CONTROL_RETRY.max_retries),
                 network_to_game: response_sender,
                 client_request_receiver: request_receiver,
+                queued_requests: VecDeque::new(),
+                session_epoch: SessionEpoch::default(),
+                stale_requests_dropped: 0,
                 ack_sender,
                 ack_receiver,
                 network_msg_sender,
@@ -78,292 +384,1521 @@ impl ConnectionServer {
                 unack_input_buffer: BufferedNetworkedPlayerInputs {
                     buffered_inputs: Vec::new(),
                 },
-                unack_input_seq_nums_to_frame: HashMap::new(),
+                newest_sent_input: None,
+                repeated_input_frames: 0,
+                input_sends: InputSendSchedule::default(),
+                compact_held_inputs: false,
+                remote_input_acks: InputAcks::default(),
+                world_upload_seq_nums: HashSet::new(),
+                loop_timing: RollingWindow::new(TIMING_WINDOW, 4096),
+                remote_input_stats: InputGapStats::default(),
+                ping: PingTracker::new(Instant::now()),
+                link_loss: LinkLoss::default(),
+                stats_sender: None,
+                control_receiver: None,
+                message_history: Arc::new(Mutex::new(MessageHistory::default())),
+                shutting_down: false,
+                incompatible_server: None,
+                fatal_sender: None,
+                #[cfg(test)]
+                inject_panic: false,
             })
         );
 
-        Ok((connection_server, request_sender, response_receiver))
+        Ok((connection_server, RequestSender::new(request_sender), response_receiver))
     }
-    pub fn start(server: Arc<Mutex<ConnectionServer>>) {
+    // input datagrams per second, 0 sends one per frame, set before the connection is started
+    pub fn set_input_send_rate(&mut self, rate_hz: u32) {
+        self.input_sends = InputSendSchedule::new(rate_hz);
+    }
+    // set before the connection is started
+    pub fn set_compact_held_inputs(&mut self, compact: bool) {
+        self.compact_held_inputs = compact;
+    }
+    // run() holds the ConnectionServer lock, the game hears from the network thread over these channels.
+    // set before the connection is started
+    pub fn diagnostics(&mut self) -> NetDiagnostics {
+        let (stats_sender, stats) = mpsc::channel();
+        let (control, control_receiver) = mpsc::channel();
+        self.stats_sender = Some(stats_sender);
+        self.control_receiver = Some(control_receiver);
+        NetDiagnostics { stats, latest: NetStats::default(), control }
+    }
+    // how many datagrams each way are remembered, set before the connection is started
+    pub fn set_message_history_len(&mut self, len: usize) {
+        *self.message_history.lock().unwrap_or_else(PoisonError::into_inner) = MessageHistory::new(len);
+    }
+    // the returned channel only ever carries NetworkEvent::Fatal, once one of the network threads died
+    pub fn start(server: Arc<Mutex<ConnectionServer>>) -> mpsc::Receiver<NetworkEvent> {
+        let (fatal_sender, fatal_receiver) = mpsc::channel();
+        server.lock().unwrap().fatal_sender = Some(fatal_sender.clone());
         thread::spawn(move || {
-            server.lock().unwrap().run();
+            let result = panic::catch_unwind(
+                AssertUnwindSafe(|| {
+                    server.lock().unwrap_or_else(PoisonError::into_inner).run();
+                })
+            );
+            if let Err(payload) = result {
+                let reason = panic_reason(payload.as_ref());
+                eprintln!("Network thread panicked: {}", reason);
+                let mut server = server.lock().unwrap_or_else(PoisonError::into_inner);
+                server.close_game_channels();
+                let _ = fatal_sender.send(NetworkEvent::Fatal(reason));
+                // requests sent before the game saw the Fatal event are dropped instead of failing
+                while server.client_request_receiver.recv().is_ok() {}
+            }
         });
+        fatal_receiver
+    }
+    // moves everything the game queued off the channel and hands out the oldest request of the current
+    // session. BeginSession and EndSession take effect as soon as they are queued, so what an ended
+    // session still had waiting (e.g. a world upload) is dropped instead of reaching the next one
+    fn next_request(&mut self) -> Result<GameRequestToNetwork, mpsc::TryRecvError> {
+        let disconnected = loop {
+            match self.client_request_receiver.try_recv() {
+                Ok(tagged) => {
+                    match tagged.request {
+                        GameRequestToNetwork::BeginSession => {
+                            self.session_epoch = self.session_epoch.max(tagged.epoch);
+                        }
+                        GameRequestToNetwork::EndSession => {
+                            self.session_epoch = self.session_epoch.max(SessionEpoch(tagged.epoch.0 + 1));
+                            self.forget_session_sends();
+                        }
+                        _ => {}
+                    }
+                    self.queued_requests.push_back(tagged);
+                }
+                Err(mpsc::TryRecvError::Empty) => {
+                    break false;
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    break true;
+                }
+            }
+        };
+        let queued = self.queued_requests.len();
+        let session_epoch = self.session_epoch;
+        self.queued_requests.retain(|tagged| {
+            match tagged.request {
+                // not tied to a session
+                | GameRequestToNetwork::Reconnect
+                | GameRequestToNetwork::BeginSession
+                | GameRequestToNetwork::EndSession => true,
+                // the last word of a failed join, sent right before its session ends
+                GameRequestToNetwork::DirectRequest(NetworkMessage::ClientJoinFailed(_)) => {
+                    tagged.epoch.0 + 1 >= session_epoch.0
+                }
+                _ => tagged.epoch >= session_epoch,
+            }
+        });
+        let dropped = queued - self.queued_requests.len();
+        if dropped > 0 {
+            self.stale_requests_dropped += dropped;
+            eprintln!(
+                "Dropped {} requests of an ended session ({} so far)",
+                dropped,
+                self.stale_requests_dropped
+            );
+        }
+        match self.queued_requests.pop_front() {
+            Some(tagged) => Ok(tagged.request),
+            None if disconnected => Err(mpsc::TryRecvError::Disconnected),
+            None => Err(mpsc::TryRecvError::Empty),
+        }
+    }
+    // stops resending the ended session's world upload, its unacked inputs aren't repeated either
+    fn forget_session_sends(&mut self) {
+        for seq_num in self.world_upload_seq_nums.drain() {
+            self.pending_acks.on_ack(&seq_num);
+        }
+        self.unack_input_buffer.buffered_inputs.clear();
+        self.newest_sent_input = None;
+        self.remote_input_acks = InputAcks::default();
+    }
+    // drops our end of the event channel, the game sees it disconnect instead of going quiet
+    fn close_game_channels(&mut self) {
+        self.shutting_down = true;
+        self.network_to_game = mpsc::channel().0;
+        if let Some((stop, _)) = self.receive_thread.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
     }
 
-    pub fn run(&mut self) {
-        let receive_socket = Arc::clone(&self.socket);
-        let ack_sender = self.ack_sender.clone();
-        let chunk_collector = Arc::clone(&self.chunked_msg_collector);
-        let parsed_network_msg_sender = self.network_msg_sender.clone();
-        let receive_thread = thread::spawn(move || {
-            let mut buffer = MsgBuffer::default();
-            loop {
-                buffer.clear();
-                match receive_socket.recv(&mut buffer.0) {
-                    Ok(amt) if amt > 0 => {
-                        if let Ok(request) = buffer.parse_on_client() {
-                            match request {
-                                crate::types::DeserializedMessageType::NonChunked(request) => {
-                                    debug_assert!(
-                                        (request.seq_num.is_some() && request.reliable) ||
-                                            (!request.reliable && request.seq_num.is_none())
-                                    );
-                                    if let Some(seq_num) = request.seq_num {
-                                        let _ = ack_sender.send(SeqNum(seq_num));
-                                    }
-                                    let _ = parsed_network_msg_sender.send(request.msg);
-                                }
-                                crate::types::DeserializedMessageType::ChunkOfMessage(chunk) => {
-                                    let _ = ack_sender.send(SeqNum(chunk.seq_num));
-                                    let mut chunk_collector = chunk_collector.lock().unwrap();
-                                    chunk_collector.collect(chunk);
-                                    println!("Collected chunk");
-                                    if let Some(msg) = chunk_collector.try_combine() {
-                                        let _ = parsed_network_msg_sender.send(msg.msg);
-                                    }
-                                }
-                            }
+    // one request of the current session, see next_request
+    fn handle_request(&mut self, request: GameRequestToNetwork) {
+        match request {
+            GameRequestToNetwork::DirectRequest(network_msg) => {
+                match network_msg {
+                    NetworkMessage::GetOwnServerPlayerID => {
+                        eprintln!("Requesting our own player id isn't supported");
+                    }
+                    NetworkMessage::GetServerPlayerIDs => {
+                        if let Err(e) = self.get_available_player_worlds() {
+                            self.report_failure(e);
                         }
                     }
-                    Err(e) => {
-                        eprintln!("Failed to receive: {}", e);
-                        break;
+                    NetworkMessage::ClientSentWorld(sim_mem) => {
+                        if let Err(e) = self.send_player_world_state(sim_mem) {
+                            self.report_failure(e);
+                        }
+                    }
+
+                    NetworkMessage::ClientConnectToOtherWorld(id) => {
+                        if let Err(e) = self.connect_to_other_world(id) {
+                            self.report_failure(e);
+                        }
+                    }
+                    | NetworkMessage::ClientRequestResync
+                    | NetworkMessage::ClientRequestInputRange { .. } => {
+                        // what led up to a recovery request, for the logs
+                        eprintln!("Datagrams before {:?}:\n{}", network_msg, self.dump_messages());
+                        if let Err(e) = self.send_reliable(&network_msg) {
+                            self.report_failure(e);
+                        }
+                    }
+                    | NetworkMessage::ClientSetTickRate(_)
+                    | NetworkMessage::ClientStartedHosting
+                    | NetworkMessage::ClientJoinFailed(_)
+                    | NetworkMessage::ClientInputStreamPaused { .. }
+                    | NetworkMessage::ClientInputStreamResumed { .. } => {
+                        if let Err(e) = self.send_reliable(&network_msg) {
+                            self.report_failure(e);
+                        }
+                    }
+                    // unreliable like the inputs it stands in for, the next one covers a lost one
+                    NetworkMessage::ClientInputStreamPausedThrough { .. } => {
+                        if let Err(e) = self.send_once(&network_msg) {
+                            self.report_failure(e);
+                        }
+                    }
+                    NetworkMessage::ClientWorldBaseline(frame) => {
+                        // the joiner starts from the world, our frames up to it are no use to anyone
+                        self.unack_input_buffer.discard_acknowledged_frames(frame);
+                        if let Err(e) = self.send_reliable(&network_msg) {
+                            self.report_failure(e);
+                        }
+                    }
+                    | NetworkMessage::ClientSentPlayerInputs(_)
+                    | NetworkMessage::ClientSentPlayerInputRuns(_) => {
+                        eprintln!(
+                            "Client cannot send buffered inputs, network takes caree of this"
+                        );
+                    }
+                    _ => {
+                        eprintln!(
+                            "Tried to run server side NetworkMessage on client {:?}",
+                            network_msg
+                        );
                     }
-                    _ => {}
                 }
             }
-        });
+            GameRequestToNetwork::IndirectRequest(game_msg) => {
+                self.handle_game_message(game_msg);
+            }
+            GameRequestToNetwork::Reconnect => {
+                if let Err(e) = self.reconnect() {
+                    self.report_failure(e);
+                }
+            }
+            // applied when they were queued, see next_request
+            GameRequestToNetwork::BeginSession => {}
+            GameRequestToNetwork::EndSession => {
+                // the rest of the connection group hears that we left
+                if let Err(e) = self.send_reliable(&NetworkMessage::ClientLeaveSession) {
+                    self.report_failure(e);
+                }
+            }
+        }
+    }
 
+    pub fn run(&mut self) {
+        self.spawn_receiver();
+        if let Err(e) = self.send_reliable(&NetworkMessage::Hello(local_protocol(), self.session)) {
+            self.report_failure(e);
+        }
+        let mut last_stats_publish = Instant::now();
         loop {
-            if let Ok(ack) = self.ack_receiver.try_recv() {
-                self.send_ack(ack);
+            let iteration_start = Instant::now();
+            #[cfg(test)]
+            if self.inject_panic {
+                panic!("injected network panic");
             }
+            self.send_pending_acks();
             if let Ok(msg) = self.network_msg_receiver.try_recv() {
                 match msg {
                     NetworkMessage::ServerSentWorld(data) => {
                         println!("server sent world arrived");
-                        let _ = self.network_to_game.send(NetworkMessage::ServerSentWorld(data));
-                    }
-                    NetworkMessage::ServerSentPlayerInputs(inputs) => {
                         let _ = self.network_to_game.send(
-                            NetworkMessage::ServerSentPlayerInputs(inputs)
+                            NetworkEvent::Message(NetworkMessage::ServerSentWorld(data))
                         );
                     }
                     NetworkMessage::ServerSideAck(acked_seq_num) => {
                         self.handle_ack(acked_seq_num);
                         LOGGER.log_received_ack(acked_seq_num.0);
                     }
-                    NetworkMessage::ServerSentPlayerIDs(ids) => {
-                        let _ = self.network_to_game.send(
-                            NetworkMessage::ServerSentPlayerIDs(ids)
-                        );
+                    NetworkMessage::ServerInputsAckedThrough(frame) => {
+                        self.handle_inputs_acked_through(frame);
                     }
-                    NetworkMessage::ServerRequestHostForWorldData => {
+                    NetworkMessage::HelloAck(server_info) => {
+                        self.handle_hello_ack(server_info);
+                    }
+                    NetworkMessage::Pong { nonce, send_time_micros } => {
+                        self.ping.on_pong(Instant::now(), nonce, send_time_micros);
+                    }
+                    NetworkMessage::ServerSentPlayerInputs(slot, mut inputs) => {
+                        self.remote_input_stats.add(inputs.normalize());
+                        self.remote_input_acks.record(inputs.buffered_inputs.iter().map(|inp| inp.frame));
                         let _ = self.network_to_game.send(
-                            NetworkMessage::ServerRequestHostForWorldData
+                            NetworkEvent::Message(NetworkMessage::ServerSentPlayerInputs(slot, inputs))
                         );
                     }
+                    | NetworkMessage::ServerSentPlayerIDs(..)
+                    | NetworkMessage::ServerRequestHostForWorldData
+                    | NetworkMessage::ServerAssignPlayerSlot(_)
+                    | NetworkMessage::PlayerJoined(_)
+                    | NetworkMessage::PlayerLeft(_)
+                    | NetworkMessage::ServerPlayerListChanged
+                    | NetworkMessage::ServerRequestInputRange { .. }
+                    | NetworkMessage::ServerJoinFailed(..) => {
+                        let _ = self.network_to_game.send(NetworkEvent::Message(msg));
+                    }
+                    | NetworkMessage::ServerInputStreamPaused { .. }
+                    | NetworkMessage::ServerInputStreamResumed { .. }
+                    | NetworkMessage::ServerInputStreamPausedThrough { .. } => {
+                        self.skip_remote_pause(&msg);
+                        let _ = self.network_to_game.send(NetworkEvent::Message(msg));
+                    }
                     _ => {}
                 }
             }
-            match self.client_request_receiver.try_recv() {
-                Ok(request) => {
-                    match request {
-                        GameRequestToNetwork::DirectRequest(network_msg) => {
-                            match network_msg {
-                                NetworkMessage::GetOwnServerPlayerID => {
-                                    todo!();
-                                }
-                                NetworkMessage::GetServerPlayerIDs => {
-                                    if let Err(e) = self.get_available_player_worlds() {
-                                        eprintln!("Error getting available player worlds: {}", e);
-                                    }
-                                }
-                                NetworkMessage::ClientSentWorld(sim_mem) => {
-                                    if let Err(e) = self.send_player_world_state(sim_mem) {
-                                        eprintln!("Error sending player world state: {}", e);
-                                    }
-                                }
+            match self.next_request() {
+                Ok(request) => self.handle_request(request),
+                Err(mpsc::TryRecvError::Empty) => {
+                    // No message received, continue with other operations
+                }
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    // Channel has been disconnected, exit the loop
+                    self.shutting_down = true;
+                    break;
+                }
+            }
 
-                                NetworkMessage::ClientConnectToOtherWorld(id) => {
-                                    if let Err(e) = self.connect_to_other_world(id) {
-                                        eprintln!("Error connecting to other world: {}", e);
+            self.answer_control();
+            self.handle_retransmissions();
+            self.send_ping_if_due();
+            self.send_input_ack_if_due();
+
+            let now = Instant::now();
+            self.loop_timing.record_at(now, now.duration_since(iteration_start));
+            if now.duration_since(last_stats_publish) >= NET_STATS_PUBLISH_INTERVAL {
+                last_stats_publish = now;
+                if let Some(stats_sender) = &self.stats_sender {
+                    let _ = stats_sender.send(NetStats {
+                        loop_iteration: self.loop_timing.summary_at(now),
+                        remote_inputs: self.remote_input_stats,
+                        rtt: self.ping.rtt(),
+                        reliable_loss: self.link_loss.reliable.loss_at(now),
+                        input_loss: self.link_loss.inputs.loss_at(now),
+                        repeated_input_frames: self.repeated_input_frames,
+                        predicted_steps_per_second: 0.0, // the game's to fill in
+                    });
+                }
+                if self.link_loss.became_poor_at(now) {
+                    let _ = self.network_to_game.send(NetworkEvent::PoorConnection);
+                }
+            }
+        }
+        if let Some((stop, receive_thread)) = self.receive_thread.take() {
+            stop.store(true, Ordering::Relaxed);
+            let _ = receive_thread.join(); // a panic in there was already reported as fatal
+        }
+    }
+    // replaces the thread reading from the socket, the old one stops within RECEIVE_POLL_INTERVAL
+    fn spawn_receiver(&mut self) {
+        if let Some((stop, _)) = self.receive_thread.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = Arc::clone(&stop);
+        let receive_socket = Arc::clone(&self.socket);
+        let ack_sender = self.ack_sender.clone();
+        let chunk_collector = Arc::clone(&self.chunked_msg_collector);
+        let parsed_network_msg_sender = self.network_msg_sender.clone();
+        let message_history = Arc::clone(&self.message_history);
+        let fatal_sender = self.fatal_sender.clone();
+        let receive_thread = thread::spawn(move || {
+            let result = panic::catch_unwind(
+                AssertUnwindSafe(|| {
+                    Self::receive_loop(
+                        &receive_socket,
+                        &thread_stop,
+                        &ack_sender,
+                        &chunk_collector,
+                        &parsed_network_msg_sender,
+                        &message_history
+                    )
+                })
+            );
+            if let Err(payload) = result {
+                let reason = panic_reason(payload.as_ref());
+                eprintln!("Receive thread panicked: {}", reason);
+                if let Some(fatal_sender) = fatal_sender {
+                    let _ = fatal_sender.send(NetworkEvent::Fatal(reason));
+                }
+            }
+        });
+        self.receive_thread = Some((stop, receive_thread));
+    }
+    fn receive_loop(
+        receive_socket: &UdpSocket,
+        thread_stop: &AtomicBool,
+        ack_sender: &mpsc::Sender<SeqNum>,
+        chunk_collector: &Mutex<ChunkedMessageCollector>,
+        parsed_network_msg_sender: &mpsc::Sender<NetworkMessage>,
+        message_history: &Mutex<MessageHistory>
+    ) {
+        let mut buffer = MsgBuffer::default();
+        while !thread_stop.load(Ordering::Relaxed) {
+            buffer.clear();
+            match receive_socket.recv(&mut buffer.0) {
+                Ok(amt) if amt > 0 => {
+                    if let Ok(request) = buffer.parse_on_client(amt) {
+                        let msg = match request {
+                            crate::types::DeserializedMessageType::NonChunked(ref request) => Some(&request.msg),
+                            crate::types::DeserializedMessageType::ChunkOfMessage(_) => None,
+                        };
+                        let record = MessageRecord::new(Instant::now(), Direction::Received, &buffer.0[..amt], msg);
+                        message_history.lock().unwrap_or_else(PoisonError::into_inner).push((), record);
+                        match request {
+                            crate::types::DeserializedMessageType::NonChunked(request) => {
+                                debug_assert!(
+                                    (request.seq_num.is_some() && request.reliable) ||
+                                        (!request.reliable && request.seq_num.is_none())
+                                );
+                                // a closed channel means the connection is gone, nobody reads what comes in
+                                if let Some(seq_num) = request.seq_num {
+                                    if ack_sender.send(SeqNum(seq_num)).is_err() {
+                                        break;
                                     }
                                 }
-                                NetworkMessage::ClientSentPlayerInputs(_) => {
-                                    panic!(
-                                        "Client cannot send buffered inputs, network takes caree of this"
-                                    );
+                                if parsed_network_msg_sender.send(request.msg).is_err() {
+                                    break;
+                                }
+                            }
+                            crate::types::DeserializedMessageType::ChunkOfMessage(chunk) => {
+                                if ack_sender.send(SeqNum(chunk.seq_num)).is_err() {
+                                    break;
                                 }
-                                _ => {
-                                    panic!(
-                                        "Tried to run server side NetworkMessage on client {:?}",
-                                        network_msg
+                                let mut chunk_collector = chunk_collector
+                                    .lock()
+                                    .unwrap_or_else(PoisonError::into_inner);
+                                if let Err(e) = chunk_collector.collect(chunk) {
+                                    eprintln!(
+                                        "Refused a chunk from the server: {} ({} messages dropped so far)",
+                                        e,
+                                        chunk_collector.dropped_groups
                                     );
                                 }
-                            }
-                        }
-                        GameRequestToNetwork::IndirectRequest(game_msg) => {
-                            match game_msg {
-                                GameMessage::ClientSentPlayerInputs(inp) => {
-                                    if let Err(e) = self.send_player_inputs(inp) {
-                                        let error = match e {
-                                            SendInputsError::Disconnected => { "couldn't reach other player" }
-                                            SendInputsError::IO(io_e) => { &io_e.to_string() }
-                                        };
-                                        eprintln!("Error sending player inputs: {:?}", error);
-                                  
+                                println!("Collected chunk");
+                                if let Some(msg) = chunk_collector.try_combine() {
+                                    if parsed_network_msg_sender.send(msg.msg).is_err() {
+                                        break;
                                     }
                                 }
                             }
                         }
                     }
                 }
-                Err(mpsc::TryRecvError::Empty) => {
-                    // No message received, continue with other operations
-                }
-                Err(mpsc::TryRecvError::Disconnected) => {
-                    // Channel has been disconnected, exit the loop
+                Err(e) if
+                    e.kind() == io::ErrorKind::WouldBlock ||
+                    e.kind() == io::ErrorKind::TimedOut
+                => {}
+                Err(e) => {
+                    eprintln!("Failed to receive: {}", e);
                     break;
                 }
+                _ => {}
             }
-
-            self.handle_retransmissions();
         }
-        receive_thread.join().unwrap();
     }
-    pub fn handle_server_input_ack(&mut self, seq_num: SeqNum) -> bool {
-        if let Some(frame) = self.unack_input_seq_nums_to_frame.remove(&seq_num) {
-            self.unack_input_buffer.discard_acknowledged_frames(frame);
-            return true;
+    fn answer_control(&mut self) {
+        let Some(control_receiver) = &self.control_receiver else {
+            return;
+        };
+        let requests: Vec<NetworkControl> = control_receiver.try_iter().collect();
+        for request in requests {
+            match request {
+                NetworkControl::DumpMessages(reply) => {
+                    let _ = reply.send(self.dump_messages());
+                }
+            }
         }
-        return false;
+    }
+    fn dump_messages(&self) -> String {
+        self.message_history.lock().unwrap_or_else(PoisonError::into_inner).dump(Instant::now())
+    }
+    // binds a fresh socket, e.g. after the local address changed, and tells the server it's still us
+    pub fn reconnect(&mut self) -> Result<(), NetError> {
+        self.ensure_running()?;
+        let socket = Self::bind_towards(self.server_addr)?;
+        socket.set_read_timeout(Some(RECEIVE_POLL_INTERVAL))?;
+        self.socket = Arc::new(socket);
+        self.spawn_receiver();
+        self.send_reliable(&NetworkMessage::Hello(local_protocol(), self.session)).map(|_| ())
+    }
+
+
+    // the server got every input up to `frame`, the later datagrams don't need to repeat them
+    pub fn handle_inputs_acked_through(&mut self, frame: u32) {
+        self.link_loss.inputs.on_acked_through(frame);
+        self.unack_input_buffer.discard_acknowledged_frames(frame);
     }
     pub fn handle_ack(&mut self, acked_seq_num: SeqNum) {
-        let handled = self.handle_server_input_ack(acked_seq_num);
-        if handled {
-            return;
+        if let Some(acked) = self.pending_acks.on_ack(&acked_seq_num) {
+            self.link_loss.reliable.on_ack(acked_seq_num);
+            LOGGER.log_acked_after_retries(acked_seq_num.0, acked.retries);
+        }
+        if self.world_upload_seq_nums.remove(&acked_seq_num) && self.world_upload_seq_nums.is_empty() {
+            let _ = self.network_to_game.send(NetworkEvent::WorldUploadComplete);
         }
-        self.pending_acks.remove(&acked_seq_num);
     }
 
-    pub fn send_reliable(&mut self, request: &NetworkMessage) -> Result<(), std::io::Error> {
-        let seq_num = self.sequence_number.get_seq_num();
-        let serialized_message = request.serialize(
-            crate::types::NetworkMessageType::ResendUntilAck(seq_num)
-        );
+    fn handle_hello_ack(&mut self, server_info: ProtocolInfo) {
+        if let Err(e) = local_protocol().check_compatible(&server_info) {
+            self.incompatible_server = Some(server_info);
+            self.report_failure(e);
+        }
+    }
+    fn report_failure(&self, error: NetError) {
+        let _ = self.network_to_game.send(NetworkEvent::Failed(error));
+    }
+    fn ensure_running(&self) -> Result<(), NetError> {
+        if self.shutting_down {
+            return Err(NetError::ShuttingDown);
+        }
+        if let Some(theirs) = self.incompatible_server {
+            return Err(NetError::IncompatiblePeer { ours: local_protocol(), theirs });
+        }
+        Ok(())
+    }
+
+    // returns the seq nums the message went out with, one per chunk
+    pub fn send_reliable(&mut self, request: &NetworkMessage) -> Result<Vec<SeqNum>, NetError> {
+        self.ensure_running()?;
+        if let NetworkMessage::ClientSentWorld(ref data) = request {
+            if data.len() > MAX_CHUNKED_PAYLOAD_LEN {
+                return Err(NetError::PayloadTooLarge { len: data.len() });
+            }
+        }
+        let serialized_message = request.serialize(NetworkMessageType::ReliableTransfer {
+            seq_num: self.sequence_number.seq_num,
+            transfer_id: self.next_transfer_id,
+        });
+        let policy = retry_policy(request.retry_class());
         match serialized_message {
             crate::types::SerializedMessageType::Chunked(chunks) => {
-                let mut i = 0;
-                for msg in chunks.bytes {
+                self.next_transfer_id = self.next_transfer_id.wrapping_add(1);
+                let mut seq_nums = Vec::with_capacity(chunks.chunks.len());
+                for msg in chunks.chunks {
+                    let seq_num = self.sequence_number.get_seq_num();
                     debug_assert!(
-                        u16::from_le_bytes([msg[SEQ_NUM_BYTE_POS], msg[SEQ_NUM_BYTE_POS + 1]]) ==
-                            seq_num.0 + (i as u16)
+                        u16::from_le_bytes([
+                            msg.bytes[SEQ_NUM_BYTE_POS],
+                            msg.bytes[SEQ_NUM_BYTE_POS + 1],
+                        ]) == seq_num.0
                     );
-                    i += 1;
-                    self.socket.send(&msg)?;
-                    self.pending_acks.insert(seq_num, (
-                        Instant::now(),
-                        SerializedNetworkMessage { bytes: msg },
-                    ));
+                    self.send_datagram(&msg.bytes, None)?;
+                    let now = Instant::now();
+                    self.pending_acks.insert_with_policy(now, seq_num, msg, policy);
+                    self.link_loss.reliable.on_send(now, seq_num);
                     LOGGER.log_sent_packet(seq_num.0);
+                    seq_nums.push(seq_num);
                 }
-                Ok(())
+                Ok(seq_nums)
             }
             crate::types::SerializedMessageType::NonChunked(serialized_message) => {
-                self.socket.send(&serialized_message.bytes)?;
-                self.pending_acks.insert(seq_num, (Instant::now(), serialized_message));
-                Ok(())
+                let seq_num = self.sequence_number.get_seq_num();
+                self.send_datagram(&serialized_message.bytes, Some(request))?;
+                let now = Instant::now();
+                self.pending_acks.insert_with_policy(now, seq_num, serialized_message, policy);
+                self.link_loss.reliable.on_send(now, seq_num);
+                Ok(vec![seq_num])
             }
         }
     }
 
-    fn send_ack(&self, seq_num: SeqNum) {
-        let ack_message = NetworkMessage::ClientSideAck(seq_num).serialize(
-            NetworkMessageType::ResendUntilAck(seq_num)
-        );
-        match ack_message {
-            crate::types::SerializedMessageType::NonChunked(serialized_msg) => {
-                if let Err(e) = self.socket.send(&serialized_msg.bytes) {
-                    eprintln!("Failed to send ACK to server: {}", e);
+    // every datagram to the server goes through here so the message history sees it
+    fn send_datagram(&self, bytes: &[u8], msg: Option<&NetworkMessage>) -> io::Result<usize> {
+        let record = MessageRecord::new(Instant::now(), Direction::Sent, bytes, msg);
+        self.message_history.lock().unwrap_or_else(PoisonError::into_inner).push((), record);
+        self.socket.send(bytes)
+    }
+    // everything the receive thread acked since the last tick goes out in as few datagrams as possible
+    fn send_pending_acks(&self) {
+        let pending: Vec<SeqNum> = self.ack_receiver.try_iter().collect();
+        for seq_nums in pending.chunks(MAX_ACKS_PER_DATAGRAM) {
+            let ack = NetworkMessage::ClientSideAcks(seq_nums.to_vec());
+            match ack.serialize(NetworkMessageType::SendOnce) {
+                crate::types::SerializedMessageType::NonChunked(serialized_msg) => {
+                    if let Err(e) = self.send_datagram(&serialized_msg.bytes, Some(&ack)) {
+                        eprintln!("Failed to send ACK to server: {}", e);
+                    }
+                }
+                crate::types::SerializedMessageType::Chunked(_) => {
+                    eprintln!("ack shouldnt be chunked, dropped {} acks", seq_nums.len());
                 }
             }
-            crate::types::SerializedMessageType::Chunked(_) => {
-                panic!("ack shouldnt be chunked");
+        }
+    }
+    fn send_ping_if_due(&mut self) {
+        if self.ensure_running().is_err() {
+            return;
+        }
+        let Some(ping) = self.ping.poll(Instant::now()) else {
+            return;
+        };
+        let crate::types::SerializedMessageType::NonChunked(msg) = ping.serialize(
+            NetworkMessageType::SendOnce
+        ) else {
+            unreachable!("a ping fits one datagram");
+        };
+        // a lost ping is just a missing sample, the next one follows PING_INTERVAL later
+        if let Err(e) = self.send_datagram(&msg.bytes, Some(&ping)) {
+            eprintln!("Failed to send ping: {}", e);
+        }
+    }
+    // nothing is resent if it gets lost
+    fn send_once(&self, msg: &NetworkMessage) -> Result<(), NetError> {
+        self.ensure_running()?;
+        let crate::types::SerializedMessageType::NonChunked(serialized) = msg.serialize(
+            NetworkMessageType::SendOnce
+        ) else {
+            unreachable!("only messages that fit one datagram are sent once");
+        };
+        self.send_datagram(&serialized.bytes, Some(msg))?;
+        Ok(())
+    }
+    // a paused peer's frames are never relayed, the server waits for our ack past them all the same
+    fn skip_remote_pause(&mut self, msg: &NetworkMessage) {
+        match *msg {
+            NetworkMessage::ServerInputStreamPaused { last_frame, .. } => {
+                self.remote_input_acks.on_paused(last_frame);
+            }
+            NetworkMessage::ServerInputStreamPausedThrough { through_frame, .. } => {
+                self.remote_input_acks.paused_through(through_frame);
             }
+            NetworkMessage::ServerInputStreamResumed { from_frame, .. } => {
+                if let Some(through_frame) = from_frame.checked_sub(1) {
+                    self.remote_input_acks.paused_through(through_frame);
+                }
+            }
+            _ => {}
+        }
+    }
+    // unreliable like the inputs it acks, the next one covers a lost one
+    fn send_input_ack_if_due(&mut self) {
+        if self.ensure_running().is_err() {
+            return;
+        }
+        let Some(frame) = self.remote_input_acks.due_at(Instant::now()) else {
+            return;
+        };
+        let ack = NetworkMessage::ClientInputsAckedThrough(frame);
+        let crate::types::SerializedMessageType::NonChunked(msg) = ack.serialize(
+            NetworkMessageType::SendOnce
+        ) else {
+            unreachable!("an input ack fits one datagram");
+        };
+        if let Err(e) = self.send_datagram(&msg.bytes, Some(&ack)) {
+            eprintln!("Failed to send input ack: {}", e);
         }
     }
     fn handle_retransmissions(&mut self) {
         let now = Instant::now();
-        let mut to_retry = Vec::new();
-
-        {
-            for (seq, (sent_time, request)) in self.pending_acks.iter() {
-                if now.duration_since(*sent_time) > RETRY_TIMEOUT {
-                    to_retry.push((*seq, request.clone()));
-                }
+        let resent = self.pending_acks.tick(now);
+        for (seq, request) in &resent {
+            LOGGER.log_sent_retransmission(seq.0);
+            self.link_loss.reliable.on_send(now, *seq);
+            if let Err(e) = self.send_datagram(&request.bytes, None) {
+                eprintln!("Failed to resend message {:?}: {}", seq, e);
             }
-            self.pending_acks.retain(|_, (sent_time, _)| {
-                now.duration_since(*sent_time) < RETRY_TIMEOUT * MAX_RETRIES
-            });
         }
-
-        for (seq, request) in to_retry {
-            if let Some((ref mut sent_time, _)) = self.pending_acks.get_mut(&seq) {
-                *sent_time = now;
-                LOGGER.log_sent_retransmission(seq.0);
-                if let Err(e) = self.socket.send(&request.bytes) {
-                    eprintln!("Failed to resend message {:?}: {}", seq, e);
-                }
-            }
+        if !resent.is_empty() {
+            LOGGER.log_pending_acks(
+                self.pending_acks
+                    .pending_retries()
+                    .map(|(seq, retries)| (*seq, retries))
+                    .collect()
+            );
+        }
+        let exhausted = self.pending_acks.take_exhausted();
+        for (seq, retries) in &exhausted {
+            eprintln!("Message {:?} was resent {} times and never acked", seq, retries);
+        }
+        if !exhausted.is_empty() {
+            self.report_failure(NetError::Disconnected);
         }
     }
 
-    fn send_player_world_state(&mut self, sim_mem: Vec<u8>) -> Result<(), std::io::Error> {
-        let request = NetworkMessage::ClientSentWorld(sim_mem.clone()); // TODO REWRITE THIS TO JUST USE REQUEST
-        self.send_reliable(&request)
+    fn send_player_world_state(&mut self, sim_mem: Vec<u8>) -> Result<(), NetError> {
+        let request = NetworkMessage::ClientSentWorld(sim_mem);
+        // a newer upload supersedes whatever is left of the previous one
+        self.world_upload_seq_nums = self.send_reliable(&request)?.into_iter().collect();
+        Ok(())
     }
 
-    fn get_available_player_worlds(&mut self) -> Result<(), std::io::Error> {
+    fn get_available_player_worlds(&mut self) -> Result<(), NetError> {
         let request = NetworkMessage::GetServerPlayerIDs;
-        self.send_reliable(&request)
+        self.send_reliable(&request).map(|_| ())
     }
-    fn connect_to_other_world(&mut self, id: ServerPlayerID) -> Result<(), std::io::Error> {
+    fn connect_to_other_world(&mut self, id: ServerPlayerID) -> Result<(), NetError> {
         let request = NetworkMessage::ClientConnectToOtherWorld(id);
-        self.send_reliable(&request)
-    }
-    fn send_player_inputs(&mut self, inputs: NetworkedPlayerInput) -> Result<(), SendInputsError> {
-        // if they have the same length then we couldnt send inputs for multiple seconds, so we stop sending and disconnect
-        let seq_num = self.sequence_number.get_seq_num();
-        if
-            (self.unack_input_buffer.buffered_inputs.len() + 1) * 5 >
-            MAX_UDP_PAYLOAD_DATA_LENGTH - 1 // if new input would overflow;  5 bytes 4 for frame, 1 for input, and 1 start bit for length of vec
-        {
-            self.unack_input_buffer.buffered_inputs.swap_remove(0); // remove first
-            return Err(SendInputsError::Disconnected);
+        self.send_reliable(&request).map(|_| ())
+    }
+    fn handle_game_message(&mut self, game_msg: GameMessage) {
+        let result = match game_msg {
+            GameMessage::ClientSentPlayerInputs(inp) if self.is_first_send_of(inp.frame) => {
+                self.send_player_inputs(inp, Instant::now())
+            }
+            GameMessage::ClientSentPlayerInputs(_) => Ok(()),
+            GameMessage::ClientResentPlayerInputs(inp) => self.resend_player_inputs(inp),
+        };
+        match result {
+            Err(NetError::Io(e)) if looks_like_address_change(&e) => {
+                if let Err(e) = self.reconnect() {
+                    self.report_failure(e);
+                }
+            }
+            Err(e) => self.report_failure(e),
+            Ok(()) => {}
         }
+    }
+    // moves the sent frames watermark up to `frame`, false if that frame already went out
+    fn is_first_send_of(&mut self, frame: u32) -> bool {
+        if let Some(newest) = self.newest_sent_input.filter(|newest| frame <= *newest) {
+            self.repeated_input_frames += 1;
+            eprintln!(
+                "Dropped a second input for frame {}, frames up to {} were sent already ({} so far)",
+                frame,
+                newest,
+                self.repeated_input_frames
+            );
+            return false;
+        }
+        self.newest_sent_input = Some(frame);
+        true
+    }
+    fn send_player_inputs(&mut self, inputs: NetworkedPlayerInput, now: Instant) -> Result<(), NetError> {
+        self.ensure_running()?;
         self.unack_input_buffer.insert_player_input(inputs.clone());
-        self.unack_input_seq_nums_to_frame.insert(seq_num, inputs.frame);
+        // more unacked frames than a datagram holds means nothing got through for seconds,
+        // so we stop sending and disconnect
+        let dropped = self.unack_input_buffer.trim_to_fit(BufferedNetworkedPlayerInputs::max_frames_per_packet());
+        if let (Some(oldest), Some(newest)) = (dropped.first(), dropped.last()) {
+            eprintln!(
+                "Dropped unacked input frames {} to {}, they no longer fit a datagram",
+                oldest.frame,
+                newest.frame
+            );
+            return Err(NetError::Disconnected);
+        }
+        if !self.input_sends.is_due(now) {
+            return Ok(());
+        }
         // debug_assert!(
         //     self.unack_input_buffer.buffered_inputs.windows(2).all(|i| i[0].frame + 1 == i[1].frame)
         // );
-        let request = NetworkMessage::ClientSentPlayerInputs(
-            self.unack_input_buffer.clone()
-        ).serialize(NetworkMessageType::SendOnceButReceiveAck(seq_num));
+        let unacked = self.unack_input_buffer.clone();
+        self.send_inputs_datagram(unacked)?;
+        self.link_loss.inputs.on_send(now, inputs.frame);
+        Ok(())
+    }
+    // a frame a peer asked for again goes out on its own, it must not crowd our unacked frames out of their
+    // datagram. lost once more, the peer asks again
+    fn resend_player_inputs(&mut self, inputs: NetworkedPlayerInput) -> Result<(), NetError> {
+        self.ensure_running()?;
+        self.send_inputs_datagram(BufferedNetworkedPlayerInputs { buffered_inputs: vec![inputs] })
+    }
+    fn send_inputs_datagram(&mut self, inputs: BufferedNetworkedPlayerInputs) -> Result<(), NetError> {
+        let msg = if self.compact_held_inputs && inputs.runs_are_shorter() {
+            NetworkMessage::ClientSentPlayerInputRuns(inputs)
+        } else {
+            NetworkMessage::ClientSentPlayerInputs(inputs)
+        };
 
-        match request {
+        match msg.serialize(NetworkMessageType::SendOnce) {
+            // can't happen after the trim in send_player_inputs, a datagram that big wouldn't make it anyway
+            crate::types::SerializedMessageType::NonChunked(request) if request.len() > MAX_UDP_PAYLOAD_LEN => {
+                Err(NetError::PayloadTooLarge { len: request.len() })
+            }
             crate::types::SerializedMessageType::NonChunked(request) => {
-                let res = self.socket.send(&request.bytes);
-                match res {
-                    Ok(_) => {
-                        return Ok(());
-                    }
-                    Err(e) => {
-                        return Err(SendInputsError::IO(e));
-                    }
+                self.send_datagram(&request.bytes, Some(&msg))?;
+                Ok(())
+            }
+            crate::types::SerializedMessageType::Chunked(chunks) =>
+                Err(NetError::PayloadTooLarge {
+                    len: chunks.chunks.iter().map(|chunk| chunk.len()).sum(),
+                }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{ BTreeMap, HashMap };
+    use crate::types::{ DeserializedMessageType, MsgBuffer, PlayerID, PlayerInput };
+
+    fn test_connection() -> (Arc<Mutex<ConnectionServer>>, UdpSocket) {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server_socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        let (connection, _, _) = ConnectionServer::with_server_addr(
+            server_socket.local_addr().unwrap()
+        ).unwrap();
+        (connection, server_socket)
+    }
+
+    fn recv_on_server(socket: &UdpSocket) -> Option<NetworkMessage> {
+        let mut buffer = MsgBuffer::default();
+        let amt = socket.recv(&mut buffer.0).ok()?;
+        match buffer.parse_on_server(amt).ok()? {
+            DeserializedMessageType::NonChunked(msg) => Some(msg.msg),
+            DeserializedMessageType::ChunkOfMessage(_) => None,
+        }
+    }
+
+    #[test]
+    fn test_pending_acks_are_batched_into_one_datagram() {
+        let (connection, server_socket) = test_connection();
+        let connection = connection.lock().unwrap();
+        for seq in 0..40 {
+            connection.ack_sender.send(SeqNum(seq)).unwrap();
+        }
+        connection.send_pending_acks();
+
+        let datagrams: Vec<NetworkMessage> = std::iter::from_fn(|| {
+            recv_on_server(&server_socket)
+        }).collect();
+        assert_eq!(datagrams.len(), 1);
+        let mut acked: Vec<u16> = datagrams
+            .into_iter()
+            .flat_map(|msg| {
+                match msg {
+                    NetworkMessage::ClientSideAcks(seq_nums) => seq_nums,
+                    msg => std::panic!("unexpected message {:?}", msg),
                 }
+            })
+            .map(|seq| seq.0)
+            .collect();
+        acked.sort();
+        assert_eq!(acked, (0..40).collect::<Vec<u16>>());
+    }
+
+    #[test]
+    fn test_ack_overflow_spills_into_next_datagram() {
+        let (connection, server_socket) = test_connection();
+        let connection = connection.lock().unwrap();
+        for seq in 0..(MAX_ACKS_PER_DATAGRAM + 1) as u16 {
+            connection.ack_sender.send(SeqNum(seq)).unwrap();
+        }
+        connection.send_pending_acks();
+        connection.send_pending_acks(); // nothing left, sends nothing
+
+        let datagrams: Vec<NetworkMessage> = std::iter::from_fn(|| {
+            recv_on_server(&server_socket)
+        }).collect();
+        assert_eq!(datagrams.len(), 2);
+    }
+
+    #[test]
+    fn test_unacked_input_overflow_is_disconnected() {
+        let (connection, _server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        let mut result = Ok(());
+        for frame in 1..=MAX_UDP_PAYLOAD_DATA_LENGTH as u32 {
+            result = connection.send_player_inputs(
+                NetworkedPlayerInput::new(Vec::new(), frame),
+                Instant::now()
+            );
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(matches!(result, Err(NetError::Disconnected)), "{:?}", result);
+    }
+
+    #[test]
+    fn test_the_fullest_input_datagram_goes_out_and_one_more_frame_disconnects() {
+        let (connection, _server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        let max = BufferedNetworkedPlayerInputs::max_frames_per_packet() as u32;
+        for frame in 1..=max {
+            // alternating inputs, runs wouldn't make the datagram any shorter
+            let input = if frame % 2 == 0 { PlayerInput::Left } else { PlayerInput::Right };
+            connection.send_player_inputs(NetworkedPlayerInput::new(vec![input], frame), Instant::now()).unwrap();
+        }
+        // nothing was acked, the last datagram carries every frame
+        let sent = connection.message_history.lock().unwrap().iter().last().map(|(_, record)| *record).unwrap();
+        assert_eq!(sent.frames, Some((1, max)));
+        assert!(sent.len <= MAX_UDP_PAYLOAD_LEN, "{} bytes", sent.len);
+
+        let result = connection.send_player_inputs(NetworkedPlayerInput::new(Vec::new(), max + 1), Instant::now());
+        assert!(matches!(result, Err(NetError::Disconnected)), "{:?}", result);
+    }
+
+    #[test]
+    fn test_a_resent_frame_at_the_unacked_cap_leaves_the_unacked_frames_alone() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server_socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        let (connection, _, events) = ConnectionServer::with_server_addr(
+            server_socket.local_addr().unwrap()
+        ).unwrap();
+        let mut connection = connection.lock().unwrap();
+        let max = BufferedNetworkedPlayerInputs::max_frames_per_packet() as u32;
+        // frames 1 to 10 were acked, the rest fills the unacked buffer
+        for frame in 11..10 + max {
+            connection.send_player_inputs(NetworkedPlayerInput::new(Vec::new(), frame), Instant::now()).unwrap();
+        }
+        while recv_on_server(&server_socket).is_some() {}
+        let unacked = connection.unack_input_buffer.clone();
+
+        // the peer lost frames 4 and 5, the game resends both
+        for frame in [4, 5] {
+            connection.handle_game_message(
+                GameMessage::ClientResentPlayerInputs(NetworkedPlayerInput::new(vec![PlayerInput::Left], frame))
+            );
+            let Some(NetworkMessage::ClientSentPlayerInputs(inputs)) = recv_on_server(&server_socket) else {
+                panic!("expected the resent frame {}", frame);
+            };
+            assert_eq!(inputs.buffered_inputs, vec![NetworkedPlayerInput::new(vec![PlayerInput::Left], frame)]);
+        }
+        assert!(events.try_recv().is_err(), "a resend failed the connection");
+        assert_eq!(connection.unack_input_buffer.buffered_inputs, unacked.buffered_inputs);
+        // the last frame that fits still goes out with every unacked one
+        connection.send_player_inputs(NetworkedPlayerInput::new(Vec::new(), 10 + max), Instant::now()).unwrap();
+        let Some(NetworkMessage::ClientSentPlayerInputs(inputs)) = recv_on_server(&server_socket) else {
+            panic!("expected the unacked frames");
+        };
+        assert_eq!(inputs.buffered_inputs.len(), max as usize);
+    }
+
+    #[test]
+    fn test_half_send_rate_batches_two_frames_per_datagram() {
+        const FRAME: Duration = Duration::from_nanos(16_666_667);
+        let (connection, server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        connection.set_input_send_rate(30);
+        server_socket.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let start = Instant::now();
+        let mut datagrams: Vec<Vec<u32>> = Vec::new();
+        for frame in 1..=21 {
+            let now = start + FRAME * (frame - 1);
+            connection.send_player_inputs(NetworkedPlayerInput::new(Vec::new(), frame), now).unwrap();
+            // the server acks every datagram right away
+            if let Some(msg) = recv_on_server(&server_socket) {
+                let NetworkMessage::ClientSentPlayerInputs(inputs) = msg else {
+                    std::panic!("unexpected message {:?}", msg);
+                };
+                let frames: Vec<u32> = inputs.buffered_inputs
+                    .iter()
+                    .map(|input| input.frame)
+                    .collect();
+                connection.handle_inputs_acked_through(*frames.last().unwrap());
+                datagrams.push(frames);
+            }
+        }
+        // the first frame goes out on its own, from then on every other frame
+        assert_eq!(datagrams.len(), 11);
+        assert_eq!(datagrams[0], vec![1]);
+        assert!(datagrams[1..].iter().all(|frames| frames.len() == 2), "{:?}", datagrams);
+        assert_eq!(datagrams.concat(), (1..=21).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_each_frame_goes_out_once_with_the_inputs_it_was_sampled_with() {
+        let (connection, server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        let sampled = |frame: u32| {
+            if frame.is_multiple_of(2) { vec![PlayerInput::Left] } else { vec![PlayerInput::Shoot] }
+        };
+        let sent = |frame: u32, inputs: Vec<PlayerInput>| {
+            GameMessage::ClientSentPlayerInputs(NetworkedPlayerInput::new(inputs, frame))
+        };
+        let mut received: BTreeMap<u32, Vec<Vec<PlayerInput>>> = BTreeMap::new();
+        for frame in 1..=8 {
+            connection.handle_game_message(sent(frame, sampled(frame)));
+            // a catch up labelling this tick's inputs with older frames, some of them already acked
+            connection.handle_game_message(sent(frame - 1, vec![PlayerInput::Right]));
+            connection.handle_game_message(sent(frame.saturating_sub(3), vec![PlayerInput::Right]));
+            let msg = recv_on_server(&server_socket);
+            let Some(NetworkMessage::ClientSentPlayerInputs(inputs)) = msg else {
+                panic!("expected inputs, got {:?}", msg);
+            };
+            // the server acks every datagram right away, the next one carries only the new frame
+            connection.handle_inputs_acked_through(inputs.buffered_inputs.last().unwrap().frame);
+            for input in inputs.buffered_inputs {
+                received.entry(input.frame).or_default().push(input.inputs);
+            }
+        }
+        assert!(recv_on_server(&server_socket).is_none());
+        assert_eq!(received.keys().copied().collect::<Vec<u32>>(), (1..=8).collect::<Vec<u32>>());
+        for (frame, inputs) in received {
+            assert_eq!(inputs, vec![sampled(frame)], "frame {}", frame);
+        }
+        assert_eq!(connection.repeated_input_frames, 2 * 8);
+
+        // what the server asks for again goes out as it is, the game resends what it played
+        connection.handle_game_message(
+            GameMessage::ClientResentPlayerInputs(NetworkedPlayerInput::new(sampled(3), 3))
+        );
+        let Some(NetworkMessage::ClientSentPlayerInputs(inputs)) = recv_on_server(&server_socket) else {
+            panic!("expected the resent frame");
+        };
+        assert_eq!(inputs.buffered_inputs, vec![NetworkedPlayerInput::new(sampled(3), 3)]);
+        // a new session starts its frames over
+        connection.forget_session_sends();
+        connection.handle_game_message(sent(1, sampled(1)));
+        assert!(recv_on_server(&server_socket).is_some());
+        assert_eq!(connection.repeated_input_frames, 2 * 8);
+    }
+
+    #[test]
+    fn test_a_later_input_ack_trims_what_lost_acks_left_behind() {
+        let (connection, server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        for frame in 1..=6 {
+            connection.send_player_inputs(NetworkedPlayerInput::new(Vec::new(), frame), Instant::now()).unwrap();
+        }
+        // inputs go out without a seq num, nothing waits for a per datagram ack
+        assert!(connection.pending_acks.is_empty());
+        let last = std::iter::from_fn(|| recv_on_server(&server_socket)).last();
+        let Some(NetworkMessage::ClientSentPlayerInputs(inputs)) = last else {
+            panic!("expected inputs, got {:?}", last);
+        };
+        assert_eq!(inputs.buffered_inputs.len(), 6);
+
+        // the acks through 2 and 3 were lost, the one through 4 covers them
+        connection.handle_inputs_acked_through(4);
+        let unacked = |connection: &ConnectionServer| {
+            connection.unack_input_buffer.buffered_inputs
+                .iter()
+                .map(|inp| inp.frame)
+                .collect::<Vec<u32>>()
+        };
+        assert_eq!(unacked(&connection), vec![5, 6]);
+        // a late one changes nothing
+        connection.handle_inputs_acked_through(2);
+        assert_eq!(unacked(&connection), vec![5, 6]);
+        connection.send_player_inputs(NetworkedPlayerInput::new(Vec::new(), 7), Instant::now()).unwrap();
+        let Some(NetworkMessage::ClientSentPlayerInputs(inputs)) = recv_on_server(&server_socket) else {
+            panic!("expected inputs");
+        };
+        assert_eq!(inputs.buffered_inputs.iter().map(|inp| inp.frame).collect::<Vec<u32>>(), vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn test_relayed_inputs_are_acked_past_a_peers_pause() {
+        let (connection, server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        connection.remote_input_acks.record(1..=10);
+        let slot = PlayerID::Player2;
+        connection.skip_remote_pause(&NetworkMessage::ServerInputStreamPaused { slot, last_frame: 10 });
+        connection.skip_remote_pause(&NetworkMessage::ServerInputStreamResumed { slot, from_frame: 26 });
+        connection.remote_input_acks.record(26..=30);
+        connection.send_input_ack_if_due();
+        let ack = std::iter::from_fn(|| recv_on_server(&server_socket)).find(|msg| {
+            matches!(msg, NetworkMessage::ClientInputsAckedThrough(_))
+        });
+        assert!(matches!(ack, Some(NetworkMessage::ClientInputsAckedThrough(30))));
+    }
+
+    #[test]
+    fn test_held_inputs_are_sent_as_runs_once_that_is_shorter() {
+        let (connection, server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        connection.set_compact_held_inputs(true);
+        for frame in 1..=30 {
+            let held = NetworkedPlayerInput::new(vec![PlayerInput::Right], frame);
+            connection.send_player_inputs(held, Instant::now()).unwrap();
+        }
+        let datagrams: Vec<NetworkMessage> = std::iter::from_fn(|| recv_on_server(&server_socket)).collect();
+        assert_eq!(datagrams.len(), 30);
+        // a lone frame is shorter one by one
+        assert!(matches!(datagrams[0], NetworkMessage::ClientSentPlayerInputs(_)));
+        let Some(NetworkMessage::ClientSentPlayerInputRuns(inputs)) = datagrams.last() else {
+            panic!("expected input runs, got {:?}", datagrams.last());
+        };
+        let frames: Vec<u32> = inputs.buffered_inputs
+            .iter()
+            .map(|input| input.frame)
+            .collect();
+        assert_eq!(frames, (1..=30).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_oversized_world_is_rejected() {
+        let (connection, server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        let result = connection.send_player_world_state(vec![0; MAX_CHUNKED_PAYLOAD_LEN + 1]);
+        assert!(
+            matches!(result, Err(NetError::PayloadTooLarge { len }) if len == MAX_CHUNKED_PAYLOAD_LEN + 1)
+        );
+        assert!(recv_on_server(&server_socket).is_none());
+        assert!(connection.pending_acks.is_empty());
+    }
+
+    #[test]
+    fn test_chunked_upload_is_not_copied_into_pending_acks() {
+        let (connection, _server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        let world = vec![5; MAX_UDP_PAYLOAD_DATA_LENGTH * 30];
+        connection.send_player_world_state(world).unwrap();
+        assert_eq!(connection.pending_acks.len(), 30);
+        // the table holds the only reference to each serialized chunk
+        assert!(connection.pending_acks.iter().all(|(_, msg)| Arc::strong_count(&msg.bytes) == 1));
+
+        let resent = connection.pending_acks.tick(Instant::now() + BULK_RETRY.timeout);
+        assert_eq!(resent.len(), 30);
+        for (seq, msg) in &resent {
+            let stored = connection.pending_acks
+                .iter()
+                .find(|(pending_seq, _)| *pending_seq == seq)
+                .map(|(_, stored)| stored)
+                .unwrap();
+            assert!(Arc::ptr_eq(&stored.bytes, &msg.bytes));
+            assert_eq!(Arc::strong_count(&msg.bytes), 2);
+        }
+    }
+
+    #[test]
+    fn test_world_chunks_back_off_while_lobby_requests_keep_their_timeout() {
+        let (connection, _server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        connection.send_player_world_state(vec![5; MAX_UDP_PAYLOAD_DATA_LENGTH * 2]).unwrap();
+        connection.get_available_player_worlds().unwrap();
+        let start = Instant::now();
+
+        let mut resent_at: HashMap<SeqNum, Vec<u32>> = HashMap::new();
+        let mut given_up_at = HashMap::new();
+        for tick in 1..=100 {
+            for (seq, _) in connection.pending_acks.tick(start + CONTROL_RETRY.timeout * tick) {
+                resent_at.entry(seq).or_default().push(tick);
+            }
+            for (seq, _) in connection.pending_acks.take_exhausted() {
+                given_up_at.insert(seq, tick);
             }
-            _ => panic!("Invalid type for send inputs request"),
         }
+        let (chunk, player_list) = (SeqNum(0), SeqNum(2));
+        assert_eq!(resent_at[&player_list], (1..=8).collect::<Vec<u32>>());
+        assert_eq!(CONTROL_RETRY.timeout * given_up_at[&player_list], CONTROL_RETRY.budget());
+        // 1, 2 and 4 timeouts, then every 2s
+        assert_eq!(resent_at[&chunk][..5], [1, 3, 7, 15, 23]);
+        assert_eq!(resent_at[&chunk].len() as u32, BULK_RETRY.max_retries);
+        assert_eq!(CONTROL_RETRY.timeout * given_up_at[&chunk], BULK_RETRY.budget());
+    }
+
+    #[test]
+    fn test_world_upload_completes_once_every_chunk_is_acked() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (connection, _, events) = ConnectionServer::with_server_addr(
+            server_socket.local_addr().unwrap()
+        ).unwrap();
+        let mut connection = connection.lock().unwrap();
+        connection.send_player_world_state(vec![5; MAX_UDP_PAYLOAD_DATA_LENGTH * 3]).unwrap();
+        let mut chunks: Vec<SeqNum> = connection.world_upload_seq_nums.iter().copied().collect();
+        assert_eq!(chunks.len(), 3);
+
+        let last_chunk = chunks.pop().unwrap();
+        for seq_num in &chunks {
+            connection.handle_ack(*seq_num);
+        }
+        assert!(events.try_recv().is_err(), "a partially acked upload isn't complete");
+
+        connection.handle_ack(last_chunk);
+        connection.handle_ack(last_chunk); // a duplicated ack completes nothing more
+        let completions = events
+            .try_iter()
+            .filter(|event| matches!(event, NetworkEvent::WorldUploadComplete))
+            .count();
+        assert_eq!(completions, 1);
+    }
+
+    #[test]
+    fn test_requests_after_shutdown_fail() {
+        let (connection, server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        connection.shutting_down = true;
+        assert!(matches!(connection.get_available_player_worlds(), Err(NetError::ShuttingDown)));
+        assert!(
+            matches!(
+                connection.send_player_inputs(NetworkedPlayerInput::new(Vec::new(), 1), Instant::now()),
+                Err(NetError::ShuttingDown)
+            )
+        );
+        assert!(recv_on_server(&server_socket).is_none());
+    }
+
+    #[test]
+    fn test_receive_thread_exits_once_nobody_reads_what_it_receives() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(server_socket.local_addr().unwrap()).unwrap();
+        socket.set_read_timeout(Some(RECEIVE_POLL_INTERVAL)).unwrap();
+        let client_addr = socket.local_addr().unwrap();
+        let (ack_sender, ack_receiver) = mpsc::channel();
+        let (msg_sender, msg_receiver) = mpsc::channel();
+        let (done_sender, done_receiver) = mpsc::channel();
+        let history = Arc::new(Mutex::new(MessageHistory::default()));
+        let thread_history = Arc::clone(&history);
+        thread::spawn(move || {
+            // never told to stop, only the closed channel ends it
+            let stop = AtomicBool::new(false);
+            let collector = Mutex::new(ChunkedMessageCollector::default());
+            ConnectionServer::receive_loop(&socket, &stop, &ack_sender, &collector, &msg_sender, &thread_history);
+            done_sender.send(()).unwrap();
+        });
+        drop(ack_receiver);
+        drop(msg_receiver);
+        let crate::types::SerializedMessageType::NonChunked(msg) = NetworkMessage::ServerInputsAckedThrough(
+            3
+        ).serialize(NetworkMessageType::SendOnce) else {
+            unreachable!("an input ack fits one datagram");
+        };
+        server_socket.send_to(&msg.bytes, client_addr).unwrap();
+        assert!(done_receiver.recv_timeout(Duration::from_secs(1)).is_ok(), "receive thread kept running");
+        // it was still recorded
+        let history = history.lock().unwrap();
+        let (_, record) = history.iter().next().unwrap();
+        assert_eq!((record.direction, record.frames), (Direction::Received, Some((3, 3))));
+    }
+
+    #[test]
+    fn test_connect_retries_only_an_unreachable_server_the_configured_times() {
+        let retry = ConnectRetry { attempts: 4, backoff: Duration::from_millis(10) };
+        let mut tries = 0;
+        let mut sleeps = Vec::new();
+        let result: Result<(), NetError> = retry.run(
+            || {
+                tries += 1;
+                Err(NetError::NotConnected)
+            },
+            |backoff| sleeps.push(backoff.as_millis())
+        );
+        assert!(matches!(result, Err(NetError::NotConnected)));
+        assert_eq!(tries, 4);
+        assert_eq!(sleeps, vec![10, 20, 40]);
+
+        // the server came up in between
+        let mut tries = 0;
+        let result = retry.run(
+            || {
+                tries += 1;
+                if tries < 3 { Err(NetError::NotConnected) } else { Ok(tries) }
+            },
+            |_| {}
+        );
+        assert!(matches!(result, Ok(3)));
+
+        // e.g. an address that doesn't resolve, trying again won't help
+        let mut tries = 0;
+        let result: Result<(), NetError> = retry.run(
+            || {
+                tries += 1;
+                Err(NetError::Io(io::Error::new(io::ErrorKind::InvalidInput, "bad address")))
+            },
+            |_| {}
+        );
+        assert!(matches!(result, Err(NetError::Io(_))));
+        assert_eq!(tries, 1);
+    }
+
+    #[test]
+    fn test_connect_probes_the_server_with_a_ping() {
+        let retry = ConnectRetry { attempts: 1, backoff: Duration::ZERO };
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent_addr = silent.local_addr().unwrap();
+        assert!(matches!(ConnectionServer::connect(silent_addr, retry), Err(NetError::NotConnected)));
+
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server_socket.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut buffer = MsgBuffer::default();
+            let (amt, src) = server_socket.recv_from(&mut buffer.0).unwrap();
+            let Ok(DeserializedMessageType::NonChunked(msg)) = buffer.parse_on_server(amt) else {
+                panic!("expected the probe");
+            };
+            let NetworkMessage::Ping { nonce, send_time_micros } = msg.msg else {
+                panic!("expected a ping, got {:?}", msg.msg);
+            };
+            let crate::types::SerializedMessageType::NonChunked(pong) = (NetworkMessage::Pong {
+                nonce,
+                send_time_micros,
+            }).serialize(NetworkMessageType::SendOnce) else {
+                unreachable!("a pong fits one datagram");
+            };
+            server_socket.send_to(&pong.bytes, src).unwrap();
+        });
+        assert!(ConnectionServer::connect(server_addr, retry).is_ok());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_every_probe_goes_out_from_the_same_address() {
+        let retry = ConnectRetry { attempts: 3, backoff: Duration::ZERO };
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server_socket.local_addr().unwrap();
+        // the first probe is lost, the second is answered
+        let server = thread::spawn(move || {
+            let mut buffer = MsgBuffer::default();
+            let (_, first) = server_socket.recv_from(&mut buffer.0).unwrap();
+            let (amt, second) = server_socket.recv_from(&mut buffer.0).unwrap();
+            let Ok(DeserializedMessageType::NonChunked(msg)) = buffer.parse_on_server(amt) else {
+                panic!("expected the probe");
+            };
+            let NetworkMessage::Ping { nonce, send_time_micros } = msg.msg else {
+                panic!("expected a ping, got {:?}", msg.msg);
+            };
+            let crate::types::SerializedMessageType::NonChunked(pong) = (NetworkMessage::Pong {
+                nonce,
+                send_time_micros,
+            }).serialize(NetworkMessageType::SendOnce) else {
+                unreachable!("a pong fits one datagram");
+            };
+            server_socket.send_to(&pong.bytes, second).unwrap();
+            (first, second)
+        });
+        let connection = ConnectionServer::connect_in_background(server_addr.to_string(), retry)
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap();
+        let (connection, _, _) = connection.unwrap();
+        let (first, second) = server.join().unwrap();
+        assert_eq!(first, second);
+        assert_eq!(connection.lock().unwrap().socket.local_addr().unwrap().port(), first.port());
+    }
+
+    #[test]
+    fn test_sockets_can_reach_a_server_off_this_machine() {
+        // EINVAL from connect is what a loopback-bound socket gets, no route here is a different error
+        if let Err(e) = ConnectionServer::bind_towards("10.0.0.7:9000") {
+            assert_ne!(e.kind(), io::ErrorKind::InvalidInput, "{}", e);
+        }
+        let socket = ConnectionServer::bind_towards("127.0.0.1:9000").unwrap();
+        assert!(socket.local_addr().unwrap().is_ipv4());
+    }
+
+    #[test]
+    fn test_hello_ack_with_other_features_rejects_server() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server_socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        let (connection, _requests, events) = ConnectionServer::with_server_addr(
+            server_socket.local_addr().unwrap()
+        ).unwrap();
+        let mut connection = connection.lock().unwrap();
+
+        connection.handle_hello_ack(local_protocol());
+        assert!(events.try_recv().is_err());
+        assert!(connection.get_available_player_worlds().is_ok());
+        assert!(matches!(recv_on_server(&server_socket), Some(NetworkMessage::GetServerPlayerIDs)));
+
+        let server_info = ProtocolInfo {
+            feature_bits: local_protocol().feature_bits ^ 0b10,
+            ..local_protocol()
+        };
+        connection.handle_hello_ack(server_info);
+        match events.try_recv() {
+            Ok(NetworkEvent::Failed(NetError::IncompatiblePeer { ours, theirs })) => {
+                assert_eq!(ours, local_protocol());
+                assert_eq!(theirs, server_info);
+            }
+            event => std::panic!("expected an incompatibility, got {:?}", event),
+        }
+        assert!(
+            matches!(
+                connection.get_available_player_worlds(),
+                Err(NetError::IncompatiblePeer { .. })
+            )
+        );
+        assert!(recv_on_server(&server_socket).is_none());
+    }
+
+    #[test]
+    fn test_reconnect_rebinds_and_repeats_hello_with_same_session() {
+        let (connection, server_socket) = test_connection();
+        let mut connection = connection.lock().unwrap();
+        let old_addr = connection.socket.local_addr().unwrap();
+        connection.reconnect().unwrap();
+        assert_ne!(connection.socket.local_addr().unwrap(), old_addr);
+
+        let mut buffer = MsgBuffer::default();
+        let (amt, src) = server_socket.recv_from(&mut buffer.0).unwrap();
+        assert_eq!(src, connection.socket.local_addr().unwrap());
+        match buffer.parse_on_server(amt).unwrap() {
+            DeserializedMessageType::NonChunked(msg) =>
+                match msg.msg {
+                    NetworkMessage::Hello(info, session) => {
+                        assert_eq!(info, local_protocol());
+                        assert_eq!(session, connection.session);
+                    }
+                    msg => std::panic!("unexpected message {:?}", msg),
+                }
+            DeserializedMessageType::ChunkOfMessage(_) => std::panic!("expected a whole message"),
+        }
+    }
+
+    #[test]
+    fn test_panicking_run_loop_reports_fatal_and_closes_event_channel() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (connection, request_sender, events) = ConnectionServer::with_server_addr(
+            server_socket.local_addr().unwrap()
+        ).unwrap();
+        connection.lock().unwrap().inject_panic = true;
+        let fatal = ConnectionServer::start(Arc::clone(&connection));
+
+        match fatal.recv_timeout(Duration::from_secs(2)) {
+            Ok(NetworkEvent::Fatal(reason)) => assert_eq!(reason, "injected network panic"),
+            _ => std::panic!("expected a fatal event"),
+        }
+        // nothing is left on the event channel and it reports the disconnect
+        assert!(
+            matches!(
+                events.recv_timeout(Duration::from_secs(1)),
+                Err(mpsc::RecvTimeoutError::Disconnected)
+            )
+        );
+        // requests the game sends before it noticed are swallowed instead of failing
+        assert!(request_sender.send(GameRequestToNetwork::Reconnect).is_ok());
+        drop(request_sender);
+        // the thread lets go of the (poisoned) lock once the game dropped its sender
+        assert!(connection.lock().unwrap_or_else(PoisonError::into_inner).shutting_down);
+    }
+
+    #[test]
+    fn test_stats_and_message_dumps_reach_the_game_while_the_run_loop_holds_the_lock() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (connection, request_sender, _events) = ConnectionServer::with_server_addr(
+            server_socket.local_addr().unwrap()
+        ).unwrap();
+        let mut diagnostics = connection.lock().unwrap().diagnostics();
+        let _fatal = ConnectionServer::start(connection);
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while diagnostics.stats().loop_iteration.samples == 0 {
+            assert!(Instant::now() < deadline, "no stats were published");
+            thread::sleep(Duration::from_millis(10));
+        }
+        // the Hello the run loop starts with is in the history
+        let dump = diagnostics.dump_messages(Duration::from_secs(1)).expect("the network thread didn't answer");
+        assert!(!dump.is_empty());
+
+        drop(request_sender);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while diagnostics.dump_messages(Duration::from_millis(100)).is_some() {
+            assert!(Instant::now() < deadline, "the network thread didn't stop");
+        }
+        // what was published last is kept
+        assert!(diagnostics.stats().loop_iteration.samples > 0);
+    }
+
+    fn request_kind(request: &GameRequestToNetwork) -> &'static str {
+        match request {
+            GameRequestToNetwork::DirectRequest(NetworkMessage::ClientSentWorld(_)) => "world",
+            GameRequestToNetwork::DirectRequest(_) => "direct",
+            GameRequestToNetwork::IndirectRequest(_) => "inputs",
+            GameRequestToNetwork::Reconnect => "reconnect",
+            GameRequestToNetwork::BeginSession => "begin",
+            GameRequestToNetwork::EndSession => "end",
+        }
+    }
+
+    #[test]
+    fn test_requests_of_an_ended_session_are_dropped() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (connection, mut request_sender, _events) = ConnectionServer::with_server_addr(
+            server_socket.local_addr().unwrap()
+        ).unwrap();
+        let mut connection = connection.lock().unwrap();
+        let inputs = |frame| {
+            GameRequestToNetwork::IndirectRequest(
+                GameMessage::ClientSentPlayerInputs(NetworkedPlayerInput::new(Vec::new(), frame))
+            )
+        };
+
+        request_sender.begin_session().unwrap();
+        let old_session = request_sender.clone(); // what a callback of the session holds on to
+        request_sender
+            .send(GameRequestToNetwork::DirectRequest(NetworkMessage::ClientSentWorld(vec![1; 10])))
+            .unwrap();
+        request_sender.send(inputs(1)).unwrap();
+        request_sender.end_session().unwrap();
+        request_sender.send(GameRequestToNetwork::Reconnect).unwrap(); // not tied to a session
+        request_sender.begin_session().unwrap();
+        request_sender
+            .send(GameRequestToNetwork::DirectRequest(NetworkMessage::GetServerPlayerIDs))
+            .unwrap();
+        old_session.send(inputs(2)).unwrap();
+
+        // all of it was queued before the network thread got to it, the ended session's part never runs
+        let handled: Vec<&str> = std::iter::from_fn(|| connection.next_request().ok())
+            .map(|request| request_kind(&request))
+            .collect();
+        assert_eq!(handled, vec!["begin", "end", "reconnect", "begin", "direct"]);
+        assert_eq!(connection.stale_requests_dropped, 3);
+
+        // the current session's requests still go through
+        request_sender.send(inputs(1)).unwrap();
+        assert_eq!(connection.next_request().map(|request| request_kind(&request)), Ok("inputs"));
+        drop(request_sender);
+        drop(old_session);
+        assert_eq!(connection.next_request().err(), Some(mpsc::TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_a_failed_join_is_reported_before_the_session_ends() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server_socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+        let (connection, mut request_sender, _events) = ConnectionServer::with_server_addr(
+            server_socket.local_addr().unwrap()
+        ).unwrap();
+        let mut connection = connection.lock().unwrap();
+        request_sender.begin_session().unwrap();
+        // what the game does when the host's world is rejected
+        request_sender
+            .send(GameRequestToNetwork::DirectRequest(NetworkMessage::ClientJoinFailed("bad world".to_string())))
+            .unwrap();
+        request_sender.end_session().unwrap();
+        while let Ok(request) = connection.next_request() {
+            connection.handle_request(request);
+        }
+        let sent: Vec<NetworkMessage> = std::iter::from_fn(|| recv_on_server(&server_socket)).collect();
+        assert!(
+            matches!(&sent[..], [
+                NetworkMessage::ClientJoinFailed(reason),
+                NetworkMessage::ClientLeaveSession,
+            ] if reason == "bad world"),
+            "{:?}",
+            sent
+        );
+        assert_eq!(connection.stale_requests_dropped, 0);
+    }
+
+    #[test]
+    fn test_ending_a_session_stops_resending_its_world_upload() {
+        let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (connection, mut request_sender, _events) = ConnectionServer::with_server_addr(
+            server_socket.local_addr().unwrap()
+        ).unwrap();
+        let mut connection = connection.lock().unwrap();
+        request_sender.begin_session().unwrap();
+        connection.send_player_world_state(vec![5; MAX_UDP_PAYLOAD_DATA_LENGTH * 3]).unwrap();
+        connection.send_player_inputs(NetworkedPlayerInput::new(Vec::new(), 1), Instant::now()).unwrap();
+        assert_eq!(connection.pending_acks.len(), 3);
+        assert_eq!(connection.unack_input_buffer.buffered_inputs.len(), 1);
+
+        request_sender.end_session().unwrap();
+        assert!(connection.next_request().is_ok());
+        assert!(connection.pending_acks.is_empty());
+        assert!(connection.world_upload_seq_nums.is_empty());
+        assert!(connection.unack_input_buffer.buffered_inputs.is_empty());
     }
 }
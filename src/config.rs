@@ -0,0 +1,108 @@
+use std::{ fs, io, path::Path };
+
+use crate::types::LogConfig;
+
+// launching with it starts from the defaults, the file is written again on exit
+pub const RESET_CONFIG_FLAG: &str = "--reset-config";
+
+// settings a binary remembers between launches, every one can also be given as a `--key=value` flag
+pub trait Config: Default {
+    // false for a key this build doesn't know
+    fn set(&mut self, key: &str, value: &str) -> Result<bool, &'static str>;
+    // every setting, in the order they are written
+    fn pairs(&self) -> Vec<(&'static str, String)>;
+}
+
+// `key = value` lines like the tuning file, `#` starts a comment
+pub fn parse_pairs(text: &str) -> Result<Vec<(&str, &str)>, &'static str> {
+    let mut pairs = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or("Config line is not key = value")?;
+        pairs.push((key.trim(), value.trim()));
+    }
+    Ok(pairs)
+}
+
+pub fn to_text(config: &impl Config) -> String {
+    config
+        .pairs()
+        .into_iter()
+        .map(|(key, value)| format!("{} = {}\n", key, value))
+        .collect()
+}
+
+// defaults, then the file unless RESET_CONFIG_FLAG is given, then the flags. unknown keys in the file
+// are skipped (a newer build may have written it), an unknown flag is a typo and refused
+pub fn load<C: Config>(path: &Path, args: &[String]) -> Result<C, String> {
+    let mut config = C::default();
+    let reset = args.iter().any(|arg| arg == RESET_CONFIG_FLAG);
+    if !reset {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                let in_file = |e| format!("{}: {}", path.display(), e);
+                for (key, value) in parse_pairs(&text).map_err(in_file)? {
+                    config.set(key, value).map_err(in_file)?;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(format!("{}: {}", path.display(), e));
+            }
+        }
+    }
+    for arg in args.iter().filter(|arg| *arg != RESET_CONFIG_FLAG) {
+        let (key, value) = arg
+            .strip_prefix("--")
+            .and_then(|flag| flag.split_once('='))
+            .ok_or_else(|| format!("{}: flags are --key=value", arg))?;
+        if !config.set(key, value).map_err(|e| format!("{}: {}", arg, e))? {
+            return Err(format!("{}: unknown flag", arg));
+        }
+    }
+    Ok(config)
+}
+
+pub fn save(config: &impl Config, path: &Path) -> io::Result<()> {
+    fs::write(path, to_text(config))
+}
+
+pub fn parse_bool(value: &str) -> Result<bool, &'static str> {
+    value.parse().map_err(|_| "Config value is not true or false")
+}
+
+// the log categories are `log_<category>` keys in both binaries' files
+pub fn set_log_key(log: &mut LogConfig, key: &str, value: &str) -> Result<bool, &'static str> {
+    let category = match key {
+        "log_connection" => &mut log.connection,
+        "log_world_state" => &mut log.world_state,
+        "log_player_input" => &mut log.player_input,
+        "log_message_handling" => &mut log.message_handling,
+        "log_ack" => &mut log.ack,
+        "log_error" => &mut log.error,
+        "log_debug" => &mut log.debug,
+        _ => {
+            return Ok(false);
+        }
+    };
+    *category = parse_bool(value)?;
+    Ok(true)
+}
+
+pub fn log_pairs(log: &LogConfig) -> Vec<(&'static str, String)> {
+    [
+        ("log_connection", log.connection),
+        ("log_world_state", log.world_state),
+        ("log_player_input", log.player_input),
+        ("log_message_handling", log.message_handling),
+        ("log_ack", log.ack),
+        ("log_error", log.error),
+        ("log_debug", log.debug),
+    ]
+        .into_iter()
+        .map(|(key, value)| (key, value.to_string()))
+        .collect()
+}
@@ -0,0 +1,124 @@
+use std::{ io::BufRead, sync::mpsc::{ self, Receiver }, thread };
+
+use crate::{ config, types::{ LogConfig, ServerPlayerID } };
+
+// a line typed into the server's terminal, applied by the main loop in Server::update
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConsoleCommand {
+    Status,
+    Kick(ServerPlayerID),
+    Log {
+        key: String, // the config key of the category, e.g. log_connection
+        enabled: bool,
+    },
+    Loss(f32), // simulated packet loss, simulation_mode only
+    Latency(u64), // simulated baseline latency in ms, simulation_mode only
+    History(Option<ServerPlayerID>), // recent datagrams, of everyone without a player
+    Shutdown,
+}
+
+pub const HELP: &str =
+    "commands: status | kick <player_id> | log <category> on|off | loss <0..1> | lat <ms> | \
+     history [player_id] | shutdown";
+
+pub fn parse(line: &str) -> Result<ConsoleCommand, &'static str> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words[..] {
+        ["status"] => Ok(ConsoleCommand::Status),
+        ["shutdown"] => Ok(ConsoleCommand::Shutdown),
+        ["kick", id] => {
+            let id = id.parse().map_err(|_| "Player ids are 0 to 255")?;
+            Ok(ConsoleCommand::Kick(ServerPlayerID(id)))
+        }
+        ["log", category, state] => {
+            let enabled = match state {
+                "on" => true,
+                "off" => false,
+                _ => {
+                    return Err("Log categories are turned on or off");
+                }
+            };
+            let key = format!("log_{}", category);
+            // the same names as in the config file, without the prefix
+            if config::set_log_key(&mut LogConfig::default(), &key, "true") != Ok(true) {
+                return Err("Unknown log category");
+            }
+            Ok(ConsoleCommand::Log { key, enabled })
+        }
+        ["loss", loss] => {
+            let loss: f32 = loss.parse().map_err(|_| "Loss is a number")?;
+            if !(0.0..=1.0).contains(&loss) {
+                return Err("Loss is between 0 and 1");
+            }
+            Ok(ConsoleCommand::Loss(loss))
+        }
+        ["history"] => Ok(ConsoleCommand::History(None)),
+        ["history", id] => {
+            let id = id.parse().map_err(|_| "Player ids are 0 to 255")?;
+            Ok(ConsoleCommand::History(Some(ServerPlayerID(id))))
+        }
+        ["lat", ms] => Ok(ConsoleCommand::Latency(ms.parse().map_err(|_| "Latency is whole ms")?)),
+        _ => Err("Unknown command"),
+    }
+}
+
+// reads stdin on a thread of its own, only parsed commands reach the receiver
+pub fn spawn() -> Receiver<ConsoleCommand> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        println!("{}", HELP);
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse(&line) {
+                Ok(command) => {
+                    if sender.send(command).is_err() {
+                        return; // the server is gone
+                    }
+                }
+                Err(e) => println!("{}, {}", e, HELP),
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_parse() {
+        assert_eq!(parse("status"), Ok(ConsoleCommand::Status));
+        assert_eq!(parse("  shutdown "), Ok(ConsoleCommand::Shutdown));
+        assert_eq!(parse("kick 3"), Ok(ConsoleCommand::Kick(ServerPlayerID(3))));
+        assert_eq!(
+            parse("log player_input on"),
+            Ok(ConsoleCommand::Log { key: "log_player_input".to_string(), enabled: true })
+        );
+        assert_eq!(
+            parse("log error off"),
+            Ok(ConsoleCommand::Log { key: "log_error".to_string(), enabled: false })
+        );
+        assert_eq!(parse("loss 0.05"), Ok(ConsoleCommand::Loss(0.05)));
+        assert_eq!(parse("lat 80"), Ok(ConsoleCommand::Latency(80)));
+        assert_eq!(parse("history"), Ok(ConsoleCommand::History(None)));
+        assert_eq!(parse("history 2"), Ok(ConsoleCommand::History(Some(ServerPlayerID(2)))));
+    }
+
+    #[test]
+    fn test_broken_commands_are_rejected() {
+        assert_eq!(parse("restart"), Err("Unknown command"));
+        assert_eq!(parse("kick"), Err("Unknown command"));
+        assert_eq!(parse("kick 256"), Err("Player ids are 0 to 255"));
+        assert_eq!(parse("history x"), Err("Player ids are 0 to 255"));
+        assert_eq!(parse("log gossip on"), Err("Unknown log category"));
+        assert_eq!(parse("log error maybe"), Err("Log categories are turned on or off"));
+        assert_eq!(parse("loss 1.5"), Err("Loss is between 0 and 1"));
+        assert_eq!(parse("lat -5"), Err("Latency is whole ms"));
+    }
+}
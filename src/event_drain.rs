@@ -0,0 +1,68 @@
+use std::{ collections::VecDeque, sync::mpsc::Receiver };
+
+// network events the game handles per frame at most, a burst (e.g. a finished world upload with
+// a pile of inputs behind it) is spread over the next frames instead of stalling one
+pub const MAX_EVENTS_PER_FRAME: usize = 64;
+
+// takes everything that arrived on the channel and hands out a bounded batch of it each frame
+pub struct EventDrain<T> {
+    queued: VecDeque<T>, // arrived but not handed out yet, oldest first
+    max_per_frame: usize,
+}
+
+impl<T> EventDrain<T> {
+    pub fn new(max_per_frame: usize) -> Self {
+        Self { queued: VecDeque::new(), max_per_frame }
+    }
+
+    // the oldest events first, whatever is left over comes out next frame before anything newer
+    pub fn next_batch(&mut self, receiver: &Receiver<T>) -> impl Iterator<Item = T> + '_ {
+        self.queued.extend(receiver.try_iter());
+        let count = self.queued.len().min(self.max_per_frame);
+        self.queued.drain(..count)
+    }
+
+    // events waiting for a later frame, for the overlay
+    pub fn backlog(&self) -> usize {
+        self.queued.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_burst_is_spread_over_frames_in_order() {
+        let (sender, receiver) = mpsc::channel();
+        for event in 0..500 {
+            sender.send(event).unwrap();
+        }
+        let mut drain = EventDrain::new(MAX_EVENTS_PER_FRAME);
+        let mut handled = Vec::new();
+        let mut frames = 0;
+        while handled.len() < 500 {
+            let batch: Vec<u32> = drain.next_batch(&receiver).collect();
+            assert!(batch.len() <= MAX_EVENTS_PER_FRAME);
+            handled.extend(batch);
+            frames += 1;
+            assert_eq!(drain.backlog(), 500 - handled.len());
+        }
+        assert_eq!(frames, 500usize.div_ceil(MAX_EVENTS_PER_FRAME));
+        assert_eq!(handled, (0..500).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn test_new_events_wait_behind_the_backlog() {
+        let (sender, receiver) = mpsc::channel();
+        let mut drain = EventDrain::new(2);
+        for event in 0..3 {
+            sender.send(event).unwrap();
+        }
+        assert_eq!(drain.next_batch(&receiver).collect::<Vec<u32>>(), vec![0, 1]);
+        sender.send(3).unwrap();
+        assert_eq!(drain.next_batch(&receiver).collect::<Vec<u32>>(), vec![2, 3]);
+        assert_eq!(drain.backlog(), 0);
+    }
+}
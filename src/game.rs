@@ -1,47 +1,451 @@
-use client_conn::ConnectionServer;
-use input_buffer::InputBuffer;
+use bugreport::{ BugReport, InputJournal, RecentLog, BUG_REPORT_ROOT };
+use config::Config;
+use client_conn::{ ConnectRetry, ConnectionHandles, ConnectionServer, RequestSender };
+use handoff::{ HostUploadGate, JoinHandoff };
+use host_list::{ HostSelection, PlayerListPoll, PlayerListRequest, PLAYER_LIST_ATTEMPTS };
+use smoothing::RenderSmoothing;
+use world_format::{ Tagged, WorldFormat, WorldSerializer };
+use event_drain::{ EventDrain, MAX_EVENTS_PER_FRAME };
+use peer_clock::PeerClock;
+use snapshots::SnapshotSchedule;
+use tuning::{ HostSettings, TUNING_FILE };
+use prediction_check::{ FrameDigest, PredictionCheck };
+use role::{ role_from_keys, RoleChoice, SessionRole };
+use roster::SessionRoster;
+use sim_rng::SimRng;
+use input_buffer::{ InputBuffer, PlayerInputs };
+use input_pause::LocalPause;
+use std::{ path::Path, sync::mpsc, time::{ Duration, Instant } };
+use timing::{
+    FixedStepDriver,
+    FramePhase,
+    FrameTimings,
+    RateCounter,
+    TimingSummary,
+    AHEAD_LIMIT_FRAMES,
+    AHEAD_RELEASE_FRAMES,
+    DEFAULT_MAX_CATCH_UP_FRAMES,
+    TIMING_WINDOW,
+};
 use macroquad::prelude::*;
 use memory::{ PageAllocator, PAGE_SIZE_BYTES };
 use types::{
-    Bullet,
-    Enemy,
+    GameRequestToNetwork,
     GameState,
+    LogConfig,
+    Logger,
+    NetError,
+    NetworkEvent,
     NetworkedPlayerInput,
-    Player,
     PlayerID,
     PlayerInput,
-    ServerPlayerID,
+    PredictedFrame,
+    TickRate,
+    VerifiedFrame,
+    MAX_PLAYER_COUNT,
+    MAX_REQUESTED_FRAMES,
+};
+use world::{
+    Bullet,
+    Enemy,
+    EnemyPool,
+    GameTuning,
+    Player,
     Simulation,
     BULLET_SIZE,
     ENEMY_SIZE,
     MAX_BULLETS,
     MAX_ENEMIES,
-    RELOAD_TIME,
+    WORLD_LAYOUT_HASH,
 };
 use crate::types::NetworkMessage;
-const PHYSICS_FRAME_TIME: f32 = 1.0 / 60.0;
-use ::rand::{ rngs::StdRng, Rng, SeedableRng };
-mod types;
-mod type_impl;
-mod input_buffer;
+const INPUT_PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+const RESYNC_AFTER: Duration = Duration::from_secs(1); // a frame gap this old asks the host for its world
+const REQUEST_INPUTS_AFTER_TICKS: u32 = 10; // ticks the same missing remote frames hold up the verified frames
+const BUG_REPORT_DUMP_TIMEOUT: Duration = Duration::from_millis(200); // the network thread answers every iteration
+const SIMULATION_PAGES: usize = 8; // players, enemy pool, enemy slots, frame, tick rate, tuning, rng
+const MAX_SIMULATION_PAGES: usize = 64; // a host snapshot is grown into up to this many, beyond it the join fails
+const DEFAULT_ENEMY_CAPACITY: u32 = 20;
+const SNAPSHOT_HEADER_LEN: usize = 8; // u32 enemy capacity, u32 WORLD_LAYOUT_HASH
+// logical play field the simulation runs in, independent of the window so all peers despawn at the same place
+const ARENA_WIDTH: f32 = 800.0;
+const ARENA_HEIGHT: f32 = 600.0;
+const CLIENT_CONFIG_FILE: &str = "unlockrs.cfg";
+use unlockrs::{ config, input_ack, input_buffer, link_loss, memory, message_history, retransmit, type_impl, types };
+mod bugreport;
 mod client_conn;
-mod memory;
-fn simple_hash(frame_number: u32) -> u32 {
-    let bytes = frame_number.to_le_bytes();
-    let mut hash = 0u32;
-    for &byte in &bytes {
-        hash ^= byte as u32;
-        hash = hash.wrapping_mul(31);
+mod event_drain;
+mod handoff;
+mod host_list;
+mod input_pause;
+mod peer_clock;
+mod ping;
+mod prediction_check;
+mod role;
+mod roster;
+mod sim_rng;
+mod smoothing;
+mod snapshots;
+mod timing;
+mod tuning;
+mod world;
+mod world_format;
+#[cfg(test)]
+use unlockrs::network_simulator;
+#[cfg(test)]
+mod two_client_session;
+// a request the chosen role doesn't make is dropped before it reaches the server
+fn send_for_role(
+    role_choice: &RoleChoice,
+    request_sender: &RequestSender,
+    msg: NetworkMessage
+) -> Result<(), std::sync::mpsc::SendError<GameRequestToNetwork>> {
+    if let Err(e) = role_choice.check(&msg) {
+        eprintln!("Dropped {:?}: {}", msg, e);
+        return Ok(());
+    }
+    request_sender.send(GameRequestToNetwork::DirectRequest(msg))
+}
+// if we are ahead, then we will wait for the other player,
+// if we are behind we need to be able to  simulate based solely on the other players, because otherwise we will never catchup
+// therefore each verified frame is based only on whether we have inputs from all other players regardless of our inputs
+fn send_empty_inputs_for_unplayed_frames(
+    request_sender: RequestSender,
+    local_player_id: PlayerID
+) -> impl FnMut(&PlayerInputs) {
+    move |verif_frame_input| {
+        if verif_frame_input.inputs[local_player_id as usize].is_some() {
+            return;
+        }
+        let request = GameRequestToNetwork::IndirectRequest(
+            types::GameMessage::ClientSentPlayerInputs(
+                NetworkedPlayerInput::new(
+                    Vec::new(), // send empty inputs as we didnt play for this frame yet
+                    verif_frame_input.frame
+                )
+            )
+        );
+        if let Err(e) = request_sender.send(request) {
+            eprintln!("Failed to send empty inputs for frame {}: {}", verif_frame_input.frame, e);
+        }
+    }
+}
+fn simulation_allocator() -> PageAllocator {
+    PageAllocator::new(PAGE_SIZE_BYTES * SIMULATION_PAGES, PAGE_SIZE_BYTES)
+}
+// the predicted pages are overwritten by the verified ones, that is only sound while both
+// allocators laid out the same data the same way, otherwise the prediction is rebuilt
+fn restore_prediction(
+    verified: &Simulation,
+    verif_allocator: &PageAllocator,
+    predicted: &mut Simulation,
+    pred_allocator: &mut PageAllocator
+) {
+    if let Err(mismatch) = pred_allocator.restore_from(verif_allocator) {
+        eprintln!("Rebuilding the prediction from the verified state: {}", mismatch);
+        *pred_allocator = simulation_allocator();
+        *predicted = Simulation::new_from_serialized(
+            verified.serialize(verif_allocator),
+            pred_allocator
+        ).expect("the verified state is always a valid snapshot");
+    }
+}
+// a periodic snapshot from the host becomes the new verified state unless we are already past it,
+// returns the frame it was taken at when it was applied. `applied_world_frame` is the newest host world
+// we were set to, a world resent after its acks got lost arrives again and is not applied twice
+fn apply_host_snapshot(
+    world_format: &dyn WorldSerializer,
+    data: Vec<u8>,
+    verified: &mut Simulation,
+    verif_allocator: &mut PageAllocator,
+    input_buffer: &mut InputBuffer,
+    applied_world_frame: &mut Option<VerifiedFrame>
+) -> Result<Option<VerifiedFrame>, &'static str> {
+    let mut snapshot_allocator = simulation_allocator();
+    let snapshot = world_format.deserialize(data, &mut snapshot_allocator)?;
+    // the session's tuning came with the world we joined with, a host can't change it midway
+    if snapshot.tuning(&snapshot_allocator) != verified.tuning(verif_allocator) {
+        return Err("Snapshot was taken with another tuning than the session's");
+    }
+    let snapshot_frame = snapshot.verified_frame(&snapshot_allocator);
+    if applied_world_frame.is_some_and(|applied| snapshot_frame <= applied) {
+        return Ok(None);
+    }
+    if snapshot_frame < verified.verified_frame(verif_allocator) {
+        return Ok(None);
+    }
+    *applied_world_frame = Some(snapshot_frame);
+    *verified = snapshot;
+    *verif_allocator = snapshot_allocator;
+    input_buffer.set_baseline(snapshot_frame);
+    Ok(Some(snapshot_frame))
+}
+// the joiner's buffer starts at the host's world, inputs held back until our slot was known are
+// routed now and the ones the world already contains are rejected by the baseline
+fn route_join_inputs(
+    input_buffer: &mut InputBuffer,
+    roster: &SessionRoster,
+    snapshot_frame: VerifiedFrame,
+    held_inputs: Vec<(PlayerID, NetworkedPlayerInput)>
+) {
+    input_buffer.update_player_count(roster.local_slot(), roster.player_count(), snapshot_frame);
+    input_buffer.set_baseline(snapshot_frame);
+    for (slot, input) in held_inputs {
+        input_buffer.insert_remote_player_inp(slot, input.inputs, input.frame);
+    }
+}
+// the next input frame is more than one frame past the verified simulation, it waits until the frames
+// in between arrive or a world from the host skips over them
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FrameGap {
+    sim_frame: VerifiedFrame,
+    input_frame: u32,
+}
+// how long the verified simulation has been held up by the same gap
+#[derive(Default)]
+struct GapWatch {
+    current: Option<(FrameGap, Instant)>,
+}
+
+impl GapWatch {
+    // true every RESYNC_AFTER the same gap stays in the way, a short one fills in by itself
+    fn is_stuck(&mut self, now: Instant, gap: FrameGap) -> bool {
+        match &mut self.current {
+            Some((current, since)) if *current == gap => {
+                if now.duration_since(*since) < RESYNC_AFTER {
+                    return false;
+                }
+                *since = now;
+                true
+            }
+            _ => {
+                self.current = Some((gap, now));
+                false
+            }
+        }
+    }
+    fn clear(&mut self) {
+        self.current = None;
+    }
+}
+// how long the verified simulation has been held up by the same few missing remote frames
+#[derive(Default)]
+struct MissingInputWatch {
+    missing: Vec<u32>,
+    stalled_ticks: u32,
+}
+
+impl MissingInputWatch {
+    // called on every tick no frame got verified, gives the range to ask for again every
+    // REQUEST_INPUTS_AFTER_TICKS the missing frames stay the same, a changing set is still arriving
+    fn on_stalled_tick(&mut self, missing: Vec<u32>) -> Option<(u32, u32)> {
+        if missing != self.missing {
+            self.missing = missing;
+            self.stalled_ticks = 0;
+        }
+        let (Some(&from_frame), Some(&to_frame)) = (self.missing.first(), self.missing.last()) else {
+            return None;
+        };
+        if self.missing.len() > MAX_REQUESTED_FRAMES {
+            return None;
+        }
+        self.stalled_ticks += 1;
+        if self.stalled_ticks < REQUEST_INPUTS_AFTER_TICKS {
+            return None;
+        }
+        self.stalled_ticks = 0;
+        Some((from_frame, to_frame))
+    }
+    fn clear(&mut self) {
+        self.missing.clear();
+        self.stalled_ticks = 0;
+    }
+}
+// advances the verified simulation by the next frame all players' inputs are in for,
+// input frames the simulation is already past are dropped on the way
+fn step_verified_frame(
+    input_buffer: &mut InputBuffer,
+    verified: &Simulation,
+    verif_allocator: &mut PageAllocator
+) -> Result<Option<VerifiedFrame>, FrameGap> {
+    let sim_frame = verified.verified_frame(verif_allocator);
+    if input_buffer.input_frames.front().is_some_and(|front| front.frame < sim_frame.next_input_frame()) {
+        eprintln!(
+            "Dropping input frames up to {}, the verified simulation is past them: {:?}",
+            sim_frame.0,
+            input_buffer
+        );
+        input_buffer.set_baseline(sim_frame);
+    }
+    if let Some(front) = input_buffer.input_frames.front() {
+        if front.frame > sim_frame.next_input_frame() {
+            return Err(FrameGap { sim_frame, input_frame: front.frame });
+        }
+    }
+    let Some(verif_frame_input) = input_buffer.pop_next_verified_frame() else {
+        return Ok(None);
+    };
+    let tick_rate = verified.tick_rate(verif_allocator);
+    verified.update(tick_rate.frame_time(), verif_frame_input.inputs, verif_allocator);
+    let stepped = verified.verified_frame(verif_allocator);
+    debug_assert!(stepped.0 == verif_frame_input.frame);
+    if stepped.0.is_multiple_of(tick_rate.frames_in(INPUT_PRUNE_INTERVAL)) {
+        input_buffer.prune_before(stepped.next_input_frame(), true);
+    }
+    Ok(Some(stepped))
+}
+// the frame an input sampled now is played on, the one after the simulation the player is shown:
+// the prediction with a peer in the session, the verified simulation alone
+fn local_input_frame(
+    roster: &SessionRoster,
+    predicted: &Simulation,
+    pred_allocator: &PageAllocator,
+    verified: &Simulation,
+    verif_allocator: &PageAllocator
+) -> u32 {
+    if roster.player_count() > 1 {
+        predicted.predicted_frame(pred_allocator).next_input_frame()
+    } else {
+        verified.verified_frame(verif_allocator).next_input_frame()
+    }
+}
+// the server and our own simulations get the sampled input for the same frame
+fn submit_local_input<E>(
+    input_buffer: &mut InputBuffer,
+    inputs: Vec<PlayerInput>,
+    frame: u32,
+    send: impl FnOnce(NetworkedPlayerInput) -> Result<(), E>
+) -> Result<(), E> {
+    send(NetworkedPlayerInput::new(inputs.clone(), frame))?;
+    input_buffer.insert_curr_player_inp(inputs, frame);
+    Ok(())
+}
+// whether a step is due this render frame, and how many frames the driver skipped to get there. the
+// skipped frames' inputs are never sent, a joiner asks the host for its world instead
+fn advance_step_driver<E>(
+    step_driver: &mut FixedStepDriver,
+    dt: f32,
+    joined: bool,
+    send: impl FnOnce(NetworkMessage) -> Result<(), E>
+) -> Result<(bool, u32), E> {
+    let stepping = step_driver.advance(dt);
+    let skipped = step_driver.take_skipped_frames();
+    if skipped > 0 && joined {
+        send(NetworkMessage::ClientRequestResync)?;
+    }
+    Ok((stepping, skipped))
+}
+// re-predicts every frame past the prediction's current one, nothing is sent from here: our input for a
+// frame went out when it was sampled. `check` gets each result to compare once the frame is verified.
+// returns how many frames were simulated
+fn step_predicted_frames(
+    input_buffer: &InputBuffer,
+    predicted: &Simulation,
+    pred_allocator: &mut PageAllocator,
+    check: &mut PredictionCheck
+) -> u32 {
+    let frame_time = predicted.tick_rate(pred_allocator).frame_time();
+    let mut stepped = 0;
+    for (_, pred_frame_input) in input_buffer.excluding_iter_after_last_verified() {
+        let next_frame = predicted.predicted_frame(pred_allocator).next_input_frame();
+        // by doing this we exclude verified automatically as it would be in the .frame from verified update above
+        if next_frame > pred_frame_input.frame {
+            continue;
+        }
+        if next_frame != pred_frame_input.frame {
+            // frames are missing in between, nothing past them can be predicted until they arrive
+            break;
+        }
+        predicted.update(frame_time, pred_frame_input.inputs, pred_allocator);
+        debug_assert!(predicted.current_frame(pred_allocator) == pred_frame_input.frame);
+        check.record_predicted(predicted.predicted_frame(pred_allocator), predicted.digest(pred_allocator));
+        stepped += 1;
+    }
+    stepped
+}
+// how far the local prediction is past where the other player's simulation is estimated to be
+fn frames_ahead(peer_clock: &PeerClock, predicted: &Simulation, pred_allocator: &PageAllocator) -> u32 {
+    predicted
+        .predicted_frame(pred_allocator)
+        .0.saturating_sub(peer_clock.estimated_remote_frame().unwrap_or(0))
+}
+// the format our worlds go out in, a world we receive is read in whichever format it says
+fn world_format(structured: bool) -> Tagged {
+    Tagged(if structured { WorldFormat::LittleEndianFields } else { WorldFormat::RawPages })
+}
+fn session_step_driver(tick_rate: TickRate, max_catch_up: u32) -> FixedStepDriver {
+    let mut driver = FixedStepDriver::new(tick_rate.frame_time(), AHEAD_LIMIT_FRAMES, AHEAD_RELEASE_FRAMES);
+    driver.set_max_catch_up(max_catch_up);
+    driver
+}
+fn arena_camera() -> Camera2D {
+    Camera2D::from_display_rect(Rect::new(0.0, 0.0, ARENA_WIDTH, ARENA_HEIGHT))
+}
+// the window in pixels, only the HUD is laid out by it. tests have no window, they set the size they want
+#[cfg(not(test))]
+fn window_size() -> Vec2 {
+    vec2(screen_width(), screen_height())
+}
+#[cfg(test)]
+thread_local! {
+    static TEST_WINDOW_SIZE: std::cell::Cell<Vec2> = const {
+        std::cell::Cell::new(vec2(ARENA_WIDTH, ARENA_HEIGHT))
+    };
+}
+#[cfg(test)]
+fn window_size() -> Vec2 {
+    TEST_WINDOW_SIZE.with(std::cell::Cell::get)
+}
+fn timing_overlay_text(label: &str, summary: &TimingSummary) -> String {
+    format!(
+        "{:<10} avg {:>6.2}ms | p95 {:>6.2}ms | max {:>6.2}ms",
+        label,
+        summary.avg.as_secs_f32() * 1000.0,
+        summary.p95.as_secs_f32() * 1000.0,
+        summary.max.as_secs_f32() * 1000.0
+    )
+}
+fn loss_overlay_text(loss: Option<f32>) -> String {
+    match loss {
+        Some(loss) => format!("{:.1}%", loss * 100.0),
+        None => "-".to_string(),
+    }
+}
+fn incompatible_hosts_notice(count: u8) -> String {
+    match count {
+        1 => "1 host runs an incompatible game version".to_string(),
+        count => format!("{} hosts run an incompatible game version", count),
     }
+}
+// FNV-1a, std's DefaultHasher isn't guaranteed to be stable across releases
+struct StateHasher(u64);
 
-    hash
+impl StateHasher {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+    fn write_u32(&mut self, value: u32) {
+        for byte in value.to_le_bytes() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    fn write_f32(&mut self, value: f32) {
+        self.write_u32(value.to_bits());
+    }
+    fn write_vec2(&mut self, value: Vec2) {
+        self.write_f32(value.x);
+        self.write_f32(value.y);
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
 }
 
 impl Player {
     fn new(x: f32, color: Color) -> Self {
         Self {
-            position: vec2(x, screen_height() - 50.0),
-            speed: 150.0,
+            position: vec2(x, ARENA_HEIGHT - 50.0),
             color,
             bullets: [
                 Bullet {
@@ -51,29 +455,30 @@ impl Player {
                 MAX_BULLETS
             ],
             movement_input: 0.0,
-            shoot_input: false,
+            shoot_input: 0,
+            _padding: [0; 3],
             curr_reload_time: 0.0,
         }
     }
 
-    fn update(&mut self, dt: f32) {
-        self.position.x += self.movement_input * self.speed * dt;
-        self.position.x = self.position.x.clamp(20.0, screen_width() - 20.0);
+    fn update(&mut self, dt: f32, tuning: &GameTuning) {
+        self.position.x += self.movement_input * tuning.player_speed * dt;
+        self.position.x = self.position.x.clamp(20.0, ARENA_WIDTH - 20.0);
         self.curr_reload_time += dt;
-        if self.shoot_input && self.curr_reload_time > RELOAD_TIME {
+        if self.shoot_input != 0 && self.curr_reload_time > tuning.reload_time {
             self.curr_reload_time = 0.0;
             if
                 let Some(bullet) = self.bullets
                     .iter_mut()
-                    .find(|b| (b.position.y <= 0.0 || b.position.y >= screen_height()))
+                    .find(|b| (b.position.y <= 0.0 || b.position.y >= ARENA_HEIGHT))
             {
                 bullet.position = self.position;
-                bullet.velocity = vec2(0.0, -500.0);
+                bullet.velocity = vec2(0.0, -tuning.bullet_speed);
             }
         }
 
         for bullet in &mut self.bullets {
-            if bullet.position.y > 0.0 && bullet.position.y < screen_height() {
+            if bullet.position.y > 0.0 && bullet.position.y < ARENA_HEIGHT {
                 bullet.position += bullet.velocity * dt;
             } else {
                 bullet.position = vec2(-5.0, -5.0);
@@ -93,93 +498,144 @@ impl Enemy {
     fn new(x: f32, y: f32) -> Self {
         Self {
             position: vec2(x, y),
+            id: 0, // assigned by EnemyPool::spawn
         }
     }
 
-    fn new_random_at_top(frame: u32) -> Self {
-        let seed = simple_hash(frame) as u64;
-        let mut rng = StdRng::seed_from_u64(seed);
+    fn new_random_at_top(rng: &mut SimRng) -> Self {
         Self {
-            position: vec2(rng.gen_range(40.0..screen_width() - 40.0), 0.0),
+            position: vec2(rng.gen_range(40.0..ARENA_WIDTH - 40.0), 0.0),
+            id: 0,
         }
     }
 
     fn is_active(&self) -> bool {
-        self.position.y >= 0.0 && self.position.y < screen_height()
+        self.position.y >= 0.0 && self.position.y < ARENA_HEIGHT
     }
 
     fn deactivate(&mut self) {
         self.position = vec2(-5.0, -5.0);
     }
 
-    fn update(&mut self, dt: f32) {
-        self.position.y += 100.0 * dt;
-        if self.position.y >= screen_height() {
+    fn update(&mut self, dt: f32, tuning: &GameTuning) {
+        self.position.y += tuning.enemy_speed * dt;
+        if self.position.y >= ARENA_HEIGHT {
             self.deactivate();
         }
     }
 
     fn draw(&self) {
-        if self.is_active() {
-            draw_rectangle(
-                self.position.x - 20.0,
-                self.position.y - 20.0,
-                ENEMY_SIZE,
-                ENEMY_SIZE,
-                RED
-            );
-        }
+        draw_rectangle(
+            self.position.x - 20.0,
+            self.position.y - 20.0,
+            ENEMY_SIZE,
+            ENEMY_SIZE,
+            RED
+        );
     }
 
-    fn update_all(enemies: &mut [Enemy], dt: f32, frame: u32) {
-        let mut enemy_cnt = 0;
-
-        for enemy in enemies.iter_mut() {
-            if enemy.is_active() {
-                enemy_cnt += 1;
-                enemy.update(dt);
+    fn update_all(
+        pool: &mut EnemyPool,
+        slots: &mut [Enemy],
+        dt: f32,
+        frame: u32,
+        tick_rate: TickRate,
+        tuning: &GameTuning,
+        rng: &mut SimRng
+    ) {
+        let mut i = 0;
+        while i < pool.active_count as usize {
+            slots[i].update(dt, tuning);
+            if slots[i].is_active() {
+                i += 1;
+            } else {
+                pool.remove(slots, i); // slot i now holds the former last active enemy, update it next
             }
         }
 
-        // Move active enemies to the front
-        enemies.sort_by_key(|enemy| !enemy.is_active());
-
-        if frame % 120 == 0 && enemy_cnt < MAX_ENEMIES {
-            enemies[enemy_cnt as usize] = Enemy::new_random_at_top(frame);
+        // an interval shorter than a frame spawns every frame
+        let spawn_interval = tick_rate.frames_in(Duration::from_secs_f32(tuning.enemy_spawn_interval)).max(1);
+        if frame % spawn_interval == 0 && (pool.active_count as usize) < slots.len() {
+            pool.spawn(slots, Enemy::new_random_at_top(rng));
         }
     }
 
     fn check_intersection_bullets(
-        enemies: &mut [Enemy],
+        pool: &mut EnemyPool,
+        slots: &mut [Enemy],
         bullets: &[Bullet]
     ) -> [bool; MAX_BULLETS] {
         let mut collisions = [false; MAX_BULLETS];
-        for enemy in enemies.iter_mut().filter(|e| e.is_active()) {
-            for (i, bullet) in bullets.iter().enumerate() {
-                if
-                    enemy.position.distance(bullet.position) < (BULLET_SIZE + ENEMY_SIZE) / 2.0 &&
-                    !collisions[i]
-                {
-                    enemy.deactivate();
-                    collisions[i] = true;
-                }
+        let mut i = 0;
+        while i < pool.active_count as usize {
+            let enemy_position = slots[i].position;
+            let hit = bullets
+                .iter()
+                .enumerate()
+                .find(|(bullet_idx, bullet)| {
+                    !collisions[*bullet_idx] &&
+                        enemy_position.distance(bullet.position) < (BULLET_SIZE + ENEMY_SIZE) / 2.0
+                })
+                .map(|(bullet_idx, _)| bullet_idx);
+            if let Some(bullet_idx) = hit {
+                collisions[bullet_idx] = true;
+                pool.remove(slots, i);
+            } else {
+                i += 1;
             }
         }
 
         collisions
     }
-    fn draw_all(enemies: &[Enemy]) {
-        for enemy in enemies.iter() {
-            enemy.is_active();
-            {
-                enemy.draw();
-            }
+    fn draw_all(pool: &EnemyPool, slots: &[Enemy], smoothing: &mut RenderSmoothing) {
+        for enemy in pool.active(slots) {
+            let mut drawn = *enemy;
+            drawn.position = smoothing.enemy(enemy.id, enemy.position);
+            drawn.draw();
         }
+        smoothing.retain_enemies(pool.active(slots).iter().map(|enemy| enemy.id));
+    }
+}
+
+impl EnemyPool {
+    fn new() -> Self {
+        Self {
+            active_count: 0,
+            next_id: 0,
+        }
+    }
+
+    fn active<'a>(&self, slots: &'a [Enemy]) -> &'a [Enemy] {
+        &slots[..self.active_count as usize]
+    }
+
+    // caller has to check for capacity, ids are handed out in spawn order and never reused
+    fn spawn(&mut self, slots: &mut [Enemy], mut enemy: Enemy) {
+        debug_assert!((self.active_count as usize) < slots.len());
+        enemy.id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        slots[self.active_count as usize] = enemy;
+        self.active_count += 1;
+    }
+
+    fn remove(&mut self, slots: &mut [Enemy], idx: usize) {
+        debug_assert!(idx < (self.active_count as usize));
+        let last_active = (self.active_count - 1) as usize;
+        slots.swap(idx, last_active);
+        slots[last_active].deactivate();
+        self.active_count -= 1;
     }
 }
 
 impl Simulation {
-    fn new(alloc: &mut PageAllocator) -> Self {
+    fn new(
+        alloc: &mut PageAllocator,
+        enemy_capacity: u32,
+        tick_rate: TickRate,
+        tuning: GameTuning,
+        seed: u64
+    ) -> Self {
+        debug_assert!((1..=MAX_ENEMIES as u32).contains(&enemy_capacity));
         let player_ptr = alloc
             .alloc_and_write_fixed(&Player::new(100.0, BLUE))
             .expect("Failed to alloc player");
@@ -187,20 +643,83 @@ impl Simulation {
             .alloc_and_write_fixed(&Player::new(250.0, GREEN))
             .expect("Failed to alloc 2nd player");
         let enemies_arr_ptr = alloc
-            .alloc_and_write_fixed(&[Enemy::new(-5.0, -5.0); MAX_ENEMIES as usize])
+            .alloc_and_write_fixed(&EnemyPool::new())
             .expect("Failed to alloc enemies");
+        let enemy_slots = alloc
+            .alloc_and_write_array(enemy_capacity as usize, &Enemy::new(-5.0, -5.0))
+            .expect("Failed to alloc enemy slots");
         let frame = alloc.alloc_and_write_fixed(&(0 as u32)).expect("Failed to alloc spawn timer");
+        let tick_rate = alloc.alloc_and_write_fixed(&tick_rate).expect("Failed to alloc tick rate");
+        let tuning = alloc.alloc_and_write_fixed(&tuning).expect("Failed to alloc tuning");
+        let rng = alloc.alloc_and_write_fixed(&SimRng::new(seed)).expect("Failed to alloc rng");
         Self {
             player1: player_ptr,
             player2: player2_ptr,
             enemies: enemies_arr_ptr,
+            enemy_slots,
             frame: frame,
+            tick_rate,
+            tuning,
+            rng,
+        }
+    }
+    // the pages are prefixed with the session parameters the joiner needs to lay out the same pages
+    fn serialize(&self, alloc: &PageAllocator) -> Vec<u8> {
+        let mut data = (self.enemy_slots.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&WORLD_LAYOUT_HASH.to_le_bytes());
+        data.extend_from_slice(&alloc.get_copy_of_state());
+        data
+    }
+    fn new_from_serialized(data: Vec<u8>, alloc: &mut PageAllocator) -> Result<Self, &'static str> {
+        let header = data.get(..SNAPSHOT_HEADER_LEN).ok_or("Snapshot is missing its header")?;
+        let enemy_capacity = u32::from_le_bytes(header[..4].try_into().unwrap());
+        // the server only offers hosts of our layout, this catches whatever slipped past it
+        if u32::from_le_bytes(header[4..].try_into().unwrap()) != WORLD_LAYOUT_HASH {
+            return Err("Snapshot is from an incompatible game version");
+        }
+        if enemy_capacity == 0 || (enemy_capacity as usize) > MAX_ENEMIES {
+            return Err("Snapshot enemy capacity is not supported by this build");
+        }
+        let mut sim = Self::new(alloc, enemy_capacity, TickRate::default(), GameTuning::default(), 0);
+        let pages = &data[SNAPSHOT_HEADER_LEN..];
+        if let Err(out_of_pages) = alloc.set_memory(pages) {
+            // the host's allocator has more pages and pages are handed out from the back, so ours is
+            // grown to the same size before laying the simulation out again. one retry is all it gets
+            *alloc = simulation_allocator();
+            let retried = alloc.try_grow(out_of_pages.required, MAX_SIMULATION_PAGES).and_then(|()| {
+                sim = Self::new(alloc, enemy_capacity, TickRate::default(), GameTuning::default(), 0);
+                alloc.set_memory(pages)
+            });
+            retried.map_err(|e| {
+                eprintln!("Snapshot pages don't fit: {}", e);
+                "Snapshot needs more pages than this build allows"
+            })?;
         }
+        // the pages are in use from here on, a rejected snapshot leaves `alloc` to be replaced
+        TickRate::new(sim.tick_rate(alloc).hz()).map_err(
+            |_| "Snapshot tick rate is not supported by this build"
+        )?;
+        if !sim.tuning(alloc).is_usable() {
+            return Err("Snapshot tuning is not usable");
+        }
+        Ok(sim)
+    }
+    fn tick_rate(&self, alloc: &PageAllocator) -> TickRate {
+        alloc.read_fixed(&self.tick_rate)
+    }
+    fn tuning(&self, alloc: &PageAllocator) -> GameTuning {
+        alloc.read_fixed(&self.tuning)
+    }
+    // the last frame this simulation stepped to
+    fn current_frame(&self, alloc: &PageAllocator) -> u32 {
+        alloc.read_fixed(&self.frame)
     }
-    fn new_from_serialized(data: Vec<u8>, alloc: &mut PageAllocator) -> Self {
-        let sim = Self::new(alloc);
-        alloc.set_memory(&data);
-        return sim;
+    // current_frame as the verified simulation's or the prediction's, the two don't mix
+    fn verified_frame(&self, alloc: &PageAllocator) -> VerifiedFrame {
+        VerifiedFrame(self.current_frame(alloc))
+    }
+    fn predicted_frame(&self, alloc: &PageAllocator) -> PredictedFrame {
+        PredictedFrame(self.current_frame(alloc))
     }
 
     fn update(
@@ -217,22 +736,30 @@ impl Simulation {
         }
 
         let frame = alloc.read_fixed(&self.frame);
+        let tick_rate = self.tick_rate(alloc);
+        let tuning = self.tuning(alloc);
         let player1 = alloc.read_fixed(&self.player1);
         let player2 = alloc.read_fixed(&self.player2);
 
-        let enemies = alloc.mut_read_fixed(&self.enemies);
-        Enemy::update_all(enemies, dt, frame);
+        let mut enemies = alloc.read_fixed(&self.enemies);
+        let mut rng = alloc.read_fixed(&self.rng);
+        let slots = alloc.mut_read_array(&self.enemy_slots);
+        Enemy::update_all(&mut enemies, slots, dt, frame, tick_rate, &tuning, &mut rng);
         let player1_bullet_collisions = Enemy::check_intersection_bullets(
-            enemies,
+            &mut enemies,
+            slots,
             &player1.bullets
         );
         let player2_bullet_collisions = Enemy::check_intersection_bullets(
-            enemies,
+            &mut enemies,
+            slots,
             &player2.bullets
         );
+        alloc.write_fixed_to_memory(&self.enemies, &enemies);
+        alloc.write_fixed_to_memory(&self.rng, &rng);
 
         let player1 = alloc.mut_read_fixed(&self.player1);
-        player1.update(dt);
+        player1.update(dt, &tuning);
         for i in 0..player1_bullet_collisions.len() {
             if player1_bullet_collisions[i] {
                 player1.bullets[i].position = vec2(-5.0, -5.0);
@@ -240,7 +767,7 @@ impl Simulation {
         }
 
         let player2 = alloc.mut_read_fixed(&self.player2);
-        player2.update(dt);
+        player2.update(dt, &tuning);
         for i in 0..player2_bullet_collisions.len() {
             if player2_bullet_collisions[i] {
                 player2.bullets[i].position = vec2(-5.0, -5.0);
@@ -250,19 +777,72 @@ impl Simulation {
         *frame += 1;
     }
 
-    fn draw(&self, local_player_id: PlayerID, other_player_connected: bool, alloc: &PageAllocator) {
-        if local_player_id == PlayerID::Player1 {
-            alloc.read_fixed(&self.player1).draw();
+    // hashes the gameplay relevant fields, not the raw pages, those contain struct padding
+    fn state_hash(&self, alloc: &PageAllocator) -> u64 {
+        let mut hash = StateHasher::new();
+        hash.write_u32(alloc.read_fixed(&self.frame));
+        let rng = alloc.read_fixed(&self.rng).state();
+        hash.write_u32(rng as u32);
+        hash.write_u32((rng >> 32) as u32);
+        for player in [alloc.read_fixed(&self.player1), alloc.read_fixed(&self.player2)] {
+            hash.write_vec2(player.position);
+            hash.write_f32(player.movement_input);
+            hash.write_u32(u32::from(player.shoot_input != 0));
+            hash.write_f32(player.curr_reload_time);
+            for bullet in player.bullets {
+                hash.write_vec2(bullet.position);
+                hash.write_vec2(bullet.velocity);
+            }
+        }
+        let enemies = alloc.read_fixed(&self.enemies);
+        hash.write_u32(enemies.active_count);
+        hash.write_u32(enemies.next_id);
+        for enemy in enemies.active(alloc.read_array(&self.enemy_slots)) {
+            hash.write_u32(enemy.id);
+            hash.write_vec2(enemy.position);
+        }
+        hash.finish()
+    }
+    fn digest(&self, alloc: &PageAllocator) -> FrameDigest {
+        FrameDigest {
+            hash: self.state_hash(alloc),
+            players: [alloc.read_fixed(&self.player1).position, alloc.read_fixed(&self.player2).position],
+            active_enemies: alloc.read_fixed(&self.enemies).active_count,
+        }
+    }
+
+    // draws copies, smoothing only moves where things show up on screen. players `paused` says so of
+    // get a badge
+    fn draw(
+        &self,
+        roster: &SessionRoster,
+        alloc: &PageAllocator,
+        smoothing: &mut RenderSmoothing,
+        paused: impl Fn(PlayerID) -> bool
+    ) {
+        let draw_player = |player_id: PlayerID, smoothing: &mut RenderSmoothing| {
+            let mut player = match player_id {
+                PlayerID::Player1 => alloc.read_fixed(&self.player1),
+                PlayerID::Player2 => alloc.read_fixed(&self.player2),
+            };
+            player.position = smoothing.player(player_id, player.position);
+            player.draw();
+            if paused(player_id) {
+                draw_text("paused", player.position.x - 24.0, player.position.y - 16.0, 18.0, GRAY);
+            }
+        };
+        if roster.local_slot() == PlayerID::Player1 {
+            draw_player(PlayerID::Player1, smoothing);
 
-            if other_player_connected {
-                alloc.read_fixed(&self.player2).draw();
+            if roster.is_peer_connected(PlayerID::Player2) {
+                draw_player(PlayerID::Player2, smoothing);
             }
         } else {
-            alloc.read_fixed(&self.player1).draw();
-            alloc.read_fixed(&self.player2).draw();
+            draw_player(PlayerID::Player1, smoothing);
+            draw_player(PlayerID::Player2, smoothing);
         }
         let enemies = alloc.read_fixed(&self.enemies);
-        Enemy::draw_all(&enemies);
+        Enemy::draw_all(&enemies, alloc.read_array(&self.enemy_slots), smoothing);
     }
 
     fn handle_player_input(
@@ -280,168 +860,558 @@ impl Simulation {
                 player_to_change = alloc.mut_read_fixed(&self.player2);
             }
         }
-        player_to_change.shoot_input = false;
+        // the inputs are the keys held this frame, in no particular order: Left and Right add up
+        // to the direction so holding both cancels out and holding neither stands still
+        let mut left = false;
+        let mut right = false;
+        let mut shoot = false;
         for input in inputs {
             match input {
                 PlayerInput::Left => {
-                    player_to_change.movement_input = -1.0;
+                    left = true;
                 }
                 PlayerInput::Right => {
-                    player_to_change.movement_input = 1.0;
+                    right = true;
                 }
                 PlayerInput::Shoot => {
-                    player_to_change.shoot_input = true;
+                    shoot = true;
                 }
             }
         }
+        player_to_change.movement_input = f32::from(right as u8) - f32::from(left as u8);
+        player_to_change.shoot_input = u8::from(shoot);
+    }
+}
+// Every input is level triggered: Shoot is "shoot intent this frame" and stays set for as long as
+// the key is held, the sim fires whenever the reload allows. Edge triggering would depend on how
+// render frames line up with physics frames and drop taps between two physics frames.
+fn sample_player_inputs(key_down: impl Fn(KeyCode) -> bool) -> Vec<PlayerInput> {
+    let mut inputs = Vec::new();
+    if key_down(KeyCode::A) || key_down(KeyCode::Left) {
+        inputs.push(PlayerInput::Left);
+    }
+    if key_down(KeyCode::D) || key_down(KeyCode::Right) {
+        inputs.push(PlayerInput::Right);
+    }
+    if key_down(KeyCode::W) || key_down(KeyCode::Up) {
+        inputs.push(PlayerInput::Shoot);
+    }
+    inputs
+}
+
+// what the game remembers between launches in CLIENT_CONFIG_FILE, flags on the command line win
+#[derive(Debug, Clone, PartialEq)]
+struct ClientConfig {
+    server_addr: String,
+    tuning_file: String,
+    render_smoothing: bool, // toggled with V
+    connect_attempts: u32, // probes of the server at startup before giving up on it
+    connect_backoff_ms: u32, // after the first failed probe, doubled after each further one
+    compact_held_inputs: bool, // a held input is sent once with how many frames repeat it
+    message_history: usize, // datagrams each way kept for bug reports and recovery logs
+    max_catch_up_frames: u32, // steps worked off after a stall, a joiner further behind asks for the world
+    structured_world: bool, // our worlds go out field by field instead of as raw pages, bigger but portable
+    log: LogConfig,
+}
+
+impl ClientConfig {
+    fn connect_retry(&self) -> ConnectRetry {
+        ConnectRetry {
+            attempts: self.connect_attempts,
+            backoff: Duration::from_millis(u64::from(self.connect_backoff_ms)),
+        }
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            server_addr: "127.0.0.1:8080".to_string(),
+            tuning_file: TUNING_FILE.to_string(),
+            render_smoothing: false,
+            connect_attempts: 5,
+            connect_backoff_ms: 200,
+            compact_held_inputs: true,
+            message_history: message_history::DEFAULT_MESSAGE_HISTORY,
+            max_catch_up_frames: DEFAULT_MAX_CATCH_UP_FRAMES,
+            structured_world: false,
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl Config for ClientConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<bool, &'static str> {
+        match key {
+            "server_addr" => {
+                self.server_addr = value.to_string();
+            }
+            "tuning_file" => {
+                self.tuning_file = value.to_string();
+            }
+            "render_smoothing" => {
+                self.render_smoothing = config::parse_bool(value)?;
+            }
+            "connect_attempts" => {
+                self.connect_attempts = value.parse().map_err(|_| "Config value is not a whole number")?;
+            }
+            "connect_backoff_ms" => {
+                self.connect_backoff_ms = value.parse().map_err(|_| "Config value is not a whole number")?;
+            }
+            "compact_held_inputs" => {
+                self.compact_held_inputs = config::parse_bool(value)?;
+            }
+            "message_history" => {
+                self.message_history = value.parse().map_err(|_| "Config value is not a whole number")?;
+            }
+            "max_catch_up_frames" => {
+                self.max_catch_up_frames = value.parse().map_err(|_| "Config value is not a whole number")?;
+            }
+            "structured_world" => {
+                self.structured_world = config::parse_bool(value)?;
+            }
+            _ => {
+                return config::set_log_key(&mut self.log, key, value);
+            }
+        }
+        Ok(true)
+    }
+    fn pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![
+            ("server_addr", self.server_addr.clone()),
+            ("tuning_file", self.tuning_file.clone()),
+            ("render_smoothing", self.render_smoothing.to_string()),
+            ("connect_attempts", self.connect_attempts.to_string()),
+            ("connect_backoff_ms", self.connect_backoff_ms.to_string()),
+            ("compact_held_inputs", self.compact_held_inputs.to_string()),
+            ("message_history", self.message_history.to_string()),
+            ("max_catch_up_frames", self.max_catch_up_frames.to_string()),
+            ("structured_world", self.structured_world.to_string())
+        ];
+        pairs.extend(config::log_pairs(&self.log));
+        pairs
     }
 }
-pub const MAX_PLAYER_COUNT: u8 = 2;
 
 #[macroquad::main("2 Player Cube Shooter")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut pred_allocator = PageAllocator::new(PAGE_SIZE_BYTES * 5, PAGE_SIZE_BYTES);
-    let mut verif_allocator = PageAllocator::new(PAGE_SIZE_BYTES * 5, PAGE_SIZE_BYTES);
+    let mut pred_allocator = simulation_allocator();
+    let mut verif_allocator = simulation_allocator();
 
     let mut predicted_simulation: Option<Simulation> = None;
     let mut verified_simulation: Option<Simulation> = None;
 
-    let (connection_server, request_sender, server_message_rcv) = ConnectionServer::new()?;
-    ConnectionServer::start(connection_server);
-    let mut local_player_id = PlayerID::Player1;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config_path = Path::new(CLIENT_CONFIG_FILE);
+    let mut client_config: ClientConfig = config::load(config_path, &args)?;
+    let world_format = world_format(client_config.structured_world);
+    // closing the window ends the loop below, so the settings get written
+    prevent_quit();
+    // the rest of the file is read again when hosting, it may have changed by then
+    let input_send_rate = HostSettings::load(Path::new(&client_config.tuning_file)).map_or_else(
+        |e| {
+            eprintln!("Sending inputs every frame, {}", e);
+            0
+        },
+        |settings| settings.input_send_rate
+    );
+    let (connection_server, mut request_sender, mut server_message_rcv) = ConnectionServer::connect(
+        client_config.server_addr.as_str(),
+        client_config.connect_retry()
+    )?;
+    connection_server.lock().unwrap().set_input_send_rate(input_send_rate);
+    connection_server.lock().unwrap().set_compact_held_inputs(client_config.compact_held_inputs);
+    connection_server.lock().unwrap().set_message_history_len(client_config.message_history);
+    let mut diagnostics = connection_server.lock().unwrap().diagnostics();
+    let mut network_fatal_rcv = ConnectionServer::start(connection_server);
+    let mut roster = SessionRoster::default(); // who plays in the session, as the server tells it
+    let mut world_requested = false; // a joiner asked for the host's world and didn't get it yet
 
     let mut chose_player = false;
     let mut game_state = GameState::ChooseMode;
-    let mut other_player_ids: Vec<u8> = Vec::new();
-    let mut timer = 0.0;
+    let mut role_choice = RoleChoice::default();
+    let mut host_selection = HostSelection::new();
+    let mut incompatible_hosts = 0u8; // hosts the server left out of the list, their world wouldn't load here
+    let mut player_list_request: Option<PlayerListRequest> = None;
+    let mut menu_notice: Option<String> = None; // why we are back on the menu
+    // M was pressed after the connection was lost, the new one is probed on its own thread
+    let mut reconnecting: Option<mpsc::Receiver<Result<ConnectionHandles, NetError>>> = None;
+    // replaced when a session starts
+    let mut step_driver = session_step_driver(TickRate::default(), client_config.max_catch_up_frames);
+    let mut peer_clock = PeerClock::new(TickRate::default());
     let mut input_buffer = InputBuffer::new();
-    let mut session_player_count = 1;
+    let mut prediction_check = PredictionCheck::default();
+    let mut poor_connection = false; // the network thread saw a lot of loss on this connection
+    let mut join_failure: Option<String> = None; // why the last player that tried to join us gave up
+    let mut snapshot_schedule = SnapshotSchedule::default(); // only a host ever sends any
+    let mut playing_events = EventDrain::new(MAX_EVENTS_PER_FRAME);
+    let logger = Logger::new(client_config.log);
+    let mut join_handoff = JoinHandoff::new();
+    // newest host world the verified simulation was set to
+    let mut applied_world_frame: Option<VerifiedFrame> = None;
+    let mut upload_gate: Option<HostUploadGate> = None; // only the host uploads its world
+    let mut frame_gap = GapWatch::default();
+    let mut missing_inputs = MissingInputWatch::default();
+    let mut frame_timings = FrameTimings::new();
+    // predicted frames simulated per second, rollbacks make it exceed the tick rate
+    let mut predicted_steps = RateCounter::new(TIMING_WINDOW, 1024);
+    let mut enemy_capacity = DEFAULT_ENEMY_CAPACITY; // only used when hosting, joiners take the host's
+    let mut host_tick_rate = TickRate::default(); // same
+    let mut render_smoothing = RenderSmoothing::default();
+    if client_config.render_smoothing {
+        render_smoothing.toggle();
+    }
+    let mut recent_log = RecentLog::default(); // kept across sessions, it goes into bug reports
+    let mut input_journal = InputJournal::default();
+    let mut local_pause = LocalPause::default();
     loop {
+        if is_quit_requested() {
+            client_config.render_smoothing = render_smoothing.enabled();
+            config::save(&client_config, config_path)?;
+            return Ok(());
+        }
         clear_background(BLACK);
+        let mut net_stats = diagnostics.stats();
+        net_stats.predicted_steps_per_second = predicted_steps.per_second();
+        if is_key_pressed(KeyCode::F12) {
+            let report = BugReport {
+                input_buffer: format!("{:#?}", input_buffer),
+                state_diff: bugreport::state_diff(
+                    &verif_allocator.get_copy_of_state(),
+                    &pred_allocator.get_copy_of_state(),
+                    PAGE_SIZE_BYTES
+                ),
+                net_stats: format!("{:#?}", net_stats),
+                messages: diagnostics
+                    .dump_messages(BUG_REPORT_DUMP_TIMEOUT)
+                    .unwrap_or_else(|| "The network thread didn't answer".to_string()),
+                log: &recent_log,
+                journal: &input_journal,
+            };
+            match bugreport::export(&report, Path::new(BUG_REPORT_ROOT)) {
+                Ok(dir) => eprintln!("Wrote a bug report to {}", dir.display()),
+                Err(e) => eprintln!("Couldn't write a bug report: {}", e),
+            }
+        }
+        if let Ok(NetworkEvent::Fatal(reason)) = network_fatal_rcv.try_recv() {
+            game_state = GameState::NetworkLost(reason);
+        }
 
         match game_state {
             GameState::ChooseMode => {
                 draw_text("Choose mode:", 20.0, 40.0, 30.0, WHITE);
                 draw_text("Press 'H' to Host", 20.0, 80.0, 20.0, WHITE);
                 draw_text("Press 'J' to Join", 20.0, 110.0, 20.0, WHITE);
+                draw_text(
+                    &format!("Max enemies when hosting: {} (Up/Down)", enemy_capacity),
+                    20.0,
+                    140.0,
+                    20.0,
+                    GRAY
+                );
+                draw_text(
+                    &format!("Tick rate when hosting: {} Hz (T)", host_tick_rate.hz()),
+                    20.0,
+                    170.0,
+                    20.0,
+                    GRAY
+                );
+                if let Some(ref notice) = menu_notice {
+                    draw_text(notice, 20.0, 200.0, 20.0, RED);
+                }
+                if is_key_pressed(KeyCode::Up) {
+                    enemy_capacity = (enemy_capacity + 1).min(MAX_ENEMIES as u32);
+                } else if is_key_pressed(KeyCode::Down) {
+                    enemy_capacity = (enemy_capacity - 1).max(1);
+                }
+                if is_key_pressed(KeyCode::T) {
+                    host_tick_rate = host_tick_rate.next();
+                }
 
-                if is_key_pressed(KeyCode::H) {
-                    verified_simulation = Some(Simulation::new(&mut verif_allocator));
-                    predicted_simulation = Some(Simulation::new(&mut pred_allocator));
-                    game_state = GameState::Playing;
-                } else if is_key_pressed(KeyCode::J) {
-                    request_sender.send(
-                        types::GameRequestToNetwork::DirectRequest(
+                // both keys in one frame choose nothing, a chosen role stays until we are back here
+                let role = role_from_keys(is_key_pressed(KeyCode::H), is_key_pressed(KeyCode::J));
+                match role.map(|role| role_choice.choose(role)) {
+                    Some(Ok(SessionRole::Host)) => {
+                        // read on every start so edits apply without a restart, joiners never read it,
+                        // they get the host's tuning with the world
+                        match HostSettings::load(Path::new(&client_config.tuning_file)) {
+                            Ok(HostSettings { tuning, snapshot_interval_frames, .. }) => {
+                                request_sender.begin_session()?;
+                                // joiners get the seed with the world
+                                let seed = ::rand::random();
+                                verified_simulation = Some(
+                                    Simulation::new(
+                                        &mut verif_allocator,
+                                        enemy_capacity,
+                                        host_tick_rate,
+                                        tuning,
+                                        seed
+                                    )
+                                );
+                                predicted_simulation = Some(
+                                    Simulation::new(
+                                        &mut pred_allocator,
+                                        enemy_capacity,
+                                        host_tick_rate,
+                                        tuning,
+                                        seed
+                                    )
+                                );
+                                // the server checks our input frames against it
+                                send_for_role(
+                                    &role_choice,
+                                    &request_sender,
+                                    NetworkMessage::ClientSetTickRate(host_tick_rate)
+                                )?;
+                                send_for_role(
+                                    &role_choice,
+                                    &request_sender,
+                                    NetworkMessage::ClientStartedHosting
+                                )?;
+                                step_driver = session_step_driver(
+                                    host_tick_rate,
+                                    client_config.max_catch_up_frames
+                                );
+                                peer_clock = PeerClock::new(host_tick_rate);
+                                upload_gate = Some(HostUploadGate::new());
+                                snapshot_schedule = SnapshotSchedule::new(snapshot_interval_frames);
+                                menu_notice = None;
+                                input_buffer.on_verified_frame(
+                                    send_empty_inputs_for_unplayed_frames(
+                                        request_sender.clone(),
+                                        roster.local_slot()
+                                    )
+                                );
+                                game_state = GameState::Playing;
+                            }
+                            Err(e) => {
+                                role_choice.reset();
+                                roster = SessionRoster::default();
+                                menu_notice = Some(format!("Can't host, {}", e));
+                            }
+                        }
+                    }
+                    Some(Ok(SessionRole::Joiner)) => {
+                        request_sender.begin_session()?;
+                        send_for_role(
+                            &role_choice,
+                            &request_sender,
                             NetworkMessage::GetServerPlayerIDs
-                        )
-                    )?;
-                    game_state = GameState::WaitingForPlayerList;
+                        )?;
+                        player_list_request = Some(PlayerListRequest::new(Instant::now()));
+                        menu_notice = None;
+                        game_state = GameState::WaitingForPlayerList;
+                    }
+                    Some(Err(e)) => eprintln!("{}", e),
+                    None => {}
                 }
             }
             GameState::WaitingForPlayerList => {
                 draw_text("Waiting for player list...", 20.0, 40.0, 30.0, WHITE);
-                if let Ok(NetworkMessage::ServerSentPlayerIDs(ids)) = server_message_rcv.try_recv() {
-                    // println!("received ids {:?}", ids);
-                    other_player_ids = ids;
-                    game_state = GameState::ChoosePlayer;
+                let mut failed = false;
+                match server_message_rcv.try_recv() {
+                    Ok(NetworkEvent::Message(NetworkMessage::ServerSentPlayerIDs(ids, other_layout))) => {
+                        // println!("received ids {:?}", ids);
+                        host_selection.set_hosts(&ids);
+                        incompatible_hosts = other_layout;
+                        player_list_request = None;
+                        game_state = GameState::ChoosePlayer;
+                    }
+                    Ok(NetworkEvent::Failed(e)) => {
+                        eprintln!("Network error: {}", e);
+                        failed = true;
+                    }
+                    _ => {}
+                }
+                if let Some(ref mut request) = player_list_request {
+                    draw_text(
+                        &format!("attempt {}/{}", request.attempts(), PLAYER_LIST_ATTEMPTS),
+                        20.0,
+                        70.0,
+                        20.0,
+                        GRAY
+                    );
+                    match request.poll(Instant::now(), failed) {
+                        PlayerListPoll::Waiting => {}
+                        PlayerListPoll::Retry => {
+                            send_for_role(
+                                &role_choice,
+                                &request_sender,
+                                NetworkMessage::GetServerPlayerIDs
+                            )?;
+                        }
+                        PlayerListPoll::GiveUp => {
+                            menu_notice = Some("The server didn't send a player list".to_string());
+                            request_sender.end_session()?;
+                            player_list_request = None;
+                            role_choice.reset();
+                            roster = SessionRoster::default();
+                            game_state = GameState::ChooseMode;
+                        }
+                    }
                 }
             }
             GameState::ChoosePlayer => {
                 draw_text("Choose a player to connect to:", 20.0, 40.0, 30.0, WHITE);
-                for (i, id) in other_player_ids.iter().enumerate() {
+                draw_text(
+                    &format!(
+                        "Up/Down to select, Left/Right to change page, Enter to connect | page {}/{}",
+                        host_selection.page() + 1,
+                        host_selection.page_count()
+                    ),
+                    20.0,
+                    65.0,
+                    16.0,
+                    GRAY
+                );
+                for (i, id) in host_selection.visible().iter().enumerate() {
+                    let highlighted = host_selection.highlighted() == Some(*id);
                     draw_text(
-                        &format!("Press {} for Player {}", i, id),
+                        &format!("{} Player {}", if highlighted { ">" } else { " " }, id.0),
                         20.0,
-                        80.0 + 30.0 * (i as f32),
+                        100.0 + 30.0 * (i as f32),
                         20.0,
-                        WHITE
+                        if highlighted { YELLOW } else { WHITE }
                     );
                 }
-                let keycodes = [
-                    KeyCode::Key0,
-                    KeyCode::Key1,
-                    KeyCode::Key2,
-                    KeyCode::Key3,
-                    KeyCode::Key4,
-                    KeyCode::Key5,
-                    KeyCode::Key6,
-                    KeyCode::Key7,
-                    KeyCode::Key8,
-                    KeyCode::Key9,
-                ];
-
-                for i in 0..9 {
-                    if
-                        is_key_pressed(keycodes[i as usize]) &&
-                        (i as usize) < other_player_ids.len()
-                    {
-                        let player_to_connect_to: ServerPlayerID = ServerPlayerID(
-                            other_player_ids[i as usize]
-                        );
-                        request_sender.send(
-                            types::GameRequestToNetwork::DirectRequest(
+                if incompatible_hosts > 0 {
+                    draw_text(
+                        &incompatible_hosts_notice(incompatible_hosts),
+                        20.0,
+                        100.0 + 30.0 * (host_selection.visible().len() as f32) + 10.0,
+                        16.0,
+                        GRAY
+                    );
+                }
+
+                if !chose_player {
+                    if is_key_pressed(KeyCode::Up) {
+                        host_selection.move_up();
+                    }
+                    if is_key_pressed(KeyCode::Down) {
+                        host_selection.move_down();
+                    }
+                    if is_key_pressed(KeyCode::Left) {
+                        host_selection.prev_page();
+                    }
+                    if is_key_pressed(KeyCode::Right) {
+                        host_selection.next_page();
+                    }
+                    if is_key_pressed(KeyCode::Enter) {
+                        if let Some(player_to_connect_to) = host_selection.confirm() {
+                            send_for_role(
+                                &role_choice,
+                                &request_sender,
                                 NetworkMessage::ClientConnectToOtherWorld(player_to_connect_to)
-                            )
-                        )?;
-                        chose_player = true;
-                        break;
+                            )?;
+                            chose_player = true;
+                        }
+                    }
+                    // a refreshed list only moves the highlight along with its host
+                    match server_message_rcv.try_recv() {
+                        Ok(NetworkEvent::Message(NetworkMessage::ServerSentPlayerIDs(ids, other_layout))) => {
+                            host_selection.set_hosts(&ids);
+                            incompatible_hosts = other_layout;
+                        }
+                        // someone started hosting, no need to wait for the player to refresh
+                        Ok(NetworkEvent::Message(NetworkMessage::ServerPlayerListChanged)) => {
+                            send_for_role(
+                                &role_choice,
+                                &request_sender,
+                                NetworkMessage::GetServerPlayerIDs
+                            )?;
+                        }
+                        Ok(NetworkEvent::Failed(e)) => eprintln!("Network error: {}", e),
+                        _ => {}
                     }
                 }
 
                 if chose_player {
                     if let Ok(msg) = server_message_rcv.try_recv() {
                         match msg {
-                            NetworkMessage::ServerSentPlayerInputs(inputs) => {
-                                for input in inputs.buffered_inputs {
-                                    let other_player = input.inputs;
-                                    println!(
-                                        "received inputs while loading |  frame : {:?}",
-                                        input.frame
-                                    );
-                                    input_buffer.insert_other_player_inp(
-                                        other_player.clone(),
-                                        input.frame
-                                    );
-                                }
-                            }
-                            NetworkMessage::ServerSentWorld(data) => {
-                                verified_simulation = Some(
-                                    Simulation::new_from_serialized(
-                                        data.clone(),
-                                        &mut verif_allocator
-                                    )
-                                );
-                                predicted_simulation = Some(
-                                    Simulation::new_from_serialized(data, &mut pred_allocator)
-                                );
-                                debug_assert!(
-                                    verif_allocator.read_fixed(
-                                        &verified_simulation.unwrap().frame
-                                    ) ==
-                                        pred_allocator.read_fixed(
-                                            &predicted_simulation.unwrap().frame
-                                        )
-                                );
-                                debug_assert!(
-                                    verif_allocator.read_fixed(
-                                        &verified_simulation.unwrap().frame
-                                    ) > 0
-                                );
-                                session_player_count = session_player_count + 1;
-                                local_player_id = PlayerID::Player2;
-                                game_state = GameState::Playing;
-                                input_buffer.update_player_count(
-                                    local_player_id,
-                                    session_player_count,
-                                    verif_allocator.read_fixed(&verified_simulation.unwrap().frame)
+                            NetworkEvent::Message(
+                                NetworkMessage::ServerSentPlayerInputs(slot, inputs),
+                            ) => {
+                                println!(
+                                    "received inputs while loading |  frames : {:?}",
+                                    inputs.buffered_inputs.iter().map(|inp| inp.frame).collect::<Vec<u32>>()
                                 );
+                                join_handoff.on_remote_inputs(slot, inputs.buffered_inputs);
+                            }
+                            NetworkEvent::Message(NetworkMessage::ServerSentWorld(data)) => {
+                                join_handoff.on_world(data);
                             }
+                            NetworkEvent::Message(NetworkMessage::ServerAssignPlayerSlot(slot)) => {
+                                roster.on_slot_assigned(slot);
+                                join_handoff.on_slot(slot);
+                            }
+                            NetworkEvent::Message(NetworkMessage::PlayerJoined(id)) => {
+                                roster.on_player_joined(id);
+                            }
+                            NetworkEvent::Message(NetworkMessage::PlayerLeft(id)) => {
+                                roster.on_player_left(id);
+                            }
+                            NetworkEvent::Failed(e) => eprintln!("Network error: {}", e),
                             _ =>
                                 println!(
                                     "Unexpected message received when waiting for world download"
                                 ),
                         }
                     }
+                    // only start once we have both the world and our slot and heard of the host, our first
+                    // input frame is snapshot frame + 1
+                    let join = if roster.player_count() > 1 { join_handoff.try_complete() } else { None };
+                    if let Some(join) = join {
+                        // checked on the verified side first, a snapshot this build can't run is dropped
+                        let (verified, predicted) = match
+                            world_format.deserialize(join.world.clone(), &mut verif_allocator)
+                        {
+                            Ok(verified) => (
+                                verified,
+                                world_format.deserialize(join.world, &mut pred_allocator)?,
+                            ),
+                            Err(e) => {
+                                eprintln!("Rejected the host's world: {}", e);
+                                verif_allocator = simulation_allocator();
+                                menu_notice = Some(format!("Rejected the host's world: {}", e));
+                                // the host sees why we are gone again
+                                send_for_role(
+                                    &role_choice,
+                                    &request_sender,
+                                    NetworkMessage::ClientJoinFailed(e.to_string())
+                                )?;
+                                request_sender.end_session()?;
+                                chose_player = false;
+                                role_choice.reset();
+                                roster = SessionRoster::default();
+                                game_state = GameState::ChooseMode;
+                                next_frame().await;
+                                continue;
+                            }
+                        };
+                        step_driver = session_step_driver(
+                            verified.tick_rate(&verif_allocator),
+                            client_config.max_catch_up_frames
+                        );
+                        peer_clock = PeerClock::new(verified.tick_rate(&verif_allocator));
+                        verified_simulation = Some(verified);
+                        predicted_simulation = Some(predicted);
+                        debug_assert!(
+                            verified_simulation.unwrap().current_frame(&verif_allocator) ==
+                                predicted_simulation.unwrap().current_frame(&pred_allocator)
+                        );
+                        debug_assert!(
+                            verified_simulation.unwrap().current_frame(&verif_allocator) > 0
+                        );
+                        debug_assert!(join.slot == roster.local_slot());
+                        input_buffer.on_verified_frame(
+                            send_empty_inputs_for_unplayed_frames(request_sender.clone(), roster.local_slot())
+                        );
+                        game_state = GameState::Playing;
+                        let snapshot_frame = verified_simulation.unwrap().verified_frame(&verif_allocator);
+                        applied_world_frame = Some(snapshot_frame);
+                        route_join_inputs(&mut input_buffer, &roster, snapshot_frame, join.inputs);
+                    }
                 }
             }
             GameState::Playing => {
@@ -451,195 +1421,1682 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         predicted_simulation,
                     )
                 {
+                    let mut phase_start = Instant::now();
                     let dt = get_frame_time();
-                    timer += dt;
-                    let mut curr_player = Vec::new();
-                    if is_key_down(KeyCode::A) || is_key_down(KeyCode::Left) {
-                        curr_player.push(PlayerInput::Left);
+                    let curr_player = sample_player_inputs(is_key_down);
+                    if is_key_pressed(KeyCode::R) {
+                        request_sender.send(GameRequestToNetwork::Reconnect)?;
                     }
-                    if is_key_down(KeyCode::D) || is_key_down(KeyCode::Right) {
-                        curr_player.push(PlayerInput::Right);
+                    if is_key_pressed(KeyCode::V) {
+                        render_smoothing.toggle();
                     }
-                    if is_key_down(KeyCode::W) || is_key_down(KeyCode::Up) {
-                        curr_player.push(PlayerInput::Shoot);
+                    if is_key_pressed(KeyCode::P) {
+                        local_pause.request_toggle();
                     }
-                    if timer >= PHYSICS_FRAME_TIME {
-                        timer -= PHYSICS_FRAME_TIME;
-                        request_sender.send(
-                            types::GameRequestToNetwork::IndirectRequest(
-                                types::GameMessage::ClientSentPlayerInputs(
-                                    NetworkedPlayerInput::new(curr_player.clone(), if
-                                        session_player_count > 1
-                                    {
-                                        pred_allocator.read_fixed(&predicted_simulation.frame) + 1
-                                    } else {
-                                        verif_allocator.read_fixed(&verified_simulation.frame)
-                                    })
+                    let joined = roster.player_count() > 1 && upload_gate.is_none();
+                    let (stepping, skipped) = advance_step_driver(&mut step_driver, dt, joined, |msg| {
+                        request_sender.send(types::GameRequestToNetwork::DirectRequest(msg))
+                    })?;
+                    if skipped > 0 {
+                        // too far behind to send every missed input
+                        recent_log.push(format!("Skipped {} frames after a stall", skipped));
+                        eprintln!("Skipped {} frames after a stall", skipped);
+                    }
+                    if stepping {
+                        let input_frame = local_input_frame(
+                            &roster,
+                            predicted_simulation,
+                            &pred_allocator,
+                            verified_simulation,
+                            &verif_allocator
+                        );
+                        if let Some(marker) = local_pause.take_marker(input_frame) {
+                            recent_log.push(format!("{:?}", marker));
+                            request_sender.send(types::GameRequestToNetwork::DirectRequest(marker))?;
+                        }
+                        if local_pause.sends(input_frame) {
+                            submit_local_input(&mut input_buffer, curr_player.clone(), input_frame, |input| {
+                                request_sender.send(
+                                    types::GameRequestToNetwork::IndirectRequest(
+                                        types::GameMessage::ClientSentPlayerInputs(input)
+                                    )
                                 )
-                            )
-                        )?;
-
-                        input_buffer.insert_curr_player_inp(curr_player.clone(), if
-                            session_player_count > 1
-                        {
-                            pred_allocator.read_fixed(&predicted_simulation.frame) + 1
+                            })?;
+                            input_journal.record(input_frame, &curr_player);
                         } else {
-                            verif_allocator.read_fixed(&verified_simulation.frame) + 1
-                        });
-                        while let Ok(msg) = server_message_rcv.try_recv() {
+                            // the peers fill the paused frames with empty inputs, we play them the same
+                            // and tell them so, they fill no further than we said
+                            input_buffer.insert_curr_player_inp(Vec::new(), input_frame);
+                            request_sender.send(
+                                types::GameRequestToNetwork::DirectRequest(
+                                    NetworkMessage::ClientInputStreamPausedThrough { through_frame: input_frame }
+                                )
+                            )?;
+                        }
+                        phase_start = frame_timings.end_phase(FramePhase::InputSampling, phase_start);
+                        for msg in playing_events.next_batch(&server_message_rcv) {
                             match msg {
-                                NetworkMessage::ServerSentPlayerInputs(inputs) => {
-                                    for input in inputs.buffered_inputs {
-                                        let other_player = input.inputs;
-                                        input_buffer.insert_other_player_inp(
-                                            other_player.clone(),
+                                NetworkEvent::Message(
+                                    NetworkMessage::ServerSentPlayerInputs(slot, inputs),
+                                ) => {
+                                    let inputs = match upload_gate {
+                                        Some(ref mut gate) =>
+                                            gate.on_remote_inputs(slot, inputs.buffered_inputs),
+                                        None =>
+                                            inputs.buffered_inputs
+                                                .into_iter()
+                                                .map(|inp| (slot, inp))
+                                                .collect(),
+                                    };
+                                    for (slot, input) in inputs {
+                                        input_buffer.insert_remote_player_inp(
+                                            slot,
+                                            input.inputs,
                                             input.frame
                                         );
                                     }
-                                }
-                                NetworkMessage::ServerRequestHostForWorldData => {
-                                    if session_player_count == 1 {
-                                        // TODO and player id is not the same as other player
-                                        session_player_count += 1;
-                                        input_buffer.update_player_count(
-                                            local_player_id,
-                                            session_player_count,
-                                            verif_allocator.read_fixed(&verified_simulation.frame)
-                                        ); // start predicting
-                                        pred_allocator.set_memory(
-                                            &verif_allocator.get_copy_of_state()
-                                        );
+                                    if let Some(newest) = input_buffer.newest_remote_frame() {
+                                        peer_clock.on_remote_frame(newest, net_stats.rtt);
                                     }
-                                    // this also means that we are connecting with someone and its now a mulitplayer lobby
-                                    request_sender.send(
-                                        types::GameRequestToNetwork::DirectRequest(
-                                            NetworkMessage::ClientSentWorld(
-                                                verif_allocator.get_copy_of_state()
-                                            )
-                                        )
-                                    )?;
-
-                                    request_sender.send(
-                                        types::GameRequestToNetwork::IndirectRequest(
-                                            types::GameMessage::ClientSentPlayerInputs(
-                                                NetworkedPlayerInput::new(
-                                                    curr_player.clone(),
-                                                    verif_allocator.read_fixed(
-                                                        &verified_simulation.frame
-                                                    ) + 1
+                                }
+                                // the other player is stuck on frames of ours that got lost, sent again as played
+                                NetworkEvent::Message(
+                                    NetworkMessage::ServerRequestInputRange { from_frame, to_frame },
+                                ) => {
+                                    for (frame, inputs) in input_journal.frames_in(from_frame, to_frame) {
+                                        request_sender.send(
+                                            types::GameRequestToNetwork::IndirectRequest(
+                                                types::GameMessage::ClientResentPlayerInputs(
+                                                    NetworkedPlayerInput::new(inputs.clone(), *frame)
                                                 )
                                             )
-                                        )
-                                    )?;
+                                        )?;
+                                    }
                                 }
-                                _ => {}
-                            }
-                        }
-                        let mut new_verified_state = false;
-                        while let Some(verif_frame_input) = input_buffer.pop_next_verified_frame() {
-                            // if we are ahead, then we will wait for the other player,
-                            // if we are behind we need to be able to  simulate based solely on the other players, because otherwise we will never catchup
-                            // therefore each verified frame is based only on whether we have inputs from all other players regardless of our inputs
-                            if verif_frame_input.inputs[local_player_id as usize].is_none() {
-                                request_sender.send(
-                                    types::GameRequestToNetwork::IndirectRequest(
-                                        types::GameMessage::ClientSentPlayerInputs(
-                                            NetworkedPlayerInput::new(
-                                                // take closest input we have)
-                                                Vec::new(), // send empty inputs as we didnt play for this frame yet
-                                                verif_frame_input.frame
-                                            )
+                                NetworkEvent::Message(
+                                    NetworkMessage::ServerInputStreamPaused { slot, last_frame },
+                                ) => {
+                                    let paused = input_buffer.pause_remote(slot, last_frame);
+                                    if paused {
+                                        recent_log.push(format!("{:?} paused after frame {}", slot, last_frame));
+                                    }
+                                }
+                                NetworkEvent::Message(
+                                    NetworkMessage::ServerInputStreamPausedThrough { slot, through_frame },
+                                ) => {
+                                    input_buffer.remote_paused_through(slot, through_frame);
+                                }
+                                NetworkEvent::Message(
+                                    NetworkMessage::ServerInputStreamResumed { slot, from_frame },
+                                ) => {
+                                    let resumed = input_buffer.resume_remote(slot, from_frame);
+                                    if resumed {
+                                        recent_log.push(
+                                            format!("{:?} plays again from frame {}", slot, from_frame)
+                                        );
+                                    }
+                                }
+                                NetworkEvent::WorldUploadComplete => {
+                                    if let Some(ref mut gate) = upload_gate {
+                                        for (slot, input) in gate.on_upload_complete() {
+                                            input_buffer.insert_remote_player_inp(
+                                                slot,
+                                                input.inputs,
+                                                input.frame
+                                            );
+                                        }
+                                    }
+                                }
+                                // the server decides which slot the host plays in, not the order of joining
+                                NetworkEvent::Message(NetworkMessage::ServerAssignPlayerSlot(slot)) => {
+                                    if roster.on_slot_assigned(slot) {
+                                        input_buffer.update_player_count(
+                                            roster.local_slot(),
+                                            roster.player_count(),
+                                            verified_simulation.verified_frame(&verif_allocator)
+                                        );
+                                    }
+                                }
+                                // answered below once the joiner is counted
+                                NetworkEvent::Message(
+                                    NetworkMessage::ServerRequestHostForWorldData,
+                                ) if upload_gate.is_some() => {
+                                    world_requested = true;
+                                }
+                                NetworkEvent::PoorConnection => {
+                                    poor_connection = true;
+                                }
+                                NetworkEvent::Message(NetworkMessage::PlayerJoined(id)) => {
+                                    recent_log.push(format!("Player {} joined", id.0));
+                                    logger.connection(|| format!("Player {} joined", id.0));
+                                    if roster.on_player_joined(id) {
+                                        // the verified frames wait for the new player's inputs from here on
+                                        input_buffer.update_player_count(
+                                            roster.local_slot(),
+                                            roster.player_count(),
+                                            verified_simulation.verified_frame(&verif_allocator)
+                                        );
+                                        if let Some(marker) = local_pause.announcement() {
+                                            request_sender.send(
+                                                types::GameRequestToNetwork::DirectRequest(marker)
+                                            )?;
+                                        }
+                                        restore_prediction(
+                                            verified_simulation,
+                                            &verif_allocator,
+                                            predicted_simulation,
+                                            &mut pred_allocator
+                                        );
+                                    }
+                                }
+                                NetworkEvent::Message(NetworkMessage::PlayerLeft(id)) => {
+                                    recent_log.push(format!("Player {} left", id.0));
+                                    logger.connection(|| format!("Player {} left", id.0));
+                                    if roster.on_player_left(id) {
+                                        // the verified frames go on without its inputs
+                                        input_buffer.update_player_count(
+                                            roster.local_slot(),
+                                            roster.player_count(),
+                                            verified_simulation.verified_frame(&verif_allocator)
+                                        );
+                                    }
+                                }
+                                NetworkEvent::Message(NetworkMessage::ServerJoinFailed(id, reason)) => {
+                                    let failure = format!("Player {} couldn't join: {}", id.0, reason);
+                                    recent_log.push(failure.clone());
+                                    logger.connection(|| &failure);
+                                    join_failure = Some(failure);
+                                }
+                                NetworkEvent::Message(NetworkMessage::ServerSentWorld(data)) => {
+                                    match
+                                        apply_host_snapshot(
+                                            &world_format,
+                                            data,
+                                            verified_simulation,
+                                            &mut verif_allocator,
+                                            &mut input_buffer,
+                                            &mut applied_world_frame
                                         )
+                                    {
+                                        Ok(Some(frame)) => {
+                                            recent_log.push(
+                                                format!("Applied the host's snapshot of frame {}", frame.0)
+                                            );
+                                            logger.world_state(
+                                                || format!("Applied the host's snapshot of frame {}", frame.0)
+                                            );
+                                            restore_prediction(
+                                                verified_simulation,
+                                                &verif_allocator,
+                                                predicted_simulation,
+                                                &mut pred_allocator
+                                            );
+                                        }
+                                        Ok(None) => {
+                                            logger.world_state(
+                                                || "Skipped a host snapshot, the world we have is as new or newer"
+                                            );
+                                        }
+                                        Err(e) => {
+                                            recent_log.push(format!("Ignored the host's snapshot: {}", e));
+                                            eprintln!("Ignored the host's snapshot: {}", e);
+                                        }
+                                    }
+                                }
+                                NetworkEvent::Failed(e) => {
+                                    recent_log.push(format!("Network error: {}", e));
+                                    eprintln!("Network error: {}", e);
+                                }
+                                _ => {}
+                            }
+                        }
+                        // the verified frames stop at the joiner's first frame once it is counted, so that's
+                        // the frame its world is taken at
+                        if world_requested && roster.player_count() > 1 {
+                            world_requested = false;
+                            let snapshot_frame = verified_simulation.verified_frame(&verif_allocator);
+                            if let Some(ref mut gate) = upload_gate {
+                                gate.begin_upload(snapshot_frame);
+                            }
+                            // the joiner gets our inputs from the world's frame on, not what piled up before it
+                            send_for_role(
+                                &role_choice,
+                                &request_sender,
+                                NetworkMessage::ClientWorldBaseline(snapshot_frame.0)
+                            )?;
+                            send_for_role(
+                                &role_choice,
+                                &request_sender,
+                                NetworkMessage::ClientSentWorld(
+                                    world_format.serialize(verified_simulation, &verif_allocator)
+                                )
+                            )?;
+                        }
+                        phase_start = frame_timings.end_phase(FramePhase::NetworkDrain, phase_start);
+                        let mut new_verified_state = false;
+                        loop {
+                            let frame = match
+                                step_verified_frame(
+                                    &mut input_buffer,
+                                    verified_simulation,
+                                    &mut verif_allocator
+                                )
+                            {
+                                Ok(Some(frame)) => frame,
+                                Ok(None) => {
+                                    frame_gap.clear();
+                                    break;
+                                }
+                                Err(gap) => {
+                                    // only a joiner can ask for the world again, the host's is the reference
+                                    if
+                                        roster.player_count() > 1 &&
+                                        upload_gate.is_none() &&
+                                        frame_gap.is_stuck(Instant::now(), gap)
+                                    {
+                                        recent_log.push(format!("Requesting the host's world after {:?}", gap));
+                                        eprintln!(
+                                            "Requesting the host's world, input frames skip past the verified simulation: {:?}",
+                                            input_buffer
+                                        );
+                                        request_sender.send(
+                                            types::GameRequestToNetwork::DirectRequest(
+                                                NetworkMessage::ClientRequestResync
+                                            )
+                                        )?;
+                                    }
+                                    break;
+                                }
+                            };
+                            if
+                                let Some(misprediction) = prediction_check.check_verified(
+                                    frame,
+                                    &verified_simulation.digest(&verif_allocator)
+                                )
+                            {
+                                recent_log.push(format!("{}", misprediction));
+                                logger.world_state(|| {
+                                    format!(
+                                        "{} ({} of {} frames so far)",
+                                        misprediction,
+                                        prediction_check.mispredicted,
+                                        prediction_check.checked
+                                    )
+                                });
+                            }
+                            // a joiner still catching up on the join upload would get two worlds
+                            let uploading = upload_gate.as_ref().is_none_or(HostUploadGate::is_uploading);
+                            if roster.player_count() > 1 && !uploading && snapshot_schedule.is_due(frame) {
+                                send_for_role(
+                                    &role_choice,
+                                    &request_sender,
+                                    NetworkMessage::ClientSentWorld(
+                                        world_format.serialize(verified_simulation, &verif_allocator)
                                     )
                                 )?;
                             }
-
-                            debug_assert!(
-                                verif_allocator.read_fixed(&verified_simulation.frame) + 1 ==
-                                    verif_frame_input.frame,
-                                "verif frame inp {:?}",
-                                verif_frame_input
-                            );
-                            verified_simulation.update(
-                                PHYSICS_FRAME_TIME,
-                                verif_frame_input.inputs.clone(),
-                                &mut verif_allocator
-                            );
-                            debug_assert!(
-                                verif_allocator.read_fixed(&verified_simulation.frame) ==
-                                    verif_frame_input.frame
-                            );
                             new_verified_state = true;
                         }
-                        if new_verified_state && session_player_count > 1 {
-                            pred_allocator.set_memory(&verif_allocator.get_copy_of_state());
-                        }
-
-                        for (
-                            _,
-                            pred_frame_input,
-                        ) in input_buffer.excluding_iter_after_last_verified() {
+                        if new_verified_state {
+                            missing_inputs.clear();
+                        } else if roster.player_count() > 1 {
                             if
-                                pred_allocator.read_fixed(&predicted_simulation.frame) < // by doing this we exclude verified automatically as it would be in the .frame from verified update above
-                                pred_frame_input.frame
+                                let Some((from_frame, to_frame)) = missing_inputs.on_stalled_tick(
+                                    input_buffer.missing_remote_frames()
+                                )
                             {
+                                recent_log.push(
+                                    format!("Requesting remote frames {} to {} again", from_frame, to_frame)
+                                );
+                                eprintln!(
+                                    "Requesting remote frames {} to {} again: {:?}",
+                                    from_frame,
+                                    to_frame,
+                                    input_buffer
+                                );
                                 request_sender.send(
-                                    types::GameRequestToNetwork::IndirectRequest(
-                                        types::GameMessage::ClientSentPlayerInputs(
-                                            NetworkedPlayerInput::new(
-                                                curr_player.clone(),
-                                                pred_frame_input.frame
-                                            )
-                                        )
+                                    types::GameRequestToNetwork::DirectRequest(
+                                        NetworkMessage::ClientRequestInputRange { from_frame, to_frame }
                                     )
                                 )?;
-
-                                debug_assert!(
-                                    pred_allocator.read_fixed(&predicted_simulation.frame) + 1 ==
-                                        pred_frame_input.frame,
-                                    "curr frame {} vs next frames input {}",
-                                    pred_allocator.read_fixed(&predicted_simulation.frame) + 1,
-                                    pred_frame_input.frame
-                                );
-                                predicted_simulation.update(
-                                    PHYSICS_FRAME_TIME,
-                                    pred_frame_input.inputs.clone(),
-                                    &mut pred_allocator
-                                );
-                                debug_assert!(
-                                    pred_allocator.read_fixed(&predicted_simulation.frame) ==
-                                        pred_frame_input.frame
-                                );
                             }
                         }
+                        if new_verified_state && roster.player_count() > 1 {
+                            restore_prediction(
+                                verified_simulation,
+                                &verif_allocator,
+                                predicted_simulation,
+                                &mut pred_allocator
+                            );
+                        }
+                        phase_start = frame_timings.end_phase(FramePhase::VerifiedSteps, phase_start);
+
+                        let stepped = step_predicted_frames(
+                            &input_buffer,
+                            predicted_simulation,
+                            &mut pred_allocator,
+                            &mut prediction_check
+                        );
+                        predicted_steps.record_at(Instant::now(), stepped);
+                        frame_timings.end_phase(FramePhase::PredictedSteps, phase_start);
+                        // slow down instead of predicting further and further past the other player, unless
+                        // it paused and stands still on purpose
+                        let pace_with_peer = roster.player_count() > 1 && !input_buffer.any_remote_paused();
+                        step_driver.set_frames_ahead(if pace_with_peer {
+                            Some(frames_ahead(&peer_clock, predicted_simulation, &pred_allocator))
+                        } else {
+                            None
+                        });
                     }
 
-                    if session_player_count > 1 {
+                    let draw_start = Instant::now();
+                    // the arena is scaled to the window, resizing only changes how big it is drawn
+                    set_camera(&arena_camera());
+                    if roster.player_count() > 1 {
                         predicted_simulation.draw(
-                            local_player_id,
-                            true, // TODO
-                            &pred_allocator
+                            &roster,
+                            &pred_allocator,
+                            &mut render_smoothing,
+                            |player| input_buffer.is_remote_paused(player)
                         );
                     } else {
-                        verified_simulation.draw(local_player_id, false, &verif_allocator);
+                        verified_simulation.draw(
+                            &roster,
+                            &verif_allocator,
+                            &mut render_smoothing,
+                            |_| false
+                        );
+                    }
+                    set_default_camera();
+                    let window = window_size();
+                    if local_pause.is_paused() {
+                        draw_text(
+                            "paused, the others play on (P)",
+                            window.x / 2.0 - 130.0,
+                            window.y / 2.0,
+                            24.0,
+                            WHITE
+                        );
                     }
 
                     draw_text(
                         &format!(
-                            "Player is: {:?} | Current verified Frame: {} |  pred frame {} ",
-                            local_player_id,
-                            verif_allocator.read_fixed(&verified_simulation.frame),
-                            pred_allocator.read_fixed(&predicted_simulation.frame)
+                            "Player is: {:?} | Current verified Frame: {} |  pred frame {} ({} ahead)",
+                            roster.local_slot(),
+                            verified_simulation.current_frame(&verif_allocator),
+                            predicted_simulation.current_frame(&pred_allocator),
+                            predicted_simulation
+                                .predicted_frame(&pred_allocator)
+                                .frames_past(verified_simulation.verified_frame(&verif_allocator))
                         ),
                         25.0,
                         25.0,
                         20.0,
                         WHITE
                     );
+                    for (i, phase) in FramePhase::ALL.iter().enumerate() {
+                        draw_text(
+                            &timing_overlay_text(phase.label(), &frame_timings.summary(*phase)),
+                            25.0,
+                            45.0 + 16.0 * (i as f32),
+                            16.0,
+                            GRAY
+                        );
+                    }
+                    draw_text(
+                        &timing_overlay_text(
+                            "net loop",
+                            &net_stats.loop_iteration
+                        ),
+                        25.0,
+                        45.0 + 16.0 * (FramePhase::ALL.len() as f32),
+                        16.0,
+                        GRAY
+                    );
+                    draw_text(
+                        &format!("time dilation x{:.3}", step_driver.dilation()),
+                        25.0,
+                        45.0 + 16.0 * ((FramePhase::ALL.len() + 1) as f32),
+                        16.0,
+                        GRAY
+                    );
+                    let rtt = net_stats.rtt;
+                    draw_text(
+                        &(match rtt {
+                            Some(rtt) => format!("rtt {:.1}ms", rtt.as_secs_f32() * 1000.0),
+                            None => "rtt -".to_string(),
+                        }),
+                        25.0,
+                        45.0 + 16.0 * ((FramePhase::ALL.len() + 4) as f32),
+                        16.0,
+                        GRAY
+                    );
+                    let (reliable_loss, input_loss) = (net_stats.reliable_loss, net_stats.input_loss);
+                    draw_text(
+                        &format!(
+                            "loss: reliable {}, inputs {}",
+                            loss_overlay_text(reliable_loss),
+                            loss_overlay_text(input_loss)
+                        ),
+                        25.0,
+                        45.0 + 16.0 * ((FramePhase::ALL.len() + 5) as f32),
+                        16.0,
+                        GRAY
+                    );
+                    draw_text(
+                        &format!("network backlog: {} events", playing_events.backlog()),
+                        25.0,
+                        45.0 + 16.0 * ((FramePhase::ALL.len() + 6) as f32),
+                        16.0,
+                        GRAY
+                    );
+                    draw_text(
+                        &format!("predicted steps: {:.0}/s", net_stats.predicted_steps_per_second),
+                        25.0,
+                        45.0 + 16.0 * ((FramePhase::ALL.len() + 7) as f32),
+                        16.0,
+                        GRAY
+                    );
+                    // anything but 0 means the loop asked to send a frame it already sent
+                    draw_text(
+                        &format!("repeated input frames: {}", net_stats.repeated_input_frames),
+                        25.0,
+                        45.0 + 16.0 * ((FramePhase::ALL.len() + 8) as f32),
+                        16.0,
+                        GRAY
+                    );
+                    if poor_connection {
+                        draw_text("poor connection", window.x - 170.0, 25.0, 20.0, RED);
+                    }
+                    if let Some(ref failure) = join_failure {
+                        draw_text(failure, 25.0, window.y - 25.0, 20.0, RED);
+                    }
+                    let remote_inputs = net_stats.remote_inputs;
+                    draw_text(
+                        &format!(
+                            "remote inputs: {} duplicate, {} missing frames",
+                            remote_inputs.duplicates,
+                            remote_inputs.missing_frames
+                        ),
+                        25.0,
+                        45.0 + 16.0 * ((FramePhase::ALL.len() + 3) as f32),
+                        16.0,
+                        GRAY
+                    );
+                    draw_text(
+                        &format!(
+                            "render smoothing {} (V)",
+                            if render_smoothing.enabled() { "on" } else { "off" }
+                        ),
+                        25.0,
+                        45.0 + 16.0 * ((FramePhase::ALL.len() + 2) as f32),
+                        16.0,
+                        GRAY
+                    );
+                    frame_timings.end_phase(FramePhase::Draw, draw_start);
                 }
             }
+            GameState::NetworkLost(ref reason) => {
+                draw_text("Lost the network connection:", 20.0, 40.0, 30.0, RED);
+                draw_text(reason, 20.0, 80.0, 20.0, WHITE);
+                let Some(connecting) = &reconnecting else {
+                    draw_text("Press 'M' to return to the menu", 20.0, 110.0, 20.0, WHITE);
+                    if is_key_pressed(KeyCode::M) {
+                        reconnecting = Some(
+                            ConnectionServer::connect_in_background(
+                                client_config.server_addr.clone(),
+                                client_config.connect_retry()
+                            )
+                        );
+                    }
+                    next_frame().await;
+                    continue;
+                };
+                draw_text("Connecting to the server...", 20.0, 110.0, 20.0, WHITE);
+                let result = match connecting.try_recv() {
+                    Ok(result) => result,
+                    Err(mpsc::TryRecvError::Empty) => {
+                        next_frame().await;
+                        continue;
+                    }
+                    Err(mpsc::TryRecvError::Disconnected) => Err(NetError::Disconnected),
+                };
+                reconnecting = None;
+                // everything from the old session goes, the new connection starts from scratch
+                let (connection_server, sender, receiver) = match result {
+                    Ok(handles) => handles,
+                    Err(e) => {
+                        // still down, M tries again
+                        game_state = GameState::NetworkLost(e.to_string());
+                        next_frame().await;
+                        continue;
+                    }
+                };
+                connection_server.lock().unwrap().set_input_send_rate(input_send_rate);
+                connection_server.lock().unwrap().set_compact_held_inputs(client_config.compact_held_inputs);
+                connection_server.lock().unwrap().set_message_history_len(client_config.message_history);
+                diagnostics = connection_server.lock().unwrap().diagnostics();
+                network_fatal_rcv = ConnectionServer::start(connection_server);
+                request_sender = sender;
+                server_message_rcv = receiver;
+                pred_allocator = simulation_allocator();
+                verif_allocator = simulation_allocator();
+                predicted_simulation = None;
+                verified_simulation = None;
+                chose_player = false;
+                host_selection = HostSelection::new();
+                player_list_request = None;
+                menu_notice = None;
+                step_driver = session_step_driver(TickRate::default(), client_config.max_catch_up_frames);
+                peer_clock = PeerClock::new(TickRate::default());
+                input_buffer = InputBuffer::new();
+                prediction_check = PredictionCheck::default();
+                poor_connection = false;
+                join_failure = None;
+                snapshot_schedule = SnapshotSchedule::default();
+                playing_events = EventDrain::new(MAX_EVENTS_PER_FRAME);
+                roster = SessionRoster::default();
+                world_requested = false;
+                join_handoff = JoinHandoff::new();
+                applied_world_frame = None;
+                upload_gate = None;
+                frame_gap = GapWatch::default();
+                missing_inputs = MissingInputWatch::default();
+                input_journal = InputJournal::default();
+                local_pause = LocalPause::default();
+                role_choice.reset();
+                game_state = GameState::ChooseMode;
+            }
         }
 
         next_frame().await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use ::rand::{ rngs::StdRng, Rng, SeedableRng };
+    use types::DEFAULT_TICK_RATE_HZ;
+
+    const SESSION_SEED: u64 = 0x5eed;
+
+    const PHYSICS_FRAME_TIME: f32 = 1.0 / (DEFAULT_TICK_RATE_HZ as f32);
+
+    fn assert_partition(pool: &EnemyPool, slots: &[Enemy], expected: &HashMap<u32, Vec2>) {
+        assert_eq!(pool.active_count as usize, expected.len());
+        for enemy in pool.active(slots) {
+            assert_eq!(expected.get(&enemy.id), Some(&enemy.position), "enemy {} moved", enemy.id);
+        }
+        for enemy in &slots[pool.active_count as usize..] {
+            assert_eq!(enemy.position, vec2(-5.0, -5.0));
+        }
+    }
+
+    #[test]
+    fn test_enemy_pool_partition_and_id_stability() {
+        let mut pool = EnemyPool::new();
+        let mut slots = [Enemy::new(-5.0, -5.0); DEFAULT_ENEMY_CAPACITY as usize];
+        let mut expected: HashMap<u32, Vec2> = HashMap::new();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut last_id = None;
+
+        for frame in 0..600u32 {
+            if frame % 3 == 0 && (pool.active_count as usize) < slots.len() {
+                let position = vec2(frame as f32, (frame % 50) as f32);
+                pool.spawn(&mut slots, Enemy::new(position.x, position.y));
+                let spawned = pool.active(&slots)[(pool.active_count - 1) as usize];
+                if let Some(last_id) = last_id {
+                    assert!(spawned.id > last_id);
+                }
+                last_id = Some(spawned.id);
+                expected.insert(spawned.id, position);
+            }
+            if frame % 4 == 0 && pool.active_count > 0 {
+                let idx = rng.gen_range(0..pool.active_count as usize);
+                let killed = pool.active(&slots)[idx].id;
+                pool.remove(&mut slots, idx);
+                expected.remove(&killed);
+            }
+            assert_partition(&pool, &slots, &expected);
+        }
+        assert_eq!(pool.next_id, last_id.unwrap() + 1);
+    }
+
+    #[test]
+    fn test_enemy_pool_remove_last_and_refill() {
+        let mut pool = EnemyPool::new();
+        let mut slots = [Enemy::new(-5.0, -5.0); MAX_ENEMIES];
+        for i in 0..MAX_ENEMIES {
+            pool.spawn(&mut slots, Enemy::new(i as f32, 1.0));
+        }
+        assert_eq!(pool.active_count as usize, MAX_ENEMIES);
+
+        pool.remove(&mut slots, MAX_ENEMIES - 1);
+        pool.remove(&mut slots, 0);
+        assert_eq!(pool.active_count as usize, MAX_ENEMIES - 2);
+        assert_eq!(pool.active(&slots)[0].id, (MAX_ENEMIES - 2) as u32); // last active took the freed slot
+
+        pool.spawn(&mut slots, Enemy::new(100.0, 1.0));
+        assert_eq!(pool.active(&slots).last().unwrap().id, MAX_ENEMIES as u32);
+    }
+
+    type ScriptedInputs = fn(u32) -> [Option<Vec<PlayerInput>>; MAX_PLAYER_COUNT as usize];
+
+    const SCENARIO_FRAMES: u32 = 600;
+
+    fn idle(_frame: u32) -> [Option<Vec<PlayerInput>>; MAX_PLAYER_COUNT as usize] {
+        [None, None]
+    }
+    fn movement_and_shooting(frame: u32) -> [Option<Vec<PlayerInput>>; MAX_PLAYER_COUNT as usize] {
+        let player1 = if (frame / 60).is_multiple_of(2) { PlayerInput::Right } else { PlayerInput::Left };
+        [Some(vec![player1]), Some(vec![PlayerInput::Left, PlayerInput::Shoot])]
+    }
+    fn sweeping_fire(frame: u32) -> [Option<Vec<PlayerInput>>; MAX_PLAYER_COUNT as usize] {
+        let (player1, player2) = if (frame / 240).is_multiple_of(2) {
+            (PlayerInput::Right, PlayerInput::Left)
+        } else {
+            (PlayerInput::Left, PlayerInput::Right)
+        };
+        [Some(vec![player1, PlayerInput::Shoot]), Some(vec![player2, PlayerInput::Shoot])]
+    }
+
+    fn new_simulation() -> (PageAllocator, Simulation) {
+        let mut alloc = simulation_allocator();
+        let sim = Simulation::new(
+            &mut alloc,
+            DEFAULT_ENEMY_CAPACITY,
+            TickRate::default(),
+            GameTuning::default(),
+            SESSION_SEED
+        );
+        (alloc, sim)
+    }
+
+    // runs the frames the script has inputs for and returns how many enemies were shot down on the way
+    fn run_script(
+        sim: &Simulation,
+        alloc: &mut PageAllocator,
+        script: ScriptedInputs,
+        until_frame: u32
+    ) -> usize {
+        let mut kills = 0;
+        let tuning = sim.tuning(alloc);
+        while sim.current_frame(alloc) < until_frame {
+            let before = alloc.read_fixed(&sim.enemies).active(alloc.read_array(&sim.enemy_slots)).to_vec();
+            sim.update(PHYSICS_FRAME_TIME, script(sim.current_frame(alloc) + 1), alloc);
+            let after = alloc.read_fixed(&sim.enemies);
+            let after = after.active(alloc.read_array(&sim.enemy_slots));
+            kills += before
+                .iter()
+                .filter(|enemy| enemy.position.y + tuning.enemy_speed * PHYSICS_FRAME_TIME < ARENA_HEIGHT)
+                .filter(|enemy| !after.iter().any(|e| e.id == enemy.id))
+                .count();
+        }
+        kills
+    }
+
+    // runs the scenario straight through and a second time resumed from a mid-point snapshot
+    fn assert_scenario(script: ScriptedInputs, golden_hash: u64) -> usize {
+        let (mut alloc, sim) = new_simulation();
+        let kills = run_script(&sim, &mut alloc, script, SCENARIO_FRAMES);
+        let hash = sim.state_hash(&alloc);
+        assert_eq!(hash, golden_hash, "state hash changed to {:#018x}", hash);
+
+        let (mut first_half_alloc, first_half_sim) = new_simulation();
+        run_script(&first_half_sim, &mut first_half_alloc, script, SCENARIO_FRAMES / 2);
+        let mut resumed_alloc = simulation_allocator();
+        let resumed_sim = Simulation::new_from_serialized(
+            first_half_sim.serialize(&first_half_alloc),
+            &mut resumed_alloc
+        ).unwrap();
+        run_script(&resumed_sim, &mut resumed_alloc, script, SCENARIO_FRAMES);
+        assert_eq!(resumed_sim.state_hash(&resumed_alloc), golden_hash);
+        kills
+    }
+
+    // goldens only change with an intentional gameplay change, update them together with it
+    #[test]
+    fn test_idle_scenario_golden_hash() {
+        let kills = assert_scenario(idle, 0xf01ed701c1e05baf);
+        assert_eq!(kills, 0);
+    }
+
+    #[test]
+    fn test_movement_and_shooting_scenario_golden_hash() {
+        assert_scenario(movement_and_shooting, 0xbb574c93911d468f);
+    }
+
+    #[test]
+    fn test_enemy_kills_scenario_golden_hash() {
+        let kills = assert_scenario(sweeping_fire, 0xbee3b2120c0010ed);
+        assert!(kills > 0);
+    }
+
+    #[test]
+    fn test_the_window_size_does_not_reach_the_simulation() {
+        let hashes: Vec<u64> = [vec2(640.0, 360.0), vec2(2560.0, 1440.0)]
+            .into_iter()
+            .map(|size| {
+                TEST_WINDOW_SIZE.with(|window| window.set(size));
+                assert_eq!(window_size(), size);
+                let (mut alloc, sim) = new_simulation();
+                run_script(&sim, &mut alloc, sweeping_fire, SCENARIO_FRAMES);
+                sim.state_hash(&alloc)
+            })
+            .collect();
+        assert_eq!(hashes[0], hashes[1]);
+        assert_eq!(hashes[0], 0xbee3b2120c0010ed); // test_enemy_kills_scenario_golden_hash
+    }
+
+    #[test]
+    fn test_snapshot_with_unsupported_enemy_capacity_is_rejected() {
+        let (alloc, sim) = new_simulation();
+        let mut snapshot = sim.serialize(&alloc);
+        snapshot[..4].copy_from_slice(&((MAX_ENEMIES as u32) + 1).to_le_bytes());
+        let mut joiner_alloc = simulation_allocator();
+        assert!(Simulation::new_from_serialized(snapshot, &mut joiner_alloc).is_err());
+        assert!(Simulation::new_from_serialized(vec![20, 0], &mut joiner_alloc).is_err());
+        // nothing was allocated for the rejected snapshots
+        assert!(Simulation::new_from_serialized(sim.serialize(&alloc), &mut joiner_alloc).is_ok());
+    }
+
+    #[test]
+    fn test_snapshot_from_another_world_layout_is_rejected() {
+        let (alloc, sim) = new_simulation();
+        let mut snapshot = sim.serialize(&alloc);
+        snapshot[4..SNAPSHOT_HEADER_LEN].copy_from_slice(&(WORLD_LAYOUT_HASH ^ 1).to_le_bytes());
+        let mut joiner_alloc = simulation_allocator();
+        assert_eq!(
+            Simulation::new_from_serialized(snapshot, &mut joiner_alloc).err(),
+            Some("Snapshot is from an incompatible game version")
+        );
+        assert_eq!(incompatible_hosts_notice(2), "2 hosts run an incompatible game version");
+    }
+
+    #[test]
+    fn test_snapshot_with_more_pages_grows_the_joiner_allocator() {
+        // a host whose allocator started out larger, its snapshot carries the extra pages
+        let mut host_alloc = PageAllocator::new(PAGE_SIZE_BYTES * (SIMULATION_PAGES + 3), PAGE_SIZE_BYTES);
+        let host = Simulation::new(
+            &mut host_alloc,
+            DEFAULT_ENEMY_CAPACITY,
+            TickRate::default(),
+            GameTuning::default(),
+            SESSION_SEED
+        );
+        run_script(&host, &mut host_alloc, sweeping_fire, 120);
+
+        let mut joiner_alloc = simulation_allocator();
+        let joined = Simulation::new_from_serialized(host.serialize(&host_alloc), &mut joiner_alloc).unwrap();
+        assert_eq!(joiner_alloc.get_copy_of_state(), host_alloc.get_copy_of_state());
+        assert_eq!(joined.current_frame(&joiner_alloc), 120);
+        assert_eq!(enemy_array(&joined, &joiner_alloc), enemy_array(&host, &host_alloc));
+    }
+
+    #[test]
+    fn test_snapshot_beyond_the_page_limit_fails_the_join() {
+        let mut host_alloc = PageAllocator::new(PAGE_SIZE_BYTES * (MAX_SIMULATION_PAGES + 1), PAGE_SIZE_BYTES);
+        let host = Simulation::new(
+            &mut host_alloc,
+            DEFAULT_ENEMY_CAPACITY,
+            TickRate::default(),
+            GameTuning::default(),
+            SESSION_SEED
+        );
+        let mut joiner_alloc = simulation_allocator();
+        assert_eq!(
+            Simulation::new_from_serialized(host.serialize(&host_alloc), &mut joiner_alloc).err(),
+            Some("Snapshot needs more pages than this build allows")
+        );
+    }
+
+    fn enemy_array(sim: &Simulation, alloc: &PageAllocator) -> Vec<(u32, Vec2)> {
+        alloc
+            .read_array(&sim.enemy_slots)
+            .iter()
+            .map(|enemy| (enemy.id, enemy.position))
+            .collect()
+    }
+
+    #[test]
+    fn test_rollback_rerun_rebuilds_the_identical_enemy_array() {
+        const CHECKPOINT: u32 = 440;
+        const UNTIL: u32 = 580;
+        let (mut original, sim) = new_simulation();
+        run_script(&sim, &mut original, sweeping_fire, UNTIL);
+
+        // the predicted side guessed idle players, so other enemies die and the slots get compacted differently
+        let (mut predicted, pred_sim) = new_simulation();
+        run_script(&pred_sim, &mut predicted, sweeping_fire, CHECKPOINT);
+        let verified = predicted.get_copy_of_state();
+        run_script(&pred_sim, &mut predicted, idle, UNTIL);
+        assert_ne!(enemy_array(&pred_sim, &predicted), enemy_array(&sim, &original));
+
+        predicted.set_memory(&verified).unwrap();
+        run_script(&pred_sim, &mut predicted, sweeping_fire, UNTIL);
+        assert_eq!(enemy_array(&pred_sim, &predicted), enemy_array(&sim, &original));
+        assert_eq!(predicted.get_copy_of_state(), original.get_copy_of_state());
+    }
+
+    // the rng after every frame up to `until`
+    fn rng_states(sim: &Simulation, alloc: &mut PageAllocator, script: ScriptedInputs, until: u32) -> Vec<u64> {
+        let mut states = Vec::new();
+        while sim.current_frame(alloc) < until {
+            run_script(sim, alloc, script, sim.current_frame(alloc) + 1);
+            states.push(alloc.read_fixed(&sim.rng).state());
+        }
+        states
+    }
+
+    #[test]
+    fn test_the_same_seed_draws_the_same_across_frames_and_rollbacks() {
+        let (mut host_alloc, host) = new_simulation();
+        let host_states = rng_states(&host, &mut host_alloc, sweeping_fire, 600);
+        // enemies spawned, so the rng moved
+        assert!(host_states.windows(2).any(|pair| pair[0] != pair[1]));
+
+        let (mut peer_alloc, peer) = new_simulation();
+        let mut peer_states = rng_states(&peer, &mut peer_alloc, sweeping_fire, 300);
+        let verified = peer_alloc.get_copy_of_state();
+        rng_states(&peer, &mut peer_alloc, idle, 600);
+        peer_alloc.set_memory(&verified).unwrap();
+        peer_states.extend(rng_states(&peer, &mut peer_alloc, sweeping_fire, 600));
+        assert_eq!(peer_states, host_states);
+        assert_eq!(peer.state_hash(&peer_alloc), host.state_hash(&host_alloc));
+
+        let mut other_alloc = simulation_allocator();
+        let other = Simulation::new(
+            &mut other_alloc,
+            DEFAULT_ENEMY_CAPACITY,
+            TickRate::default(),
+            GameTuning::default(),
+            SESSION_SEED + 1
+        );
+        assert_ne!(rng_states(&other, &mut other_alloc, sweeping_fire, 600), host_states);
+    }
+
+    #[test]
+    fn test_prediction_with_a_different_layout_is_rebuilt() {
+        let (mut verif_alloc, verified) = new_simulation();
+        run_script(&verified, &mut verif_alloc, sweeping_fire, 300);
+
+        // the prediction was set up with a smaller enemy array, its pages don't line up
+        let mut pred_alloc = simulation_allocator();
+        let mut predicted = Simulation::new(
+            &mut pred_alloc,
+            8,
+            TickRate::default(),
+            GameTuning::default(),
+            SESSION_SEED
+        );
+        assert_ne!(pred_alloc.fingerprint(), verif_alloc.fingerprint());
+
+        restore_prediction(&verified, &verif_alloc, &mut predicted, &mut pred_alloc);
+        assert_eq!(pred_alloc.fingerprint(), verif_alloc.fingerprint());
+        assert_eq!(predicted.enemy_slots.len(), DEFAULT_ENEMY_CAPACITY as usize);
+        assert_eq!(predicted.state_hash(&pred_alloc), verified.state_hash(&verif_alloc));
+        run_script(&verified, &mut verif_alloc, sweeping_fire, 400);
+        run_script(&predicted, &mut pred_alloc, sweeping_fire, 400);
+        assert_eq!(pred_alloc.get_copy_of_state(), verif_alloc.get_copy_of_state());
+
+        // matching layouts are copied in place
+        let fingerprint = pred_alloc.fingerprint();
+        run_script(&predicted, &mut pred_alloc, idle, 450);
+        restore_prediction(&verified, &verif_alloc, &mut predicted, &mut pred_alloc);
+        assert_eq!(pred_alloc.fingerprint(), fingerprint);
+        assert_eq!(pred_alloc.get_copy_of_state(), verif_alloc.get_copy_of_state());
+    }
+
+    #[test]
+    fn test_wrongly_guessed_remote_input_is_reported_as_misprediction() {
+        let (mut verif_alloc, verified) = new_simulation();
+        let (mut pred_alloc, mut predicted) = new_simulation();
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        let mut check = PredictionCheck::default();
+        for frame in 1..=10 {
+            input_buffer.insert_curr_player_inp(Vec::new(), frame);
+        }
+        // nothing verified yet, the prediction leaves the other player standing
+        step_predicted_frames(&input_buffer, &predicted, &mut pred_alloc, &mut check);
+
+        // while they were moving right all along
+        for frame in 1..=10 {
+            input_buffer.insert_remote_player_inp(PlayerID::Player2, vec![PlayerInput::Right], frame);
+        }
+        let mut mispredictions = Vec::new();
+        while let Some(frame) = step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc).unwrap() {
+            mispredictions.extend(check.check_verified(frame, &verified.digest(&verif_alloc)));
+            // rolling back picks up their last input, the frames after that are guessed right
+            restore_prediction(&verified, &verif_alloc, &mut predicted, &mut pred_alloc);
+            step_predicted_frames(&input_buffer, &predicted, &mut pred_alloc, &mut check);
+        }
+        let step = GameTuning::default().player_speed * PHYSICS_FRAME_TIME;
+        assert_eq!(mispredictions.len(), 1, "{:?}", mispredictions);
+        assert_eq!(mispredictions[0].frame, VerifiedFrame(1));
+        assert!((mispredictions[0].player_offset - step).abs() < 1e-3, "{}", mispredictions[0]);
+        assert_eq!(mispredictions[0].enemy_count_diff, 0);
+        assert_eq!((check.checked, check.mispredicted), (10, 1));
+    }
+
+    fn input_frame(frame: u32) -> PlayerInputs {
+        PlayerInputs { inputs: [Some(vec![PlayerInput::Right]), None], frame, filled: [false; 2] }
+    }
+
+    #[test]
+    fn test_join_sets_the_baseline_before_routing_held_inputs() {
+        let (mut verif_alloc, verified) = new_simulation();
+        let mut input_buffer = InputBuffer::new();
+        for frame in 1..=10 {
+            input_buffer.insert_curr_player_inp(Vec::new(), frame);
+            step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc).unwrap();
+        }
+        // the world the host sent is at frame 10, inputs around it were held back until our slot was known
+        let snapshot_frame = verified.current_frame(&verif_alloc);
+        assert_eq!(snapshot_frame, 10);
+        let held = (snapshot_frame - 2..=snapshot_frame + 2)
+            .map(|frame| (PlayerID::Player1, NetworkedPlayerInput::new(vec![PlayerInput::Left], frame)))
+            .collect();
+        let mut input_buffer = InputBuffer::new();
+        let mut roster = SessionRoster::default();
+        roster.on_slot_assigned(PlayerID::Player2);
+        roster.on_player_joined(types::ServerPlayerID(0));
+        route_join_inputs(&mut input_buffer, &roster, VerifiedFrame(snapshot_frame), held);
+        assert_eq!(
+            input_buffer.input_frames.iter().map(|f| f.frame).collect::<Vec<u32>>(),
+            vec![snapshot_frame + 1, snapshot_frame + 2]
+        );
+        // the first frame the joiner plays is the one right after the world it was given
+        input_buffer.insert_curr_player_inp(Vec::new(), snapshot_frame + 1);
+        assert_eq!(
+            step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc),
+            Ok(Some(VerifiedFrame(snapshot_frame + 1)))
+        );
+    }
+
+    #[test]
+    fn test_the_sent_and_the_inserted_local_input_share_a_frame() {
+        let (mut verif_alloc, verified) = new_simulation();
+        let (mut pred_alloc, predicted) = new_simulation();
+        *verif_alloc.mut_read_fixed(&verified.frame) = 4;
+        *pred_alloc.mut_read_fixed(&predicted.frame) = 7;
+        let solo = SessionRoster::default();
+        let mut pair = SessionRoster::default();
+        pair.on_player_joined(types::ServerPlayerID(1));
+        for (roster, expected) in [(solo, 5), (pair, 8)] {
+            let frame = local_input_frame(&roster, &predicted, &pred_alloc, &verified, &verif_alloc);
+            assert_eq!(frame, expected);
+            let mut input_buffer = InputBuffer::new();
+            let mut sent = None;
+            submit_local_input(&mut input_buffer, vec![PlayerInput::Shoot], frame, |input| {
+                sent = Some(input);
+                Ok::<(), ()>(())
+            }).unwrap();
+            let inserted = input_buffer.input_frames.back().unwrap();
+            assert_eq!(sent.map(|input| input.frame), Some(inserted.frame));
+            assert_eq!(inserted.inputs[roster.local_slot() as usize], Some(vec![PlayerInput::Shoot]));
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Sent {
+        Input(u32),
+        Resync,
+    }
+
+    // what a session at 60 Hz sends over a second of 144 fps render frames that follows a `stall`
+    fn sent_after_a_stall(stall: f32, joined: bool) -> Vec<Sent> {
+        let mut driver = session_step_driver(TickRate::default(), DEFAULT_MAX_CATCH_UP_FRAMES);
+        let mut input_buffer = InputBuffer::new();
+        let mut sent = Vec::new();
+        let mut frame = 0;
+        for render_frame in 0..=144 {
+            let dt = if render_frame == 0 { stall } else { 1.0 / 144.0 };
+            let (stepping, _) = advance_step_driver(&mut driver, dt, joined, |msg| {
+                assert!(matches!(msg, NetworkMessage::ClientRequestResync), "{:?}", msg);
+                sent.push(Sent::Resync);
+                Ok::<(), ()>(())
+            }).unwrap();
+            if stepping {
+                frame += 1;
+                submit_local_input(&mut input_buffer, Vec::new(), frame, |input| {
+                    sent.push(Sent::Input(input.frame));
+                    Ok::<(), ()>(())
+                }).unwrap();
+            }
+        }
+        sent
+    }
+
+    #[test]
+    fn test_a_clamped_catch_up_asks_for_the_world_instead_of_sending_a_burst_of_inputs() {
+        for stall in [10.0, 100.0] {
+            let sent = sent_after_a_stall(stall, true);
+            // the resync goes out before the first input of the render frame that ended the stall
+            assert_eq!(sent[0], Sent::Resync, "{} s stall", stall);
+            assert_eq!(sent.iter().filter(|sent| **sent == Sent::Resync).count(), 1);
+            let inputs = sent.len() - 1;
+            // a second's worth plus the capped catch up, whatever the stall
+            let max_inputs = (TickRate::default().hz() + DEFAULT_MAX_CATCH_UP_FRAMES + 1) as usize;
+            assert!(inputs <= max_inputs, "{} inputs after a {} s stall", inputs, stall);
+        }
+
+        // a host has nobody to ask, it only drops the frames
+        let host = sent_after_a_stall(10.0, false);
+        assert!(!host.contains(&Sent::Resync));
+        assert!(host.len() <= (TickRate::default().hz() + DEFAULT_MAX_CATCH_UP_FRAMES + 1) as usize);
+    }
+
+    #[test]
+    fn test_current_frame_reads_the_frame_pointer() {
+        let (mut alloc, sim) = new_simulation();
+        assert_eq!(sim.current_frame(&alloc), 0);
+        *alloc.mut_read_fixed(&sim.frame) = 42;
+        assert_eq!(sim.current_frame(&alloc), 42);
+        assert_eq!(sim.predicted_frame(&alloc).frames_past(VerifiedFrame(40)), 2);
+        assert_eq!(sim.predicted_frame(&alloc).frames_past(sim.verified_frame(&alloc)), 0);
+    }
+
+    #[test]
+    fn test_stale_verified_frame_is_dropped_and_stepping_continues() {
+        let (mut verif_alloc, verified) = new_simulation();
+        let mut input_buffer = InputBuffer::new();
+        for frame in 1..=5 {
+            input_buffer.insert_curr_player_inp(vec![PlayerInput::Right], frame);
+        }
+        for frame in 1..=3 {
+            assert_eq!(
+                step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc),
+                Ok(Some(VerifiedFrame(frame)))
+            );
+        }
+        // a frame the simulation already played turns up in front again
+        input_buffer.input_frames.push_front(input_frame(2));
+        assert_eq!(step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc), Ok(Some(VerifiedFrame(4))));
+        assert_eq!(verified.current_frame(&verif_alloc), 4);
+        assert_eq!(step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc), Ok(Some(VerifiedFrame(5))));
+        assert_eq!(step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc), Ok(None));
+    }
+
+    #[test]
+    fn test_frame_gap_waits_and_asks_for_a_resync_once_it_lasts() {
+        let (mut verif_alloc, verified) = new_simulation();
+        let (mut pred_alloc, predicted) = new_simulation();
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.input_frames.push_back(input_frame(3));
+        let gap = FrameGap { sim_frame: VerifiedFrame(0), input_frame: 3 };
+        assert_eq!(step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc), Err(gap));
+        // nothing was applied to the wrong frame
+        assert_eq!(verified.current_frame(&verif_alloc), 0);
+        assert_eq!(input_buffer.input_frames.len(), 1);
+        let mut check = PredictionCheck::default();
+        assert_eq!(step_predicted_frames(&input_buffer, &predicted, &mut pred_alloc, &mut check), 0);
+        assert_eq!(predicted.current_frame(&pred_alloc), 0);
+
+        let start = Instant::now();
+        let mut watch = GapWatch::default();
+        assert!(!watch.is_stuck(start, gap));
+        assert!(!watch.is_stuck(start + RESYNC_AFTER / 2, gap));
+        assert!(watch.is_stuck(start + RESYNC_AFTER, gap));
+        assert!(!watch.is_stuck(start + RESYNC_AFTER, gap));
+        assert!(watch.is_stuck(start + RESYNC_AFTER * 2, gap));
+
+        // the missing frames turn up late, stepping goes on from there
+        input_buffer.insert_curr_player_inp(vec![PlayerInput::Right], 1);
+        input_buffer.insert_curr_player_inp(vec![PlayerInput::Right], 2);
+        for frame in 1..=3 {
+            assert_eq!(
+                step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc),
+                Ok(Some(VerifiedFrame(frame)))
+            );
+        }
+    }
+
+    #[test]
+    fn test_stall_on_a_removed_remote_frame_is_recovered_by_re_requesting_it() {
+        let (mut verif_alloc, verified) = new_simulation();
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        // the peer played frames 1 to 10, the datagram with frame 5 never arrived
+        let mut peer_journal = InputJournal::default();
+        for frame in 1..=10 {
+            input_buffer.insert_curr_player_inp(vec![PlayerInput::Right], frame);
+            peer_journal.record(frame, &[PlayerInput::Left]);
+            if frame != 5 {
+                input_buffer.insert_remote_player_inp(PlayerID::Player2, vec![PlayerInput::Left], frame);
+            }
+        }
+        let mut watch = MissingInputWatch::default();
+        let mut requested = None;
+        for _ in 0..REQUEST_INPUTS_AFTER_TICKS {
+            while step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc).unwrap().is_some() {}
+            assert_eq!(verified.current_frame(&verif_alloc), 4);
+            assert!(requested.is_none(), "asked again before the stall lasted");
+            requested = watch.on_stalled_tick(input_buffer.missing_remote_frames());
+        }
+        let Some((from_frame, to_frame)) = requested else {
+            panic!("the stall never asked for the missing frame");
+        };
+        assert_eq!((from_frame, to_frame), (5, 5));
+
+        // the peer answers the forwarded request from what it played
+        for (frame, inputs) in peer_journal.frames_in(from_frame, to_frame) {
+            input_buffer.insert_remote_player_inp(PlayerID::Player2, inputs.clone(), *frame);
+        }
+        while step_verified_frame(&mut input_buffer, &verified, &mut verif_alloc).unwrap().is_some() {}
+        assert_eq!(verified.current_frame(&verif_alloc), 10);
+        assert!(input_buffer.missing_remote_frames().is_empty());
+    }
+
+    #[test]
+    fn test_missing_frames_that_keep_changing_are_not_requested() {
+        let mut watch = MissingInputWatch::default();
+        for tick in 0..REQUEST_INPUTS_AFTER_TICKS * 3 {
+            // still arriving, every tick a different frame is the missing one
+            assert_eq!(watch.on_stalled_tick(vec![tick + 1]), None);
+        }
+        let too_many: Vec<u32> = (1..=(MAX_REQUESTED_FRAMES as u32) + 1).collect();
+        for _ in 0..REQUEST_INPUTS_AFTER_TICKS * 3 {
+            assert_eq!(watch.on_stalled_tick(too_many.clone()), None);
+        }
+    }
+
+    fn config_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("unlockrs-{}-{}.cfg", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_client_config_round_trips_every_field() {
+        let config = ClientConfig {
+            server_addr: "10.0.0.7:9000".to_string(),
+            tuning_file: "fast.toml".to_string(),
+            render_smoothing: true,
+            connect_attempts: 2,
+            connect_backoff_ms: 50,
+            compact_held_inputs: false,
+            message_history: 64,
+            max_catch_up_frames: 12,
+            structured_world: true,
+            log: LogConfig {
+                connection: true,
+                world_state: true,
+                player_input: true,
+                message_handling: false,
+                ack: true,
+                error: true,
+                debug: true,
+            },
+        };
+        let path = config_path("round-trip");
+        config::save(&config, &path).unwrap();
+        assert_eq!(config::load::<ClientConfig>(&path, &[]), Ok(config));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_client_config_skips_unknown_keys_but_not_unknown_flags() {
+        let path = config_path("unknown-keys");
+        std::fs::write(&path, "player_name = ada # not ours\nserver_addr = 10.0.0.7:9000\n").unwrap();
+        let config = config::load::<ClientConfig>(&path, &[]).unwrap();
+        assert_eq!(config.server_addr, "10.0.0.7:9000");
+        assert!(config::load::<ClientConfig>(&path, &["--player_name=ada".to_string()]).is_err());
+        assert!(config::load::<ClientConfig>(&path, &["--render_smoothing".to_string()]).is_err());
+        std::fs::write(&path, "render_smoothing = maybe\n").unwrap();
+        assert!(config::load::<ClientConfig>(&path, &[]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_flags_override_the_file_and_reset_skips_it() {
+        let path = config_path("precedence");
+        std::fs::write(&path, "server_addr = 10.0.0.7:9000\ntuning_file = fast.toml\n").unwrap();
+        let args = ["--server_addr=10.0.0.8:9000".to_string()];
+        let config = config::load::<ClientConfig>(&path, &args).unwrap();
+        assert_eq!(config.server_addr, "10.0.0.8:9000");
+        assert_eq!(config.tuning_file, "fast.toml");
+
+        let args = [config::RESET_CONFIG_FLAG.to_string(), "--log_debug=true".to_string()];
+        let config = config::load::<ClientConfig>(&path, &args).unwrap();
+        assert_eq!(config, ClientConfig {
+            log: LogConfig { debug: true, ..LogConfig::default() },
+            ..ClientConfig::default()
+        });
+        std::fs::remove_file(&path).unwrap();
+        // no file yet is the first launch
+        assert_eq!(config::load::<ClientConfig>(&path, &[]), Ok(ClientConfig::default()));
+    }
+
+    #[test]
+    fn test_snapshot_with_unsupported_tick_rate_is_rejected() {
+        let (mut alloc, sim) = new_simulation();
+        // TickRate::new refuses it, the bytes of a TickRate are its rate
+        alloc.write_fixed_to_memory(&sim.tick_rate, &45u32);
+        let mut joiner_alloc = simulation_allocator();
+        assert_eq!(
+            Simulation::new_from_serialized(sim.serialize(&alloc), &mut joiner_alloc).err(),
+            Some("Snapshot tick rate is not supported by this build")
+        );
+    }
+
+    #[test]
+    fn test_30hz_session_advances_at_its_own_cadence() {
+        let tick_rate = TickRate::new(30).unwrap();
+        let mut host_alloc = simulation_allocator();
+        let host = Simulation::new(
+            &mut host_alloc,
+            DEFAULT_ENEMY_CAPACITY,
+            tick_rate,
+            GameTuning::default(),
+            SESSION_SEED
+        );
+        let mut alloc = simulation_allocator();
+        let sim = Simulation::new_from_serialized(host.serialize(&host_alloc), &mut alloc).unwrap();
+        assert_eq!(sim.tick_rate(&alloc), tick_rate);
+
+        let mut driver = session_step_driver(sim.tick_rate(&alloc), DEFAULT_MAX_CATCH_UP_FRAMES);
+        let mut input_buffer = InputBuffer::new();
+        let mut input_frames = Vec::new();
+        // two seconds of 60 fps render frames, a hair longer so float rounding can't drop a step
+        let render_dt = (1.0 / 60.0) * 1.001;
+        for render_frame in 1..=120 {
+            if driver.advance(render_dt) {
+                let frame = sim.current_frame(&alloc) + 1;
+                input_buffer.insert_curr_player_inp(Vec::new(), frame);
+                input_frames.push(frame);
+            }
+            while let Some(verified) = input_buffer.pop_next_verified_frame() {
+                sim.update(sim.tick_rate(&alloc).frame_time(), verified.inputs, &mut alloc);
+            }
+            // one physics step every other render frame
+            assert_eq!(sim.current_frame(&alloc), render_frame / 2);
+        }
+        assert_eq!(input_frames, (1..=tick_rate.hz() * 2).collect::<Vec<u32>>());
+        assert_eq!(tick_rate.frames_in(INPUT_PRUNE_INTERVAL), 300);
+    }
+
+    #[test]
+    fn test_enemies_spawn_after_the_same_time_at_every_tick_rate() {
+        let tuning = GameTuning { enemy_spawn_interval: 0.5, ..GameTuning::default() };
+        for hz in [30, 60] {
+            let tick_rate = TickRate::new(hz).unwrap();
+            let mut alloc = simulation_allocator();
+            let sim = Simulation::new(&mut alloc, DEFAULT_ENEMY_CAPACITY, tick_rate, tuning, SESSION_SEED);
+            let mut spawn_frames = Vec::new();
+            while sim.current_frame(&alloc) < 2 * hz {
+                let before = alloc.read_fixed(&sim.enemies).next_id;
+                sim.update(tick_rate.frame_time(), idle(0), &mut alloc);
+                if alloc.read_fixed(&sim.enemies).next_id != before {
+                    spawn_frames.push(sim.current_frame(&alloc));
+                }
+            }
+            // one every half second from the first step on, read after the step counted the frame up
+            let interval = hz / 2;
+            assert_eq!(spawn_frames, (0..4).map(|i| i * interval + 1).collect::<Vec<u32>>(), "{} Hz", hz);
+        }
+    }
+
+    #[test]
+    fn test_host_snapshot_replaces_a_verified_state_that_is_behind() {
+        let mut host_alloc = simulation_allocator();
+        let host = Simulation::new(
+            &mut host_alloc,
+            DEFAULT_ENEMY_CAPACITY,
+            TickRate::default(),
+            GameTuning::default(),
+            SESSION_SEED
+        );
+        let mut joiner_alloc = simulation_allocator();
+        let mut joiner = world_format(false)
+            .deserialize(world_format(false).serialize(&host, &host_alloc), &mut joiner_alloc)
+            .unwrap();
+        run_script(&host, &mut host_alloc, sweeping_fire, 120);
+        run_script(&joiner, &mut joiner_alloc, sweeping_fire, 100);
+        let snapshot = world_format(false).serialize(&host, &host_alloc);
+
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(100));
+        for frame in 101..=125 {
+            input_buffer.insert_curr_player_inp(Vec::new(), frame);
+        }
+        let applied = apply_host_snapshot(
+            &world_format(false),
+            snapshot.clone(),
+            &mut joiner,
+            &mut joiner_alloc,
+            &mut input_buffer,
+            &mut Some(VerifiedFrame(0)) // the world the joiner started from
+        );
+        assert_eq!(applied, Ok(Some(VerifiedFrame(120))));
+        assert_eq!(joiner.state_hash(&joiner_alloc), host.state_hash(&host_alloc));
+        assert_eq!(input_buffer.input_frames.front().map(|inputs| inputs.frame), Some(121));
+        // a late input for a frame the snapshot covered is not played again
+        input_buffer.insert_remote_player_inp(PlayerID::Player2, Vec::new(), 110);
+        assert_eq!(input_buffer.input_frames.front().map(|inputs| inputs.frame), Some(121));
+
+        // once we are past it the snapshot is stale, even one we never applied
+        run_script(&joiner, &mut joiner_alloc, sweeping_fire, 125);
+        let ahead = joiner.state_hash(&joiner_alloc);
+        let applied = apply_host_snapshot(
+            &world_format(false),
+            snapshot,
+            &mut joiner,
+            &mut joiner_alloc,
+            &mut input_buffer,
+            &mut Some(VerifiedFrame(0))
+        );
+        assert_eq!(applied, Ok(None));
+        assert_eq!(joiner.state_hash(&joiner_alloc), ahead);
+    }
+
+    #[test]
+    fn test_same_tuning_moves_enemies_and_bullets_identically() {
+        let tuning = GameTuning { bullet_speed: 900.0, enemy_speed: 250.0, ..GameTuning::default() };
+        let [(mut a_alloc, a), (mut b_alloc, b)] = [(); 2].map(|_| {
+            let mut alloc = simulation_allocator();
+            let sim = Simulation::new(
+                &mut alloc,
+                DEFAULT_ENEMY_CAPACITY,
+                TickRate::default(),
+                tuning,
+                SESSION_SEED
+            );
+            (alloc, sim)
+        });
+        let positions = |sim: &Simulation, alloc: &PageAllocator| {
+            let enemies: Vec<(u32, Vec2)> = alloc
+                .read_fixed(&sim.enemies)
+                .active(alloc.read_array(&sim.enemy_slots))
+                .iter()
+                .map(|enemy| (enemy.id, enemy.position))
+                .collect();
+            let bullets: Vec<Vec2> = [alloc.read_fixed(&sim.player1), alloc.read_fixed(&sim.player2)]
+                .iter()
+                .flat_map(|player| player.bullets.map(|bullet| bullet.position))
+                .collect();
+            (enemies, bullets)
+        };
+        let mut moved_enemies = 0;
+        for frame in 1..=240 {
+            let (enemies_before, _) = positions(&a, &a_alloc);
+            a.update(PHYSICS_FRAME_TIME, sweeping_fire(frame), &mut a_alloc);
+            b.update(PHYSICS_FRAME_TIME, sweeping_fire(frame), &mut b_alloc);
+            let (enemies, bullets) = positions(&a, &a_alloc);
+            let (b_enemies, b_bullets) = positions(&b, &b_alloc);
+            assert_eq!((&enemies, &bullets), (&b_enemies, &b_bullets));
+            // an enemy that is still there fell by the tuned speed
+            for (id, before) in enemies_before {
+                if let Some((_, after)) = enemies.iter().find(|(other, _)| *other == id) {
+                    assert_eq!(after.y, before.y + tuning.enemy_speed * PHYSICS_FRAME_TIME);
+                    moved_enemies += 1;
+                }
+            }
+        }
+        assert!(moved_enemies > 0);
+    }
+
+    #[test]
+    fn test_host_snapshot_with_another_tuning_is_rejected() {
+        let (mut host_alloc, host) = new_simulation();
+        let (mut joiner_alloc, mut joiner) = new_simulation();
+        run_script(&host, &mut host_alloc, sweeping_fire, 20);
+        *host_alloc.mut_read_fixed(&host.tuning) = GameTuning { bullet_speed: 900.0, ..GameTuning::default() };
+        let before = joiner.state_hash(&joiner_alloc);
+        let applied = apply_host_snapshot(
+            &world_format(false),
+            world_format(false).serialize(&host, &host_alloc),
+            &mut joiner,
+            &mut joiner_alloc,
+            &mut InputBuffer::new(),
+            &mut None
+        );
+        assert_eq!(applied, Err("Snapshot was taken with another tuning than the session's"));
+        assert_eq!(joiner.state_hash(&joiner_alloc), before);
+    }
+
+    #[test]
+    fn test_host_world_delivered_twice_is_applied_once() {
+        let (mut host_alloc, host) = new_simulation();
+        let mut joiner_alloc = simulation_allocator();
+        let mut joiner = world_format(false)
+            .deserialize(world_format(false).serialize(&host, &host_alloc), &mut joiner_alloc)
+            .unwrap();
+        let mut applied_world_frame = Some(VerifiedFrame(0));
+        // the joiner went its own way, the host's world of the same frame sets it right
+        run_script(&host, &mut host_alloc, sweeping_fire, 60);
+        run_script(&joiner, &mut joiner_alloc, idle, 60);
+        let snapshot = world_format(false).serialize(&host, &host_alloc);
+        let mut input_buffer = InputBuffer::new();
+        input_buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(60));
+        let mut deliver = |joiner: &mut Simulation, joiner_alloc: &mut PageAllocator| {
+            apply_host_snapshot(
+                &world_format(false),
+                snapshot.clone(),
+                joiner,
+                joiner_alloc,
+                &mut input_buffer,
+                &mut applied_world_frame
+            )
+        };
+        assert_eq!(deliver(&mut joiner, &mut joiner_alloc), Ok(Some(VerifiedFrame(60))));
+        assert_eq!(joiner.state_hash(&joiner_alloc), host.state_hash(&host_alloc));
+        // its chunks were resent and reassembled again, right away or after we played on from it
+        assert_eq!(deliver(&mut joiner, &mut joiner_alloc), Ok(None));
+
+        joiner.update(PHYSICS_FRAME_TIME, sweeping_fire(61), &mut joiner_alloc);
+        let played_on = joiner.state_hash(&joiner_alloc);
+        assert_eq!(deliver(&mut joiner, &mut joiner_alloc), Ok(None));
+        assert_eq!(joiner.current_frame(&joiner_alloc), 61);
+        assert_eq!(joiner.state_hash(&joiner_alloc), played_on);
+    }
+
+    #[test]
+    fn test_joiners_play_with_the_hosts_tuning() {
+        let host_tuning = HostSettings::parse(
+            "player_speed = 300\nreload_time = 0.2\nenemy_spawn_interval = 0.5"
+        ).unwrap().tuning;
+        let mut host_alloc = simulation_allocator();
+        let host = Simulation::new(
+            &mut host_alloc,
+            DEFAULT_ENEMY_CAPACITY,
+            TickRate::default(),
+            host_tuning,
+            SESSION_SEED
+        );
+        let snapshot = world_format(false).serialize(&host, &host_alloc);
+
+        // both joiners have a tuning file of their own, joining never looks at it
+        let joiner_files = ["player_speed = 50\nbullet_speed = 900", "enemy_speed = 400"];
+        let mut joiners: Vec<(PageAllocator, Simulation)> = joiner_files
+            .iter()
+            .map(|file| {
+                assert_ne!(HostSettings::parse(file).unwrap().tuning, host_tuning);
+                let mut alloc = simulation_allocator();
+                let sim = world_format(false).deserialize(snapshot.clone(), &mut alloc).unwrap();
+                assert_eq!(sim.tuning(&alloc), host_tuning);
+                (alloc, sim)
+            })
+            .collect();
+        for (alloc, sim) in joiners.iter_mut() {
+            run_script(sim, alloc, sweeping_fire, SCENARIO_FRAMES);
+        }
+        run_script(&host, &mut host_alloc, sweeping_fire, SCENARIO_FRAMES);
+        for (alloc, sim) in &joiners {
+            assert_eq!(sim.state_hash(alloc), host.state_hash(&host_alloc));
+        }
+
+        // a joiner that had used its own file would have drifted away
+        let mut local_alloc = simulation_allocator();
+        let local = Simulation::new(
+            &mut local_alloc,
+            DEFAULT_ENEMY_CAPACITY,
+            TickRate::default(),
+            HostSettings::parse(joiner_files[0]).unwrap().tuning,
+            SESSION_SEED
+        );
+        run_script(&local, &mut local_alloc, sweeping_fire, SCENARIO_FRAMES);
+        assert_ne!(local.state_hash(&local_alloc), host.state_hash(&host_alloc));
+    }
+
+    #[test]
+    fn test_forty_enemy_session_survives_snapshot_restore() {
+        let mut alloc = simulation_allocator();
+        let sim = Simulation::new(&mut alloc, 40, TickRate::default(), GameTuning::default(), SESSION_SEED);
+        let mut pool = alloc.read_fixed(&sim.enemies);
+        let slots = alloc.mut_read_array(&sim.enemy_slots);
+        for i in 0..40 {
+            pool.spawn(slots, Enemy::new(40.0 + 18.0 * (i as f32), 10.0));
+        }
+        alloc.write_fixed_to_memory(&sim.enemies, &pool);
+        run_script(&sim, &mut alloc, idle, 120); // frame 120 spawns, the pool is full though
+
+        let mut joiner_alloc = simulation_allocator();
+        let joined = Simulation::new_from_serialized(sim.serialize(&alloc), &mut joiner_alloc).unwrap();
+        assert_eq!(joined.enemy_slots.len(), 40);
+        assert_eq!(joiner_alloc.read_fixed(&joined.enemies).active_count, 40);
+        assert_eq!(joined.state_hash(&joiner_alloc), sim.state_hash(&alloc));
+        run_script(&sim, &mut alloc, idle, 200);
+        run_script(&joined, &mut joiner_alloc, idle, 200);
+        assert_eq!(joined.state_hash(&joiner_alloc), sim.state_hash(&alloc));
+    }
+
+    // none of this may depend on the window, peers with different window sizes have to despawn at the same place
+    #[test]
+    fn test_held_shoot_key_is_sampled_every_frame() {
+        // per frame: which keys are down
+        let frames: [&[KeyCode]; 6] = [
+            &[],
+            &[KeyCode::W],
+            &[KeyCode::W, KeyCode::Left],
+            &[KeyCode::Up],
+            &[KeyCode::Right],
+            &[KeyCode::A, KeyCode::D, KeyCode::W],
+        ];
+        let sampled: Vec<Vec<PlayerInput>> = frames
+            .iter()
+            .map(|down| sample_player_inputs(|key| down.contains(&key)))
+            .collect();
+        assert_eq!(sampled, vec![
+            vec![],
+            vec![PlayerInput::Shoot],
+            vec![PlayerInput::Left, PlayerInput::Shoot],
+            vec![PlayerInput::Shoot],
+            vec![PlayerInput::Right],
+            vec![PlayerInput::Left, PlayerInput::Right, PlayerInput::Shoot]
+        ]);
+
+        // holding shoot keeps firing, limited only by the reload
+        let tuning = GameTuning::default();
+        let mut player = Player::new(100.0, BLUE);
+        let mut shots = 0;
+        for _ in 0..120 {
+            player.shoot_input = u8::from(
+                sample_player_inputs(|key| key == KeyCode::W).contains(&PlayerInput::Shoot)
+            );
+            let reload_before = player.curr_reload_time;
+            player.update(PHYSICS_FRAME_TIME, &tuning);
+            if player.curr_reload_time < reload_before {
+                shots += 1;
+            }
+        }
+        let expected = (120.0 * PHYSICS_FRAME_TIME / tuning.reload_time).floor() as i32;
+        assert!((shots - expected).abs() <= 1, "{} shots, expected about {}", shots, expected);
+    }
+
+    #[test]
+    fn test_left_and_right_add_up_in_any_order() {
+        let (mut alloc, sim) = new_simulation();
+        for held in 0..8u8 {
+            let (left, right, shoot) = (held & 1 != 0, held & 2 != 0, held & 4 != 0);
+            let mut inputs: Vec<PlayerInput> = [
+                (left, PlayerInput::Left),
+                (right, PlayerInput::Right),
+                (shoot, PlayerInput::Shoot),
+            ]
+                .into_iter()
+                .filter_map(|(is_held, input)| is_held.then_some(input))
+                .collect();
+            let expected = match (left, right) {
+                (true, false) => -1.0,
+                (false, true) => 1.0,
+                _ => 0.0, // both cancel out, neither stands still
+            };
+            for _ in 0..2 {
+                sim.handle_player_input(PlayerID::Player1, &inputs, &mut alloc);
+                let player = alloc.read_fixed(&sim.player1);
+                assert_eq!(player.movement_input, expected, "{:?}", inputs);
+                assert_eq!(player.shoot_input, u8::from(shoot), "{:?}", inputs);
+                inputs.reverse();
+            }
+        }
+    }
+
+    #[test]
+    fn test_releasing_the_keys_stops_the_player() {
+        // Right is held for ten frames, then let go
+        fn press_then_release(frame: u32) -> [Option<Vec<PlayerInput>>; MAX_PLAYER_COUNT as usize] {
+            [Some(if frame <= 10 { vec![PlayerInput::Right] } else { Vec::new() }), None]
+        }
+        let (mut alloc, sim) = new_simulation();
+        run_script(&sim, &mut alloc, press_then_release, 5);
+        let moving = alloc.read_fixed(&sim.player1).position.x;
+        run_script(&sim, &mut alloc, press_then_release, 10);
+        let released = alloc.read_fixed(&sim.player1).position.x;
+        assert!(released > moving);
+        run_script(&sim, &mut alloc, press_then_release, 60);
+        assert_eq!(alloc.read_fixed(&sim.player1).position.x, released);
+        assert_eq!(alloc.read_fixed(&sim.player1).movement_input, 0.0);
+    }
+
+    #[test]
+    fn test_any_shoot_byte_a_snapshot_brings_is_a_valid_flag() {
+        let (mut alloc, sim) = new_simulation();
+        let idle = alloc.get_copy_of_state();
+        alloc.mut_read_fixed(&sim.player1).shoot_input = 1;
+        let shooting = alloc.get_copy_of_state();
+        let flag = (0..idle.len()).find(|&i| idle[i] != shooting[i]).unwrap();
+        let expected = {
+            sim.update(PHYSICS_FRAME_TIME, [None, None], &mut alloc);
+            sim.state_hash(&alloc)
+        };
+        // a byte no build of ours writes, as a peer or a corrupted transfer may send it
+        let mut odd = shooting;
+        odd[flag] = 0xa5;
+        alloc.set_memory(&odd).unwrap();
+        assert_eq!(alloc.read_fixed(&sim.player1).shoot_input, 0xa5);
+        sim.update(PHYSICS_FRAME_TIME, [None, None], &mut alloc);
+        assert_eq!(sim.state_hash(&alloc), expected);
+    }
+
+    #[test]
+    fn test_despawn_thresholds_use_the_arena() {
+        let tuning = GameTuning::default();
+        let mut enemy = Enemy::new(100.0, ARENA_HEIGHT - 2.0);
+        enemy.update(PHYSICS_FRAME_TIME, &tuning);
+        assert!(enemy.is_active(), "enemy at {} despawned early", enemy.position.y);
+        enemy.update(PHYSICS_FRAME_TIME, &tuning);
+        assert!(!enemy.is_active());
+
+        let mut player = Player::new(100.0, BLUE);
+        assert_eq!(player.position.y, ARENA_HEIGHT - 50.0);
+        player.shoot_input = 1;
+        player.curr_reload_time = tuning.reload_time + 1.0;
+        player.update(PHYSICS_FRAME_TIME, &tuning);
+        player.shoot_input = 0;
+        let mut frames_in_flight = 0;
+        while player.bullets.iter().any(|b| b.position.y > 0.0 && b.position.y < ARENA_HEIGHT) {
+            player.update(PHYSICS_FRAME_TIME, &tuning);
+            frames_in_flight += 1;
+        }
+        // bullet speed from ARENA_HEIGHT - 50 up to the top edge
+        let expected_frames = ((ARENA_HEIGHT - 50.0) / (tuning.bullet_speed * PHYSICS_FRAME_TIME)).ceil() as i32;
+        assert!((frames_in_flight - expected_frames).abs() <= 1, "{} frames", frames_in_flight);
+
+        player.movement_input = 1.0;
+        for _ in 0..600 {
+            player.update(PHYSICS_FRAME_TIME, &tuning);
+        }
+        assert_eq!(player.position.x, ARENA_WIDTH - 20.0);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::types::{ NetworkedPlayerInput, PlayerID, VerifiedFrame };
+
+// Joiner side: the world snapshot and the slot assignment can arrive in any order,
+// the game only starts playing once it has both. Remote inputs that arrive before that are
+// held, they can only be routed once the InputBuffer knows which slot is ours.
+#[derive(Debug, Default)]
+pub struct JoinHandoff {
+    world: Option<Vec<u8>>,
+    slot: Option<PlayerID>,
+    held_inputs: Vec<(PlayerID, NetworkedPlayerInput)>,
+}
+
+pub struct CompletedJoin {
+    pub world: Vec<u8>,
+    pub slot: PlayerID,
+    pub inputs: Vec<(PlayerID, NetworkedPlayerInput)>, // in the order they arrived
+}
+
+impl JoinHandoff {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn on_world(&mut self, data: Vec<u8>) {
+        self.world = Some(data);
+    }
+    pub fn on_slot(&mut self, slot: PlayerID) {
+        self.slot = Some(slot);
+    }
+    pub fn on_remote_inputs(&mut self, slot: PlayerID, inputs: Vec<NetworkedPlayerInput>) {
+        self.held_inputs.extend(inputs.into_iter().map(|inp| (slot, inp)));
+    }
+    pub fn try_complete(&mut self) -> Option<CompletedJoin> {
+        if self.world.is_none() || self.slot.is_none() {
+            return None;
+        }
+        Some(CompletedJoin {
+            world: self.world.take()?,
+            slot: self.slot.take()?,
+            inputs: std::mem::take(&mut self.held_inputs),
+        })
+    }
+}
+
+// Host side: remote inputs that arrive while the world upload is still in flight are held back
+// and only handed to the InputBuffer once the server acked the whole snapshot.
+// Inputs at or below the snapshot frame belong to a world the joiner never had and are dropped.
+#[derive(Debug, Default)]
+pub struct HostUploadGate {
+    snapshot_frame: Option<VerifiedFrame>,
+    uploading: bool,
+    held_inputs: Vec<(PlayerID, NetworkedPlayerInput)>,
+}
+
+impl HostUploadGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn begin_upload(&mut self, snapshot_frame: VerifiedFrame) {
+        self.snapshot_frame = Some(snapshot_frame);
+        self.uploading = true;
+        self.held_inputs.retain(|(_, inp)| inp.frame >= snapshot_frame.next_input_frame());
+    }
+    pub fn is_uploading(&self) -> bool {
+        self.uploading
+    }
+    // returns the inputs that can be inserted right away
+    pub fn on_remote_inputs(
+        &mut self,
+        slot: PlayerID,
+        inputs: Vec<NetworkedPlayerInput>
+    ) -> Vec<(PlayerID, NetworkedPlayerInput)> {
+        let snapshot_frame = self.snapshot_frame;
+        let inputs = inputs
+            .into_iter()
+            .filter(|inp| snapshot_frame.is_none_or(|snapshot| inp.frame >= snapshot.next_input_frame()))
+            .map(|inp| (slot, inp));
+        if self.uploading || snapshot_frame.is_none() {
+            self.held_inputs.extend(inputs);
+            return Vec::new();
+        }
+        inputs.collect()
+    }
+    // returns the held back inputs in the order they arrived
+    pub fn on_upload_complete(&mut self) -> Vec<(PlayerID, NetworkedPlayerInput)> {
+        self.uploading = false;
+        std::mem::take(&mut self.held_inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_buffer::{ InputBuffer, PlayerInputs };
+    use crate::types::PlayerInput;
+
+    const SNAPSHOT_FRAME: u32 = 10;
+    const FRAMES: u32 = 20;
+
+    #[derive(Clone, Copy)]
+    enum Event {
+        HostRequest,
+        World,
+        Slot,
+        JoinerInputs,
+        UploadComplete,
+    }
+
+    fn host_input(frame: u32) -> Vec<PlayerInput> {
+        if frame.is_multiple_of(2) { vec![PlayerInput::Left] } else { vec![PlayerInput::Shoot] }
+    }
+    fn joiner_input(frame: u32) -> Vec<PlayerInput> {
+        if frame.is_multiple_of(3) { vec![PlayerInput::Right, PlayerInput::Shoot] } else { Vec::new() }
+    }
+
+    fn drain_verified(buffer: &mut InputBuffer) -> Vec<PlayerInputs> {
+        std::iter::from_fn(|| buffer.pop_next_verified_frame()).collect()
+    }
+
+    fn run_handoff(order: &[Event]) -> (Vec<PlayerInputs>, Vec<PlayerInputs>) {
+        let joiner_frames: Vec<NetworkedPlayerInput> = (SNAPSHOT_FRAME + 1..=SNAPSHOT_FRAME + FRAMES)
+            .map(|frame| NetworkedPlayerInput::new(joiner_input(frame), frame))
+            .collect();
+
+        // host received ServerRequestHostForWorldData at SNAPSHOT_FRAME and started uploading
+        let mut host_buffer = InputBuffer::new();
+        host_buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(SNAPSHOT_FRAME));
+        let mut gate = HostUploadGate::new();
+        // stale inputs relative to an older world, must never be applied
+        gate.on_remote_inputs(
+            PlayerID::Player2,
+            vec![NetworkedPlayerInput::new(vec![PlayerInput::Left], SNAPSHOT_FRAME)]
+        );
+        for frame in SNAPSHOT_FRAME + 1..=SNAPSHOT_FRAME + FRAMES {
+            host_buffer.insert_curr_player_inp(host_input(frame), frame);
+        }
+
+        let mut joiner_buffer = InputBuffer::new();
+        let mut handoff = JoinHandoff::new();
+        let mut joined = false;
+        for event in order {
+            match event {
+                Event::HostRequest => gate.begin_upload(VerifiedFrame(SNAPSHOT_FRAME)),
+                Event::World => handoff.on_world(vec![0; 8]),
+                Event::Slot => handoff.on_slot(PlayerID::Player2),
+                Event::JoinerInputs => {
+                    for (slot, inp) in gate.on_remote_inputs(PlayerID::Player2, joiner_frames.clone()) {
+                        host_buffer.insert_remote_player_inp(slot, inp.inputs, inp.frame);
+                    }
+                }
+                Event::UploadComplete => {
+                    for (slot, inp) in gate.on_upload_complete() {
+                        host_buffer.insert_remote_player_inp(slot, inp.inputs, inp.frame);
+                    }
+                }
+            }
+            if !joined {
+                if let Some(join) = handoff.try_complete() {
+                    joined = true;
+                    joiner_buffer.update_player_count(join.slot, 2, VerifiedFrame(SNAPSHOT_FRAME));
+                    for frame in SNAPSHOT_FRAME + 1..=SNAPSHOT_FRAME + FRAMES {
+                        joiner_buffer.insert_curr_player_inp(joiner_input(frame), frame);
+                        joiner_buffer.insert_remote_player_inp(PlayerID::Player1, host_input(frame), frame);
+                    }
+                }
+            }
+        }
+        assert!(joined);
+        (drain_verified(&mut host_buffer), drain_verified(&mut joiner_buffer))
+    }
+
+    fn assert_converged(host: &[PlayerInputs], joiner: &[PlayerInputs]) {
+        assert_eq!(host.len(), FRAMES as usize);
+        assert_eq!(joiner.len(), FRAMES as usize);
+        for (i, (h, j)) in host.iter().zip(joiner.iter()).enumerate() {
+            assert_eq!(h.frame, SNAPSHOT_FRAME + 1 + (i as u32));
+            assert_eq!(h.frame, j.frame);
+            assert_eq!(h.inputs, j.inputs);
+        }
+    }
+
+    #[test]
+    fn test_joiner_inputs_before_host_request() {
+        let (host, joiner) = run_handoff(
+            &[Event::JoinerInputs, Event::HostRequest, Event::Slot, Event::World, Event::UploadComplete]
+        );
+        assert_converged(&host, &joiner);
+    }
+
+    #[test]
+    fn test_joiner_inputs_before_upload_complete() {
+        let (host, joiner) = run_handoff(
+            &[Event::HostRequest, Event::World, Event::Slot, Event::JoinerInputs, Event::UploadComplete]
+        );
+        assert_converged(&host, &joiner);
+    }
+
+    #[test]
+    fn test_upload_complete_before_joiner_inputs() {
+        let (host, joiner) = run_handoff(
+            &[Event::HostRequest, Event::UploadComplete, Event::Slot, Event::World, Event::JoinerInputs]
+        );
+        assert_converged(&host, &joiner);
+    }
+
+    #[test]
+    fn test_join_waits_for_world_and_slot() {
+        let mut handoff = JoinHandoff::new();
+        handoff.on_world(vec![1, 2, 3]);
+        assert!(handoff.try_complete().is_none());
+        handoff.on_slot(PlayerID::Player2);
+        let join = handoff.try_complete().unwrap();
+        assert_eq!(join.world, vec![1, 2, 3]);
+        assert_eq!(join.slot, PlayerID::Player2);
+
+        let mut handoff = JoinHandoff::new();
+        handoff.on_slot(PlayerID::Player2);
+        assert!(handoff.try_complete().is_none());
+        handoff.on_world(vec![4]);
+        assert!(handoff.try_complete().is_some());
+    }
+
+    #[test]
+    fn test_gate_drops_inputs_at_or_before_snapshot() {
+        let mut gate = HostUploadGate::new();
+        gate.begin_upload(VerifiedFrame(SNAPSHOT_FRAME));
+        let held = gate.on_remote_inputs(
+            PlayerID::Player2,
+            vec![
+                NetworkedPlayerInput::new(Vec::new(), SNAPSHOT_FRAME - 1),
+                NetworkedPlayerInput::new(Vec::new(), SNAPSHOT_FRAME),
+                NetworkedPlayerInput::new(Vec::new(), SNAPSHOT_FRAME + 1)
+            ]
+        );
+        assert!(held.is_empty());
+        let released = gate.on_upload_complete();
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].0, PlayerID::Player2);
+        assert_eq!(released[0].1.frame, SNAPSHOT_FRAME + 1);
+
+        let passed = gate.on_remote_inputs(
+            PlayerID::Player2,
+            vec![NetworkedPlayerInput::new(Vec::new(), SNAPSHOT_FRAME + 2)]
+        );
+        assert_eq!(passed.len(), 1);
+    }
+
+    #[test]
+    fn test_join_holds_remote_inputs_until_complete() {
+        let mut handoff = JoinHandoff::new();
+        handoff.on_remote_inputs(
+            PlayerID::Player1,
+            vec![NetworkedPlayerInput::new(vec![PlayerInput::Shoot], SNAPSHOT_FRAME + 1)]
+        );
+        handoff.on_world(vec![0; 8]);
+        handoff.on_remote_inputs(
+            PlayerID::Player1,
+            vec![NetworkedPlayerInput::new(Vec::new(), SNAPSHOT_FRAME + 2)]
+        );
+        handoff.on_slot(PlayerID::Player2);
+        let join = handoff.try_complete().unwrap();
+        let frames: Vec<(PlayerID, u32)> = join.inputs
+            .iter()
+            .map(|(slot, inp)| (*slot, inp.frame))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![(PlayerID::Player1, SNAPSHOT_FRAME + 1), (PlayerID::Player1, SNAPSHOT_FRAME + 2)]
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use std::time::{ Duration, Instant };
+
+use crate::types::ServerPlayerID;
+
+pub const HOSTS_PER_PAGE: usize = 8;
+pub const PLAYER_LIST_ATTEMPTS: u32 = 3;
+// a bit longer than the network thread resends a reliable message before giving up on it
+pub const PLAYER_LIST_TIMEOUT: Duration = Duration::from_secs(3);
+
+// ChoosePlayer screen state. The highlight is tracked by ServerPlayerID, not by index,
+// so a refreshed host list can reorder or drop entries without redirecting the selection.
+#[derive(Debug, Default)]
+pub struct HostSelection {
+    hosts: Vec<ServerPlayerID>, // sorted, no duplicates
+    highlighted: Option<ServerPlayerID>,
+}
+
+impl HostSelection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn set_hosts(&mut self, ids: &[u8]) {
+        let old_idx = self.highlighted_index();
+        let mut hosts: Vec<ServerPlayerID> = ids
+            .iter()
+            .map(|id| ServerPlayerID(*id))
+            .collect();
+        hosts.sort();
+        hosts.dedup();
+        self.hosts = hosts;
+        let still_listed = self.highlighted.is_some_and(|id| self.hosts.contains(&id));
+        if !still_listed {
+            // the highlighted host left, stay at the same position in the list
+            self.highlighted = old_idx
+                .map(|idx| idx.min(self.hosts.len().saturating_sub(1)))
+                .or(Some(0))
+                .and_then(|idx| self.hosts.get(idx).copied());
+        }
+    }
+    pub fn highlighted(&self) -> Option<ServerPlayerID> {
+        self.highlighted
+    }
+    pub fn highlighted_index(&self) -> Option<usize> {
+        let highlighted = self.highlighted?;
+        self.hosts.iter().position(|id| *id == highlighted)
+    }
+    pub fn move_up(&mut self) {
+        if let Some(idx) = self.highlighted_index() {
+            self.highlight(idx.saturating_sub(1));
+        }
+    }
+    pub fn move_down(&mut self) {
+        if let Some(idx) = self.highlighted_index() {
+            self.highlight(idx + 1);
+        }
+    }
+    pub fn next_page(&mut self) {
+        if let Some(idx) = self.highlighted_index() {
+            self.highlight((idx / HOSTS_PER_PAGE + 1) * HOSTS_PER_PAGE);
+        }
+    }
+    pub fn prev_page(&mut self) {
+        if let Some(idx) = self.highlighted_index() {
+            self.highlight((idx / HOSTS_PER_PAGE).saturating_sub(1) * HOSTS_PER_PAGE);
+        }
+    }
+    pub fn page(&self) -> usize {
+        self.highlighted_index().unwrap_or(0) / HOSTS_PER_PAGE
+    }
+    pub fn page_count(&self) -> usize {
+        self.hosts.len().div_ceil(HOSTS_PER_PAGE).max(1)
+    }
+    // hosts on the page the highlight is on
+    pub fn visible(&self) -> &[ServerPlayerID] {
+        let start = self.page() * HOSTS_PER_PAGE;
+        let end = (start + HOSTS_PER_PAGE).min(self.hosts.len());
+        &self.hosts[start..end]
+    }
+    // the host to connect to, captured when confirming so nothing that arrives later can change it
+    pub fn confirm(&self) -> Option<ServerPlayerID> {
+        self.highlighted
+    }
+    fn highlight(&mut self, idx: usize) {
+        if self.hosts.is_empty() {
+            return;
+        }
+        self.highlighted = Some(self.hosts[idx.min(self.hosts.len() - 1)]);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PlayerListPoll {
+    Waiting,
+    Retry, // send GetServerPlayerIDs again
+    GiveUp,
+}
+
+// WaitingForPlayerList screen state: how often the list was asked for and since when we wait
+#[derive(Debug)]
+pub struct PlayerListRequest {
+    attempts: u32,
+    sent_at: Instant,
+}
+
+impl PlayerListRequest {
+    // `now` is when the first request went out
+    pub fn new(now: Instant) -> Self {
+        Self { attempts: 1, sent_at: now }
+    }
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+    // `failed` is set when the network thread reported an error since the last poll,
+    // that attempt is over right away instead of running into the timeout
+    pub fn poll(&mut self, now: Instant, failed: bool) -> PlayerListPoll {
+        if !failed && now.saturating_duration_since(self.sent_at) < PLAYER_LIST_TIMEOUT {
+            return PlayerListPoll::Waiting;
+        }
+        if self.attempts >= PLAYER_LIST_ATTEMPTS {
+            return PlayerListPoll::GiveUp;
+        }
+        self.attempts += 1;
+        self.sent_at = now;
+        PlayerListPoll::Retry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection(ids: &[u8]) -> HostSelection {
+        let mut selection = HostSelection::new();
+        selection.set_hosts(ids);
+        selection
+    }
+
+    #[test]
+    fn test_empty_list_has_nothing_to_confirm() {
+        let mut selection = selection(&[]);
+        selection.move_down();
+        selection.next_page();
+        assert!(selection.confirm().is_none());
+        assert!(selection.visible().is_empty());
+        assert_eq!(selection.page_count(), 1);
+    }
+
+    #[test]
+    fn test_list_is_sorted_and_navigation_clamps() {
+        let mut selection = selection(&[7, 3, 5, 3]);
+        assert_eq!(selection.visible(), &[ServerPlayerID(3), ServerPlayerID(5), ServerPlayerID(7)]);
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(3)));
+        selection.move_up();
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(3)));
+        selection.move_down();
+        selection.move_down();
+        selection.move_down();
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(7)));
+        selection.move_up();
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(5)));
+    }
+
+    #[test]
+    fn test_more_hosts_than_fit_on_a_page() {
+        let ids: Vec<u8> = (0..20).collect();
+        let mut selection = selection(&ids);
+        assert_eq!(selection.page_count(), 3);
+        assert_eq!(selection.visible().len(), HOSTS_PER_PAGE);
+
+        for _ in 0..HOSTS_PER_PAGE {
+            selection.move_down();
+        }
+        assert_eq!(selection.page(), 1);
+        assert_eq!(selection.visible()[0], ServerPlayerID(HOSTS_PER_PAGE as u8));
+
+        selection.next_page();
+        assert_eq!(selection.page(), 2);
+        assert_eq!(selection.visible().len(), 20 - 2 * HOSTS_PER_PAGE);
+        selection.next_page();
+        assert_eq!(selection.page(), 2);
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(19)));
+
+        selection.prev_page();
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(HOSTS_PER_PAGE as u8)));
+        selection.prev_page();
+        selection.prev_page();
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(0)));
+    }
+
+    #[test]
+    fn test_refresh_keeps_highlighted_host() {
+        let mut selection = selection(&[10, 20, 30]);
+        selection.move_down();
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(20)));
+
+        // new hosts sort in front of the highlighted one, the index shifts but the host doesn't
+        selection.set_hosts(&[30, 1, 20, 2, 10]);
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(20)));
+        assert_eq!(selection.highlighted_index(), Some(3));
+    }
+
+    #[test]
+    fn test_refresh_after_highlighted_host_left() {
+        let mut selection = selection(&[10, 20, 30]);
+        selection.move_down();
+        selection.set_hosts(&[10, 30]);
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(30)));
+
+        selection.set_hosts(&[10]);
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(10)));
+        selection.set_hosts(&[]);
+        assert!(selection.confirm().is_none());
+        selection.set_hosts(&[4]);
+        assert_eq!(selection.confirm(), Some(ServerPlayerID(4)));
+    }
+
+    #[test]
+    fn test_player_list_request_gives_up_after_repeated_failures() {
+        let start = Instant::now();
+        let mut request = PlayerListRequest::new(start);
+        assert_eq!(request.poll(start + PLAYER_LIST_TIMEOUT / 2, false), PlayerListPoll::Waiting);
+        // a reported failure retries right away, an unanswered one after the timeout
+        assert_eq!(request.poll(start + PLAYER_LIST_TIMEOUT / 2, true), PlayerListPoll::Retry);
+        let resent = start + PLAYER_LIST_TIMEOUT / 2;
+        assert_eq!(request.poll(resent + PLAYER_LIST_TIMEOUT / 2, false), PlayerListPoll::Waiting);
+        assert_eq!(request.poll(resent + PLAYER_LIST_TIMEOUT, false), PlayerListPoll::Retry);
+        assert_eq!(request.attempts(), PLAYER_LIST_ATTEMPTS);
+        assert_eq!(
+            request.poll(resent + PLAYER_LIST_TIMEOUT * 2, false),
+            PlayerListPoll::GiveUp
+        );
+    }
+}
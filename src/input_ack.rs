@@ -0,0 +1,180 @@
+use std::{ collections::BTreeSet, time::{ Duration, Instant } };
+
+// how often the receiving side of an input stream tells the sender how far it got
+pub const INPUT_ACK_INTERVAL: Duration = Duration::from_millis(100);
+// frames past a gap that are remembered until it is filled, a sender never has this many unacked
+const MAX_FRAMES_PAST_GAP: usize = 1024;
+
+// The receiving end of one peer's input stream. Inputs are never resent, every datagram repeats what
+// wasn't acked yet, so one cumulative ack of the highest frame received without a gap lets the sender
+// drop everything up to there. A frame missing inside one batch is one the sender never had, e.g.
+// the ones around a joiner's snapshot, only gaps between batches are waited for. The stream starts
+// at the oldest frame of the first batch, a lost ack is covered by the next one.
+// The frames of a pause are never sent, they count as received once every frame before it arrived.
+#[derive(Debug, Default)]
+pub struct InputAcks {
+    through: Option<u32>,
+    past_gap: BTreeSet<u32>, // received, but an older frame is still missing
+    reported: Option<(Instant, u32)>,
+    paused_after: Option<u32>, // the last frame sent before the sender's latest pause
+    paused_through: u32, // the sender said it sends nothing after paused_after up to here
+}
+
+impl InputAcks {
+    // the frames of one batch
+    pub fn record(&mut self, frames: impl IntoIterator<Item = u32>) {
+        let (oldest, newest) = frames
+            .into_iter()
+            .fold((u32::MAX, None), |(oldest, newest), frame| {
+                (oldest.min(frame), newest.max(Some(frame)))
+            });
+        let Some(newest) = newest else {
+            return;
+        };
+        // a batch spanning more than a sender ever has unacked can't be honest, it isn't filled in
+        let oldest = oldest.max(newest.saturating_sub(MAX_FRAMES_PAST_GAP as u32));
+        let oldest = match self.through {
+            Some(u32::MAX) => {
+                return; // every frame there is was acked
+            }
+            Some(through) => oldest.max(through + 1),
+            None => oldest,
+        };
+        self.past_gap.extend(oldest..=newest);
+        if self.through.is_none() {
+            self.through = self.past_gap.pop_first();
+        }
+        self.advance();
+        self.skip_pause();
+        while self.past_gap.len() > MAX_FRAMES_PAST_GAP {
+            self.past_gap.pop_last();
+        }
+    }
+    // the sender sends nothing after `last_frame` until it resumes
+    pub fn on_paused(&mut self, last_frame: u32) {
+        self.paused_after = Some(last_frame);
+        self.skip_pause();
+    }
+    // nothing of the pause up to `frame` is sent, said while paused and when resuming after `frame`.
+    // heard before the pause itself it waits for it
+    pub fn paused_through(&mut self, frame: u32) {
+        self.paused_through = self.paused_through.max(frame);
+        self.skip_pause();
+    }
+    fn skip_pause(&mut self) {
+        let Some(last_frame) = self.paused_after else {
+            return;
+        };
+        let paused_through = self.paused_through;
+        if self.through.is_some_and(|through| through >= last_frame && through < paused_through) {
+            self.through = Some(paused_through);
+            self.past_gap.retain(|frame| *frame > paused_through);
+            self.advance();
+        }
+    }
+    fn advance(&mut self) {
+        while
+            let Some(next) = self.through
+                .and_then(|through| through.checked_add(1))
+                .filter(|next| self.past_gap.remove(next))
+        {
+            self.through = Some(next);
+        }
+    }
+    pub fn through(&self) -> Option<u32> {
+        self.through
+    }
+    // the frame to ack now, at most once per INPUT_ACK_INTERVAL and only when it moved
+    pub fn due_at(&mut self, now: Instant) -> Option<u32> {
+        let through = self.through()?;
+        if let Some((reported_at, reported)) = self.reported {
+            if reported == through || now.duration_since(reported_at) < INPUT_ACK_INTERVAL {
+                return None;
+            }
+        }
+        self.reported = Some((now, through));
+        Some(through)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acks_stop_at_the_first_gap_until_it_is_filled() {
+        let mut acks = InputAcks::default();
+        assert_eq!(acks.through(), None);
+        acks.record([5, 6, 7]);
+        assert_eq!(acks.through(), Some(7));
+        // 8 was lost, the redundant batches after it are reordered
+        acks.record([10, 9]);
+        acks.record([11]);
+        assert_eq!(acks.through(), Some(7));
+        acks.record([6, 7, 8, 9]);
+        assert_eq!(acks.through(), Some(11));
+        // already acked frames don't move it back
+        acks.record([3]);
+        assert_eq!(acks.through(), Some(11));
+        // the sender never had 13
+        acks.record([12, 14]);
+        assert_eq!(acks.through(), Some(14));
+    }
+
+    #[test]
+    fn test_acks_go_out_once_per_interval_when_they_moved() {
+        let start = Instant::now();
+        let mut acks = InputAcks::default();
+        assert_eq!(acks.due_at(start), None);
+        acks.record([1, 2]);
+        assert_eq!(acks.due_at(start), Some(2));
+        acks.record([3]);
+        assert_eq!(acks.due_at(start + INPUT_ACK_INTERVAL / 2), None);
+        assert_eq!(acks.due_at(start + INPUT_ACK_INTERVAL), Some(3));
+        // nothing new arrived
+        assert_eq!(acks.due_at(start + INPUT_ACK_INTERVAL * 3), None);
+    }
+
+    #[test]
+    fn test_the_last_frame_can_be_acked() {
+        let mut acks = InputAcks::default();
+        acks.record([u32::MAX - 1, u32::MAX]);
+        assert_eq!(acks.through(), Some(u32::MAX));
+        acks.record([u32::MAX]);
+        assert_eq!(acks.through(), Some(u32::MAX));
+
+        let mut acks = InputAcks::default();
+        acks.record([u32::MAX - 2]);
+        acks.record([u32::MAX]);
+        acks.record([u32::MAX - 1]);
+        assert_eq!(acks.through(), Some(u32::MAX));
+    }
+
+    #[test]
+    fn test_the_frames_of_a_pause_count_once_the_frames_before_it_arrived() {
+        let mut acks = InputAcks::default();
+        acks.record(1..=9);
+        // 10 is lost, the pause after it can't be acked yet
+        acks.on_paused(10);
+        acks.paused_through(20);
+        assert_eq!(acks.through(), Some(9));
+        acks.record(8..=10);
+        assert_eq!(acks.through(), Some(20));
+        // resumed at 26, the inputs after it were already acked before
+        acks.paused_through(25);
+        acks.record(26..=30);
+        acks.record(26..=60);
+        assert_eq!(acks.through(), Some(60));
+
+        // the resume was heard before the pause
+        let mut acks = InputAcks::default();
+        acks.record(1..=10);
+        acks.paused_through(25);
+        acks.record(27..=30);
+        assert_eq!(acks.through(), Some(10));
+        acks.on_paused(10);
+        assert_eq!(acks.through(), Some(25));
+        acks.record(26..=26);
+        assert_eq!(acks.through(), Some(30));
+    }
+}
@@ -1,10 +1,11 @@
 use std::collections::VecDeque;
-use crate::{ types::{ PlayerID, PlayerInput }, MAX_PLAYER_COUNT };
+use crate::types::{ PlayerID, PlayerInput, VerifiedFrame, MAX_PLAYER_COUNT };
 
 #[derive(Debug, Clone)]
 pub struct PlayerInputs {
     pub inputs: [Option<Vec<PlayerInput>>; MAX_PLAYER_COUNT as usize],
     pub frame: u32,
+    pub filled: [bool; MAX_PLAYER_COUNT as usize], // empty because the player paused, not because it sent that
 }
 
 impl PlayerInputs {
@@ -12,11 +13,13 @@ impl PlayerInputs {
         PlayerInputs {
             inputs: [None, None],
             frame,
+            filled: [false; MAX_PLAYER_COUNT as usize],
         }
     }
 
     fn insert_player_input(&mut self, input: Vec<PlayerInput>, player_id: PlayerID) {
         self.inputs[player_id as usize] = Some(input);
+        self.filled[player_id as usize] = false;
     }
 
     pub fn is_verified(&self, local_player: PlayerID, player_count: u8) -> bool {
@@ -38,12 +41,106 @@ impl PlayerInputs {
     }
 }
 
+// where each slot's inputs go when players are renumbered, new_slot_of[old slot] is the new slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotPermutation<const N: usize> {
+    new_slot_of: [usize; N],
+}
+
+pub type PlayerSlots = SlotPermutation<{ MAX_PLAYER_COUNT as usize }>;
+
+impl<const N: usize> SlotPermutation<N> {
+    // with two slots the game only ever swaps, any other order comes from the tests so far
+    #[cfg(test)]
+    pub fn new(new_slot_of: [usize; N]) -> Result<Self, &'static str> {
+        let mut taken = [false; N];
+        for new_slot in new_slot_of {
+            match taken.get_mut(new_slot) {
+                None => {
+                    return Err("Slot permutation points past the last slot");
+                }
+                Some(true) => {
+                    return Err("Slot permutation moves two slots to the same one");
+                }
+                Some(taken) => {
+                    *taken = true;
+                }
+            }
+        }
+        Ok(Self { new_slot_of })
+    }
+    // a and b trade places, everyone else stays, a == b is the identity
+    pub fn swap(a: usize, b: usize) -> Self {
+        let mut new_slot_of = std::array::from_fn(|slot| slot);
+        new_slot_of.swap(a, b);
+        Self { new_slot_of }
+    }
+    pub fn new_slot(&self, old_slot: usize) -> usize {
+        self.new_slot_of[old_slot]
+    }
+    pub fn is_identity(&self) -> bool {
+        self.new_slot_of
+            .iter()
+            .enumerate()
+            .all(|(old_slot, new_slot)| old_slot == *new_slot)
+    }
+    pub fn apply<T>(&self, slots: &mut [Option<T>; N]) {
+        let old = std::mem::replace(slots, std::array::from_fn(|_| None));
+        for (old_slot, value) in old.into_iter().enumerate() {
+            slots[self.new_slot_of[old_slot]] = value;
+        }
+    }
+}
+
+type VerifiedFrameCallback = Box<dyn FnMut(&PlayerInputs)>;
+
+#[derive(Default)]
+struct VerifiedFrameObservers(Vec<VerifiedFrameCallback>);
+
+impl std::fmt::Debug for VerifiedFrameObservers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} verified frame observers", self.0.len())
+    }
+}
+
+// a remote player paused on purpose: it sends nothing after `last_frame` and plays again from
+// `from_frame` once its Resumed arrives. only the frames it said it played empty, up to `empty_through`,
+// count as empty inputs, a frame past that may be one it already plays for real
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct IdleStretch {
+    last_frame: u32,
+    empty_through: u32,
+    from_frame: Option<u32>,
+    playing_again: bool, // an input after `last_frame` arrived ahead of the Resumed
+}
+
+impl IdleStretch {
+    fn covers(&self, frame: u32) -> bool {
+        frame > self.last_frame && self.from_frame.is_none_or(|from_frame| frame < from_frame)
+    }
+    fn fills(&self, frame: u32) -> bool {
+        self.covers(frame) && frame <= self.empty_through
+    }
+}
+
+// what became of a remote input handed to the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertResult {
+    Inserted,
+    IgnoredOwnSlot,
+    IgnoredTooOld, // at or below the verified baseline
+}
+
 #[derive(Debug)]
 pub struct InputBuffer {
     pub input_frames: VecDeque<PlayerInputs>,
     last_verified_inputs: [Option<Vec<PlayerInput>>; MAX_PLAYER_COUNT as usize],
     pub player_count: u8,
     local_player: PlayerID,
+    newest_remote_frame: Option<u32>,
+    baseline: Option<VerifiedFrame>, // late inputs for anything up to here are stale
+    idle: [Option<IdleStretch>; MAX_PLAYER_COUNT as usize],
+    verified_frame_observers: VerifiedFrameObservers,
 }
 
 impl InputBuffer {
@@ -53,33 +150,47 @@ impl InputBuffer {
             last_verified_inputs: [None, None],
             player_count: 1,
             local_player: PlayerID::Player1,
+            newest_remote_frame: None,
+            baseline: None,
+            idle: [None, None],
+            verified_frame_observers: VerifiedFrameObservers::default(),
         }
     }
+    // called with every frame once it is popped as verified, in frame order
+    pub fn on_verified_frame(&mut self, callback: impl FnMut(&PlayerInputs) + 'static) {
+        self.verified_frame_observers.0.push(Box::new(callback));
+    }
+    // two player shorthand: our slot moved to `local_player`, the other player takes the one we left
     pub fn update_player_count(
         &mut self,
         local_player: PlayerID,
         player_cnt: u8,
-        curr_verified_frame: u32
+        curr_verified_frame: VerifiedFrame
     ) {
-        if local_player == self.local_player {
+        let slots = PlayerSlots::swap(self.local_player as usize, local_player as usize);
+        self.reassign_slots(&slots, player_cnt, curr_verified_frame);
+    }
+    // every player's inputs move to the slot `slots` gives them, ours included
+    pub fn reassign_slots(&mut self, slots: &PlayerSlots, player_cnt: u8, curr_verified_frame: VerifiedFrame) {
+        if slots.is_identity() {
             // verified sim is running in single player so when it switches then we need to reset this
             self.last_verified_inputs = [None, None];
         } else {
             //move accumulated frames (from server) to the correct player and 0 out ours
-            self.input_frames.retain(|input_frame| input_frame.frame >= curr_verified_frame + 1);
-            // swap because we changed player we need to swap inputs to new location
-            self.input_frames
-                .iter_mut()
-                .for_each(|input_frame|
-                    input_frame.inputs.swap(local_player as usize, self.local_player as usize)
-                );
+            self.input_frames.retain(|input_frame| input_frame.frame >= curr_verified_frame.next_input_frame());
+            self.input_frames.iter_mut().for_each(|input_frame| slots.apply(&mut input_frame.inputs));
+            slots.apply(&mut self.last_verified_inputs);
         }
-        self.local_player = local_player;
+        self.local_player = PlayerID::from_usize(slots.new_slot(self.local_player as usize)).expect(
+            "slot permutations stay within MAX_PLAYER_COUNT"
+        );
+        // a pause belongs to whoever played the slot before
+        self.idle = [None, None];
         println!("updating player count to {:?}", self);
         self.player_count = player_cnt;
     }
     pub fn insert_curr_player_inp(&mut self, inp: Vec<PlayerInput>, frame: u32) {
-        debug_assert!(frame != 0); // no input can happen before its first drawn
+        invariant!(frame != 0, "no input can happen before its first drawn, frame {}", frame);
         // frame 0 doesnt exist in arra
         // println!(
         //     "inserted curr player {:?} input at frame {}, input {:?}",
@@ -114,44 +225,50 @@ impl InputBuffer {
         //     self.input_frames.iter().find(|inp| inp.inputs[self.local_player as usize].is_none())
         // );
 
-        debug_assert!(
+        invariant!(
             self.input_frames
                 .iter()
                 .zip(self.input_frames.iter().skip(1))
-                .all(|(a, b)| a.frame <= b.frame)
+                .all(|(a, b)| a.frame <= b.frame),
+            "input frames out of order after inserting frame {}",
+            frame
         );
         // println!(
         //     "state after inserting curr player now {:?}",
         //     self.input_frames.iter().find(|f| f.frame == frame)
         // );
+        self.fill_idle_frames();
     }
+    // two player shorthand for the tests, the game routes by the slot the server stamped
+    #[cfg(test)]
     pub fn insert_other_player_inp(&mut self, inp: Vec<PlayerInput>, frame: u32) {
-        if
-            let Some(first_input_frame_local) = self.input_frames
-                .iter()
-                .find(|input_frame| input_frame.inputs[self.local_player as usize].is_some())
-        {
-            if frame < first_input_frame_local.frame {
-                // println!(
-                //     "tried to insert frame thats before current own player input frame : {}, actual curr frame ; {}",
-                //     frame,
-                //     first_input_frame_local.frame
-                // );
-                return;
-            }
-        }
-        //
-        debug_assert!(frame != 0); // no input can happen before its first drawn
-        // debug_assert!(other != self.local_player);
-        // frame 0 doesnt exist in arra
         let other_player_id = if self.local_player == PlayerID::Player1 {
             PlayerID::Player2
         } else {
             PlayerID::Player1
         };
+        self.insert_remote_player_inp(other_player_id, inp, frame);
+    }
+    pub fn insert_remote_player_inp(
+        &mut self,
+        player: PlayerID,
+        inp: Vec<PlayerInput>,
+        frame: u32
+    ) -> InsertResult {
+        if player == self.local_player {
+            // a session never forwards our own inputs back to us
+            return InsertResult::IgnoredOwnSlot;
+        }
+        if self.baseline.is_some_and(|baseline| frame < baseline.next_input_frame()) {
+            // already part of the verified state, either popped or covered by a snapshot
+            return InsertResult::IgnoredTooOld;
+        }
+        //
+        invariant!(frame != 0, "no input can happen before its first drawn, frame {}", frame);
+        // frame 0 doesnt exist in arra
         // println!(
         //     "inserted other player {:?} input at frame {}, input {:?}",
-        //     other_player_id,
+        //     player,
         //     frame,
         //     inp
         // );
@@ -161,39 +278,168 @@ impl InputBuffer {
             self.input_frames.push_back(inp);
         }
         if let Some(existing_input) = self.input_frames.iter_mut().find(|pi| pi.frame == frame) {
-            existing_input.insert_player_input(inp, other_player_id);
+            existing_input.insert_player_input(inp, player);
             // println!("updated existing input with new inp {:?}", existing_input);
         } else {
             let mut new_inputs = PlayerInputs::new(frame);
-            new_inputs.insert_player_input(inp, other_player_id);
+            new_inputs.insert_player_input(inp, player);
             self.input_frames.insert(
                 self.input_frames.partition_point(|pi| pi.frame < frame),
                 new_inputs
             );
         }
-        debug_assert!(
+        self.newest_remote_frame = self.newest_remote_frame.max(Some(frame));
+        if let Some(stretch) = self.idle[player as usize].as_mut().filter(|stretch| stretch.covers(frame)) {
+            // playing again and the Resumed hasn't arrived yet. the frames before this one may be lost
+            // inputs, only the Resumed says which were empty
+            stretch.playing_again = true;
+        }
+        invariant!(
             self.input_frames
                 .iter()
                 .zip(self.input_frames.iter().skip(1))
-                .all(|(a, b)| a.frame <= b.frame)
+                .all(|(a, b)| a.frame <= b.frame),
+            "input frames out of order after inserting frame {}",
+            frame
         );
         // println!(
         //     "state after inserting other now {:?}",
         //     self.input_frames.iter().find(|f| f.frame == frame)
         // );
+        self.fill_idle_frames();
+        InsertResult::Inserted
+    }
+    // `player` sends no inputs after `last_frame`, the frames after it verify with empty inputs for it
+    // as far as it tells us it played them empty. false for our own slot and for a pause older than
+    // the stretch we know of
+    pub fn pause_remote(&mut self, player: PlayerID, last_frame: u32) -> bool {
+        if player == self.local_player {
+            return false;
+        }
+        if let Some(stretch) = self.idle[player as usize] {
+            let stale = match stretch.from_frame {
+                Some(from_frame) => last_frame < from_frame,
+                None => last_frame <= stretch.last_frame,
+            };
+            if stale {
+                return false;
+            }
+        }
+        self.idle[player as usize] = Some(IdleStretch {
+            last_frame,
+            empty_through: last_frame,
+            from_frame: None,
+            playing_again: false,
+        });
+        true
+    }
+    // `player` played the frames of its pause up to `through_frame` with empty inputs
+    pub fn remote_paused_through(&mut self, player: PlayerID, through_frame: u32) {
+        let Some(stretch) = self.idle[player as usize].as_mut() else {
+            return;
+        };
+        stretch.empty_through = stretch.empty_through.max(through_frame);
+        self.fill_idle_frames();
+    }
+    // `player` plays again from `from_frame` on, every frame of the pause before it was empty.
+    // false for our own slot, a stale one and a repeat
+    pub fn resume_remote(&mut self, player: PlayerID, from_frame: u32) -> bool {
+        let Some(stretch) = self.idle[player as usize].as_mut() else {
+            return false;
+        };
+        if from_frame <= stretch.last_frame || stretch.from_frame.is_some() {
+            return false;
+        }
+        stretch.from_frame = Some(from_frame);
+        stretch.empty_through = stretch.empty_through.max(from_frame - 1);
+        self.fill_idle_frames();
+        true
+    }
+    // paused and not playing again yet, as far as we heard
+    pub fn is_remote_paused(&self, player: PlayerID) -> bool {
+        self.idle[player as usize].is_some_and(|stretch| stretch.from_frame.is_none() && !stretch.playing_again)
+    }
+    pub fn any_remote_paused(&self) -> bool {
+        (0..self.player_count as usize)
+            .filter_map(PlayerID::from_usize)
+            .any(|player| self.is_remote_paused(player))
+    }
+    fn fill_idle_frames(&mut self) {
+        for (player, stretch) in self.idle.iter().enumerate() {
+            let Some(stretch) = stretch else {
+                continue;
+            };
+            for input_frame in self.input_frames.iter_mut() {
+                if stretch.fills(input_frame.frame) && input_frame.inputs[player].is_none() {
+                    input_frame.inputs[player] = Some(Vec::new());
+                    input_frame.filled[player] = true;
+                }
+            }
+        }
+    }
+    // highest frame any remote player sent an input for, kept after the frame is verified and popped
+    pub fn newest_remote_frame(&self) -> Option<u32> {
+        self.newest_remote_frame
+    }
+    // frames some remote player skipped, a later frame of theirs already arrived so these were lost or are late
+    pub fn missing_remote_frames(&self) -> Vec<u32> {
+        let Some(newest) = self.newest_remote_frame else {
+            return Vec::new();
+        };
+        self.input_frames
+            .iter()
+            .take_while(|input_frame| input_frame.frame < newest)
+            .filter(|input_frame| !input_frame.is_verified(self.local_player, self.player_count))
+            .map(|input_frame| input_frame.frame)
+            .collect()
     }
     pub fn pop_next_verified_frame(&mut self) -> Option<PlayerInputs> {
         if let Some(front) = self.input_frames.front() {
             if front.is_verified(self.local_player, self.player_count) {
-                let res = self.input_frames.pop_front().unwrap();
+                let mut res = self.input_frames.pop_front().unwrap();
+                self.baseline = Some(VerifiedFrame(res.frame));
+                self.forget_finished_stretches();
+                for observer in self.verified_frame_observers.0.iter_mut() {
+                    observer(&res);
+                }
+                // the others get empty inputs for a frame we never played, play it with those too
+                res.inputs[self.local_player as usize].get_or_insert_with(Vec::new);
                 self.last_verified_inputs = res.inputs.clone();
-
                 return Some(res);
             }
         }
         None
     }
 
+    // a snapshot replaced the verified state up to `frame`, its inputs are done with like popped ones
+    // and remote inputs at or below it are rejected from now on
+    pub fn set_baseline(&mut self, frame: VerifiedFrame) {
+        self.input_frames.retain(|input_frame| input_frame.frame >= frame.next_input_frame());
+        self.baseline = self.baseline.max(Some(frame));
+        self.forget_finished_stretches();
+    }
+    // a stretch whose last frame is verified can't fill anything anymore
+    fn forget_finished_stretches(&mut self) {
+        let Some(baseline) = self.baseline else {
+            return;
+        };
+        for stretch in self.idle.iter_mut() {
+            let from_frame = stretch.and_then(|stretch| stretch.from_frame);
+            if from_frame.is_some_and(|from_frame| from_frame <= baseline.next_input_frame()) {
+                *stretch = None;
+            }
+        }
+    }
+
+    // drops frames strictly below `frame`, with `shrink` the deque also gives back capacity
+    // it kept from a burst of frames (e.g. a long catch up), leaving some headroom
+    pub fn prune_before(&mut self, frame: u32, shrink: bool) {
+        self.input_frames.retain(|input_frame| input_frame.frame >= frame);
+        if shrink {
+            self.input_frames.shrink_to(self.input_frames.len() * 2);
+        }
+    }
+
     pub fn excluding_iter_after_last_verified(
         &self
     ) -> impl Iterator<Item = (usize, PlayerInputs)> + '_ {
@@ -217,6 +463,8 @@ impl InputBuffer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::invariants::ExpectViolations;
+    use std::{ cell::RefCell, rc::Rc };
     #[test]
     fn test_new() {
         let buffer = InputBuffer::new();
@@ -229,7 +477,7 @@ mod tests {
     #[test]
     fn test_update_player_count_same_player() {
         let mut buffer = InputBuffer::new();
-        buffer.update_player_count(PlayerID::Player1, 2, 5);
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(5));
         assert_eq!(buffer.last_verified_inputs, [None, None]);
         assert_eq!(buffer.local_player, PlayerID::Player1);
         assert_eq!(buffer.player_count, 2);
@@ -239,12 +487,47 @@ mod tests {
     fn test_update_player_count_different_player() {
         let mut buffer = InputBuffer::new();
         buffer.insert_curr_player_inp(Vec::new(), 5);
-        buffer.update_player_count(PlayerID::Player2, 2, 5);
+        buffer.update_player_count(PlayerID::Player2, 2, VerifiedFrame(5));
         assert_eq!(buffer.local_player, PlayerID::Player2);
         assert_eq!(buffer.player_count, 2);
         assert!(buffer.input_frames.iter().all(|f| f.frame >= 6));
     }
 
+    #[test]
+    fn test_join_permutes_three_players_inputs_into_their_new_slots() {
+        // MAX_PLAYER_COUNT is 2 in this build, the remap itself doesn't care how many slots there are
+        let mut frames: Vec<[Option<Vec<PlayerInput>>; 3]> = (0..4)
+            .map(|_| [Some(vec![PlayerInput::Left]), Some(vec![PlayerInput::Right]), None])
+            .collect();
+        // the late joiner takes slot 0, the players in 0 and 1 move up by one
+        let slots = SlotPermutation::new([1, 2, 0]).unwrap();
+        for inputs in frames.iter_mut() {
+            slots.apply(inputs);
+        }
+        for inputs in &frames {
+            assert_eq!(inputs, &[None, Some(vec![PlayerInput::Left]), Some(vec![PlayerInput::Right])]);
+        }
+        assert!(SlotPermutation::new([1, 1, 0]).is_err());
+        assert!(SlotPermutation::new([0, 1, 3]).is_err());
+        assert!(SlotPermutation::<3>::swap(2, 2).is_identity());
+    }
+
+    #[test]
+    fn test_reassign_slots_moves_every_pending_frame() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        buffer.insert_curr_player_inp(vec![PlayerInput::Left], 1);
+        buffer.insert_other_player_inp(vec![PlayerInput::Right], 1);
+        buffer.insert_other_player_inp(vec![PlayerInput::Shoot], 2);
+        buffer.reassign_slots(&PlayerSlots::new([1, 0]).unwrap(), 2, VerifiedFrame(0));
+        assert_eq!(buffer.local_player, PlayerID::Player2);
+        assert_eq!(
+            buffer.input_frames[0].inputs,
+            [Some(vec![PlayerInput::Right]), Some(vec![PlayerInput::Left])]
+        );
+        assert_eq!(buffer.input_frames[1].inputs, [Some(vec![PlayerInput::Shoot]), None]);
+    }
+
     #[test]
     fn test_insert_curr_player_inp() {
         let mut buffer = InputBuffer::new();
@@ -357,7 +640,7 @@ mod tests {
         buffer.insert_other_player_inp(Vec::new(), 3);
 
         // Switch local player to Player 2 (Player 1 becomes "the other player")
-        buffer.update_player_count(PlayerID::Player2, 2, 0);
+        buffer.update_player_count(PlayerID::Player2, 2, VerifiedFrame(0));
 
         // Now Player 2 is the local player, and previously inserted Player 2 inputs
         // should now be treated as Player 1's inputs after the switch.
@@ -399,7 +682,7 @@ mod tests {
         }
 
         // Switch local player to Player 2
-        buffer.update_player_count(PlayerID::Player2, 2, 0);
+        buffer.update_player_count(PlayerID::Player2, 2, VerifiedFrame(0));
 
         // After switching, Player 1's inputs should be moved to Player 2 and vice versa
         for frame_input in buffer.input_frames.iter() {
@@ -430,4 +713,323 @@ mod tests {
             .collect();
         assert_eq!(inputs_after_verified.len(), 0);
     }
+
+    #[test]
+    fn test_on_verified_frame_fires_once_per_verified_frame() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        let seen: Rc<RefCell<Vec<PlayerInputs>>> = Rc::new(RefCell::new(Vec::new()));
+        let seen_by_observer = seen.clone();
+        buffer.on_verified_frame(move |verified| seen_by_observer.borrow_mut().push(verified.clone()));
+
+        for frame in 1..=3 {
+            buffer.insert_curr_player_inp(vec![PlayerInput::Left], frame);
+        }
+        buffer.insert_other_player_inp(vec![PlayerInput::Shoot], 1);
+        buffer.insert_other_player_inp(vec![PlayerInput::Right], 2);
+
+        let popped: Vec<PlayerInputs> = std::iter::from_fn(|| buffer.pop_next_verified_frame()).collect();
+        assert_eq!(popped.len(), 2);
+        // frame 3 is still missing the other player's input
+        assert!(buffer.pop_next_verified_frame().is_none());
+
+        let seen = seen.borrow();
+        assert_eq!(seen.len(), 2);
+        for (observed, popped) in seen.iter().zip(popped.iter()) {
+            assert_eq!(observed.frame, popped.frame);
+            assert_eq!(observed.inputs, popped.inputs);
+        }
+        assert_eq!(seen[0].frame, 1);
+        assert_eq!(seen[0].inputs[PlayerID::Player2 as usize], Some(vec![PlayerInput::Shoot]));
+        assert_eq!(seen[1].frame, 2);
+        assert_eq!(seen[1].inputs[PlayerID::Player2 as usize], Some(vec![PlayerInput::Right]));
+    }
+
+    #[test]
+    fn test_remote_inputs_are_routed_by_slot() {
+        for (local, remote) in [
+            (PlayerID::Player1, PlayerID::Player2),
+            (PlayerID::Player2, PlayerID::Player1),
+        ] {
+            let mut buffer = InputBuffer::new();
+            buffer.update_player_count(local, 2, VerifiedFrame(0));
+            buffer.insert_curr_player_inp(vec![PlayerInput::Left], 1);
+            buffer.insert_remote_player_inp(remote, vec![PlayerInput::Shoot], 1);
+            // stamped with our own slot, can't be another player's input
+            buffer.insert_remote_player_inp(local, vec![PlayerInput::Right], 2);
+
+            let verified = buffer.pop_next_verified_frame().expect("frame 1 has both inputs");
+            assert_eq!(verified.inputs[local as usize], Some(vec![PlayerInput::Left]));
+            assert_eq!(verified.inputs[remote as usize], Some(vec![PlayerInput::Shoot]));
+            assert!(buffer.input_frames.iter().all(|f| f.frame != 2));
+        }
+    }
+
+    #[test]
+    fn test_newest_remote_frame_survives_popping() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        assert_eq!(buffer.newest_remote_frame(), None);
+        for frame in 1..=5 {
+            buffer.insert_curr_player_inp(Vec::new(), frame);
+        }
+        buffer.insert_remote_player_inp(PlayerID::Player2, Vec::new(), 3);
+        buffer.insert_remote_player_inp(PlayerID::Player2, Vec::new(), 1);
+        assert_eq!(buffer.newest_remote_frame(), Some(3));
+        buffer.insert_remote_player_inp(PlayerID::Player2, Vec::new(), 2);
+        assert_eq!(std::iter::from_fn(|| buffer.pop_next_verified_frame()).count(), 3);
+        assert_eq!(buffer.newest_remote_frame(), Some(3));
+    }
+
+    #[test]
+    fn test_missing_remote_frames_are_the_ones_skipped() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        for frame in 1..=8 {
+            buffer.insert_curr_player_inp(Vec::new(), frame);
+        }
+        for frame in [1, 2, 4, 6] {
+            buffer.insert_remote_player_inp(PlayerID::Player2, Vec::new(), frame);
+        }
+        // 7 and 8 simply haven't been sent yet
+        assert_eq!(buffer.missing_remote_frames(), vec![3, 5]);
+        assert_eq!(std::iter::from_fn(|| buffer.pop_next_verified_frame()).count(), 2);
+        buffer.insert_remote_player_inp(PlayerID::Player2, Vec::new(), 3);
+        assert_eq!(buffer.missing_remote_frames(), vec![5]);
+    }
+
+    #[test]
+    fn test_remote_input_for_a_popped_frame_is_discarded() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        for frame in 1..=2 {
+            buffer.insert_curr_player_inp(vec![PlayerInput::Left], frame);
+            buffer.insert_other_player_inp(vec![PlayerInput::Right], frame);
+        }
+        assert_eq!(std::iter::from_fn(|| buffer.pop_next_verified_frame()).count(), 2);
+
+        // a reordered datagram still carrying frame 2
+        buffer.insert_other_player_inp(vec![PlayerInput::Shoot], 2);
+        assert!(buffer.input_frames.is_empty());
+        assert!(buffer.pop_next_verified_frame().is_none());
+
+        buffer.insert_other_player_inp(vec![PlayerInput::Shoot], 3);
+        assert_eq!(buffer.input_frames.iter().map(|f| f.frame).collect::<Vec<u32>>(), vec![3]);
+    }
+
+    #[test]
+    fn test_remote_inputs_at_or_below_the_baseline_are_rejected() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player2, 2, VerifiedFrame(10));
+        buffer.set_baseline(VerifiedFrame(10));
+        let mut insert = |frame| {
+            buffer.insert_remote_player_inp(PlayerID::Player1, vec![PlayerInput::Left], frame)
+        };
+        assert_eq!(insert(9), InsertResult::IgnoredTooOld);
+        assert_eq!(insert(10), InsertResult::IgnoredTooOld);
+        assert_eq!(insert(11), InsertResult::Inserted);
+        assert_eq!(
+            buffer.insert_remote_player_inp(PlayerID::Player2, Vec::new(), 12),
+            InsertResult::IgnoredOwnSlot
+        );
+        assert_eq!(buffer.input_frames.iter().map(|f| f.frame).collect::<Vec<u32>>(), vec![11]);
+
+        // a later baseline drops what it covers and never moves back
+        buffer.set_baseline(VerifiedFrame(11));
+        buffer.set_baseline(VerifiedFrame(5));
+        assert!(buffer.input_frames.is_empty());
+        assert_eq!(
+            buffer.insert_remote_player_inp(PlayerID::Player1, Vec::new(), 11),
+            InsertResult::IgnoredTooOld
+        );
+    }
+
+    #[test]
+    fn test_in_order_inserts_violate_nothing() {
+        // any violation fails the test
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        for frame in 1..=10 {
+            buffer.insert_curr_player_inp(vec![PlayerInput::Left], frame);
+        }
+        for frame in (1..=10).rev() {
+            buffer.insert_other_player_inp(Vec::new(), frame);
+        }
+        assert_eq!(std::iter::from_fn(|| buffer.pop_next_verified_frame()).count(), 10);
+    }
+
+    #[test]
+    fn test_input_for_frame_zero_records_violation() {
+        let violations = ExpectViolations::start();
+        let mut buffer = InputBuffer::new();
+        buffer.insert_other_player_inp(Vec::new(), 0);
+        assert_eq!(
+            violations.take(),
+            vec!["no input can happen before its first drawn, frame 0".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_prune_before_keeps_frames_at_and_after() {
+        let mut buffer = InputBuffer::new();
+        for frame in 1..=10 {
+            buffer.insert_other_player_inp(Vec::new(), frame);
+        }
+        for frame in 1..=10 {
+            buffer.insert_curr_player_inp(vec![PlayerInput::Shoot], frame);
+        }
+        buffer.prune_before(4, false);
+        let frames: Vec<u32> = buffer.input_frames
+            .iter()
+            .map(|f| f.frame)
+            .collect();
+        assert_eq!(frames, (4..=10).collect::<Vec<u32>>());
+        assert!(
+            buffer.input_frames
+                .iter()
+                .all(|f| f.inputs[PlayerID::Player1 as usize] == Some(vec![PlayerInput::Shoot]))
+        );
+
+        buffer.prune_before(4, false);
+        assert_eq!(buffer.input_frames.len(), 7);
+        buffer.prune_before(100, false);
+        assert!(buffer.input_frames.is_empty());
+    }
+
+    #[test]
+    fn test_prune_before_shrinks_capacity() {
+        let mut buffer = InputBuffer::new();
+        for frame in 1..=1000 {
+            buffer.insert_curr_player_inp(Vec::new(), frame);
+        }
+        let grown_capacity = buffer.input_frames.capacity();
+        buffer.prune_before(995, true);
+        assert_eq!(buffer.input_frames.front().unwrap().frame, 995);
+        assert!(buffer.input_frames.capacity() < grown_capacity);
+        assert!(buffer.input_frames.capacity() >= buffer.input_frames.len());
+    }
+
+    #[test]
+    fn test_a_paused_remote_player_plays_empty_inputs_until_it_resumes() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        for frame in 1..=3 {
+            buffer.insert_remote_player_inp(PlayerID::Player2, vec![PlayerInput::Left], frame);
+        }
+        assert!(buffer.pause_remote(PlayerID::Player2, 3));
+        assert!(buffer.is_remote_paused(PlayerID::Player2));
+        assert!(buffer.any_remote_paused());
+        for frame in 1..=10 {
+            buffer.insert_curr_player_inp(Vec::new(), frame);
+        }
+        // nothing arrives for 4 to 10, only that it played 4 to 7 empty
+        for frame in 4..=7 {
+            buffer.remote_paused_through(PlayerID::Player2, frame);
+        }
+        assert!(buffer.missing_remote_frames().is_empty());
+
+        assert!(buffer.resume_remote(PlayerID::Player2, 8));
+        assert!(!buffer.is_remote_paused(PlayerID::Player2));
+        let mut popped: Vec<PlayerInputs> = std::iter::from_fn(|| buffer.pop_next_verified_frame()).collect();
+        assert_eq!(popped.last().map(|f| f.frame), Some(7));
+        for frame in 8..=10 {
+            buffer.insert_remote_player_inp(PlayerID::Player2, vec![PlayerInput::Right], frame);
+        }
+        popped.extend(std::iter::from_fn(|| buffer.pop_next_verified_frame()));
+
+        // every frame once, in order, with what the player really did on each
+        assert_eq!(popped.iter().map(|f| f.frame).collect::<Vec<u32>>(), (1..=10).collect::<Vec<u32>>());
+        let remote: Vec<Vec<PlayerInput>> = popped
+            .into_iter()
+            .map(|f| f.inputs[PlayerID::Player2 as usize].clone().unwrap())
+            .collect();
+        assert_eq!(remote[..3], vec![vec![PlayerInput::Left]; 3]);
+        assert!(remote[3..7].iter().all(Vec::is_empty));
+        assert_eq!(remote[7..], vec![vec![PlayerInput::Right]; 3]);
+        // the stretch is verified, later frames wait for inputs again
+        buffer.insert_curr_player_inp(Vec::new(), 11);
+        assert!(buffer.pop_next_verified_frame().is_none());
+    }
+
+    #[test]
+    fn test_inputs_before_a_late_resumed_end_the_pause() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        for frame in 1..=10 {
+            buffer.insert_curr_player_inp(Vec::new(), frame);
+        }
+        assert!(buffer.pause_remote(PlayerID::Player2, 2));
+        // the player resumed at 5, its inputs from 6 on overtook the Resumed and 5 got lost
+        buffer.insert_remote_player_inp(PlayerID::Player2, vec![PlayerInput::Shoot], 6);
+        assert!(!buffer.is_remote_paused(PlayerID::Player2));
+        let filled = |buffer: &InputBuffer, frame: u32| {
+            let input_frame = buffer.input_frames.iter().find(|f| f.frame == frame).unwrap();
+            input_frame.inputs[PlayerID::Player2 as usize].clone()
+        };
+        // nothing said 3 to 5 were empty yet
+        assert_eq!(buffer.missing_remote_frames(), vec![1, 2, 3, 4, 5]);
+        buffer.remote_paused_through(PlayerID::Player2, 3);
+        assert_eq!(filled(&buffer, 3), Some(Vec::new()));
+
+        assert!(buffer.resume_remote(PlayerID::Player2, 5));
+        assert_eq!(filled(&buffer, 4), Some(Vec::new()));
+        // a repeat or a later one changes nothing
+        assert!(!buffer.resume_remote(PlayerID::Player2, 5));
+        assert!(!buffer.resume_remote(PlayerID::Player2, 6));
+        assert_eq!(filled(&buffer, 5), None);
+        assert_eq!(filled(&buffer, 7), None);
+        assert_eq!(buffer.missing_remote_frames(), vec![1, 2, 5]);
+    }
+
+    #[test]
+    fn test_a_late_resumed_never_leaves_a_played_frame_verified_empty() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        for frame in 1..=20 {
+            buffer.insert_curr_player_inp(Vec::new(), frame);
+        }
+        // the player paused after 3 and played 4 to 7 empty, its Resumed for 8 is still on the way
+        assert!(buffer.pause_remote(PlayerID::Player2, 3));
+        for frame in 1..=3 {
+            buffer.insert_remote_player_inp(PlayerID::Player2, vec![PlayerInput::Left], frame);
+        }
+        for frame in 4..=7 {
+            buffer.remote_paused_through(PlayerID::Player2, frame);
+        }
+        let mut popped: Vec<PlayerInputs> = std::iter::from_fn(|| buffer.pop_next_verified_frame()).collect();
+        // we are far ahead, still frame 8 waits for what the player did on it
+        assert_eq!(popped.last().map(|f| f.frame), Some(7));
+        for frame in 8..=12 {
+            buffer.insert_remote_player_inp(PlayerID::Player2, vec![PlayerInput::Right], frame);
+        }
+        popped.extend(std::iter::from_fn(|| buffer.pop_next_verified_frame()));
+        assert_eq!(popped.last().map(|f| f.frame), Some(12));
+        // it turns up once the frames it announces are verified already, with nothing left to fill
+        assert!(buffer.resume_remote(PlayerID::Player2, 8));
+        assert!(buffer.pop_next_verified_frame().is_none());
+        let remote: Vec<Vec<PlayerInput>> = popped
+            .into_iter()
+            .map(|f| f.inputs[PlayerID::Player2 as usize].clone().unwrap())
+            .collect();
+        assert!(remote[3..7].iter().all(Vec::is_empty));
+        assert_eq!(remote[7..], vec![vec![PlayerInput::Right]; 5]);
+    }
+
+    #[test]
+    fn test_stale_and_own_pauses_are_ignored() {
+        let mut buffer = InputBuffer::new();
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        assert!(!buffer.pause_remote(PlayerID::Player1, 3));
+        assert!(!buffer.resume_remote(PlayerID::Player2, 3));
+        assert!(buffer.pause_remote(PlayerID::Player2, 3));
+        assert!(!buffer.pause_remote(PlayerID::Player2, 3));
+        assert!(buffer.resume_remote(PlayerID::Player2, 9));
+        // the resend of the first pause arrived after its Resumed
+        assert!(!buffer.pause_remote(PlayerID::Player2, 3));
+        assert!(buffer.pause_remote(PlayerID::Player2, 12));
+        assert!(buffer.is_remote_paused(PlayerID::Player2));
+        // a new player in the slot doesn't inherit the pause
+        buffer.update_player_count(PlayerID::Player1, 2, VerifiedFrame(0));
+        assert!(!buffer.any_remote_paused());
+    }
 }
@@ -0,0 +1,65 @@
+use crate::types::{
+    PlayerInput,
+    PLAYER_MOVE_LEFT_BYTE_POS,
+    PLAYER_MOVE_RIGHT_BYTE_POS,
+    PLAYER_SHOOT_BYTE_POS,
+};
+
+// One byte per player and frame, a bit per PlayerInput. Bits this build has no PlayerInput for
+// belong to newer builds, whatever only passes inputs along has to keep them (see unknown_bits).
+pub const KNOWN_INPUT_BITS: u8 =
+    (1 << PLAYER_MOVE_LEFT_BYTE_POS) | (1 << PLAYER_MOVE_RIGHT_BYTE_POS) | (1 << PLAYER_SHOOT_BYTE_POS);
+
+fn bit(input: PlayerInput) -> u8 {
+    match input {
+        PlayerInput::Left => 1 << PLAYER_MOVE_LEFT_BYTE_POS,
+        PlayerInput::Right => 1 << PLAYER_MOVE_RIGHT_BYTE_POS,
+        PlayerInput::Shoot => 1 << PLAYER_SHOOT_BYTE_POS,
+    }
+}
+
+pub fn pack(inputs: &[PlayerInput]) -> u8 {
+    inputs.iter().fold(0, |byte, input| byte | bit(*input))
+}
+
+// always in Left, Right, Shoot order, duplicates in what was packed are gone
+pub fn unpack(byte: u8) -> Vec<PlayerInput> {
+    [PlayerInput::Left, PlayerInput::Right, PlayerInput::Shoot]
+        .into_iter()
+        .filter(|input| byte & bit(*input) != 0)
+        .collect()
+}
+
+// pack(&unpack(byte)) | unknown_bits(byte) == byte
+pub fn unknown_bits(byte: u8) -> u8 {
+    byte & !KNOWN_INPUT_BITS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_combination_of_inputs_round_trips() {
+        let all = [PlayerInput::Left, PlayerInput::Right, PlayerInput::Shoot];
+        for combination in 0..1u32 << all.len() {
+            let inputs: Vec<PlayerInput> = all
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| combination & (1 << i) != 0)
+                .map(|(_, input)| *input)
+                .collect();
+            let byte = pack(&inputs);
+            assert_eq!(unknown_bits(byte), 0);
+            assert_eq!(unpack(byte), inputs);
+        }
+    }
+
+    #[test]
+    fn test_unknown_bits_survive_a_round_trip() {
+        for byte in 0..=u8::MAX {
+            assert_eq!(pack(&unpack(byte)) | unknown_bits(byte), byte, "byte {:#010b}", byte);
+            assert_eq!(pack(&unpack(byte)) & unknown_bits(byte), 0);
+        }
+    }
+}
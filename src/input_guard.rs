@@ -0,0 +1,303 @@
+use std::{ collections::BTreeMap, fmt::Display, time::Instant };
+
+use crate::types::{ BufferedNetworkedPlayerInputs, PlayerInput, TickRate };
+
+pub const FRAME_TOLERANCE: u32 = 30; // half a second of clock drift and startup jitter
+pub const RELAYED_HISTORY_FRAMES: u32 = 256; // well past the client's redundant input buffer
+pub const MAX_REJECTIONS: u32 = 10;
+pub const MAX_ANCHOR_FRAME: u32 = 120 * 60 * 60 * 24 * 7; // a week of play at the highest tick rate
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputRejection {
+    TooFarAhead {
+        frame: u32,
+        expected: u32,
+    },
+    Rewrite {
+        frame: u32,
+    },
+    ImplausibleStart {
+        frame: u32,
+    },
+    Blocked, // rejected too often, nothing from this source is relayed anymore
+}
+
+impl Display for InputRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputRejection::TooFarAhead { frame, expected } =>
+                write!(f, "frame {} is too far ahead of expected frame {}", frame, expected),
+            InputRejection::Rewrite { frame } =>
+                write!(f, "frame {} was already relayed with different inputs", frame),
+            InputRejection::ImplausibleStart { frame } =>
+                write!(f, "frame {} is too late for a stream to start at", frame),
+            InputRejection::Blocked => write!(f, "source is blocked after too many rejections"),
+        }
+    }
+}
+
+// Per source sanity checks before the server relays an input packet. The stream is anchored at the
+// first accepted packet, from then on frames can only advance at about the session's tick rate of wall
+// clock time, and a frame that was relayed once can be resent but never changed. The frames of a pause
+// are never sent, they count as relayed once the stream reached the pause.
+#[derive(Debug, Default)]
+pub struct InputStreamGuard {
+    tick_rate: TickRate,
+    anchor: Option<(Instant, u32)>,
+    highest_frame: u32,
+    contiguous_frame: Option<u32>, // every frame from the first accepted one up to here was relayed
+    relayed: BTreeMap<u32, Vec<PlayerInput>>,
+    rejections: u32,
+    paused_after: Option<u32>,
+    paused_through: u32,
+}
+
+impl InputStreamGuard {
+    pub fn new(tick_rate: TickRate) -> Self {
+        Self { tick_rate, ..Self::default() }
+    }
+    pub fn check_at(
+        &mut self,
+        now: Instant,
+        inputs: &BufferedNetworkedPlayerInputs
+    ) -> Result<(), InputRejection> {
+        if self.rejections >= MAX_REJECTIONS {
+            return Err(InputRejection::Blocked);
+        }
+        let result = self.validate(now, inputs);
+        match result {
+            Ok(()) => self.record(now, inputs),
+            Err(_) => {
+                self.rejections += 1;
+            }
+        }
+        result
+    }
+    pub fn on_paused(&mut self, last_frame: u32) {
+        self.paused_after = Some(last_frame);
+        self.skip_pause();
+    }
+    // a pause can't claim frames the stream couldn't have reached yet either
+    pub fn paused_through_at(&mut self, now: Instant, frame: u32) -> Result<(), InputRejection> {
+        if self.rejections >= MAX_REJECTIONS {
+            return Err(InputRejection::Blocked);
+        }
+        if let Err(rejection) = self.check_window(now, frame) {
+            self.rejections += 1;
+            return Err(rejection);
+        }
+        self.paused_through = self.paused_through.max(frame);
+        self.skip_pause();
+        Ok(())
+    }
+    pub fn rejections(&self) -> u32 {
+        self.rejections
+    }
+    pub fn contiguous_frame(&self) -> Option<u32> {
+        self.contiguous_frame
+    }
+    fn validate(
+        &self,
+        now: Instant,
+        inputs: &BufferedNetworkedPlayerInputs
+    ) -> Result<(), InputRejection> {
+        let Some(newest) = inputs.buffered_inputs.iter().map(|inp| inp.frame).max() else {
+            return Ok(());
+        };
+        self.check_window(now, newest)?;
+        for inp in &inputs.buffered_inputs {
+            if self.relayed.get(&inp.frame).is_some_and(|relayed| *relayed != inp.inputs) {
+                return Err(InputRejection::Rewrite { frame: inp.frame });
+            }
+        }
+        Ok(())
+    }
+    fn check_window(&self, now: Instant, newest: u32) -> Result<(), InputRejection> {
+        match self.anchor {
+            Some((anchor_time, anchor_frame)) => {
+                let elapsed = now.saturating_duration_since(anchor_time).as_secs_f32();
+                let expected = anchor_frame.saturating_add((elapsed * (self.tick_rate.hz() as f32)) as u32);
+                if newest > expected.saturating_add(FRAME_TOLERANCE) {
+                    return Err(InputRejection::TooFarAhead { frame: newest, expected });
+                }
+            }
+            // the peer picks the first frame, one near u32::MAX would leave no room to count up from
+            None if newest > MAX_ANCHOR_FRAME => {
+                return Err(InputRejection::ImplausibleStart { frame: newest });
+            }
+            None => {}
+        }
+        Ok(())
+    }
+    fn record(&mut self, now: Instant, inputs: &BufferedNetworkedPlayerInputs) {
+        for inp in &inputs.buffered_inputs {
+            self.relayed.entry(inp.frame).or_insert_with(|| inp.inputs.clone());
+            self.highest_frame = self.highest_frame.max(inp.frame);
+        }
+        if self.anchor.is_none() && !inputs.buffered_inputs.is_empty() {
+            self.anchor = Some((now, self.highest_frame));
+        }
+        self.contiguous_frame = self.contiguous_frame.or_else(|| {
+            inputs.buffered_inputs.iter().map(|inp| inp.frame).min()
+        });
+        self.advance_contiguous();
+        self.skip_pause();
+        let oldest_kept = self.highest_frame.saturating_sub(RELAYED_HISTORY_FRAMES);
+        self.relayed = self.relayed.split_off(&oldest_kept);
+    }
+    fn skip_pause(&mut self) {
+        let Some(last_frame) = self.paused_after else {
+            return;
+        };
+        let paused_through = self.paused_through;
+        if self.contiguous_frame.is_some_and(|frame| frame >= last_frame && frame < paused_through) {
+            self.contiguous_frame = Some(paused_through);
+            self.advance_contiguous();
+        }
+    }
+    fn advance_contiguous(&mut self) {
+        while
+            let Some(frame) = self.contiguous_frame.filter(|frame| self.relayed.contains_key(&(frame + 1)))
+        {
+            self.contiguous_frame = Some(frame + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::NetworkedPlayerInput;
+    use std::time::Duration;
+
+    fn packet(frames: &[(u32, Vec<PlayerInput>)]) -> BufferedNetworkedPlayerInputs {
+        BufferedNetworkedPlayerInputs {
+            buffered_inputs: frames
+                .iter()
+                .map(|(frame, inp)| NetworkedPlayerInput::new(inp.clone(), *frame))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_frames_within_the_window_are_accepted() {
+        let start = Instant::now();
+        let mut guard = InputStreamGuard::default();
+        // a joiner starts at the snapshot frame, not at 1
+        assert_eq!(guard.check_at(start, &packet(&[(1000, Vec::new())])), Ok(()));
+        assert_eq!(
+            guard.check_at(start, &packet(&[(1000 + FRAME_TOLERANCE, Vec::new())])),
+            Ok(())
+        );
+        // one second later 60 more frames are expected
+        let later = start + Duration::from_secs(1);
+        assert_eq!(
+            guard.check_at(later, &packet(&[(1060 + FRAME_TOLERANCE, Vec::new())])),
+            Ok(())
+        );
+        assert_eq!(guard.rejections(), 0);
+    }
+
+    #[test]
+    fn test_frames_past_the_window_are_rejected() {
+        let start = Instant::now();
+        let mut guard = InputStreamGuard::default();
+        guard.check_at(start, &packet(&[(10, Vec::new())])).unwrap();
+        let later = start + Duration::from_millis(500);
+        assert_eq!(
+            guard.check_at(later, &packet(&[(11, Vec::new()), (41 + FRAME_TOLERANCE, Vec::new())])),
+            Err(InputRejection::TooFarAhead { frame: 41 + FRAME_TOLERANCE, expected: 40 })
+        );
+        assert_eq!(guard.rejections(), 1);
+        // nothing of the rejected packet was recorded, frame 11 can still arrive with other inputs
+        assert_eq!(guard.check_at(later, &packet(&[(11, vec![PlayerInput::Shoot])])), Ok(()));
+    }
+
+    #[test]
+    fn test_a_stream_cant_start_near_the_end_of_the_frame_range() {
+        let start = Instant::now();
+        let mut guard = InputStreamGuard::default();
+        assert_eq!(
+            guard.check_at(start, &packet(&[(u32::MAX, Vec::new())])),
+            Err(InputRejection::ImplausibleStart { frame: u32::MAX })
+        );
+        assert_eq!(guard.check_at(start, &packet(&[(MAX_ANCHOR_FRAME, Vec::new())])), Ok(()));
+    }
+
+    #[test]
+    fn test_the_window_saturates_at_the_last_frame() {
+        let start = Instant::now();
+        let mut guard = InputStreamGuard::default();
+        guard.check_at(start, &packet(&[(MAX_ANCHOR_FRAME, Vec::new())])).unwrap();
+        // years later the expected frame is past u32::MAX
+        let later = start + Duration::from_secs(60 * 60 * 24 * 365 * 3);
+        assert_eq!(guard.check_at(later, &packet(&[(u32::MAX, Vec::new())])), Ok(()));
+    }
+
+    #[test]
+    fn test_window_follows_the_session_tick_rate() {
+        let start = Instant::now();
+        let mut guard = InputStreamGuard::new(TickRate::new(30).unwrap());
+        guard.check_at(start, &packet(&[(1, Vec::new())])).unwrap();
+        let later = start + Duration::from_secs(1);
+        assert_eq!(
+            guard.check_at(later, &packet(&[(32 + FRAME_TOLERANCE, Vec::new())])),
+            Err(InputRejection::TooFarAhead { frame: 32 + FRAME_TOLERANCE, expected: 31 })
+        );
+        assert_eq!(guard.check_at(later, &packet(&[(31 + FRAME_TOLERANCE, Vec::new())])), Ok(()));
+    }
+
+    #[test]
+    fn test_resent_frames_must_not_change() {
+        let start = Instant::now();
+        let mut guard = InputStreamGuard::default();
+        guard.check_at(start, &packet(&[(1, vec![PlayerInput::Left])])).unwrap();
+        // the redundant buffer resends frame 1 unchanged
+        assert_eq!(
+            guard.check_at(start, &packet(&[(1, vec![PlayerInput::Left]), (2, Vec::new())])),
+            Ok(())
+        );
+        assert_eq!(
+            guard.check_at(start, &packet(&[(2, vec![PlayerInput::Shoot]), (3, Vec::new())])),
+            Err(InputRejection::Rewrite { frame: 2 })
+        );
+        assert_eq!(guard.rejections(), 1);
+    }
+
+    #[test]
+    fn test_source_is_blocked_after_too_many_rejections() {
+        let start = Instant::now();
+        let mut guard = InputStreamGuard::default();
+        guard.check_at(start, &packet(&[(1, Vec::new())])).unwrap();
+        for _ in 0..MAX_REJECTIONS {
+            assert!(
+                matches!(
+                    guard.check_at(start, &packet(&[(1, vec![PlayerInput::Right])])),
+                    Err(InputRejection::Rewrite { frame: 1 })
+                )
+            );
+        }
+        assert_eq!(
+            guard.check_at(start, &packet(&[(2, Vec::new())])),
+            Err(InputRejection::Blocked)
+        );
+    }
+
+    #[test]
+    fn test_a_pause_moves_the_contiguous_frame_within_the_window() {
+        let start = Instant::now();
+        let mut guard = InputStreamGuard::default();
+        guard.check_at(start, &packet(&[(1, Vec::new()), (2, Vec::new())])).unwrap();
+        guard.on_paused(2);
+        let later = start + Duration::from_secs(1);
+        assert_eq!(guard.paused_through_at(later, 40), Ok(()));
+        assert_eq!(guard.contiguous_frame(), Some(40));
+        assert_eq!(
+            guard.paused_through_at(later, 63 + FRAME_TOLERANCE),
+            Err(InputRejection::TooFarAhead { frame: 63 + FRAME_TOLERANCE, expected: 62 })
+        );
+        assert_eq!(guard.contiguous_frame(), Some(40));
+        guard.check_at(later, &packet(&[(41, Vec::new())])).unwrap();
+        assert_eq!(guard.contiguous_frame(), Some(41));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::types::NetworkMessage;
+
+pub const RESUME_LEAD_FRAMES: u32 = 15; // time for the Resumed to reach the peers before they wait on our inputs
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PauseState {
+    Playing,
+    Paused {
+        last_frame: u32,
+    },
+    Resuming {
+        from_frame: u32,
+    },
+}
+
+// Our side of a pause: our input stream stops and the peers are told after which frame, they fill the
+// frames after it with empty inputs instead of waiting for them. Each paused frame is confirmed with a
+// ClientInputStreamPausedThrough and they fill no further, a lost or late message only makes them wait.
+// Resuming announces a frame a little ahead and plays empty inputs until then.
+// The simulation doesn't stop, the others keep playing.
+#[derive(Debug)]
+pub struct LocalPause {
+    state: PauseState,
+    toggle_requested: bool,
+}
+
+impl Default for LocalPause {
+    fn default() -> Self {
+        Self { state: PauseState::Playing, toggle_requested: false }
+    }
+}
+
+impl LocalPause {
+    // taken up with the next sampled frame, a key press may fall on a render frame without a step
+    pub fn request_toggle(&mut self) {
+        self.toggle_requested = true;
+    }
+    // the marker to send before the input of `input_frame`, if a requested toggle changed anything.
+    // a toggle while resuming is dropped, the frame to play from is already announced
+    pub fn take_marker(&mut self, input_frame: u32) -> Option<NetworkMessage> {
+        if !std::mem::take(&mut self.toggle_requested) {
+            return None;
+        }
+        match self.state {
+            PauseState::Playing => {
+                let last_frame = input_frame.saturating_sub(1);
+                self.state = PauseState::Paused { last_frame };
+                Some(NetworkMessage::ClientInputStreamPaused { last_frame })
+            }
+            PauseState::Paused { .. } => {
+                let from_frame = input_frame + RESUME_LEAD_FRAMES;
+                self.state = PauseState::Resuming { from_frame };
+                Some(NetworkMessage::ClientInputStreamResumed { from_frame })
+            }
+            PauseState::Resuming { .. } => None,
+        }
+    }
+    // false for the frames a pause covers, their input is empty and not sent
+    pub fn sends(&mut self, input_frame: u32) -> bool {
+        if let PauseState::Resuming { from_frame } = self.state {
+            if input_frame >= from_frame {
+                self.state = PauseState::Playing;
+            }
+        }
+        self.state == PauseState::Playing
+    }
+    pub fn is_paused(&self) -> bool {
+        self.state != PauseState::Playing
+    }
+    // a player that joins during the pause has to hear of it too
+    pub fn announcement(&self) -> Option<NetworkMessage> {
+        match self.state {
+            PauseState::Playing => None,
+            PauseState::Paused { last_frame } => Some(NetworkMessage::ClientInputStreamPaused { last_frame }),
+            PauseState::Resuming { from_frame } => Some(NetworkMessage::ClientInputStreamResumed { from_frame }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause_and_resume_leave_no_frame_out() {
+        let mut pause = LocalPause::default();
+        let mut sent = Vec::new();
+        let mut markers = Vec::new();
+        for frame in 1..=60 {
+            if frame == 11 || frame == 31 {
+                pause.request_toggle();
+            }
+            markers.extend(pause.take_marker(frame));
+            if pause.sends(frame) {
+                sent.push(frame);
+            }
+        }
+        assert!(
+            matches!(markers[..], [
+                NetworkMessage::ClientInputStreamPaused { last_frame: 10 },
+                NetworkMessage::ClientInputStreamResumed { from_frame },
+            ] if from_frame == 31 + RESUME_LEAD_FRAMES)
+        );
+        // what isn't sent is exactly what the peers fill in
+        let resumed_from = 31 + RESUME_LEAD_FRAMES;
+        assert_eq!(sent, (1..=10).chain(resumed_from..=60).collect::<Vec<u32>>());
+        assert!(!pause.is_paused());
+    }
+
+    #[test]
+    fn test_a_toggle_while_resuming_is_dropped() {
+        let mut pause = LocalPause::default();
+        assert!(pause.take_marker(5).is_none());
+        pause.request_toggle();
+        pause.take_marker(5);
+        pause.request_toggle();
+        pause.take_marker(6);
+        pause.request_toggle();
+        assert!(pause.take_marker(7).is_none());
+        assert!(
+            matches!(pause.announcement(), Some(NetworkMessage::ClientInputStreamResumed { from_frame })
+                if from_frame == 6 + RESUME_LEAD_FRAMES)
+        );
+        assert!(!pause.sends(7));
+        assert!(pause.is_paused());
+        assert!(pause.sends(6 + RESUME_LEAD_FRAMES));
+        assert!(pause.announcement().is_none());
+    }
+}
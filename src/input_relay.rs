@@ -0,0 +1,287 @@
+use std::{ collections::HashMap, net::SocketAddr, time::Instant };
+
+use crate::{
+    input_ack::InputAcks,
+    input_guard::{ InputRejection, InputStreamGuard },
+    types::{ BufferedNetworkedPlayerInputs, InputGapStats, NetworkedPlayerInput, TickRate },
+};
+
+// everything the relay keeps for one client, moved as a whole when the client rebinds
+#[derive(Debug, Default)]
+pub struct RelayState {
+    unacked: Option<BufferedNetworkedPlayerInputs>,
+    floor: Option<u32>,
+    acks: Option<InputAcks>,
+    guard: Option<InputStreamGuard>,
+}
+
+// a batch that passed the checks, normalized
+pub struct ReceivedInputs {
+    pub inputs: BufferedNetworkedPlayerInputs,
+    pub gaps: InputGapStats,
+}
+
+// what went into one peer's unacked buffer
+pub struct RelayedInputs {
+    pub inputs: BufferedNetworkedPlayerInputs, // the part of the batch past the peer's floor
+    pub dropped: usize, // oldest unacked frames dropped so the buffer still fits one datagram
+    pub oldest_unacked: u32,
+    pub newest_unacked: u32,
+}
+
+// The server's part of every input stream. A client's batch is checked by its InputStreamGuard and
+// acked by frame, then relayed to each peer past the frame the peer's world starts at. Frames a peer
+// didn't ack yet are kept for it, a ClientRequestInputRange is answered from them.
+#[derive(Debug, Default)]
+pub struct InputRelay {
+    unacked: HashMap<SocketAddr, BufferedNetworkedPlayerInputs>, // only connected clients have one
+    floors: HashMap<SocketAddr, u32>, // a joiner's world frame, inputs up to it aren't relayed to it
+    acks: HashMap<SocketAddr, InputAcks>, // how far each client's own inputs arrived, acked back to it
+    guards: HashMap<SocketAddr, InputStreamGuard>,
+}
+
+impl InputRelay {
+    pub fn connect(&mut self, addr: SocketAddr) {
+        self.unacked.insert(addr, BufferedNetworkedPlayerInputs::default());
+    }
+    pub fn take(&mut self, addr: &SocketAddr) -> RelayState {
+        RelayState {
+            unacked: self.unacked.remove(addr),
+            floor: self.floors.remove(addr),
+            acks: self.acks.remove(addr),
+            guard: self.guards.remove(addr),
+        }
+    }
+    pub fn put(&mut self, addr: SocketAddr, state: RelayState) {
+        if let Some(unacked) = state.unacked {
+            self.unacked.insert(addr, unacked);
+        }
+        if let Some(floor) = state.floor {
+            self.floors.insert(addr, floor);
+        }
+        if let Some(acks) = state.acks {
+            self.acks.insert(addr, acks);
+        }
+        if let Some(guard) = state.guard {
+            self.guards.insert(addr, guard);
+        }
+    }
+    // the next session's frames start over
+    pub fn leave_session(&mut self, addr: &SocketAddr) {
+        self.guards.remove(addr);
+        self.acks.remove(addr);
+        self.floors.remove(addr);
+    }
+    // the stream is checked again from its next batch on, e.g. at the new tick rate
+    pub fn restart_checks(&mut self, addr: &SocketAddr) {
+        self.guards.remove(addr);
+    }
+
+    pub fn receive(
+        &mut self,
+        now: Instant,
+        src: &SocketAddr,
+        mut inputs: BufferedNetworkedPlayerInputs,
+        tick_rate: TickRate
+    ) -> Result<ReceivedInputs, InputRejection> {
+        let gaps = inputs.normalize();
+        self.guards
+            .entry(*src)
+            .or_insert_with(|| InputStreamGuard::new(tick_rate))
+            .check_at(now, &inputs)?;
+        self.acks
+            .entry(*src)
+            .or_default()
+            .record(inputs.buffered_inputs.iter().map(|inp| inp.frame));
+        Ok(ReceivedInputs { inputs, gaps })
+    }
+    // `src` sends nothing after `last_frame` until it resumes
+    pub fn on_paused(&mut self, src: &SocketAddr, last_frame: u32) {
+        if let Some(guard) = self.guards.get_mut(src) {
+            guard.on_paused(last_frame);
+        }
+        if let Some(acks) = self.acks.get_mut(src) {
+            acks.on_paused(last_frame);
+        }
+    }
+    // nothing of the pause up to `frame` is sent, it is acked and relayed on as if it had arrived
+    pub fn paused_through(&mut self, now: Instant, src: &SocketAddr, frame: u32) -> Result<(), InputRejection> {
+        if let Some(guard) = self.guards.get_mut(src) {
+            guard.paused_through_at(now, frame)?;
+        }
+        if let Some(acks) = self.acks.get_mut(src) {
+            acks.paused_through(frame);
+        }
+        Ok(())
+    }
+    pub fn rejections(&self, src: &SocketAddr) -> u32 {
+        self.guards.get(src).map_or(0, InputStreamGuard::rejections)
+    }
+    pub fn contiguous_frame(&self, addr: &SocketAddr) -> Option<u32> {
+        self.guards.get(addr)?.contiguous_frame()
+    }
+
+    // None if nothing of the batch is of use to `target` or it isn't connected
+    pub fn relay_to(
+        &mut self,
+        target: &SocketAddr,
+        inputs: &BufferedNetworkedPlayerInputs
+    ) -> Option<RelayedInputs> {
+        // frames the target's world already contains are of no use to it
+        let inputs = match self.floors.get(target) {
+            Some(&floor) => BufferedNetworkedPlayerInputs {
+                buffered_inputs: inputs.buffered_inputs
+                    .iter()
+                    .filter(|inp| inp.frame > floor)
+                    .cloned()
+                    .collect(),
+            },
+            None => inputs.clone(),
+        };
+        if inputs.buffered_inputs.is_empty() {
+            return None;
+        }
+        let unacked = self.unacked.get_mut(target)?;
+        unacked.bulk_insert_player_input(inputs.clone());
+        // a peer that stops acking must not grow this without bound
+        let dropped = unacked.trim_to_fit(BufferedNetworkedPlayerInputs::max_frames_per_packet()).len();
+        let oldest_unacked = unacked.buffered_inputs.first()?.frame;
+        let newest_unacked = unacked.last_frame()?;
+        Some(RelayedInputs { inputs, dropped, oldest_unacked, newest_unacked })
+    }
+    // the host's world of `frame` is on its way to `peer`, what piled up for it before is dropped
+    pub fn set_floor(&mut self, peer: SocketAddr, frame: u32) {
+        let floor = self.floors.entry(peer).or_default();
+        *floor = (*floor).max(frame);
+        if let Some(unacked) = self.unacked.get_mut(&peer) {
+            unacked.discard_acknowledged_frames(frame);
+        }
+    }
+    // false if `src` isn't connected
+    pub fn on_acked_through(&mut self, src: &SocketAddr, frame: u32) -> bool {
+        let Some(unacked) = self.unacked.get_mut(src) else {
+            return false;
+        };
+        unacked.discard_acknowledged_frames(frame);
+        true
+    }
+    pub fn unacked(&self, addr: &SocketAddr) -> Option<&BufferedNetworkedPlayerInputs> {
+        self.unacked.get(addr)
+    }
+    // the frames of `from_frame..=to_frame` `addr` still has to ack
+    pub fn unacked_in(&self, addr: &SocketAddr, from_frame: u32, to_frame: u32) -> Vec<NetworkedPlayerInput> {
+        self.unacked
+            .get(addr)
+            .map(|unacked| {
+                unacked.buffered_inputs
+                    .iter()
+                    .filter(|inp| (from_frame..=to_frame).contains(&inp.frame))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    pub fn acks(&self, addr: &SocketAddr) -> Option<&InputAcks> {
+        self.acks.get(addr)
+    }
+    // the acks to send now, each client is told how far its inputs arrived
+    pub fn due_acks(&mut self, now: Instant) -> Vec<(SocketAddr, u32)> {
+        self.acks
+            .iter_mut()
+            .filter_map(|(addr, acks)| Some((*addr, acks.due_at(now)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_guard::MAX_ANCHOR_FRAME;
+
+    fn batch(frames: impl IntoIterator<Item = u32>) -> BufferedNetworkedPlayerInputs {
+        BufferedNetworkedPlayerInputs {
+            buffered_inputs: frames
+                .into_iter()
+                .map(|frame| NetworkedPlayerInput::new(Vec::new(), frame))
+                .collect(),
+        }
+    }
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+    fn frames(inputs: &BufferedNetworkedPlayerInputs) -> Vec<u32> {
+        inputs.buffered_inputs
+            .iter()
+            .map(|inp| inp.frame)
+            .collect()
+    }
+
+    #[test]
+    fn test_a_peer_only_gets_frames_past_its_floor_until_it_acks_them() {
+        let (host, joiner) = (addr(1), addr(2));
+        let mut relay = InputRelay::default();
+        relay.connect(host);
+        relay.connect(joiner);
+        let now = Instant::now();
+        let received = relay.receive(now, &host, batch([7, 5, 6, 5]), TickRate::default()).unwrap();
+        assert_eq!(frames(&received.inputs), vec![5, 6, 7]);
+        assert_eq!(received.gaps.duplicates, 1);
+
+        relay.set_floor(joiner, 5);
+        let relayed = relay.relay_to(&joiner, &received.inputs).unwrap();
+        assert_eq!(frames(&relayed.inputs), vec![6, 7]);
+        assert_eq!((relayed.oldest_unacked, relayed.newest_unacked), (6, 7));
+        assert!(relay.relay_to(&joiner, &batch([4, 5])).is_none());
+
+        assert_eq!(relay.unacked_in(&joiner, 6, 8).len(), 2);
+        assert!(relay.on_acked_through(&joiner, 6));
+        assert_eq!(frames(relay.unacked(&joiner).unwrap()), vec![7]);
+        assert_eq!(relay.due_acks(now), vec![(host, 7)]);
+    }
+
+    #[test]
+    fn test_a_rejected_batch_is_not_acked() {
+        let src = addr(1);
+        let mut relay = InputRelay::default();
+        relay.connect(src);
+        let now = Instant::now();
+        assert!(relay.receive(now, &src, batch([MAX_ANCHOR_FRAME + 1]), TickRate::default()).is_err());
+        assert_eq!(relay.rejections(&src), 1);
+        assert!(relay.acks(&src).is_none_or(|acks| acks.through().is_none()));
+    }
+
+    #[test]
+    fn test_a_rebound_client_takes_its_stream_along() {
+        let (old, new) = (addr(1), addr(2));
+        let mut relay = InputRelay::default();
+        relay.connect(old);
+        relay.receive(Instant::now(), &old, batch([1, 2]), TickRate::default()).unwrap();
+        relay.set_floor(old, 1);
+
+        let state = relay.take(&old);
+        relay.put(new, state);
+        assert!(relay.unacked(&old).is_none());
+        assert_eq!(relay.acks(&new).unwrap().through(), Some(2));
+        assert_eq!(relay.contiguous_frame(&new), Some(2));
+        assert!(relay.relay_to(&new, &batch([1])).is_none());
+        assert!(relay.relay_to(&new, &batch([2])).is_some());
+    }
+
+    #[test]
+    fn test_a_resumed_stream_is_acked_past_its_pause() {
+        let src = addr(1);
+        let mut relay = InputRelay::default();
+        relay.connect(src);
+        let now = Instant::now();
+        relay.receive(now, &src, batch(1..=10), TickRate::default()).unwrap();
+        relay.on_paused(&src, 10);
+        relay.paused_through(now, &src, 20).unwrap();
+        assert_eq!(relay.due_acks(now), vec![(src, 20)]);
+        // resumed from 26
+        relay.paused_through(now, &src, 25).unwrap();
+        relay.receive(now, &src, batch(26..=30), TickRate::default()).unwrap();
+        relay.receive(now, &src, batch(26..=40), TickRate::default()).unwrap();
+        assert_eq!(relay.acks(&src).unwrap().through(), Some(40));
+        assert_eq!(relay.contiguous_frame(&src), Some(40));
+    }
+}
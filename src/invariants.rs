@@ -0,0 +1,108 @@
+// `invariant!` is a debug_assert! that test builds can observe: under cfg(test) a violation panics like
+// any failed assert, unless the test expects one. While an `ExpectViolations` guard lives on the current
+// thread violations are recorded for it instead, so a test can check which invariant fired.
+// Outside of tests it is exactly debug_assert!.
+macro_rules! invariant {
+    ($cond:expr $(,)?) => {
+        invariant!($cond, "{}", stringify!($cond))
+    };
+    ($cond:expr, $($arg:tt)+) => {
+        #[cfg(test)]
+        {
+            if !$cond {
+                $crate::invariants::record_violation(format!($($arg)+));
+            }
+        }
+        #[cfg(not(test))]
+        {
+            debug_assert!($cond, $($arg)+);
+        }
+    };
+}
+
+#[cfg(test)]
+thread_local! {
+    static EXPECTED: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(test)]
+pub fn record_violation(message: String) {
+    let unexpected = EXPECTED.with(|expected| {
+        match expected.borrow_mut().as_mut() {
+            Some(violations) => {
+                violations.push(message);
+                None
+            }
+            None => Some(message),
+        }
+    });
+    if let Some(message) = unexpected {
+        panic!("invariant violated: {}", message);
+    }
+}
+
+// records the violations on this thread until dropped, every one must be taken by then
+#[cfg(test)]
+pub struct ExpectViolations(());
+
+#[cfg(test)]
+impl ExpectViolations {
+    pub fn start() -> Self {
+        EXPECTED.with(|expected| {
+            assert!(expected.borrow_mut().replace(Vec::new()).is_none(), "already expecting violations");
+        });
+        ExpectViolations(())
+    }
+    // returns and clears everything recorded so far
+    pub fn take(&self) -> Vec<String> {
+        EXPECTED.with(|expected| expected.borrow_mut().as_mut().map(std::mem::take).unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+impl Drop for ExpectViolations {
+    fn drop(&mut self) {
+        let unchecked = EXPECTED.with(|expected| expected.borrow_mut().take()).unwrap_or_default();
+        if !unchecked.is_empty() && !std::thread::panicking() {
+            panic!("unchecked invariant violations: {:?}", unchecked);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expected_violation_is_recorded_instead_of_panicking() {
+        let violations = ExpectViolations::start();
+        let frame = 0;
+        invariant!(frame != 0, "frame {} is before the first drawn frame", frame);
+        invariant!(frame == 0);
+        assert_eq!(violations.take(), vec!["frame 0 is before the first drawn frame".to_string()]);
+        assert!(violations.take().is_empty());
+    }
+
+    #[test]
+    fn test_expected_violation_without_message_records_condition() {
+        let violations = ExpectViolations::start();
+        let len = 3;
+        invariant!(len > 5);
+        assert_eq!(violations.take(), vec!["len > 5".to_string()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "invariant violated: len > 5")]
+    fn test_unexpected_violation_fails_the_test() {
+        let len = 3;
+        invariant!(len > 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "unchecked invariant violations")]
+    fn test_expected_violation_nobody_checked_fails_the_test() {
+        let _violations = ExpectViolations::start();
+        let len = 3;
+        invariant!(len > 5);
+    }
+}
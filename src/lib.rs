@@ -0,0 +1,21 @@
+// The protocol both binaries speak: the messages, how they go over the wire and the allocator
+// the game's world lives in. Tools that talk to a server or read its datagrams build on this
+// instead of copying the modules, the game's simulation stays in the game binary.
+#[macro_use]
+mod invariants;
+mod input_codec;
+pub mod packet_layout;
+pub mod types;
+pub mod type_impl;
+pub mod memory;
+pub mod input_buffer;
+pub mod network_simulator;
+// shared by the server and the game, not meant for anyone else
+pub mod config;
+pub mod input_ack;
+pub mod input_guard;
+pub mod input_relay;
+pub mod link_loss;
+pub mod message_history;
+pub mod retransmit;
+pub mod send_queue;
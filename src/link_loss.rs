@@ -0,0 +1,221 @@
+use std::{ collections::VecDeque, time::{ Duration, Instant } };
+
+use crate::types::SeqNum;
+
+pub const LOSS_WINDOW: Duration = Duration::from_secs(5);
+// a send younger than this may still get its ack, it isn't counted either way yet
+pub const ACK_GRACE: Duration = Duration::from_secs(1);
+const MIN_SETTLED_SENDS: usize = 20; // fewer than this in the window say nothing about the link
+pub const POOR_CONNECTION_LOSS: f32 = 0.1;
+pub const POOR_CONNECTION_AFTER: Duration = Duration::from_secs(3);
+
+struct Send<K> {
+    at: Instant,
+    key: K,
+    acked: bool,
+}
+
+// share of the datagrams sent over the last LOSS_WINDOW that were never acked, each is sent
+// under a key, its seq num or, for inputs, the newest frame it carried. a resend is one more
+// datagram with the same key so the first try counts as lost
+pub struct LossEstimator<K = SeqNum> {
+    sends: VecDeque<Send<K>>, // oldest first
+}
+
+impl<K> Default for LossEstimator<K> {
+    fn default() -> Self {
+        Self { sends: VecDeque::new() }
+    }
+}
+
+impl<K: PartialEq> LossEstimator<K> {
+    pub fn on_send(&mut self, now: Instant, key: K) {
+        self.evict(now);
+        self.sends.push_back(Send { at: now, key, acked: false });
+    }
+
+    // the ack belongs to the latest try, acks for keys that left the window are ignored
+    pub fn on_ack(&mut self, key: K) {
+        if let Some(send) = self.sends.iter_mut().rev().find(|send| send.key == key) {
+            send.acked = true;
+        }
+    }
+
+    // None until enough sends are old enough to have been acked
+    pub fn loss_at(&mut self, now: Instant) -> Option<f32> {
+        self.evict(now);
+        let settled = self.sends
+            .iter()
+            .filter(|send| now.duration_since(send.at) >= ACK_GRACE);
+        let (count, lost) = settled.fold((0, 0), |(count, lost), send| {
+            (count + 1, lost + usize::from(!send.acked))
+        });
+        if count < MIN_SETTLED_SENDS {
+            return None;
+        }
+        Some((lost as f32) / (count as f32))
+    }
+
+    fn evict(&mut self, now: Instant) {
+        while self.sends.front().is_some_and(|send| now.duration_since(send.at) > LOSS_WINDOW) {
+            self.sends.pop_front();
+        }
+    }
+}
+
+impl LossEstimator<u32> {
+    // a cumulative input ack, every datagram whose newest frame it covers got through. the frames of
+    // a lost datagram are repeated in the next one, so only loss longer than that shows up here
+    pub fn on_acked_through(&mut self, frame: u32) {
+        for send in self.sends.iter_mut().filter(|send| send.key <= frame) {
+            send.acked = true;
+        }
+    }
+}
+
+// both ways of looking at one peer: reliable messages and their resends, and input datagrams
+// that are never resent and acked by frame
+#[derive(Default)]
+pub struct LinkLoss {
+    pub reliable: LossEstimator,
+    pub inputs: LossEstimator<u32>,
+    poor_connection: PoorConnectionAlarm,
+}
+
+impl LinkLoss {
+    // the worse of the two, either one alone is enough to make playing unpleasant
+    pub fn loss_at(&mut self, now: Instant) -> Option<f32> {
+        match (self.reliable.loss_at(now), self.inputs.loss_at(now)) {
+            (Some(reliable), Some(inputs)) => Some(reliable.max(inputs)),
+            (reliable, inputs) => reliable.or(inputs),
+        }
+    }
+
+    // true once per link, when it has been bad for long enough to tell the player
+    pub fn became_poor_at(&mut self, now: Instant) -> bool {
+        let loss = self.loss_at(now);
+        self.poor_connection.update(now, loss)
+    }
+}
+
+// trips once the loss stayed above POOR_CONNECTION_LOSS for POOR_CONNECTION_AFTER, only the first time
+#[derive(Default)]
+pub struct PoorConnectionAlarm {
+    above_since: Option<Instant>,
+    raised: bool,
+}
+
+impl PoorConnectionAlarm {
+    // true exactly once, on the update that trips it
+    pub fn update(&mut self, now: Instant, loss: Option<f32>) -> bool {
+        if self.raised {
+            return false;
+        }
+        if !loss.is_some_and(|loss| loss > POOR_CONNECTION_LOSS) {
+            self.above_since = None;
+            return false;
+        }
+        let above_since = *self.above_since.get_or_insert(now);
+        if now.duration_since(above_since) < POOR_CONNECTION_AFTER {
+            return false;
+        }
+        self.raised = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: Duration = Duration::from_millis(50);
+
+    // one send every STEP starting at `start`, returns when the last one went out
+    fn send_all(
+        estimator: &mut LossEstimator,
+        start: Instant,
+        count: u16,
+        lost: impl Fn(u16) -> bool
+    ) -> Instant {
+        for seq in 0..count {
+            estimator.on_send(start + STEP * u32::from(seq), SeqNum(seq));
+            if !lost(seq) {
+                estimator.on_ack(SeqNum(seq));
+            }
+        }
+        start + STEP * u32::from(count - 1)
+    }
+
+    #[test]
+    fn test_unacked_sends_count_once_they_are_settled() {
+        let start = Instant::now();
+        let mut estimator = LossEstimator::default();
+        let now = send_all(&mut estimator, start, 40, |seq| seq % 4 == 0);
+        // 20 sends are older than ACK_GRACE, 5 of them weren't acked
+        assert_eq!(estimator.loss_at(now), Some(0.25));
+
+        // a late ack still counts
+        estimator.on_ack(SeqNum(0));
+        assert_eq!(estimator.loss_at(now), Some(0.2));
+
+        let mut young = LossEstimator::default();
+        let now = send_all(&mut young, start, 19, |seq| seq % 4 == 0);
+        assert_eq!(young.loss_at(now + ACK_GRACE), None);
+    }
+
+    #[test]
+    fn test_resends_count_the_earlier_tries_as_lost() {
+        let start = Instant::now();
+        let mut estimator = LossEstimator::default();
+        let mut now = start;
+        for seq in 0..10 {
+            // sent three times, only the last try is acked
+            for _ in 0..3 {
+                estimator.on_send(now, SeqNum(seq));
+                now += STEP;
+            }
+            estimator.on_ack(SeqNum(seq));
+        }
+        let loss = estimator.loss_at(now + ACK_GRACE).unwrap();
+        assert!((loss - 2.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_a_cumulative_input_ack_settles_every_datagram_it_covers() {
+        let start = Instant::now();
+        let mut inputs = LossEstimator::<u32>::default();
+        for frame in 0..40 {
+            inputs.on_send(start + STEP * frame, frame);
+            // most acks are lost, every fifth one gets through
+            if frame % 5 == 4 && frame < 30 {
+                inputs.on_acked_through(frame);
+            }
+        }
+        // all 40 are old enough to count, the last 10 were never acked
+        assert_eq!(inputs.loss_at(start + STEP * 39 + ACK_GRACE), Some(0.25));
+    }
+
+    #[test]
+    fn test_old_sends_leave_the_window() {
+        let start = Instant::now();
+        let mut estimator = LossEstimator::default();
+        let now = send_all(&mut estimator, start, 40, |_| true);
+        let now = send_all(&mut estimator, now + LOSS_WINDOW, 120, |_| false);
+        assert_eq!(estimator.loss_at(now), Some(0.0));
+    }
+
+    #[test]
+    fn test_alarm_needs_the_loss_to_stay_high_and_trips_once() {
+        let start = Instant::now();
+        let mut alarm = PoorConnectionAlarm::default();
+        assert!(!alarm.update(start, Some(0.5)));
+        // a dip resets the clock
+        assert!(!alarm.update(start + POOR_CONNECTION_AFTER / 2, Some(0.05)));
+        assert!(!alarm.update(start + POOR_CONNECTION_AFTER, Some(0.5)));
+        assert!(!alarm.update(start + POOR_CONNECTION_AFTER * 3 / 2, None));
+        let restart = start + POOR_CONNECTION_AFTER * 2;
+        assert!(!alarm.update(restart, Some(0.5)));
+        assert!(alarm.update(restart + POOR_CONNECTION_AFTER, Some(0.5)));
+        assert!(!alarm.update(restart + POOR_CONNECTION_AFTER * 2, Some(0.5)));
+    }
+}
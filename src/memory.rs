@@ -1,12 +1,88 @@
-use std::{ any::TypeId, marker::PhantomData };
+use std::{ any::TypeId, fmt::Display, hash::{ DefaultHasher, Hash, Hasher }, marker::PhantomData };
+use macroquad::{ color::Color, math::Vec2 };
 
 pub const PAGE_SIZE_BYTES: usize = 512;
+
+/// What may live in the pages. They are copied byte for byte, into snapshots and onto the wire by
+/// get_copy_of_state. Implement it with impl_pod!, which fails the build when the listed fields don't
+/// add up to the size of the type.
+///
+/// # Safety
+/// The type holds no pointers and has no padding: padding bytes are never initialized and would carry
+/// whatever was in memory before. Every bit pattern has to be a valid value, pages are read back from
+/// snapshots a peer sent, so no bool or enum: a flag is a u8
+pub unsafe trait Pod: Copy + 'static {}
+
+// the size of one field, only compiles for fields that are Pod themselves
+pub const fn pod_field_size<T, F: Pod>(_field: fn(&T) -> &F) -> usize {
+    size_of::<F>()
+}
+
+// impl_pod!(Type { field, other_field }) with every field of the struct, tuple structs name them 0, 1, ..
+#[macro_export]
+macro_rules! impl_pod {
+    ($ty:ty { $($field:tt),+ $(,)? }) => {
+        const _: () = assert!(
+            std::mem::size_of::<$ty>() == 0 $(+ $crate::memory::pod_field_size(|value: &$ty| &value.$field))+,
+            concat!(stringify!($ty), " has padding or fields that aren't listed")
+        );
+        unsafe impl $crate::memory::Pod for $ty {}
+    };
+}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
+unsafe impl Pod for f32 {}
+unsafe impl Pod for f64 {}
+// no padding between the elements of an array of T without padding
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+impl_pod!(Vec2 { x, y });
+impl_pod!(Color { r, g, b, a });
 #[derive(Debug)]
 pub struct PageAllocator {
     memory: Vec<u8>, // Contiguous memory
     page_size: usize, // Size of each page
     total_pages: usize, // Total number of pages
     free_list: Vec<usize>, // List of free pages (holds offsets)
+    growth_pages: Option<usize>, // Pages added when the free list runs dry, None means fixed size
+    allocation_log: u64, // hash over every alloc / dealloc in order, see fingerprint
+}
+
+// Two allocators with the same fingerprint hand out the same pointers for the same data,
+// so one's pages can be copied over the other's. Only meaningful within one build.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutFingerprint {
+    pub page_size: usize,
+    pub pages: usize,
+    pub allocation_log: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutMismatch {
+    pub ours: LayoutFingerprint,
+    pub theirs: LayoutFingerprint,
+}
+
+impl Display for LayoutMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "allocator layouts differ, ours {:?} theirs {:?}", self.ours, self.theirs)
+    }
+}
+// pages that don't fit the allocator, sizes in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfPages {
+    pub required: usize,
+    pub available: usize,
+}
+
+impl Display for OutOfPages {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "needs {} bytes of pages, there are only {}", self.required, self.available)
+    }
 }
 #[derive(Debug, Clone, Copy)]
 pub struct FixedDataPtr<T> {
@@ -40,6 +116,21 @@ impl<T: 'static> FixedDataPtr<T> {
     }
 }
 
+// a run of T within one page, the length is only known at runtime
+#[derive(Debug, Clone, Copy)]
+pub struct ArrayDataPtr<T> {
+    page_ptr: usize,
+    len: usize,
+    type_id: TypeId,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> ArrayDataPtr<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+
 pub struct DynamicDataPtr {
     page_ptr: usize,
     capacity: usize, // to find out how many pages are occupied
@@ -57,29 +148,91 @@ impl PageAllocator {
             page_size,
             total_pages,
             free_list,
+            growth_pages: None,
+            allocation_log: 0,
+        }
+    }
+    // pages are only ever appended so existing pointers (offsets) stay valid after growing
+    pub fn new_growable(total_size: usize, page_size: usize, growth_pages: usize) -> Self {
+        debug_assert!(growth_pages > 0);
+        PageAllocator {
+            growth_pages: Some(growth_pages),
+            ..Self::new(total_size, page_size)
+        }
+    }
+    fn grow(&mut self, pages: usize) {
+        let first_new_page = self.total_pages;
+        self.total_pages += pages;
+        self.memory.resize(self.total_pages * self.page_size, 0);
+        self.free_list.extend((first_new_page..self.total_pages).map(|p| p * self.page_size));
+    }
+    pub fn fingerprint(&self) -> LayoutFingerprint {
+        LayoutFingerprint {
+            page_size: self.page_size,
+            pages: self.memory.len() / self.page_size,
+            allocation_log: self.allocation_log,
         }
     }
+    // copies the pages of `other` over ours, refused when the pointers into them wouldn't line up
+    pub fn restore_from(&mut self, other: &PageAllocator) -> Result<(), LayoutMismatch> {
+        let (ours, theirs) = (self.fingerprint(), other.fingerprint());
+        if ours != theirs {
+            return Err(LayoutMismatch { ours, theirs });
+        }
+        self.memory.copy_from_slice(&other.memory);
+        Ok(())
+    }
+    fn log_allocation(&mut self, page_ptr: usize, type_id: TypeId, len: usize) {
+        let mut hasher = DefaultHasher::new();
+        (self.allocation_log, page_ptr, type_id, len).hash(&mut hasher);
+        self.allocation_log = hasher.finish();
+    }
     pub fn get_copy_of_state(&self) -> Vec<u8> {
         return self.memory.clone();
     }
-    pub fn set_memory(&mut self, data: &[u8]) {
-        self.memory = Vec::new();
-        self.memory.extend_from_slice(data);
+    // `data` replaces our pages from the start, pages it doesn't cover are zeroed. more than we have
+    // is refused instead of resizing behind the free list's back, see try_grow
+    pub fn set_memory(&mut self, data: &[u8]) -> Result<(), OutOfPages> {
+        if data.len() > self.memory.len() {
+            return Err(OutOfPages { required: data.len(), available: self.memory.len() });
+        }
+        let (covered, rest) = self.memory.split_at_mut(data.len());
+        covered.copy_from_slice(data);
+        rest.fill(0);
+        Ok(())
+    }
+    // appends pages until `required` bytes fit, as long as that stays within `max_pages`
+    pub fn try_grow(&mut self, required: usize, max_pages: usize) -> Result<(), OutOfPages> {
+        let pages = required.div_ceil(self.page_size);
+        if pages > max_pages {
+            return Err(OutOfPages { required, available: max_pages * self.page_size });
+        }
+        if pages > self.total_pages {
+            self.grow(pages - self.total_pages);
+        }
+        Ok(())
     }
 
     pub fn alloc_fixed<T: 'static>(&mut self) -> Option<FixedDataPtr<T>> {
         debug_assert!(std::mem::size_of::<T>() < PAGE_SIZE_BYTES, "no allocation across pages");
+        if self.free_list.is_empty() {
+            if let Some(growth_pages) = self.growth_pages {
+                self.grow(growth_pages);
+            }
+        }
         let start = self.free_list.pop();
         if let Some(start) = start {
+            self.log_allocation(start, TypeId::of::<T>(), 1);
             return Some(FixedDataPtr::new(start));
         }
         return None;
     }
 
     pub fn dealloc_fixed<T>(&mut self, ptr: FixedDataPtr<T>) {
+        self.log_allocation(ptr.page_ptr, ptr.type_id, 0);
         self.free_list.push(ptr.page_ptr);
     }
-    pub fn alloc_and_write_fixed<T: Copy + 'static>(
+    pub fn alloc_and_write_fixed<T: Pod>(
         &mut self,
         data: &T
     ) -> Option<FixedDataPtr<T>> {
@@ -89,7 +242,7 @@ impl PageAllocator {
         }
         return None;
     }
-    pub fn write_fixed_to_memory<T: Copy + 'static, U: 'static>(
+    pub fn write_fixed_to_memory<T: Pod, U: Pod>(
         &mut self,
         ptr: &FixedDataPtr<T>,
         data: &U
@@ -106,6 +259,7 @@ impl PageAllocator {
             );
         }
 
+        // every byte of a Pod value is initialized, there is no padding to copy garbage from
         unsafe {
             let src = data as *const U as *const u8;
             let dst = self.memory[start..end].as_mut_ptr();
@@ -120,7 +274,57 @@ impl PageAllocator {
         }
     }
 
-    pub fn read_fixed<T: Copy + 'static>(&self, ptr: &FixedDataPtr<T>) -> T {
+    pub fn alloc_and_write_array<T: Pod>(
+        &mut self,
+        len: usize,
+        fill: &T
+    ) -> Option<ArrayDataPtr<T>> {
+        debug_assert!(len * size_of::<T>() < PAGE_SIZE_BYTES, "no allocation across pages");
+        let page = self.alloc_fixed::<T>()?;
+        let ptr = ArrayDataPtr {
+            page_ptr: page.page_ptr,
+            len,
+            type_id: TypeId::of::<T>(),
+            _phantom: PhantomData,
+        };
+        self.log_allocation(ptr.page_ptr, ptr.type_id, len);
+        self.mut_read_array(&ptr).fill(*fill);
+        Some(ptr)
+    }
+
+    pub fn read_array<T: Pod>(&self, ptr: &ArrayDataPtr<T>) -> &[T] {
+        let start = ptr.page_ptr;
+        let end = start + ptr.len * size_of::<T>();
+
+        if end > self.memory.len() {
+            panic!("PageAllocator access out of bounds");
+        }
+
+        if TypeId::of::<T>() != ptr.type_id {
+            panic!("Type mismatch: trying to read a different type than what was stored");
+        }
+
+        unsafe { std::slice::from_raw_parts(self.memory.as_ptr().add(start) as *const T, ptr.len) }
+    }
+
+    pub fn mut_read_array<T: Pod>(&mut self, ptr: &ArrayDataPtr<T>) -> &mut [T] {
+        let start = ptr.page_ptr;
+        let end = start + ptr.len * size_of::<T>();
+
+        if end > self.memory.len() {
+            panic!("PageAllocator access out of bounds");
+        }
+
+        if TypeId::of::<T>() != ptr.type_id {
+            panic!("Type mismatch: trying to read a different type than what was stored");
+        }
+
+        unsafe {
+            std::slice::from_raw_parts_mut(self.memory.as_mut_ptr().add(start) as *mut T, ptr.len)
+        }
+    }
+
+    pub fn read_fixed<T: Pod>(&self, ptr: &FixedDataPtr<T>) -> T {
         let start = ptr.page_ptr;
         let end = start + ptr.data_size;
 
@@ -138,7 +342,7 @@ impl PageAllocator {
         }
     }
 
-    pub fn mut_read_fixed<T: Copy + 'static>(&mut self, ptr: &FixedDataPtr<T>) -> &mut T {
+    pub fn mut_read_fixed<T: Pod>(&mut self, ptr: &FixedDataPtr<T>) -> &mut T {
         let start = ptr.page_ptr;
         let end = start + ptr.data_size;
 
@@ -217,4 +421,117 @@ mod tests {
         // This should panic because it exceeds the page size
         allocator.alloc_and_write_fixed(&data);
     }
+
+    #[test]
+    fn test_fixed_allocator_runs_out_of_pages() {
+        let mut allocator = PageAllocator::new(1024, PAGE_SIZE_BYTES);
+        assert!(allocator.alloc_fixed::<u32>().is_some());
+        assert!(allocator.alloc_fixed::<u32>().is_some());
+        assert!(allocator.alloc_fixed::<u32>().is_none());
+    }
+
+    #[test]
+    fn test_growable_allocator_keeps_existing_pointers_valid() {
+        let mut allocator = PageAllocator::new_growable(1024, PAGE_SIZE_BYTES, 3);
+        let first = allocator.alloc_and_write_fixed(&11u32).unwrap();
+        let second = allocator.alloc_and_write_fixed(&[7u8; 64]).unwrap();
+
+        let grown: Vec<FixedDataPtr<u64>> = (0..5u64)
+            .map(|i| allocator.alloc_and_write_fixed(&(i * 100)).unwrap())
+            .collect();
+        assert_eq!(allocator.total_pages, 8);
+        assert_eq!(allocator.get_copy_of_state().len(), 8 * PAGE_SIZE_BYTES);
+
+        assert_eq!(allocator.read_fixed(&first), 11);
+        assert_eq!(allocator.read_fixed(&second), [7u8; 64]);
+        for (i, ptr) in grown.iter().enumerate() {
+            assert_eq!(allocator.read_fixed(ptr), (i as u64) * 100);
+        }
+    }
+
+    #[test]
+    fn test_array_is_filled_and_writable() {
+        let mut allocator = PageAllocator::new(1024, PAGE_SIZE_BYTES);
+        let ptr = allocator.alloc_and_write_array(40, &3u32).unwrap();
+        assert_eq!(ptr.len(), 40);
+        assert!(allocator.read_array(&ptr).iter().all(|v| *v == 3));
+
+        allocator.mut_read_array(&ptr)[39] = 9;
+        let copy = allocator.get_copy_of_state();
+        let mut restored = PageAllocator::new(1024, PAGE_SIZE_BYTES);
+        restored.set_memory(&copy).unwrap();
+        assert_eq!(restored.read_array(&ptr)[39], 9);
+        assert_eq!(restored.read_array(&ptr)[0], 3);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    #[repr(C)]
+    struct Shot {
+        speed: f32,
+        fired: u8,
+        _padding: [u8; 3],
+        id: u32,
+    }
+    impl_pod!(Shot { speed, fired, _padding, id });
+
+    #[test]
+    fn test_written_structs_have_the_same_bytes_whatever_was_in_the_page() {
+        let shot = Shot { speed: 1.5, fired: 1, _padding: [0; 3], id: 7 };
+        let mut expected = Vec::new();
+        expected.extend(1.5f32.to_ne_bytes());
+        expected.extend([1, 0, 0, 0]);
+        expected.extend(7u32.to_ne_bytes());
+
+        // nothing of what was in the page before shows through
+        for garbage in [0x00, 0xa5, 0xff] {
+            let mut allocator = PageAllocator::new(1024, PAGE_SIZE_BYTES);
+            allocator.set_memory(&[garbage; 1024]).unwrap();
+            let ptr = allocator.alloc_and_write_fixed(&shot).unwrap();
+            assert_eq!(allocator.read_fixed(&ptr), shot);
+            let state = allocator.get_copy_of_state();
+            assert_eq!(state[ptr.page_ptr..ptr.page_ptr + size_of::<Shot>()], expected);
+        }
+    }
+
+    #[test]
+    fn test_larger_memory_is_refused_until_grown() {
+        let mut small = PageAllocator::new(1024, PAGE_SIZE_BYTES);
+        let one = small.alloc_and_write_fixed(&1u32).unwrap();
+        let large = vec![7u8; 3 * PAGE_SIZE_BYTES];
+        assert_eq!(small.set_memory(&large), Err(OutOfPages { required: 1536, available: 1024 }));
+        assert_eq!(small.read_fixed(&one), 1, "a refused copy leaves the pages alone");
+
+        assert_eq!(small.try_grow(large.len(), 2), Err(OutOfPages { required: 1536, available: 1024 }));
+        assert_eq!(small.try_grow(large.len(), 4), Ok(()));
+        assert_eq!(small.set_memory(&large), Ok(()));
+        assert_eq!(small.get_copy_of_state(), large);
+        // the grown pages are handed out like any other
+        assert!(small.alloc_fixed::<u32>().is_some());
+        assert!(small.alloc_fixed::<u32>().is_some());
+        assert!(small.alloc_fixed::<u32>().is_none());
+    }
+
+    #[test]
+    fn test_restore_is_refused_across_different_layouts() {
+        let mut verified = PageAllocator::new(2048, PAGE_SIZE_BYTES);
+        let mut predicted = PageAllocator::new(2048, PAGE_SIZE_BYTES);
+        let counter = verified.alloc_and_write_fixed(&1u32).unwrap();
+        predicted.alloc_and_write_fixed(&1u32).unwrap();
+        verified.write_fixed_to_memory(&counter, &5);
+        assert_eq!(predicted.restore_from(&verified), Ok(()));
+        assert_eq!(predicted.read_fixed(&counter), 5);
+
+        // same page count, but the next page holds an array on one side and a u64 on the other
+        verified.alloc_and_write_array(8, &2u32).unwrap();
+        predicted.alloc_and_write_fixed(&2u64).unwrap();
+        let before = predicted.get_copy_of_state();
+        let err = predicted.restore_from(&verified).unwrap_err();
+        assert_eq!(err.ours, predicted.fingerprint());
+        assert_eq!(err.theirs, verified.fingerprint());
+        assert_eq!(err.ours.pages, err.theirs.pages);
+        assert_eq!(predicted.get_copy_of_state(), before);
+
+        let smaller = PageAllocator::new(1024, PAGE_SIZE_BYTES);
+        assert!(PageAllocator::new(2048, PAGE_SIZE_BYTES).restore_from(&smaller).is_err());
+    }
 }
@@ -0,0 +1,224 @@
+use std::{ collections::VecDeque, fmt::{ Debug, Write }, time::Instant };
+
+use crate::{
+    packet_layout::{ DISCRIMINANT_BIT_START_POS, RELIABLE_FLAG_BYTE_POS, SEQ_NUM_BYTE_POS },
+    types::NetworkMessage,
+};
+
+pub const DEFAULT_MESSAGE_HISTORY: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+// one datagram as its header tells it, plus the frames it is about for inputs, their acks and range
+// requests. the payload itself isn't kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessageRecord {
+    pub at: Instant,
+    pub direction: Direction,
+    pub kind: u8, // the discriminator
+    pub seq_num: Option<u16>, // reliable ones only
+    pub frames: Option<(u32, u32)>, // oldest and newest
+    pub len: usize, // of the whole datagram
+}
+
+impl MessageRecord {
+    // `msg` is what went into or came out of `datagram`, None where only the bytes are at hand,
+    // e.g. a resend or one chunk of a message
+    pub fn new(at: Instant, direction: Direction, datagram: &[u8], msg: Option<&NetworkMessage>) -> Self {
+        let byte = |pos: usize| datagram.get(pos).copied().unwrap_or_default();
+        let seq_num = (byte(RELIABLE_FLAG_BYTE_POS) > 0).then(|| {
+            u16::from_le_bytes([byte(SEQ_NUM_BYTE_POS), byte(SEQ_NUM_BYTE_POS + 1)])
+        });
+        Self {
+            at,
+            direction,
+            kind: byte(DISCRIMINANT_BIT_START_POS),
+            seq_num,
+            frames: msg.and_then(frames_of),
+            len: datagram.len(),
+        }
+    }
+}
+
+fn frames_of(msg: &NetworkMessage) -> Option<(u32, u32)> {
+    match msg {
+        | NetworkMessage::ClientSentPlayerInputs(inputs)
+        | NetworkMessage::ClientSentPlayerInputRuns(inputs)
+        | NetworkMessage::ServerSentPlayerInputs(_, inputs) => {
+            let frames = inputs.buffered_inputs.iter().map(|input| input.frame);
+            Some((frames.clone().min()?, frames.max()?))
+        }
+        | NetworkMessage::ClientRequestInputRange { from_frame, to_frame }
+        | NetworkMessage::ServerRequestInputRange { from_frame, to_frame } => Some((*from_frame, *to_frame)),
+        | NetworkMessage::ClientInputsAckedThrough(frame)
+        | NetworkMessage::ServerInputsAckedThrough(frame)
+        | NetworkMessage::ClientWorldBaseline(frame)
+        | NetworkMessage::ClientInputStreamPaused { last_frame: frame }
+        | NetworkMessage::ClientInputStreamResumed { from_frame: frame }
+        | NetworkMessage::ClientInputStreamPausedThrough { through_frame: frame }
+        | NetworkMessage::ServerInputStreamPausedThrough { through_frame: frame, .. }
+        | NetworkMessage::ServerInputStreamPaused { last_frame: frame, .. }
+        | NetworkMessage::ServerInputStreamResumed { from_frame: frame, .. } => Some((*frame, *frame)),
+        _ => None,
+    }
+}
+
+// The last `capacity` datagrams each way, oldest first, for a look at what went over the wire right
+// before something went wrong. `K` tells whose datagram it was, the server keys them by client address
+#[derive(Debug)]
+pub struct MessageHistory<K = ()> {
+    records: VecDeque<(K, MessageRecord)>,
+    capacity: usize, // 0 records nothing
+}
+
+impl<K> Default for MessageHistory<K> {
+    fn default() -> Self {
+        Self::new(DEFAULT_MESSAGE_HISTORY)
+    }
+}
+
+impl<K> MessageHistory<K> {
+    pub fn new(capacity: usize) -> Self {
+        Self { records: VecDeque::with_capacity(capacity), capacity }
+    }
+    pub fn push(&mut self, key: K, record: MessageRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back((key, record));
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &(K, MessageRecord)> {
+        self.records.iter()
+    }
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    // everything, without the keys
+    pub fn dump(&self, now: Instant) -> String {
+        let mut dump = String::new();
+        for (_, record) in &self.records {
+            write_line(&mut dump, record, now);
+        }
+        dump
+    }
+}
+
+impl<K: PartialEq> MessageHistory<K> {
+    // only the datagrams of `key`
+    pub fn dump_for(&self, key: &K, now: Instant) -> String {
+        let mut dump = String::new();
+        for (_, record) in self.records.iter().filter(|(record_key, _)| record_key == key) {
+            write_line(&mut dump, record, now);
+        }
+        dump
+    }
+}
+
+impl<K: Debug> MessageHistory<K> {
+    // everything, each line starting with its key
+    pub fn dump_with_keys(&self, now: Instant) -> String {
+        let mut dump = String::new();
+        for (key, record) in &self.records {
+            write!(dump, "{:?} ", key).unwrap();
+            write_line(&mut dump, record, now);
+        }
+        dump
+    }
+}
+
+// how long before `now` the record was in ms first
+fn write_line(dump: &mut String, record: &MessageRecord, now: Instant) {
+    write!(
+        dump,
+        "-{}ms {} kind {}",
+        now.saturating_duration_since(record.at).as_millis(),
+        if record.direction == Direction::Sent { "sent" } else { "received" },
+        record.kind
+    ).unwrap();
+    if let Some(seq_num) = record.seq_num {
+        write!(dump, " seq {}", seq_num).unwrap();
+    }
+    if let Some((oldest, newest)) = record.frames {
+        write!(dump, " frames {} to {}", oldest, newest).unwrap();
+    }
+    writeln!(dump, ", {} bytes", record.len).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::types::{ BufferedNetworkedPlayerInputs, NetworkMessageType, NetworkedPlayerInput, SeqNum };
+
+    fn datagram(msg: &NetworkMessage, msg_type: NetworkMessageType) -> Vec<u8> {
+        match msg.serialize(msg_type) {
+            crate::types::SerializedMessageType::NonChunked(msg) => msg.bytes.to_vec(),
+            crate::types::SerializedMessageType::Chunked(_) => panic!("fits one datagram"),
+        }
+    }
+
+    #[test]
+    fn test_records_take_the_header_and_the_frames_but_not_the_payload() {
+        let now = Instant::now();
+        let inputs = NetworkMessage::ClientSentPlayerInputs(BufferedNetworkedPlayerInputs {
+            buffered_inputs: (4..=9).map(|frame| NetworkedPlayerInput::new(Vec::new(), frame)).collect(),
+        });
+        let bytes = datagram(&inputs, NetworkMessageType::SendOnce);
+        let record = MessageRecord::new(now, Direction::Sent, &bytes, Some(&inputs));
+        assert_eq!(record.kind, u8::from(&inputs));
+        assert_eq!(record.seq_num, None);
+        assert_eq!(record.frames, Some((4, 9)));
+        assert_eq!(record.len, bytes.len());
+
+        let resync = NetworkMessage::ClientRequestResync;
+        let bytes = datagram(&resync, NetworkMessageType::ResendUntilAck(SeqNum(300)));
+        // a resend only has the bytes, the header says enough
+        let record = MessageRecord::new(now, Direction::Received, &bytes, None);
+        assert_eq!((record.kind, record.seq_num, record.frames), (u8::from(&resync), Some(300), None));
+    }
+
+    #[test]
+    fn test_history_keeps_the_newest_and_filters_by_key() {
+        let start = Instant::now();
+        let record = |ms: u64, len: usize| MessageRecord {
+            at: start + Duration::from_millis(ms),
+            direction: Direction::Sent,
+            kind: 3,
+            seq_num: None,
+            frames: Some((1, 2)),
+            len,
+        };
+        let mut history = MessageHistory::new(3);
+        for (i, key) in ['a', 'b', 'a', 'b', 'a'].into_iter().enumerate() {
+            history.push(key, record(i as u64, i));
+        }
+        assert_eq!(history.len(), 3);
+        let kept: Vec<usize> = history.iter().map(|(_, record)| record.len).collect();
+        assert_eq!(kept, vec![2, 3, 4]);
+
+        let now = start + Duration::from_millis(10);
+        assert_eq!(history.dump_for(&'b', now), "-7ms sent kind 3 frames 1 to 2, 3 bytes\n");
+        assert_eq!(history.dump(now).lines().count(), 3);
+        assert!(history.dump_with_keys(now).starts_with("'a' -8ms sent"));
+
+        history.clear();
+        assert!(history.is_empty());
+        let mut off = MessageHistory::new(0);
+        off.push((), record(0, 1));
+        assert!(off.is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{ cmp::Ordering, collections::BinaryHeap, net::SocketAddr, time::{ Duration, Instant } };
+use std::{ cmp::Ordering, collections::{ BinaryHeap, HashMap }, net::SocketAddr, time::{ Duration, Instant } };
 
 use rand::{ rngs::StdRng, Rng, SeedableRng };
 
@@ -31,6 +31,14 @@ impl PartialEq for DelayedMessage {
 
 impl Eq for DelayedMessage {}
 
+// how one peer's link behaves, applied to its datagrams both ways
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConditions {
+    pub latency: u64, // ms
+    pub jitter: u64, // ms, random on top of the latency
+    pub packet_loss: f32,
+}
+
 pub struct NetworkSimulator {
     receive_queue: BinaryHeap<DelayedMessage>,
     send_queue: BinaryHeap<DelayedMessage>,
@@ -38,6 +46,7 @@ pub struct NetworkSimulator {
     baseline_latency: u64,
     jitter: u64,
     packet_loss: f32,
+    links: HashMap<SocketAddr, LinkConditions>, // peers that don't get the baseline, e.g. one on a poor link
 }
 
 impl NetworkSimulator {
@@ -49,9 +58,38 @@ impl NetworkSimulator {
             baseline_latency,
             jitter,
             packet_loss,
+            links: HashMap::new(),
         }
     }
 
+    // `addr` gets these instead of the baseline until clear_link
+    pub fn set_link(&mut self, addr: SocketAddr, conditions: LinkConditions) {
+        let packet_loss = conditions.packet_loss.clamp(0.0, 1.0);
+        self.links.insert(addr, LinkConditions { packet_loss, ..conditions });
+    }
+
+    pub fn clear_link(&mut self, addr: &SocketAddr) {
+        self.links.remove(addr);
+    }
+
+    fn link(&self, addr: &SocketAddr) -> LinkConditions {
+        self.links.get(addr).copied().unwrap_or(LinkConditions {
+            latency: self.baseline_latency,
+            jitter: self.jitter,
+            packet_loss: self.packet_loss,
+        })
+    }
+
+    // None if the datagram is lost
+    fn delivery_time(&mut self, now: Instant, addr: &SocketAddr) -> Option<Instant> {
+        let link = self.link(addr);
+        if self.rng.gen::<f32>() < link.packet_loss {
+            return None;
+        }
+        let jitter = self.rng.gen_range(0..=link.jitter);
+        Some(now + Duration::from_millis(link.latency + jitter))
+    }
+
     pub fn modify_baseline_latency(&mut self, delta: i64) {
         self.baseline_latency = ((self.baseline_latency as i64) + delta).max(0) as u64;
         println!("New latency {}", self.baseline_latency);
@@ -62,17 +100,21 @@ impl NetworkSimulator {
         println!("New packet loss {}", self.packet_loss);
     }
 
+    pub fn set_baseline_latency(&mut self, latency: u64) {
+        self.baseline_latency = latency;
+    }
+
+    pub fn set_packet_loss(&mut self, packet_loss: f32) {
+        self.packet_loss = packet_loss.clamp(0.0, 1.0);
+    }
+
     pub fn modify_jitter(&mut self, delta: i64) {
         self.jitter = ((self.jitter as i64) + delta).max(0) as u64;
         println!("New jitter{}", self.jitter);
     }
 
-    pub fn enqueue_rcv_message(&mut self, data: Vec<u8>, src: SocketAddr) {
-        if self.rng.gen::<f32>() >= self.packet_loss {
-            let jitter = self.rng.gen_range(0..=self.jitter);
-            let delay = self.baseline_latency + jitter;
-            let delivery_time = Instant::now() + Duration::from_millis(delay);
-
+    pub fn enqueue_rcv_message(&mut self, now: Instant, data: Vec<u8>, src: SocketAddr) {
+        if let Some(delivery_time) = self.delivery_time(now, &src) {
             self.receive_queue.push(DelayedMessage {
                 data,
                 addr: src,
@@ -81,12 +123,8 @@ impl NetworkSimulator {
         }
     }
 
-    pub fn enqueue_send_message(&mut self, data: Vec<u8>, dst: SocketAddr) {
-        if self.rng.gen::<f32>() >= self.packet_loss {
-            let jitter = self.rng.gen_range(0..=self.jitter);
-            let delay = self.baseline_latency + jitter;
-            let delivery_time = Instant::now() + Duration::from_millis(delay);
-
+    pub fn enqueue_send_message(&mut self, now: Instant, data: Vec<u8>, dst: SocketAddr) {
+        if let Some(delivery_time) = self.delivery_time(now, &dst) {
             self.send_queue.push(DelayedMessage {
                 data,
                 addr: dst,
@@ -95,16 +133,18 @@ impl NetworkSimulator {
         }
     }
 
-    pub fn get_ready_receive_messages(&mut self) -> Vec<(Vec<u8>, SocketAddr)> {
-        NetworkSimulator::get_ready_messages(&mut self.receive_queue)
+    pub fn get_ready_receive_messages(&mut self, now: Instant) -> Vec<(Vec<u8>, SocketAddr)> {
+        NetworkSimulator::get_ready_messages(&mut self.receive_queue, now)
     }
 
-    pub fn get_ready_send_messages(&mut self) -> Vec<(Vec<u8>, SocketAddr)> {
-        NetworkSimulator::get_ready_messages(&mut self.send_queue)
+    pub fn get_ready_send_messages(&mut self, now: Instant) -> Vec<(Vec<u8>, SocketAddr)> {
+        NetworkSimulator::get_ready_messages(&mut self.send_queue, now)
     }
 
-    fn get_ready_messages(queue: &mut BinaryHeap<DelayedMessage>) -> Vec<(Vec<u8>, SocketAddr)> {
-        let now = Instant::now();
+    fn get_ready_messages(
+        queue: &mut BinaryHeap<DelayedMessage>,
+        now: Instant
+    ) -> Vec<(Vec<u8>, SocketAddr)> {
         let mut ready_messages = Vec::new();
 
         while let Some(message) = queue.peek() {
@@ -120,3 +160,63 @@ impl NetworkSimulator {
         ready_messages
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_each_link_keeps_its_own_latency_both_ways() {
+        let (good, poor, other): (SocketAddr, SocketAddr, SocketAddr) = (
+            "127.0.0.1:1000".parse().unwrap(),
+            "127.0.0.1:2000".parse().unwrap(),
+            "127.0.0.1:3000".parse().unwrap(),
+        );
+        let mut simulator = NetworkSimulator::new(1, 20, 0, 0.0);
+        simulator.set_link(good, LinkConditions { latency: 10, jitter: 0, packet_loss: 0.0 });
+        simulator.set_link(poor, LinkConditions { latency: 200, jitter: 0, packet_loss: 0.0 });
+        let start = Instant::now();
+        for addr in [good, poor, other] {
+            simulator.enqueue_rcv_message(start, vec![1], addr);
+            simulator.enqueue_send_message(start, vec![2], addr);
+        }
+        let ready_at = |simulator: &mut NetworkSimulator, ms: u64| {
+            let now = start + Duration::from_millis(ms);
+            let mut ready: Vec<SocketAddr> = simulator
+                .get_ready_receive_messages(now)
+                .into_iter()
+                .chain(simulator.get_ready_send_messages(now))
+                .map(|(_, addr)| addr)
+                .collect();
+            ready.sort();
+            ready
+        };
+        assert_eq!(ready_at(&mut simulator, 10), vec![good, good]);
+        // everyone without a link of their own gets the baseline
+        assert_eq!(ready_at(&mut simulator, 20), vec![other, other]);
+        assert_eq!(ready_at(&mut simulator, 199), vec![]);
+        assert_eq!(ready_at(&mut simulator, 200), vec![poor, poor]);
+
+        simulator.clear_link(&poor);
+        simulator.enqueue_send_message(start, vec![3], poor);
+        assert_eq!(ready_at(&mut simulator, 20), vec![poor]);
+    }
+
+    #[test]
+    fn test_a_lossy_link_loses_only_its_own_datagrams() {
+        let (good, lossy): (SocketAddr, SocketAddr) = (
+            "127.0.0.1:1000".parse().unwrap(),
+            "127.0.0.1:2000".parse().unwrap(),
+        );
+        let mut simulator = NetworkSimulator::new(1, 0, 0, 0.0);
+        simulator.set_link(lossy, LinkConditions { latency: 0, jitter: 0, packet_loss: 1.0 });
+        let now = Instant::now();
+        for _ in 0..10 {
+            simulator.enqueue_send_message(now, vec![1], good);
+            simulator.enqueue_send_message(now, vec![1], lossy);
+        }
+        let delivered = simulator.get_ready_send_messages(now);
+        assert_eq!(delivered.len(), 10);
+        assert!(delivered.iter().all(|(_, addr)| *addr == good));
+    }
+}
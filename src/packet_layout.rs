@@ -0,0 +1,99 @@
+use std::mem::{ offset_of, size_of };
+
+pub const AMT_RANDOM_BYTES: usize = 1;
+
+// the header in front of every datagram, fields in wire order. only byte arrays so there is no padding
+// and the offsets below are the byte positions, reader and writer both index the datagram by them
+#[repr(C)]
+pub struct PacketHeader {
+    pub random: [u8; AMT_RANDOM_BYTES],
+    pub reliable_flag: [u8; 1],
+    pub seq_num: [u8; 2], // u16, 0 when not reliable
+    pub base_chunk_seq_num: [u8; 2], // u16, 0 when not chunked
+    pub amt_of_chunks: [u8; 2], // u16, 0 when not chunked
+    pub transfer_id: [u8; 2], // u16, 0 when not chunked, a newer one supersedes chunks of older ones
+    pub discriminant: [u8; 1],
+}
+
+pub const RELIABLE_FLAG_BYTE_POS: usize = offset_of!(PacketHeader, reliable_flag);
+pub const SEQ_NUM_BYTE_POS: usize = offset_of!(PacketHeader, seq_num);
+pub const BASE_CHUNK_SEQ_NUM_BYTE_POS: usize = offset_of!(PacketHeader, base_chunk_seq_num);
+pub const AMT_OF_CHUNKS_BYTE_POS: usize = offset_of!(PacketHeader, amt_of_chunks);
+pub const TRANSFER_ID_BYTE_POS: usize = offset_of!(PacketHeader, transfer_id);
+pub const DISCRIMINANT_BIT_START_POS: usize = offset_of!(PacketHeader, discriminant);
+pub const DATA_BIT_START_POS: usize = size_of::<PacketHeader>();
+
+// the layout on the wire since PROTOCOL_VERSION 3, a new header field has to bump the version and these
+const _: () = assert!(RELIABLE_FLAG_BYTE_POS == 1);
+const _: () = assert!(SEQ_NUM_BYTE_POS == 2);
+const _: () = assert!(BASE_CHUNK_SEQ_NUM_BYTE_POS == 4);
+const _: () = assert!(AMT_OF_CHUNKS_BYTE_POS == 6);
+const _: () = assert!(TRANSFER_ID_BYTE_POS == 8);
+const _: () = assert!(DISCRIMINANT_BIT_START_POS == 10);
+const _: () = assert!(DATA_BIT_START_POS == 11);
+// every field follows the previous one without a gap
+const _: () = assert!(RELIABLE_FLAG_BYTE_POS == AMT_RANDOM_BYTES);
+const _: () = assert!(DATA_BIT_START_POS == DISCRIMINANT_BIT_START_POS + 1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ MessageHeader, NetworkMessage, PacketParser, MAX_CHUNKS_PER_MESSAGE };
+
+    fn to_bytes(header: &PacketHeader) -> Vec<u8> {
+        [
+            &header.random[..],
+            &header.reliable_flag,
+            &header.seq_num,
+            &header.base_chunk_seq_num,
+            &header.amt_of_chunks,
+            &header.transfer_id,
+            &header.discriminant,
+        ].concat()
+    }
+
+    fn header(reliable: bool, seq_num: u16, base_chunk_seq_num: u16, amt_of_chunks: u16) -> PacketHeader {
+        PacketHeader {
+            random: [0xa5; AMT_RANDOM_BYTES],
+            reliable_flag: [reliable as u8],
+            seq_num: seq_num.to_le_bytes(),
+            base_chunk_seq_num: base_chunk_seq_num.to_le_bytes(),
+            amt_of_chunks: amt_of_chunks.to_le_bytes(),
+            transfer_id: 0x0304u16.to_le_bytes(),
+            discriminant: [NetworkMessage::ServerRequestHostForWorldData.into()],
+        }
+    }
+
+    #[test]
+    fn test_header_built_from_the_struct_parses_back() {
+        let bytes = to_bytes(&header(true, 0x1234, 0x0102, 3));
+        assert_eq!(bytes.len(), DATA_BIT_START_POS);
+        let MessageHeader {
+            reliable,
+            seq_num,
+            base_chunk_seq_num,
+            amt_of_chunks,
+            transfer_id,
+            is_chunked,
+            message,
+        } = PacketParser::parse_header(&bytes).unwrap();
+        assert!(reliable);
+        assert_eq!(seq_num.map(|seq_num| seq_num.0), Some(0x1234));
+        assert_eq!((base_chunk_seq_num, amt_of_chunks, is_chunked), (0x0102, 3, true));
+        assert_eq!(transfer_id, 0x0304);
+        assert!(matches!(message, NetworkMessage::ServerRequestHostForWorldData));
+
+        let unreliable = PacketParser::parse_header(&to_bytes(&header(false, 0, 0, 0))).unwrap();
+        assert_eq!((unreliable.reliable, unreliable.seq_num.is_none()), (false, true));
+        assert!(!unreliable.is_chunked);
+    }
+
+    #[test]
+    fn test_header_fields_out_of_range_are_rejected() {
+        let too_many_chunks = header(true, 1, 1, (MAX_CHUNKS_PER_MESSAGE as u16) + 1);
+        assert!(PacketParser::parse_header(&to_bytes(&too_many_chunks)).is_err());
+        let mut unknown_message = header(false, 0, 0, 0);
+        unknown_message.discriminant = [u8::MAX];
+        assert!(PacketParser::parse_header(&to_bytes(&unknown_message)).is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::Duration;
+
+use crate::types::TickRate;
+
+// weight of a new sample, like the smoothed RTT one late packet doesn't throw the estimate around
+const OFFSET_SMOOTHING: f32 = 1.0 / 8.0;
+
+// where the other player's simulation is now, not where it was when its newest input left it:
+// the newest remote frame plus the frames it played while that input was on its way (half the RTT)
+#[derive(Debug, Clone)]
+pub struct PeerClock {
+    tick_rate: TickRate,
+    newest_frame: Option<u32>,
+    offset_frames: Option<f32>, // smoothed frames between the newest frame we got and the remote's current one
+}
+
+impl PeerClock {
+    pub fn new(tick_rate: TickRate) -> Self {
+        Self { tick_rate, newest_frame: None, offset_frames: None }
+    }
+
+    // called whenever remote inputs arrived, `rtt` is None until the first pong
+    pub fn on_remote_frame(&mut self, newest_frame: u32, rtt: Option<Duration>) {
+        self.newest_frame = self.newest_frame.max(Some(newest_frame));
+        let Some(rtt) = rtt else {
+            return;
+        };
+        let sample = (rtt / 2).as_secs_f32() * (self.tick_rate.hz() as f32);
+        self.offset_frames = Some(match self.offset_frames {
+            Some(offset) => offset + (sample - offset) * OFFSET_SMOOTHING,
+            None => sample,
+        });
+    }
+
+    // None until any remote input arrived, without an RTT yet it is the newest frame received
+    pub fn estimated_remote_frame(&self) -> Option<u32> {
+        let offset = self.offset_frames.unwrap_or(0.0).round() as u32;
+        self.newest_frame.map(|frame| frame + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_converges_to_the_remote_frame() {
+        const ONE_WAY_MS: u64 = 80;
+        let tick_rate = TickRate::default();
+        let frame_ms = 1000 / u64::from(tick_rate.hz());
+        let mut clock = PeerClock::new(tick_rate);
+        assert_eq!(clock.estimated_remote_frame(), None);
+
+        // the remote sends every frame, each input arrives ONE_WAY_MS later, measured RTTs jitter around
+        // twice that and the very first one is far off
+        let mut errors = Vec::new();
+        for remote_frame in 1..=300u32 {
+            let rtt_ms = match remote_frame {
+                1 => 400,
+                frame if frame % 2 == 0 => 2 * ONE_WAY_MS + 12,
+                _ => 2 * ONE_WAY_MS - 12,
+            };
+            clock.on_remote_frame(remote_frame, Some(Duration::from_millis(rtt_ms)));
+            let received_at_ms = u64::from(remote_frame) * frame_ms + ONE_WAY_MS;
+            let remote_frame_now = (received_at_ms / frame_ms) as u32;
+            let estimate = clock.estimated_remote_frame().unwrap();
+            errors.push(remote_frame_now.abs_diff(estimate));
+        }
+        assert!(errors[0] > 3, "the outlier RTT is taken as is at first");
+        assert!(errors[60..].iter().all(|error| *error <= 1), "{:?}", &errors[60..]);
+    }
+
+    #[test]
+    fn test_without_rtt_the_newest_frame_is_the_estimate() {
+        let mut clock = PeerClock::new(TickRate::default());
+        clock.on_remote_frame(10, None);
+        clock.on_remote_frame(7, None); // a reordered packet doesn't move it back
+        assert_eq!(clock.estimated_remote_frame(), Some(10));
+    }
+}
@@ -0,0 +1,167 @@
+use std::{ collections::VecDeque, time::{ Duration, Instant } };
+
+use crate::types::NetworkMessage;
+
+pub const PING_INTERVAL: Duration = Duration::from_millis(500);
+const OUTSTANDING_PINGS: usize = 8; // a ping this many pings old counts as lost, its pong is ignored
+
+// sends an unreliable Ping every PING_INTERVAL and turns the echoed send times into round trip times,
+// so there is an RTT even while no reliable message is waiting for its ack
+pub struct PingTracker {
+    clock_start: Instant, // send times are micros since this, only we ever read them back
+    next_nonce: u32,
+    last_ping: Option<Instant>,
+    outstanding: VecDeque<u32>, // nonces of pings without a pong yet, oldest first
+    smoothed_rtt: Option<Duration>,
+}
+
+impl PingTracker {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            clock_start: now,
+            next_nonce: 0,
+            last_ping: None,
+            outstanding: VecDeque::with_capacity(OUTSTANDING_PINGS),
+            smoothed_rtt: None,
+        }
+    }
+
+    // the Ping to send if one is due
+    pub fn poll(&mut self, now: Instant) -> Option<NetworkMessage> {
+        if self.last_ping.is_some_and(|last| now.duration_since(last) < PING_INTERVAL) {
+            return None;
+        }
+        self.last_ping = Some(now);
+        let nonce = self.next_nonce;
+        self.next_nonce = self.next_nonce.wrapping_add(1);
+        if self.outstanding.len() == OUTSTANDING_PINGS {
+            self.outstanding.pop_front();
+        }
+        self.outstanding.push_back(nonce);
+        Some(NetworkMessage::Ping { nonce, send_time_micros: self.micros(now) })
+    }
+
+    // the round trip of the answered ping, None for a pong of a ping that was already answered,
+    // counted as lost or never sent by us
+    pub fn on_pong(&mut self, now: Instant, nonce: u32, send_time_micros: u64) -> Option<Duration> {
+        let idx = self.outstanding.iter().position(|outstanding| *outstanding == nonce)?;
+        self.outstanding.remove(idx);
+        let rtt = Duration::from_micros(self.micros(now).checked_sub(send_time_micros)?);
+        // weighted like TCP's smoothed RTT, one late pong doesn't throw it around
+        self.smoothed_rtt = Some(match self.smoothed_rtt {
+            Some(smoothed) => (smoothed * 7 + rtt) / 8,
+            None => rtt,
+        });
+        Some(rtt)
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.smoothed_rtt
+    }
+
+    fn micros(&self, now: Instant) -> u64 {
+        now.duration_since(self.clock_start).as_micros() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use crate::{
+        network_simulator::NetworkSimulator,
+        types::{ DeserializedMessageType, MsgBuffer, NetworkMessageType, SerializedMessageType },
+    };
+
+    fn datagram(msg: NetworkMessage) -> Vec<u8> {
+        match msg.serialize(NetworkMessageType::SendOnce) {
+            SerializedMessageType::NonChunked(msg) => msg.bytes.to_vec(),
+            SerializedMessageType::Chunked(_) => panic!("pings fit one datagram"),
+        }
+    }
+
+    fn parse(bytes: &[u8], on_server: bool) -> NetworkMessage {
+        let mut buffer = MsgBuffer::default();
+        buffer.0[..bytes.len()].copy_from_slice(bytes);
+        let parsed = if on_server {
+            buffer.parse_on_server(bytes.len())
+        } else {
+            buffer.parse_on_client(bytes.len())
+        };
+        match parsed.unwrap() {
+            DeserializedMessageType::NonChunked(msg) => {
+                assert!(!msg.reliable);
+                msg.msg
+            }
+            DeserializedMessageType::ChunkOfMessage(_) => panic!("pings fit one datagram"),
+        }
+    }
+
+    #[test]
+    fn test_ping_round_trip_measures_the_simulated_delay() {
+        const LATENCY_MS: u64 = 45;
+        let client_addr: SocketAddr = "127.0.0.1:40001".parse().unwrap();
+        let mut network = NetworkSimulator::new(1458, LATENCY_MS, 0, 0.0);
+        let start = Instant::now();
+        let mut tracker = PingTracker::new(start);
+        let step = Duration::from_millis(1);
+
+        let mut rtts = Vec::new();
+        let mut now = start;
+        while now < start + PING_INTERVAL * 4 {
+            if let Some(ping) = tracker.poll(now) {
+                network.enqueue_rcv_message(now, datagram(ping), client_addr);
+            }
+            // what the server does with a ping
+            for (bytes, src) in network.get_ready_receive_messages(now) {
+                let NetworkMessage::Ping { nonce, send_time_micros } = parse(&bytes, true) else {
+                    panic!("only pings are sent");
+                };
+                let pong = NetworkMessage::Pong { nonce, send_time_micros };
+                network.enqueue_send_message(now, datagram(pong), src);
+            }
+            for (bytes, _) in network.get_ready_send_messages(now) {
+                let NetworkMessage::Pong { nonce, send_time_micros } = parse(&bytes, false) else {
+                    panic!("the server only answers with pongs");
+                };
+                rtts.extend(tracker.on_pong(now, nonce, send_time_micros));
+            }
+            now += step;
+        }
+
+        assert_eq!(rtts.len(), 4);
+        for rtt in rtts {
+            assert_eq!(rtt, Duration::from_millis(2 * LATENCY_MS));
+        }
+        assert_eq!(tracker.rtt(), Some(Duration::from_millis(2 * LATENCY_MS)));
+    }
+
+    #[test]
+    fn test_unknown_and_repeated_pongs_are_ignored() {
+        let start = Instant::now();
+        let mut tracker = PingTracker::new(start);
+        let Some(NetworkMessage::Ping { nonce, send_time_micros }) = tracker.poll(start) else {
+            panic!("the first poll pings");
+        };
+        assert!(tracker.poll(start + PING_INTERVAL / 2).is_none());
+        assert!(tracker.on_pong(start, nonce + 1, send_time_micros).is_none());
+
+        let later = start + Duration::from_millis(30);
+        assert_eq!(tracker.on_pong(later, nonce, send_time_micros), Some(Duration::from_millis(30)));
+        assert!(tracker.on_pong(later, nonce, send_time_micros).is_none());
+
+        // only the last few pings are waited for
+        let mut now = later;
+        let mut oldest = None;
+        for _ in 0..=OUTSTANDING_PINGS {
+            now += PING_INTERVAL;
+            let ping = tracker.poll(now);
+            oldest = oldest.or(ping);
+        }
+        let Some(NetworkMessage::Ping { nonce, send_time_micros }) = oldest else {
+            panic!("a ping was due every time");
+        };
+        assert!(tracker.on_pong(now, nonce, send_time_micros).is_none());
+        assert_eq!(tracker.rtt(), Some(Duration::from_millis(30)));
+    }
+}
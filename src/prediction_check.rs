@@ -0,0 +1,108 @@
+use std::{ collections::BTreeMap, fmt::Display };
+
+use macroquad::math::Vec2;
+
+use crate::types::{ PredictedFrame, VerifiedFrame };
+
+// what a simulation looked like after a frame, the hash covers everything, the rest says by how much
+// two of them differ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameDigest {
+    pub hash: u64,
+    pub players: [Vec2; 2],
+    pub active_enemies: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Misprediction {
+    pub frame: VerifiedFrame,
+    pub player_offset: f32, // px, the furthest either player was from where it really ended up
+    pub enemy_count_diff: u32,
+}
+
+impl Display for Misprediction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Frame {} was mispredicted, players off by {:.1}px, {} enemies off",
+            self.frame.0,
+            self.player_offset,
+            self.enemy_count_diff
+        )
+    }
+}
+
+// keeps what the prediction computed for each frame until that frame is verified,
+// a matching prediction means no rollback was visible for it
+#[derive(Debug, Default)]
+pub struct PredictionCheck {
+    predicted: BTreeMap<PredictedFrame, FrameDigest>,
+    pub checked: u32, // verified frames that had a prediction to compare with
+    pub mispredicted: u32,
+}
+
+impl PredictionCheck {
+    // a frame predicted again after a rollback replaces the earlier guess, the last one is what was shown
+    pub fn record_predicted(&mut self, frame: PredictedFrame, digest: FrameDigest) {
+        self.predicted.insert(frame, digest);
+    }
+
+    // frames the prediction never got to, e.g. while playing alone, aren't counted
+    pub fn check_verified(&mut self, frame: VerifiedFrame, verified: &FrameDigest) -> Option<Misprediction> {
+        // anything older can't be verified anymore
+        self.predicted = self.predicted.split_off(&frame.as_predicted());
+        let predicted = self.predicted.remove(&frame.as_predicted())?;
+        self.checked += 1;
+        if predicted.hash == verified.hash {
+            return None;
+        }
+        self.mispredicted += 1;
+        let player_offset = predicted.players
+            .iter()
+            .zip(verified.players.iter())
+            .map(|(predicted, verified)| predicted.distance(*verified))
+            .fold(0.0, f32::max);
+        Some(Misprediction {
+            frame,
+            player_offset,
+            enemy_count_diff: predicted.active_enemies.abs_diff(verified.active_enemies),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::math::vec2;
+
+    fn digest(hash: u64, player1_x: f32, active_enemies: u32) -> FrameDigest {
+        FrameDigest { hash, players: [vec2(player1_x, 0.0), vec2(0.0, 0.0)], active_enemies }
+    }
+
+    #[test]
+    fn test_only_the_last_prediction_of_a_frame_is_compared() {
+        let mut check = PredictionCheck::default();
+        check.record_predicted(PredictedFrame(5), digest(1, 10.0, 2));
+        check.record_predicted(PredictedFrame(5), digest(2, 12.0, 2)); // after a rollback
+        assert_eq!(check.check_verified(VerifiedFrame(5), &digest(2, 12.0, 2)), None);
+        assert_eq!((check.checked, check.mispredicted), (1, 0));
+
+        check.record_predicted(PredictedFrame(6), digest(3, 12.0, 2));
+        assert_eq!(
+            check.check_verified(VerifiedFrame(6), &digest(4, 15.0, 3)),
+            Some(Misprediction { frame: VerifiedFrame(6), player_offset: 3.0, enemy_count_diff: 1 })
+        );
+        assert_eq!((check.checked, check.mispredicted), (2, 1));
+    }
+
+    #[test]
+    fn test_unpredicted_and_stale_frames_are_not_counted() {
+        let mut check = PredictionCheck::default();
+        check.record_predicted(PredictedFrame(3), digest(1, 0.0, 0));
+        check.record_predicted(PredictedFrame(9), digest(1, 0.0, 0));
+        assert_eq!(check.check_verified(VerifiedFrame(7), &digest(2, 0.0, 0)), None);
+        assert_eq!(check.checked, 0);
+        // frame 3 was dropped on the way, frame 9 is still waiting
+        assert_eq!(check.predicted.keys().copied().collect::<Vec<PredictedFrame>>(), vec![PredictedFrame(9)]);
+    }
+}
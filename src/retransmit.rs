@@ -0,0 +1,210 @@
+use std::{ collections::HashMap, hash::Hash, time::{ Duration, Instant } };
+
+// how long an entry waits for its ack before it is resent, and how often that happens before giving up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub timeout: Duration, // until the first resend
+    pub backoff: u32, // every resend waits this many times longer than the one before, 1 keeps the timeout
+    pub max_timeout: Duration, // the backoff stops growing here
+    pub max_retries: u32,
+}
+
+impl RetryPolicy {
+    pub const fn fixed(timeout: Duration, max_retries: u32) -> Self {
+        Self { timeout, backoff: 1, max_timeout: timeout, max_retries }
+    }
+    // the wait after `retries` resends
+    pub fn wait_after(&self, retries: u32) -> Duration {
+        self.timeout.saturating_mul(self.backoff.saturating_pow(retries)).min(self.max_timeout)
+    }
+    // from the first send until an unacked entry is given up on, with ticks on time
+    pub fn budget(&self) -> Duration {
+        (0..=self.max_retries).map(|retries| self.wait_after(retries)).sum()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pending<V> {
+    sent_at: Instant,
+    retries: u32,
+    policy: RetryPolicy,
+    value: V,
+}
+
+// a value that left the table because it was acked, with how often it had to be resent first
+#[derive(Debug, Clone, PartialEq)]
+pub struct Acked<V> {
+    pub value: V,
+    pub retries: u32,
+}
+
+// Reliable messages waiting for their ack. Every timeout of its RetryPolicy without an ack an entry is
+// handed out again for resending, after max_retries resends it is dropped and reported by take_exhausted.
+// Only resends count towards the limit, a tick that comes late hands an entry out once, not once per timeout.
+// Entries inserted without a policy of their own get the table's fixed one.
+#[derive(Debug, Clone)]
+pub struct RetransmitTable<K, V> {
+    pending: HashMap<K, Pending<V>>,
+    policy: RetryPolicy,
+    exhausted: Vec<(K, u32)>,
+}
+
+impl<K: Eq + Hash + Copy, V: Clone> RetransmitTable<K, V> {
+    pub fn new(timeout: Duration, max_retries: u32) -> Self {
+        Self {
+            pending: HashMap::new(),
+            policy: RetryPolicy::fixed(timeout, max_retries),
+            exhausted: Vec::new(),
+        }
+    }
+    // `now` is when the value went out the first time
+    pub fn insert(&mut self, now: Instant, key: K, value: V) {
+        self.insert_with_policy(now, key, value, self.policy);
+    }
+    pub fn insert_with_policy(&mut self, now: Instant, key: K, value: V, policy: RetryPolicy) {
+        self.pending.insert(key, Pending { sent_at: now, retries: 0, policy, value });
+    }
+    pub fn on_ack(&mut self, key: &K) -> Option<Acked<V>> {
+        self.pending.remove(key).map(|pending| Acked { value: pending.value, retries: pending.retries })
+    }
+    // how often a still pending entry was resent so far
+    pub fn retries(&self, key: &K) -> Option<u32> {
+        self.pending.get(key).map(|pending| pending.retries)
+    }
+    // everything that is due for a resend, the caller is expected to send it right away
+    pub fn tick(&mut self, now: Instant) -> Vec<(K, V)> {
+        let mut resend = Vec::new();
+        let exhausted = &mut self.exhausted;
+        self.pending.retain(|key, pending| {
+            if now.saturating_duration_since(pending.sent_at) < pending.policy.wait_after(pending.retries) {
+                return true;
+            }
+            if pending.retries >= pending.policy.max_retries {
+                exhausted.push((*key, pending.retries));
+                return false;
+            }
+            pending.retries += 1;
+            pending.sent_at = now;
+            resend.push((*key, pending.value.clone()));
+            true
+        });
+        resend
+    }
+    // keys dropped since the last call because they were never acked, with how often they were resent
+    pub fn take_exhausted(&mut self) -> Vec<(K, u32)> {
+        std::mem::take(&mut self.exhausted)
+    }
+    // every pending key with how often it was resent so far
+    pub fn pending_retries(&self) -> impl Iterator<Item = (&K, u32)> {
+        self.pending.iter().map(|(key, pending)| (key, pending.retries))
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.pending.iter().map(|(key, pending)| (key, &pending.value))
+    }
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn test_unacked_entries_are_resent_after_the_timeout() {
+        let start = Instant::now();
+        let mut table = RetransmitTable::new(TIMEOUT, 3);
+        table.insert(start, 1u16, "hello");
+        assert!(table.tick(start + TIMEOUT / 2).is_empty());
+        assert_eq!(table.tick(start + TIMEOUT), vec![(1, "hello")]);
+        // the resend restarts the timeout
+        assert!(table.tick(start + TIMEOUT + TIMEOUT / 2).is_empty());
+        assert_eq!(table.tick(start + TIMEOUT * 2), vec![(1, "hello")]);
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_acked_entries_are_never_resent() {
+        let start = Instant::now();
+        let mut table = RetransmitTable::new(TIMEOUT, 3);
+        table.insert(start, 1u16, "first");
+        table.insert(start, 2u16, "second");
+        assert_eq!(table.on_ack(&1).map(|acked| acked.value), Some("first"));
+        assert_eq!(table.on_ack(&1), None);
+        assert_eq!(table.tick(start + TIMEOUT), vec![(2, "second")]);
+        table.on_ack(&2);
+        assert!(table.is_empty());
+        assert!(table.tick(start + TIMEOUT * 10).is_empty());
+        assert!(table.take_exhausted().is_empty());
+    }
+
+    #[test]
+    fn test_entries_are_dropped_after_max_retries() {
+        let start = Instant::now();
+        let mut table = RetransmitTable::new(TIMEOUT, 2);
+        table.insert(start, 7u16, "lost");
+        assert_eq!(table.tick(start + TIMEOUT).len(), 1);
+        assert_eq!(table.tick(start + TIMEOUT * 2).len(), 1);
+        assert!(table.take_exhausted().is_empty());
+        assert!(table.tick(start + TIMEOUT * 3).is_empty());
+        assert!(table.is_empty());
+        assert_eq!(table.take_exhausted(), vec![(7, 2)]);
+        assert!(table.take_exhausted().is_empty());
+    }
+
+    #[test]
+    fn test_ack_reports_how_often_the_entry_was_resent() {
+        let start = Instant::now();
+        let mut table = RetransmitTable::new(TIMEOUT, 5);
+        table.insert(start, 3u16, "slow");
+        for retry in 1..=3 {
+            assert_eq!(table.tick(start + TIMEOUT * retry).len(), 1);
+            assert_eq!(table.retries(&3), Some(retry));
+        }
+        assert_eq!(table.on_ack(&3), Some(Acked { value: "slow", retries: 3 }));
+        assert_eq!(table.retries(&3), None);
+    }
+
+    #[test]
+    fn test_late_ticks_count_one_resend_not_the_elapsed_timeouts() {
+        let start = Instant::now();
+        let mut table = RetransmitTable::new(TIMEOUT, 2);
+        table.insert(start, 4u16, "stalled");
+        // a stalled loop comes back long after max_retries timeouts have passed
+        assert_eq!(table.tick(start + TIMEOUT * 10), vec![(4, "stalled")]);
+        assert!(table.take_exhausted().is_empty());
+        assert_eq!(table.retries(&4), Some(1));
+    }
+
+    #[test]
+    fn test_each_entry_follows_its_own_policy() {
+        let start = Instant::now();
+        let mut table = RetransmitTable::new(TIMEOUT, 2);
+        let bulk = RetryPolicy { timeout: TIMEOUT, backoff: 2, max_timeout: TIMEOUT * 4, max_retries: 4 };
+        table.insert(start, 1u16, "control");
+        table.insert_with_policy(start, 2u16, "bulk", bulk);
+        // the control entry keeps its timeout, the bulk one waits 1, 2, 4 and then 4 timeouts again
+        let mut resent_at: HashMap<u16, Vec<u32>> = HashMap::new();
+        let mut given_up_at = HashMap::new();
+        for step in 1..=20 {
+            for (key, _) in table.tick(start + TIMEOUT * step) {
+                resent_at.entry(key).or_default().push(step);
+            }
+            for (key, retries) in table.take_exhausted() {
+                given_up_at.insert(key, (step, retries));
+            }
+        }
+        assert_eq!(resent_at[&1], vec![1, 2]);
+        assert_eq!(resent_at[&2], vec![1, 3, 7, 11]);
+        assert_eq!(given_up_at[&1], (3, 2));
+        assert_eq!(given_up_at[&2], (15, 4));
+        assert!(table.is_empty());
+        assert_eq!(RetryPolicy::fixed(TIMEOUT, 2).budget(), TIMEOUT * 3);
+        assert_eq!(bulk.budget(), TIMEOUT * 15);
+    }
+}
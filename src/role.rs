@@ -0,0 +1,96 @@
+use crate::types::NetworkMessage;
+
+// what this client is in its session, picked once on the menu
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionRole {
+    Host,
+    Joiner,
+}
+
+impl SessionRole {
+    // the role a request belongs to, None for the ones both send
+    pub fn of_request(msg: &NetworkMessage) -> Option<SessionRole> {
+        match msg {
+            | NetworkMessage::ClientStartedHosting
+            | NetworkMessage::ClientSetTickRate(_)
+            | NetworkMessage::ClientSentWorld(_)
+            | NetworkMessage::ClientWorldBaseline(_) => Some(SessionRole::Host),
+            | NetworkMessage::GetServerPlayerIDs
+            | NetworkMessage::ClientConnectToOtherWorld(_)
+            | NetworkMessage::ClientJoinFailed(_) => Some(SessionRole::Joiner),
+            _ => None,
+        }
+    }
+}
+
+// H and J pressed in the same frame is no choice at all
+pub fn role_from_keys(host_pressed: bool, join_pressed: bool) -> Option<SessionRole> {
+    match (host_pressed, join_pressed) {
+        (true, false) => Some(SessionRole::Host),
+        (false, true) => Some(SessionRole::Joiner),
+        _ => None,
+    }
+}
+
+// The menu's choice. Once made it stays until the session is over and the menu is shown again,
+// a second choice in between is refused instead of switching roles halfway.
+#[derive(Debug, Default)]
+pub struct RoleChoice {
+    role: Option<SessionRole>,
+}
+
+impl RoleChoice {
+    pub fn choose(&mut self, role: SessionRole) -> Result<SessionRole, &'static str> {
+        if self.role.is_some() {
+            return Err("A role was already chosen for this session");
+        }
+        self.role = Some(role);
+        Ok(role)
+    }
+    // back on the menu
+    pub fn reset(&mut self) {
+        self.role = None;
+    }
+    // requests of the other role, or of any role before one was chosen, aren't sent
+    pub fn check(&self, msg: &NetworkMessage) -> Result<(), &'static str> {
+        match SessionRole::of_request(msg) {
+            Some(role) if self.role != Some(role) => Err("Request doesn't belong to this client's role"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ServerPlayerID;
+
+    #[test]
+    fn test_a_chosen_role_ignores_the_conflicting_choice() {
+        let mut choice = RoleChoice::default();
+        assert_eq!(role_from_keys(true, true), None, "both keys in one frame choose nothing");
+        assert_eq!(role_from_keys(false, false), None);
+
+        assert_eq!(choice.choose(SessionRole::Host), Ok(SessionRole::Host));
+        assert!(choice.choose(SessionRole::Joiner).is_err());
+        assert!(choice.choose(SessionRole::Host).is_err());
+        assert!(choice.check(&NetworkMessage::GetServerPlayerIDs).is_err());
+        assert!(choice.check(&NetworkMessage::ClientConnectToOtherWorld(ServerPlayerID(1))).is_err());
+        assert!(choice.check(&NetworkMessage::ClientStartedHosting).is_ok());
+        assert!(choice.check(&NetworkMessage::ClientLeaveSession).is_ok());
+
+        // only the menu starts over
+        choice.reset();
+        assert_eq!(choice.choose(SessionRole::Joiner), Ok(SessionRole::Joiner));
+        assert!(choice.check(&NetworkMessage::ClientSentWorld(Vec::new())).is_err());
+        assert!(choice.check(&NetworkMessage::GetServerPlayerIDs).is_ok());
+    }
+
+    #[test]
+    fn test_role_requests_wait_for_a_choice() {
+        let choice = RoleChoice::default();
+        assert!(choice.check(&NetworkMessage::GetServerPlayerIDs).is_err());
+        assert!(choice.check(&NetworkMessage::ClientStartedHosting).is_err());
+        assert!(choice.check(&NetworkMessage::ClientLeaveSession).is_ok());
+    }
+}
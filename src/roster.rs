@@ -0,0 +1,92 @@
+use crate::types::{ PlayerID, ServerPlayerID, MAX_PLAYER_COUNT };
+
+// Who plays in this client's session, only ever changed by what the server tells us: our slot
+// from ServerAssignPlayerSlot, everyone else from PlayerJoined and PlayerLeft. Repeats of a
+// notification change nothing, the server resends until it gets our ack.
+#[derive(Debug)]
+pub struct SessionRoster {
+    local_slot: PlayerID,
+    peers: Vec<ServerPlayerID>,
+}
+
+impl Default for SessionRoster {
+    fn default() -> Self {
+        Self { local_slot: PlayerID::Player1, peers: Vec::new() }
+    }
+}
+
+impl SessionRoster {
+    // true if our slot moved, the input buffer has to follow
+    pub fn on_slot_assigned(&mut self, slot: PlayerID) -> bool {
+        if slot == self.local_slot {
+            return false;
+        }
+        self.local_slot = slot;
+        true
+    }
+    // true if the player count changed
+    pub fn on_player_joined(&mut self, id: ServerPlayerID) -> bool {
+        if self.peers.contains(&id) || self.player_count() >= MAX_PLAYER_COUNT {
+            return false;
+        }
+        self.peers.push(id);
+        true
+    }
+    // true if the player count changed
+    pub fn on_player_left(&mut self, id: ServerPlayerID) -> bool {
+        let before = self.peers.len();
+        self.peers.retain(|peer| *peer != id);
+        self.peers.len() != before
+    }
+    pub fn player_count(&self) -> u8 {
+        1 + (self.peers.len() as u8)
+    }
+    // the server doesn't say which slot a peer plays in, with two slots it is the one that isn't ours
+    pub fn is_peer_connected(&self, slot: PlayerID) -> bool {
+        slot != self.local_slot && !self.peers.is_empty()
+    }
+    pub fn local_slot(&self) -> PlayerID {
+        self.local_slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_leave_and_rejoin_keep_the_count_right() {
+        let mut roster = SessionRoster::default();
+        assert_eq!(roster.player_count(), 1);
+        assert!(!roster.is_peer_connected(PlayerID::Player2));
+
+        assert!(roster.on_player_joined(ServerPlayerID(4)));
+        // the server resends until it sees our ack
+        assert!(!roster.on_player_joined(ServerPlayerID(4)));
+        assert_eq!(roster.player_count(), 2);
+        assert!(roster.is_peer_connected(PlayerID::Player2));
+        assert!(!roster.is_peer_connected(PlayerID::Player1));
+
+        assert!(roster.on_player_left(ServerPlayerID(4)));
+        assert!(!roster.on_player_left(ServerPlayerID(4)));
+        assert_eq!(roster.player_count(), 1);
+
+        // a new player takes the free slot, the count doesn't grow past it
+        assert!(roster.on_player_joined(ServerPlayerID(7)));
+        assert_eq!(roster.player_count(), 2);
+        assert!(!roster.on_player_joined(ServerPlayerID(8)));
+        assert_eq!(roster.player_count(), MAX_PLAYER_COUNT);
+    }
+
+    #[test]
+    fn test_only_a_new_slot_counts_as_a_move() {
+        let mut roster = SessionRoster::default();
+        assert_eq!(roster.local_slot(), PlayerID::Player1);
+        assert!(!roster.on_slot_assigned(PlayerID::Player1));
+        assert!(roster.on_slot_assigned(PlayerID::Player2));
+        assert!(!roster.on_slot_assigned(PlayerID::Player2));
+        assert_eq!(roster.local_slot(), PlayerID::Player2);
+        roster.on_player_joined(ServerPlayerID(1));
+        assert!(roster.is_peer_connected(PlayerID::Player1));
+    }
+}
@@ -0,0 +1,123 @@
+use std::{ collections::{ HashMap, VecDeque }, hash::Hash, sync::Arc };
+
+// datagrams the server sends from its queues per update, shared round-robin between the clients
+pub const SEND_BUDGET_PER_UPDATE: usize = 16;
+
+// one client's datagrams waiting to go out, with the key (seq num) each was sent under if any
+pub type SendQueue<K> = VecDeque<(Option<K>, Arc<[u8]>)>;
+
+// Outgoing datagrams per destination, sent round-robin: every destination with something queued gets
+// one datagram before any gets a second, so one client's resend burst can't hold up everyone else's inputs.
+// What doesn't fit the budget of a call stays queued, in order, for the next one.
+#[derive(Debug)]
+pub struct SendQueues<D, K> {
+    queues: HashMap<D, SendQueue<K>>,
+    turns: VecDeque<D>, // destinations with a non empty queue, next to send first
+}
+
+impl<D, K> Default for SendQueues<D, K> {
+    fn default() -> Self {
+        Self { queues: HashMap::new(), turns: VecDeque::new() }
+    }
+}
+
+impl<D: Eq + Hash + Copy, K: PartialEq> SendQueues<D, K> {
+    // a key that is still queued for `dst` isn't queued twice, e.g. a resend that came due again.
+    // datagrams without one, like relayed inputs, are always queued
+    pub fn push(&mut self, dst: D, key: Option<K>, bytes: Arc<[u8]>) {
+        let queue = self.queues.entry(dst).or_default();
+        if key.is_some() && queue.iter().any(|(queued, _)| *queued == key) {
+            return;
+        }
+        if queue.is_empty() {
+            self.turns.push_back(dst);
+        }
+        queue.push_back((key, bytes));
+    }
+    // hands at most `budget` datagrams to `send`, returns how many it sent
+    pub fn drain(&mut self, budget: usize, mut send: impl FnMut(&D, &[u8])) -> usize {
+        let mut sent = 0;
+        while sent < budget {
+            let Some(dst) = self.turns.pop_front() else {
+                break;
+            };
+            let Some(queue) = self.queues.get_mut(&dst) else {
+                continue; // taken out since it was queued
+            };
+            let Some((_, bytes)) = queue.pop_front() else {
+                continue;
+            };
+            send(&dst, &bytes);
+            sent += 1;
+            if !queue.is_empty() {
+                self.turns.push_back(dst);
+            }
+        }
+        sent
+    }
+    pub fn take(&mut self, dst: &D) -> Option<SendQueue<K>> {
+        self.turns.retain(|turn| turn != dst);
+        self.queues.remove(dst).filter(|queue| !queue.is_empty())
+    }
+    pub fn put(&mut self, dst: D, queue: SendQueue<K>) {
+        self.take(&dst);
+        if !queue.is_empty() {
+            self.turns.push_back(dst);
+            self.queues.insert(dst, queue);
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.queues.values().map(VecDeque::len).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datagram(byte: u8) -> Arc<[u8]> {
+        Arc::from(vec![byte])
+    }
+
+    #[test]
+    fn test_a_single_datagram_goes_out_within_one_budget_despite_a_backlog() {
+        const BUDGET: usize = 4;
+        let mut queues = SendQueues::default();
+        for seq in 0..30u16 {
+            queues.push('a', Some(seq), datagram(b'a'));
+        }
+        queues.push('b', None, datagram(b'b'));
+
+        let mut sent = Vec::new();
+        assert_eq!(queues.drain(BUDGET, |dst, _| sent.push(*dst)), BUDGET);
+        assert!(sent.contains(&'b'), "{:?}", sent);
+        assert_eq!(queues.len(), 30 + 1 - BUDGET);
+
+        // the backlog keeps its order and drains over the following calls
+        let mut rest = Vec::new();
+        while queues.drain(BUDGET, |_, bytes| rest.push(bytes[0])) > 0 {}
+        assert_eq!(rest, vec![b'a'; 30 + 1 - BUDGET]);
+    }
+
+    #[test]
+    fn test_queued_keys_are_not_doubled_and_taken_queues_are_skipped() {
+        let mut queues = SendQueues::default();
+        queues.push('a', Some(1u16), datagram(1));
+        queues.push('a', Some(1), datagram(1));
+        queues.push('a', Some(2), datagram(2));
+        queues.push('b', Some(1), datagram(3));
+        queues.push('b', None, datagram(4));
+        queues.push('b', None, datagram(4));
+        assert_eq!(queues.len(), 5);
+
+        let moved = queues.take(&'a').unwrap();
+        queues.put('c', moved);
+        let mut sent = Vec::new();
+        queues.drain(usize::MAX, |dst, bytes| sent.push((*dst, bytes[0])));
+        assert_eq!(sent, vec![('b', 3), ('c', 1), ('b', 4), ('c', 2), ('b', 4)]);
+        assert_eq!(queues.len(), 0);
+    }
+}
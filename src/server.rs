@@ -1,71 +1,270 @@
-use std::net::{ SocketAddr, UdpSocket };
-use std::collections::HashMap;
+use std::net::{ SocketAddr, ToSocketAddrs, UdpSocket };
+use std::collections::{ HashMap, HashSet };
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
 use std::time::{ Duration, Instant };
+use std::path::Path;
+use std::{ fmt, io };
+use config::Config;
+use console::ConsoleCommand;
+use std::sync::mpsc::Receiver;
+use input_relay::{ InputRelay, RelayState };
+use link_loss::LinkLoss;
+use message_history::{ Direction, MessageHistory, MessageRecord };
+use retransmit::{ RetransmitTable, RetryPolicy };
+use send_queue::{ SendQueue, SendQueues, SEND_BUDGET_PER_UPDATE };
 use types::{
     BufferedNetworkedPlayerInputs,
     ChunkedMessageCollector,
     DeserializedMessage,
     DeserializedMessageType,
+    InputGapStats,
     LogConfig,
     Logger,
     MsgBuffer,
     NetworkMessage,
+    PlayerID,
+    ProtocolInfo,
+    RetryClass,
     SeqNum,
     SeqNumGenerator,
     SerializedMessageType,
     SerializedNetworkMessage,
     ServerPlayerID,
+    SessionId,
+    TickRate,
+    MAX_PLAYER_COUNT,
+    MAX_REQUESTED_FRAMES,
     SEQ_NUM_BYTE_POS,
 };
-mod type_impl;
-mod types;
-mod memory;
+use unlockrs::{ config, input_relay, link_loss, message_history, retransmit, send_queue, type_impl, types };
+mod console;
 
-const MAX_RETRIES: u32 = 120;
-const RETRY_TIMEOUT: Duration = Duration::from_millis(16);
+const CONTROL_RETRY: RetryPolicy = RetryPolicy::fixed(Duration::from_millis(16), 120);
+// a relayed world keeps going for about 20s, its chunks don't use up the lobby's quick resends
+const BULK_RETRY: RetryPolicy = RetryPolicy {
+    timeout: Duration::from_millis(16),
+    backoff: 2,
+    max_timeout: Duration::from_millis(500),
+    max_retries: 45,
+};
 const BASELINE_LATENCY: u64 = 20;
+type PendingAcks = RetransmitTable<SeqNum, SerializedNetworkMessage>;
+fn new_pending_acks() -> PendingAcks {
+    RetransmitTable::new(CONTROL_RETRY.timeout, CONTROL_RETRY.max_retries)
+}
+fn retry_policy(class: RetryClass) -> RetryPolicy {
+    match class {
+        RetryClass::Control => CONTROL_RETRY,
+        RetryClass::Bulk => BULK_RETRY,
+    }
+}
 const BASELINE_JITTER: u64 = 5;
 const BASELINE_PACKET_LOSS: f32 = 0.0;
 const NETWORK_SIM_SEED: u64 = 12345;
+const LOSS_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+const SERVER_CONFIG_FILE: &str = "unlockrs-server.cfg";
+const PORT_SCAN_RANGE: u16 = 10; // ports after the configured one tried with port_scan
 
 #[cfg(feature = "simulation_mode")]
-mod network_simulator;
-#[cfg(feature = "simulation_mode")]
-use crate::network_simulator::NetworkSimulator;
+use unlockrs::network_simulator::NetworkSimulator;
 #[cfg(feature = "simulation_mode")]
 use crossterm::{ event, terminal, ExecutableCommand };
 #[cfg(feature = "simulation_mode")]
 use crossterm::event::{ Event, KeyCode };
 #[cfg(feature = "simulation_mode")]
 use std::io::stdout;
+// why a client is left out of the host list sent to another one
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NotJoinable {
+    Requester, // never offered its own world
+    Incompatible, // its Hello didn't match this build
+    LobbyFull, // already in a session with MAX_PLAYER_COUNT players
+    OtherWorldLayout, // counted, the joiner is told how many it can't see
+}
+// everything the server keeps per client address, see remap_client
+struct ClientState {
+    player: ServerPlayerID,
+    pending_chunked_msgs: Option<ChunkedMessageCollector>,
+    connections: Option<Vec<SocketAddr>>,
+    non_input_pending_acks: Option<PendingAcks>,
+    input_relay: RelayState,
+    player_slot: Option<PlayerID>,
+    tick_rate: Option<TickRate>,
+    link_loss: Option<LinkLoss>,
+    world_layout: Option<u32>,
+    incompatible: bool,
+    outgoing: Option<SendQueue<SeqNum>>,
+}
 struct Server {
     socket: UdpSocket,
     player_to_addr: [Option<SocketAddr>; (u8::MAX as usize) + 1],
     addr_to_player: HashMap<SocketAddr, ServerPlayerID>,
+    player_slots: HashMap<SocketAddr, PlayerID>, // game slot of everyone in a session
+    tick_rates: HashMap<SocketAddr, TickRate>, // announced by hosts, joiners get their host's
     pending_chunked_msgs: HashMap<SocketAddr, ChunkedMessageCollector>,
     connections: HashMap<SocketAddr, Vec<SocketAddr>>,
     msg_buffer: MsgBuffer,
-    non_input_pending_acks: HashMap<
-        SocketAddr,
-        HashMap<SeqNum, (Instant, SerializedNetworkMessage)>
-    >,
+    non_input_pending_acks: HashMap<SocketAddr, PendingAcks>,
     sequence_number: SeqNumGenerator,
-    unack_input_seq_nums_to_frame: HashMap<SocketAddr, HashMap<SeqNum, u32>>,
-    unack_input_buffer: HashMap<SocketAddr, BufferedNetworkedPlayerInputs>,
+    next_transfer_id: u16, // of the next chunked message, shared by all clients, each only sees it grow
+    input_relay: InputRelay, // checks, acks and relays every client's inputs
+    input_gap_stats: InputGapStats, // totals over every relayed input batch
+    link_loss: HashMap<SocketAddr, LinkLoss>, // what got lost on the way to each client
+    outgoing: SendQueues<SocketAddr, SeqNum>, // resends and relayed inputs, see drain_outgoing
+    message_history: MessageHistory<SocketAddr>, // recent datagrams each way, by client, for the history command
+    last_loss_check: Instant,
+    incompatible_peers: HashSet<SocketAddr>, // sent a Hello we can't talk to, ignored from then on
+    world_layouts: HashMap<SocketAddr, u32>, // from each client's Hello, hosts are only offered to matching joiners
+    sessions: HashMap<SessionId, SocketAddr>, // last address each client said Hello from
     logger: Logger,
+    shutdown: Arc<AtomicBool>,
+    console: Option<Receiver<ConsoleCommand>>, // commands typed into the terminal, applied in update
+    #[cfg(test)]
+    inject_panic: bool, // the next update panics
     #[cfg(feature = "simulation_mode")]
     network_simulator: NetworkSimulator,
 }
 
+// why the server couldn't open its socket, main exits with exit_code so scripts can tell them apart
+#[derive(Debug)]
+enum ServerStartError {
+    InvalidAddr(String), // bind_addr doesn't resolve to an address
+    AddrInUse {
+        addr: SocketAddr,
+        scanned: u16, // further ports that were taken too
+    },
+    PermissionDenied(SocketAddr),
+    Io(SocketAddr, io::Error),
+}
+
+impl ServerStartError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            ServerStartError::Io(..) => 1,
+            ServerStartError::InvalidAddr(_) => 2,
+            ServerStartError::AddrInUse { .. } => 3,
+            ServerStartError::PermissionDenied(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for ServerStartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerStartError::InvalidAddr(addr) => write!(f, "{} is not an address to listen on", addr),
+            ServerStartError::AddrInUse { addr, scanned: 0 } =>
+                write!(
+                    f,
+                    "{} is already in use, choose another --bind_addr or start with --port_scan=true",
+                    addr
+                ),
+            ServerStartError::AddrInUse { addr, scanned } =>
+                write!(f, "{} and the {} ports after it are already in use", addr, scanned),
+            ServerStartError::PermissionDenied(addr) =>
+                write!(f, "not allowed to listen on {}, ports below 1024 usually need elevated privileges", addr),
+            ServerStartError::Io(addr, e) => write!(f, "couldn't listen on {}: {}", addr, e),
+        }
+    }
+}
+
+impl std::error::Error for ServerStartError {}
+
+// where the server listens and what it logs, read from SERVER_CONFIG_FILE and the command line
+#[derive(Debug, Clone, PartialEq)]
+struct ServerConfig {
+    bind_addr: String,
+    port_scan: bool, // a taken port moves on to the next PORT_SCAN_RANGE ones instead of giving up
+    console: bool, // commands typed into the terminal, replaces the simulator's key controls
+    message_history: usize, // datagrams each way kept for the console's history command
+    log: LogConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_addr: "127.0.0.1:8080".to_string(),
+            port_scan: false,
+            console: false,
+            message_history: message_history::DEFAULT_MESSAGE_HISTORY,
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl Config for ServerConfig {
+    fn set(&mut self, key: &str, value: &str) -> Result<bool, &'static str> {
+        match key {
+            "bind_addr" => {
+                self.bind_addr = value.to_string();
+                Ok(true)
+            }
+            "port_scan" => {
+                self.port_scan = config::parse_bool(value)?;
+                Ok(true)
+            }
+            "console" => {
+                self.console = config::parse_bool(value)?;
+                Ok(true)
+            }
+            "message_history" => {
+                self.message_history = value.parse().map_err(|_| "Config value is not a whole number")?;
+                Ok(true)
+            }
+            _ => config::set_log_key(&mut self.log, key, value),
+        }
+    }
+    fn pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = vec![
+            ("bind_addr", self.bind_addr.clone()),
+            ("port_scan", self.port_scan.to_string()),
+            ("console", self.console.to_string()),
+            ("message_history", self.message_history.to_string())
+        ];
+        pairs.extend(config::log_pairs(&self.log));
+        pairs
+    }
+}
+
 impl Server {
-    pub fn new() -> Self {
+    #[cfg(test)]
+    pub fn bind(addr: &str) -> Result<Self, ServerStartError> {
+        Self::bind_first_free(addr, 0)
+    }
+    // while the port of `addr` is taken the `scan` ports after it are tried, the first free one is used
+    pub fn bind_first_free(addr: &str, scan: u16) -> Result<Self, ServerStartError> {
+        let first = addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| ServerStartError::InvalidAddr(addr.to_string()))?;
+        let ports = (0..=scan).map_while(|offset| first.port().checked_add(offset));
+        for candidate in ports.map(|port| SocketAddr::new(first.ip(), port)) {
+            match UdpSocket::bind(candidate) {
+                Ok(socket) => {
+                    return Self::with_socket(socket).map_err(|e| ServerStartError::Io(candidate, e));
+                }
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => {}
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    return Err(ServerStartError::PermissionDenied(candidate));
+                }
+                Err(e) => {
+                    return Err(ServerStartError::Io(candidate, e));
+                }
+            }
+        }
+        Err(ServerStartError::AddrInUse { addr: first, scanned: scan })
+    }
+    fn with_socket(socket: UdpSocket) -> io::Result<Self> {
         let addr_to_player: HashMap<SocketAddr, ServerPlayerID> = HashMap::new();
-        let socket = UdpSocket::bind("127.0.0.1:8080").expect("Server Failed to bind socket.");
-        socket.set_nonblocking(true).expect("Failed to set socket to non blocking");
+        socket.set_nonblocking(true)?;
         let msg_buffer: MsgBuffer = MsgBuffer::default();
-        Server {
+        Ok(Server {
             socket,
             addr_to_player,
+            player_slots: HashMap::new(),
+            tick_rates: HashMap::new(),
             player_to_addr: [None; (u8::MAX as usize) + 1],
             connections: HashMap::new(),
             pending_chunked_msgs: HashMap::new(),
@@ -74,9 +273,21 @@ impl Server {
             sequence_number: SeqNumGenerator {
                 seq_num: SeqNum(0),
             },
-            unack_input_buffer: HashMap::new(),
-            unack_input_seq_nums_to_frame: HashMap::new(),
+            next_transfer_id: 0,
+            input_relay: InputRelay::default(),
+            input_gap_stats: InputGapStats::default(),
+            link_loss: HashMap::new(),
+            outgoing: SendQueues::default(),
+            message_history: MessageHistory::default(),
+            last_loss_check: Instant::now(),
+            incompatible_peers: HashSet::new(),
+            world_layouts: HashMap::new(),
+            sessions: HashMap::new(),
             logger: Logger::new(LogConfig::default()),
+            shutdown: Arc::new(AtomicBool::new(false)),
+            console: None,
+            #[cfg(test)]
+            inject_panic: false,
             #[cfg(feature = "simulation_mode")]
             network_simulator: NetworkSimulator::new(
                 NETWORK_SIM_SEED,
@@ -84,12 +295,32 @@ impl Server {
                 BASELINE_JITTER,
                 BASELINE_PACKET_LOSS
             ),
+        })
+    }
+    // setting the flag makes run / run_w_attached_tui return after one more update
+    pub fn shutdown_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.shutdown)
+    }
+    pub fn run(&mut self) {
+        while !self.shutdown.load(Ordering::Relaxed) {
+            self.guarded_update();
+        }
+        self.finish();
+    }
+    // a bug hit by one client's datagram shouldn't take the server down for everyone else
+    fn guarded_update(&mut self) {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| self.update())) {
+            let reason = type_impl::panic_reason(payload.as_ref());
+            self.logger.error(|| format!("Recovered from a panic in update: {}", reason));
         }
     }
+    fn finish(&mut self) {
+        self.update();
+        self.flush_retransmissions();
+        self.logger.connection(|| "Server shut down");
+    }
     #[cfg(feature = "simulation_mode")]
     pub fn run_w_attached_tui(&mut self) -> std::io::Result<()> {
-        use std::process::exit;
-
         let mut stdout = stdout();
         terminal::enable_raw_mode()?; // Enable raw mode for direct key event capture
         stdout.execute(terminal::Clear(terminal::ClearType::All))?;
@@ -104,12 +335,12 @@ impl Server {
         println!("  'l' - Increase baseline latency by 5");
         println!("  'p' - Increase packet loss by 0.01");
         println!("  'j' - Increase jitter by 5");
-        loop {
+        while !self.shutdown.load(Ordering::Relaxed) {
             if event::poll(std::time::Duration::from_millis(0))? {
                 if let Event::Key(key_event) = event::read()? {
                     match key_event.code {
                         KeyCode::Char('q') => {
-                            exit(0);
+                            self.shutdown.store(true, Ordering::Relaxed);
                         }
                         KeyCode::Char('l') => {
                             self.network_simulator.modify_baseline_latency(5);
@@ -125,48 +356,70 @@ impl Server {
                 }
             }
 
-            self.update();
+            self.guarded_update();
         }
+        terminal::disable_raw_mode()?;
+        self.finish();
+        Ok(())
     }
     pub fn update(&mut self) {
+        #[cfg(test)]
+        if std::mem::take(&mut self.inject_panic) {
+            panic!("injected server panic");
+        }
         self.msg_buffer.clear();
+        self.apply_console_commands();
 
         #[cfg(feature = "simulation_mode")]
         {
-            for (data, dst) in self.network_simulator.get_ready_send_messages() {
+            for (data, dst) in self.network_simulator.get_ready_send_messages(Instant::now()) {
                 if let Err(e) = self.socket.send_to(&data, dst) {
-                    self.logger.error(e);
+                    self.logger.error(|| e);
                 }
             }
             match self.socket.recv_from(&mut self.msg_buffer.0) {
-                Ok((_, src)) => {
-                    self.logger.debug_log_time("Received msg now!");
-                    self.network_simulator.enqueue_rcv_message(self.msg_buffer.0.to_vec(), src);
+                Ok((amt, src)) => {
+                    self.logger.debug_log_time(|| "Received msg now!");
+                    self.network_simulator.enqueue_rcv_message(
+                        Instant::now(),
+                        self.msg_buffer.0[..amt].to_vec(),
+                        src
+                    );
                 }
                 Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
                 Err(e) => {
-                    self.logger.error(e);
+                    self.logger.error(|| e);
                 }
             }
-            for (data, src) in self.network_simulator.get_ready_receive_messages() {
+            for (data, src) in self.network_simulator.get_ready_receive_messages(Instant::now()) {
                 self.msg_buffer.0[..data.len()].copy_from_slice(&data);
 
-                if !self.addr_to_player.contains_key(&src) {
-                    self.create_new_connection(&src);
+                if !self.addr_to_player.contains_key(&src) && !self.create_new_connection(&src) {
+                    continue;
                 }
 
-                let msg = self.msg_buffer.parse_on_server();
+                let msg = self.msg_buffer.parse_on_server(data.len());
                 if let Ok(server_side_msg) = msg {
+                    self.record_received(&src, data.len(), &server_side_msg);
                     match server_side_msg {
                         DeserializedMessageType::NonChunked(server_side_msg) => {
-                            self.logger.debug_log_time("Handling msg now!");
+                            self.logger.debug_log_time(|| "Handling msg now!");
                             self.handle_message(server_side_msg, &src);
                         }
                         DeserializedMessageType::ChunkOfMessage(chunk) => {
-                            self.logger.debug_log_time("Handling msg now!");
+                            self.logger.debug_log_time(|| "Handling msg now!");
                             self.send_ack(SeqNum(chunk.seq_num), &src);
                             if let Some(collector) = self.pending_chunked_msgs.get_mut(&src) {
-                                collector.collect(chunk);
+                                if let Err(e) = collector.collect(chunk) {
+                                    self.logger.error(
+                                        || format!(
+                                            "Refused a chunk from {:?}: {} ({} messages dropped so far)",
+                                            src,
+                                            e,
+                                            collector.dropped_groups
+                                        )
+                                    );
+                                }
                                 if let Some(msg) = collector.try_combine() {
                                     self.handle_message(msg, &src);
                                 }
@@ -180,13 +433,10 @@ impl Server {
         #[cfg(not(feature = "simulation_mode"))]
         {
             match self.socket.recv_from(&mut self.msg_buffer.0) {
-                Ok((_, src)) => {
-                    if !self.addr_to_player.contains_key(&src) {
-                        self.create_new_connection(&src);
-                    }
-
-                    let msg = self.msg_buffer.parse_on_server();
+                Ok((amt, src)) if self.addr_to_player.contains_key(&src) || self.create_new_connection(&src) => {
+                    let msg = self.msg_buffer.parse_on_server(amt);
                     if let Ok(server_side_msg) = msg {
+                        self.record_received(&src, amt, &server_side_msg);
                         match server_side_msg {
                             DeserializedMessageType::NonChunked(server_side_msg) => {
                                 self.handle_message(server_side_msg, &src);
@@ -194,7 +444,16 @@ impl Server {
                             DeserializedMessageType::ChunkOfMessage(chunk) => {
                                 self.send_ack(SeqNum(chunk.seq_num), &src);
                                 if let Some(collector) = self.pending_chunked_msgs.get_mut(&src) {
-                                    collector.collect(chunk);
+                                    if let Err(e) = collector.collect(chunk) {
+                                        self.logger.error(
+                                            || format!(
+                                                "Refused a chunk from {:?}: {} ({} messages dropped so far)",
+                                                src,
+                                                e,
+                                                collector.dropped_groups
+                                            )
+                                        );
+                                    }
                                     if let Some(msg) = collector.try_combine() {
                                         self.handle_message(msg, &src);
                                     }
@@ -203,92 +462,465 @@ impl Server {
                         }
                     }
                 }
+                Ok(_) => {} // no player id left for the source
                 Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => (),
-                Err(e) => self.logger.error(format!("Error receiving data: {}", e)),
+                Err(e) => self.logger.error(|| format!("Error receiving data: {}", e)),
             }
         }
         self.handle_retransmissions();
+        self.send_input_acks();
+        self.drain_outgoing(SEND_BUDGET_PER_UPDATE);
+    }
+
+    // the first `len` bytes of msg_buffer came from `src` and parsed to `msg`
+    fn record_received(&mut self, src: &SocketAddr, len: usize, msg: &DeserializedMessageType) {
+        let msg = match msg {
+            DeserializedMessageType::NonChunked(msg) => Some(&msg.msg),
+            DeserializedMessageType::ChunkOfMessage(_) => None,
+        };
+        let record = MessageRecord::new(Instant::now(), Direction::Received, &self.msg_buffer.0[..len], msg);
+        self.message_history.push(*src, record);
+    }
+    fn record_sent(&mut self, dst: &SocketAddr, bytes: &[u8], msg: Option<&NetworkMessage>) {
+        self.message_history.push(*dst, MessageRecord::new(Instant::now(), Direction::Sent, bytes, msg));
+    }
+
+    // tells every client how far its inputs arrived, unreliable like the inputs themselves
+    fn send_input_acks(&mut self) {
+        for (addr, frame) in self.input_relay.due_acks(Instant::now()) {
+            self.send_once(NetworkMessage::ServerInputsAckedThrough(frame), &addr);
+        }
     }
 
     pub fn handle_retransmissions(&mut self) {
         let now = Instant::now();
-        let mut to_retry = Vec::new();
         for (client_addr, pending_messages) in &mut self.non_input_pending_acks {
-            for (seq, (sent_time, message)) in pending_messages {
-                if now.duration_since(*sent_time) > RETRY_TIMEOUT {
-                    to_retry.push((*client_addr, *seq, message.clone()));
+            for (seq, message) in pending_messages.tick(now) {
+                if let Some(link_loss) = self.link_loss.get_mut(client_addr) {
+                    link_loss.reliable.on_send(now, seq);
                 }
+                self.logger.message(
+                    || format!(
+                        "Queued resend of message {:?} (kind {}, {} bytes) to client {:?}",
+                        seq,
+                        message.kind(),
+                        message.len(),
+                        client_addr
+                    )
+                );
+                let record = MessageRecord::new(now, Direction::Sent, &message.bytes, None);
+                self.message_history.push(*client_addr, record);
+                self.outgoing.push(*client_addr, Some(seq), message.bytes);
+            }
+            for (seq, retries) in pending_messages.take_exhausted() {
+                self.logger.connection(
+                    || format!(
+                        "Lost connection with {:?}, {:?} was resent {} times and never acked",
+                        client_addr,
+                        seq,
+                        retries
+                    )
+                );
             }
         }
-        for (client_addr, seq, message) in to_retry {
-            if let Some(pending_messages) = self.non_input_pending_acks.get_mut(&client_addr) {
-                if let Some((ref mut sent_time, _)) = pending_messages.get_mut(&seq) {
-                    *sent_time = now;
-                    match self.socket.send_to(&message.bytes, client_addr) {
-                        Ok(_) => {
-                            self.logger.message(
-                                format!("Resent message {:?} to client {:?}", seq, client_addr)
-                            );
-                        }
-                        Err(e) => {
-                            self.logger.error(
-                                format!(
-                                    "Failed to resend message {:?} to client {:?}: {}",
-                                    seq,
-                                    client_addr,
-                                    e
-                                )
-                            );
-                        }
-                    }
+        if now.duration_since(self.last_loss_check) < LOSS_CHECK_INTERVAL {
+            return;
+        }
+        self.last_loss_check = now;
+        for (client_addr, link_loss) in &mut self.link_loss {
+            if link_loss.became_poor_at(now) {
+                let (reliable, inputs) = (link_loss.reliable.loss_at(now), link_loss.inputs.loss_at(now));
+                self.logger.connection(
+                    || format!(
+                        "Poor connection to {:?}, estimated loss {:?} of reliable and {:?} of input datagrams",
+                        client_addr,
+                        reliable,
+                        inputs
+                    )
+                );
+            }
+        }
+    }
+
+    // sends up to `budget` queued datagrams, one client after the other
+    fn drain_outgoing(&mut self, budget: usize) {
+        let (socket, logger) = (&self.socket, &self.logger);
+        self.outgoing.drain(budget, |dst, bytes| {
+            if let Err(e) = socket.send_to(bytes, dst) {
+                logger.error(|| format!("Failed to send queued datagram to {:?}: {}", dst, e));
+            }
+        });
+    }
+
+    // resends every pending reliable message right away, used before shutting down
+    fn flush_retransmissions(&mut self) {
+        self.drain_outgoing(usize::MAX);
+        for (client_addr, pending_messages) in &self.non_input_pending_acks {
+            for (seq, message) in pending_messages.iter() {
+                if let Err(e) = self.socket.send_to(&message.bytes, client_addr) {
+                    self.logger.error(
+                        || format!("Failed to flush message {:?} to client {:?}: {}", seq, client_addr, e)
+                    );
                 }
             }
         }
+    }
 
-        let _ = self.non_input_pending_acks.iter_mut().map(|(_, pending_messages)| {
-            pending_messages.retain(|seq, (sent_time, _)| {
-                let resend = now.duration_since(*sent_time) < RETRY_TIMEOUT * MAX_RETRIES;
-                if !resend {
-                    self.logger.connection(format!("Lost connection with {:?}", seq));
+    // commands arrive from the console thread, the server state is only ever touched here
+    fn apply_console_commands(&mut self) {
+        let commands: Vec<ConsoleCommand> = match &self.console {
+            Some(console) => console.try_iter().collect(),
+            None => {
+                return;
+            }
+        };
+        for command in commands {
+            match command {
+                ConsoleCommand::Status => println!("{}", self.status()),
+                ConsoleCommand::Kick(player) => self.kick(player),
+                ConsoleCommand::Log { key, enabled } => {
+                    let _ = config::set_log_key(&mut self.logger.config, &key, &enabled.to_string());
+                    println!("{} {}", key, if enabled { "on" } else { "off" });
                 }
-                return resend;
-            });
-            !pending_messages.is_empty()
-        });
+                #[cfg(feature = "simulation_mode")]
+                ConsoleCommand::Loss(loss) => {
+                    self.network_simulator.set_packet_loss(loss);
+                    println!("Simulated packet loss {}", loss);
+                }
+                #[cfg(feature = "simulation_mode")]
+                ConsoleCommand::Latency(ms) => {
+                    self.network_simulator.set_baseline_latency(ms);
+                    println!("Simulated latency {} ms", ms);
+                }
+                #[cfg(not(feature = "simulation_mode"))]
+                ConsoleCommand::Loss(_) | ConsoleCommand::Latency(_) => {
+                    println!("The network simulator is only there in simulation_mode builds");
+                }
+                ConsoleCommand::History(player) => println!("{}", self.history(player)),
+                ConsoleCommand::Shutdown => self.shutdown.store(true, Ordering::Relaxed),
+            }
+        }
+    }
+    // the console's history command, all recent datagrams or only those of one player
+    fn history(&self, player: Option<ServerPlayerID>) -> String {
+        let now = Instant::now();
+        let Some(player) = player else {
+            return self.message_history.dump_with_keys(now);
+        };
+        match self.player_to_addr[player.0 as usize] {
+            Some(addr) => self.message_history.dump_for(&addr, now),
+            None => format!("No player {}", player.0),
+        }
+    }
+    // one line per client and the relayed input totals, for the console's status command
+    fn status(&mut self) -> String {
+        let now = Instant::now();
+        let mut clients: Vec<(ServerPlayerID, SocketAddr)> = self.addr_to_player
+            .iter()
+            .map(|(addr, player)| (*player, *addr))
+            .collect();
+        clients.sort_by_key(|(player, _)| player.0);
+        let mut status = String::new();
+        if let Err(problem) = self.validate_connections() {
+            status.push_str(&format!("Broken connection groups: {}\n", problem));
+        }
+        status.push_str(&format!(
+            "{} clients, {} in a session, {} datagrams queued, relayed inputs: {:?}\n",
+            clients.len(),
+            self.connections.len(),
+            self.outgoing.len(),
+            self.input_gap_stats
+        ));
+        for (player, addr) in clients {
+            let peers: Vec<u8> = self.connections
+                .get(&addr)
+                .into_iter()
+                .flatten()
+                .filter_map(|peer| self.addr_to_player.get(peer).map(|peer| peer.0))
+                .collect();
+            let loss = self.link_loss.get_mut(&addr).and_then(|link_loss| link_loss.loss_at(now));
+            status.push_str(
+                &format!(
+                    "  player {} at {:?}: slot {:?}, playing with {:?}, estimated loss {:?}{}\n",
+                    player.0,
+                    addr,
+                    self.player_slots.get(&addr),
+                    peers,
+                    loss,
+                    if self.incompatible_peers.contains(&addr) { ", incompatible" } else { "" }
+                )
+            );
+        }
+        status
+    }
+    // like leaving: the peers hear PlayerLeft, then everything kept for the client goes.
+    // its next datagram makes it a new client in the lobby
+    fn kick(&mut self, player: ServerPlayerID) {
+        let Some(addr) = self.player_to_addr[player.0 as usize] else {
+            println!("There is no player {}", player.0);
+            return;
+        };
+        self.leave_session(addr);
+        self.remove_client(&addr);
+        self.sessions.retain(|_, session_addr| *session_addr != addr);
+        self.logger.connection(|| format!("Kicked player {} at {:?}", player.0, addr));
+        self.nudge_lobby(&addr);
     }
 
-    pub fn create_new_connection(&mut self, addr: &SocketAddr) {
-        let new_id = ServerPlayerID(self.addr_to_player.len() as u8);
+    // false if every player id is taken, the source is ignored until one is free again
+    pub fn create_new_connection(&mut self, addr: &SocketAddr) -> bool {
+        // ids of clients that were kicked are handed out again, the lowest free one first
+        let Some(new_id) = self.player_to_addr
+            .iter()
+            .position(Option::is_none)
+            .map(|id| ServerPlayerID(id as u8)) else {
+            self.logger.connection(|| format!("No player id left for {:?}, ignoring it", addr));
+            return false;
+        };
         self.addr_to_player.insert(*addr, new_id);
         self.player_to_addr[new_id.0 as usize] = Some(*addr);
-        self.non_input_pending_acks.insert(*addr, HashMap::new());
+        self.non_input_pending_acks.insert(*addr, new_pending_acks());
         self.pending_chunked_msgs.insert(*addr, ChunkedMessageCollector::default());
-        self.unack_input_buffer.insert(*addr, BufferedNetworkedPlayerInputs {
-            buffered_inputs: Vec::new(),
-        });
-        self.unack_input_seq_nums_to_frame.insert(*addr, HashMap::new());
-        self.logger.connection(format!("New connection established with {:?}", addr));
+        self.input_relay.connect(*addr);
+        self.link_loss.insert(*addr, LinkLoss::default());
+        self.logger.connection(|| format!("New connection established with {:?}", addr));
+        self.nudge_lobby(addr);
+        true
+    }
+    // everyone not in a session is told to ask for the host list again, the nudge is unreliable,
+    // a client that misses it still has its list from before
+    fn nudge_lobby(&mut self, changed_by: &SocketAddr) {
+        let lobby: Vec<SocketAddr> = self.addr_to_player
+            .keys()
+            .filter(|addr| {
+                *addr != changed_by &&
+                    !self.connections.contains_key(addr) &&
+                    !self.incompatible_peers.contains(addr)
+            })
+            .copied()
+            .collect();
+        self.logger.connection(|| format!("Host list changed by {:?}, nudging {:?}", changed_by, lobby));
+        for addr in lobby {
+            self.send_once(NetworkMessage::ServerPlayerListChanged, &addr);
+        }
+    }
+
+    // the peers forget the client too, insert_client connects them again
+    fn remove_client(&mut self, addr: &SocketAddr) -> Option<ClientState> {
+        let player = self.addr_to_player.remove(addr)?;
+        self.player_to_addr[player.0 as usize] = None;
+        let connections = self.connections.remove(addr);
+        for peer in connections.iter().flatten() {
+            if let Some(peer_connections) = self.connections.get_mut(peer) {
+                peer_connections.retain(|connection| connection != addr);
+            }
+        }
+        Some(ClientState {
+            player,
+            pending_chunked_msgs: self.pending_chunked_msgs.remove(addr),
+            connections,
+            non_input_pending_acks: self.non_input_pending_acks.remove(addr),
+            input_relay: self.input_relay.take(addr),
+            player_slot: self.player_slots.remove(addr),
+            tick_rate: self.tick_rates.remove(addr),
+            link_loss: self.link_loss.remove(addr),
+            world_layout: self.world_layouts.remove(addr),
+            incompatible: self.incompatible_peers.remove(addr),
+            outgoing: self.outgoing.take(addr),
+        })
+    }
+    fn insert_client(&mut self, addr: SocketAddr, state: ClientState) {
+        self.addr_to_player.insert(addr, state.player);
+        self.player_to_addr[state.player.0 as usize] = Some(addr);
+        if let Some(collector) = state.pending_chunked_msgs {
+            self.pending_chunked_msgs.insert(addr, collector);
+        }
+        if let Some(peers) = state.connections {
+            self.connections.insert(addr, Vec::new());
+            for peer in peers {
+                self.connect(addr, peer);
+            }
+        }
+        if let Some(pending_acks) = state.non_input_pending_acks {
+            self.non_input_pending_acks.insert(addr, pending_acks);
+        }
+        self.input_relay.put(addr, state.input_relay);
+        if let Some(slot) = state.player_slot {
+            self.player_slots.insert(addr, slot);
+        }
+        if let Some(tick_rate) = state.tick_rate {
+            self.tick_rates.insert(addr, tick_rate);
+        }
+        if let Some(link_loss) = state.link_loss {
+            self.link_loss.insert(addr, link_loss);
+        }
+        if let Some(world_layout) = state.world_layout {
+            self.world_layouts.insert(addr, world_layout);
+        }
+        if state.incompatible {
+            self.incompatible_peers.insert(addr);
+        }
+        if let Some(queue) = state.outgoing {
+            self.outgoing.put(addr, queue);
+        }
+    }
+    // everyone already in the host's group hears of the joiner, the joiner hears of each of them
+    fn announce_join(&mut self, joiner_addr: SocketAddr, host_addr: SocketAddr) {
+        let joiner = self.addr_to_player[&joiner_addr];
+        let members: Vec<SocketAddr> = std::iter::once(host_addr)
+            .chain(self.connections.get(&host_addr).into_iter().flatten().copied())
+            .filter(|member| *member != joiner_addr)
+            .collect();
+        for member in members {
+            let Some(member_id) = self.addr_to_player.get(&member).copied() else {
+                continue;
+            };
+            self.send_and_resend_until_ack(NetworkMessage::PlayerJoined(joiner), &member);
+            self.send_and_resend_until_ack(NetworkMessage::PlayerJoined(member_id), &joiner_addr);
+        }
+    }
+    // takes `addr` out of its connection group, the members that are left hear of it
+    fn leave_session(&mut self, addr: SocketAddr) {
+        let Some(peers) = self.connections.remove(&addr) else {
+            return;
+        };
+        self.player_slots.remove(&addr);
+        self.input_relay.leave_session(&addr);
+        let Some(player) = self.addr_to_player.get(&addr).copied() else {
+            return;
+        };
+        for peer in peers {
+            if let Some(peer_connections) = self.connections.get_mut(&peer) {
+                peer_connections.retain(|connection| *connection != addr);
+            }
+            self.send_and_resend_until_ack(NetworkMessage::PlayerLeft(player), &peer);
+        }
+        debug_assert_eq!(self.validate_connections(), Ok(()));
+        self.logger.connection(|| format!("{:?} left its session", addr));
+    }
+    // a client rebound its socket: everything kept for the old address now belongs to the new one
+    pub fn remap_client(&mut self, old_addr: SocketAddr, new_addr: SocketAddr) {
+        // the new address got a fresh connection when its first packet arrived
+        self.remove_client(&new_addr);
+        let Some(state) = self.remove_client(&old_addr) else {
+            return;
+        };
+        self.insert_client(new_addr, state);
+        debug_assert_eq!(self.validate_connections(), Ok(()));
+    }
+    // an edge both ways, one that is already there isn't added again
+    fn connect(&mut self, a: SocketAddr, b: SocketAddr) {
+        for (from, to) in [(a, b), (b, a)] {
+            let peers = self.connections.entry(from).or_default();
+            if !peers.contains(&to) {
+                peers.push(to);
+            }
+        }
+    }
+    // every edge has its reverse, none is there twice and nobody is its own peer. a broken group
+    // relays everything twice or only one way
+    fn validate_connections(&self) -> Result<(), String> {
+        for (addr, peers) in &self.connections {
+            for (i, peer) in peers.iter().enumerate() {
+                if peer == addr {
+                    return Err(format!("{:?} is connected to itself", addr));
+                }
+                if peers[..i].contains(peer) {
+                    return Err(format!("{:?} is connected to {:?} twice", addr, peer));
+                }
+                if !self.connections.get(peer).is_some_and(|back| back.contains(addr)) {
+                    return Err(format!("{:?} is connected to {:?} but not the other way", addr, peer));
+                }
+            }
+        }
+        Ok(())
     }
 
     pub fn create_player_conn_from_to_host(
         &mut self,
-        player1_addr: SocketAddr,
-        player2_addr: SocketAddr
+        joiner_addr: SocketAddr,
+        host_addr: SocketAddr
     ) {
-        self.connections.entry(player1_addr).or_insert_with(Vec::new).push(player2_addr);
-        self.connections.entry(player2_addr).or_insert_with(Vec::new).push(player1_addr);
+        if self.connections.get(&joiner_addr).is_some_and(|peers| peers.contains(&host_addr)) {
+            // e.g. connect pressed twice, they already play together
+            self.logger.connection(|| format!("{:?} is already connected to {:?}", joiner_addr, host_addr));
+            return;
+        }
+        // the host keeps the slot it already plays in, a host that was alone takes the first one
+        let host_slot = match self.player_slots.get(&host_addr) {
+            Some(slot) => *slot,
+            None => {
+                let Some(slot) = self.free_slot(&host_addr) else {
+                    return;
+                };
+                self.player_slots.insert(host_addr, slot);
+                slot
+            }
+        };
+        let Some(joiner_slot) = self.free_slot(&host_addr) else {
+            self.logger.error(
+                || format!("No free player slot left for {:?} in the lobby of {:?}", joiner_addr, host_addr)
+            );
+            return;
+        };
+        self.connect(joiner_addr, host_addr);
+        debug_assert_eq!(self.validate_connections(), Ok(()));
+        self.player_slots.insert(joiner_addr, joiner_slot);
+        let tick_rate = self.tick_rates.get(&host_addr).copied().unwrap_or_default();
+        self.tick_rates.insert(joiner_addr, tick_rate);
+        // the input streams are checked from the start of the session, not from solo play
+        self.input_relay.restart_checks(&joiner_addr);
+        self.input_relay.restart_checks(&host_addr);
+        // clients never pick their own slot, both sides learn it from here
+        self.send_and_resend_until_ack(NetworkMessage::ServerAssignPlayerSlot(host_slot), &host_addr);
         self.send_and_resend_until_ack(
-            NetworkMessage::ServerRequestHostForWorldData,
-            &player2_addr
+            NetworkMessage::ServerAssignPlayerSlot(joiner_slot),
+            &joiner_addr
         );
+        self.send_and_resend_until_ack(NetworkMessage::ServerRequestHostForWorldData, &host_addr);
+        self.announce_join(joiner_addr, host_addr);
         self.logger.connection(
-            format!("Created connection between {:?} and {:?}", player1_addr, player2_addr)
+            || format!(
+                "Created connection between {:?} ({:?}) and host {:?} ({:?})",
+                joiner_addr,
+                joiner_slot,
+                host_addr,
+                host_slot
+            )
         );
     }
 
+    // lowest slot nobody in the lobby plays in yet
+    fn free_slot(&self, lobby: &SocketAddr) -> Option<PlayerID> {
+        let taken: Vec<PlayerID> = std::iter::once(lobby)
+            .chain(self.connections.get(lobby).into_iter().flatten())
+            .filter_map(|addr| self.player_slots.get(addr).copied())
+            .collect();
+        (0..MAX_PLAYER_COUNT as usize)
+            .filter_map(PlayerID::from_usize)
+            .find(|slot| !taken.contains(slot))
+    }
+
+    // Newest frame every player in the lobby has sent all inputs up to, so it's safe to simulate.
+    // A lobby is any of its members plus the players connected to it.
+    pub fn consensus_frame(&self, lobby: &SocketAddr) -> Option<u32> {
+        std::iter::once(lobby)
+            .chain(self.connections.get(lobby).into_iter().flatten())
+            .map(|addr| self.input_relay.contiguous_frame(addr))
+            .min()
+            .flatten()
+    }
+
     pub fn handle_message(&mut self, msg: DeserializedMessage, src: &SocketAddr) {
+        if let Some(chunks) = msg.arrived_as_chunks {
+            self.logger.debug(|| format!("Reassembled message from {} chunks from {:?}", chunks, src));
+        }
+        if self.incompatible_peers.contains(src) && !matches!(msg.msg, NetworkMessage::Hello(..)) {
+            self.logger.debug(|| format!("Ignoring message from incompatible peer {:?}", src));
+            return;
+        }
         if let Some(seq_num) = msg.seq_num {
-            self.logger.debug(format!("Message arrived with seq num {}", seq_num));
+            self.logger.debug(|| format!("Message arrived with seq num {}", seq_num));
             self.process_message(msg.msg, src);
             self.send_ack(SeqNum(seq_num), src);
         } else {
@@ -298,58 +930,255 @@ impl Server {
 
     fn process_message(&mut self, msg: NetworkMessage, src: &SocketAddr) {
         match msg {
+            NetworkMessage::Hello(client_info, session) => {
+                // nothing proves this is the same client, a replayed Hello moves the session just as well
+                if let Some(old_addr) = self.sessions.insert(session, *src) {
+                    if old_addr != *src {
+                        self.logger.connection(
+                            || format!("Session {:?} moved from {:?} to {:?}", session, old_addr, src)
+                        );
+                        self.remap_client(old_addr, *src);
+                    }
+                }
+                match ProtocolInfo::local().check_compatible(&client_info) {
+                    Ok(()) => {
+                        self.incompatible_peers.remove(src);
+                        self.world_layouts.insert(*src, client_info.world_layout);
+                    }
+                    Err(e) => {
+                        self.logger.error(|| format!("Rejecting {:?}: {}", src, e));
+                        self.incompatible_peers.insert(*src);
+                    }
+                }
+                // the client checks our side too and reports the mismatch to the player
+                self.send_and_resend_until_ack(NetworkMessage::HelloAck(ProtocolInfo::local()), src);
+            }
             NetworkMessage::ClientSentWorld(data) => {
-                self.logger.world_state("Received world state from client");
+                self.logger.world_state(|| "Received world state from client");
                 self.broadcast_reliable(NetworkMessage::ServerSentWorld(data), src);
             }
-            NetworkMessage::ClientSentPlayerInputs(inputs) => {
+            | NetworkMessage::ClientSentPlayerInputs(inputs)
+            | NetworkMessage::ClientSentPlayerInputRuns(inputs) => {
+                let tick_rate = self.tick_rates.get(src).copied().unwrap_or_default();
+                let received = match self.input_relay.receive(Instant::now(), src, inputs, tick_rate) {
+                    Ok(received) => received,
+                    Err(rejection) => {
+                        self.logger.error(
+                            || format!(
+                                "Dropped inputs from {:?}: {} ({} rejections)",
+                                src,
+                                rejection,
+                                self.input_relay.rejections(src)
+                            )
+                        );
+                        return;
+                    }
+                };
+                let (inputs, gaps) = (received.inputs, received.gaps);
+                if gaps != InputGapStats::default() {
+                    self.input_gap_stats.add(gaps);
+                    self.logger.player_input(|| {
+                        format!(
+                            "Normalized inputs from {:?}: {:?}, total {:?}",
+                            src,
+                            gaps,
+                            self.input_gap_stats
+                        )
+                    });
+                }
                 self.logger.player_input(
-                    format!("Processing player inputs from {:?}: {:?}", src, inputs)
+                    || format!("Processing player inputs from {:?}: {:?}", src, inputs)
                 );
                 self.broadcast_inputs(&inputs, src);
+                self.logger.debug(
+                    || format!("Consensus frame of {:?}'s lobby: {:?}", src, self.consensus_frame(src))
+                );
             }
             NetworkMessage::GetServerPlayerIDs => {
+                let mut other_layout = 0u8;
                 let player_ids: Vec<u8> = self.addr_to_player
                     .iter()
                     .filter_map(|(addr, player)| {
-                        if *addr != *src { Some(player.0) } else { None }
+                        match self.not_joinable(addr, *player, src) {
+                            None => Some(player.0),
+                            Some(NotJoinable::OtherWorldLayout) => {
+                                other_layout = other_layout.saturating_add(1);
+                                None
+                            }
+                            Some(_) => None,
+                        }
                     })
                     .collect();
-                self.logger.message(format!("Sending player IDs: {:?}", player_ids));
+                self.logger.message(
+                    || format!("Sending player IDs: {:?}, {} with another world layout", player_ids, other_layout)
+                );
                 self.send_and_resend_until_ack(
-                    NetworkMessage::ServerSentPlayerIDs(player_ids),
+                    NetworkMessage::ServerSentPlayerIDs(player_ids, other_layout),
                     src
                 );
             }
             NetworkMessage::ClientSideAck(seq_num) => {
                 self.handle_clients_ack(seq_num, src);
             }
+            NetworkMessage::ClientSideAcks(seq_nums) => {
+                for seq_num in seq_nums {
+                    self.handle_clients_ack(seq_num, src);
+                }
+            }
+            NetworkMessage::ClientInputsAckedThrough(frame) => {
+                self.handle_player_inputs_acked_through(frame, src);
+            }
+            NetworkMessage::ClientSetTickRate(tick_rate) => {
+                self.logger.connection(|| format!("{:?} hosts at {} Hz", src, tick_rate.hz()));
+                self.tick_rates.insert(*src, tick_rate);
+                // frames advance at the new rate from here on
+                self.input_relay.restart_checks(src);
+            }
+            NetworkMessage::ClientLeaveSession => {
+                self.leave_session(*src);
+            }
+            NetworkMessage::ClientStartedHosting => {
+                self.logger.connection(|| format!("{:?} started hosting", src));
+                self.nudge_lobby(src);
+            }
+            NetworkMessage::ClientRequestResync => {
+                // only the host answers, a joiner ignores the request
+                let peers = self.connections.get(src).cloned().unwrap_or_default();
+                self.logger.world_state(|| format!("{:?} asked for a resync, asking {:?}", src, peers));
+                for peer in peers {
+                    self.send_and_resend_until_ack(NetworkMessage::ServerRequestHostForWorldData, &peer);
+                }
+            }
+            NetworkMessage::ClientRequestInputRange { from_frame, to_frame } => {
+                self.answer_input_range(src, from_frame, to_frame);
+            }
+            NetworkMessage::ClientWorldBaseline(frame) => {
+                self.set_relay_floor(src, frame);
+            }
+            // the peers fill the pause with empty inputs, they need the slot to know whose
+            NetworkMessage::ClientInputStreamPaused { last_frame } => {
+                let Some(slot) = self.player_slots.get(src).copied() else {
+                    return;
+                };
+                self.logger.player_input(|| format!("{:?} paused after frame {}", src, last_frame));
+                self.input_relay.on_paused(src, last_frame);
+                self.broadcast_reliable(NetworkMessage::ServerInputStreamPaused { slot, last_frame }, src);
+            }
+            NetworkMessage::ClientInputStreamResumed { from_frame } => {
+                let Some(slot) = self.player_slots.get(src).copied() else {
+                    return;
+                };
+                self.logger.player_input(|| format!("{:?} plays again from frame {}", src, from_frame));
+                // the peers stop filling at the resume either way, only the acks wait for a plausible one
+                if let Some(through_frame) = from_frame.checked_sub(1) {
+                    self.skip_paused_frames(src, through_frame);
+                }
+                self.broadcast_reliable(NetworkMessage::ServerInputStreamResumed { slot, from_frame }, src);
+            }
+            NetworkMessage::ClientInputStreamPausedThrough { through_frame } => {
+                let Some(slot) = self.player_slots.get(src).copied() else {
+                    return;
+                };
+                if !self.skip_paused_frames(src, through_frame) {
+                    return;
+                }
+                let peers = self.connections.get(src).cloned().unwrap_or_default();
+                for peer in peers {
+                    self.send_once(NetworkMessage::ServerInputStreamPausedThrough { slot, through_frame }, &peer);
+                }
+            }
+            NetworkMessage::ClientJoinFailed(reason) => {
+                // the group hears why before the joiner's PlayerLeft, its own leave may be lost or late
+                let Some(joiner) = self.addr_to_player.get(src).copied() else {
+                    return;
+                };
+                self.logger.connection(|| format!("{:?} couldn't join: {}", src, reason));
+                let peers = self.connections.get(src).cloned().unwrap_or_default();
+                for peer in peers {
+                    self.send_and_resend_until_ack(NetworkMessage::ServerJoinFailed(joiner, reason.clone()), &peer);
+                }
+                self.leave_session(*src);
+            }
+            NetworkMessage::Ping { nonce, send_time_micros } => {
+                // answered right away, the client measures its round trip with it
+                self.send_once(NetworkMessage::Pong { nonce, send_time_micros }, src);
+            }
             NetworkMessage::ClientConnectToOtherWorld(id) => {
                 debug_assert!(id.0 != self.addr_to_player.get(src).unwrap().0);
                 let other_player_addr = self.player_to_addr[id.0 as usize]
                     .clone()
                     .expect("Corrupt player to addr");
-                self.logger.connection("Client requesting connection");
+                if !self.same_world_layout(&other_player_addr, src) {
+                    // the host's world wouldn't read back on the joiner, it was never offered
+                    self.logger.error(
+                        || format!("Refusing {:?}: {:?} runs another world layout", src, other_player_addr)
+                    );
+                    return;
+                }
+                self.logger.connection(|| "Client requesting connection");
                 self.create_player_conn_from_to_host(*src, other_player_addr);
             }
             _ => {
-                self.logger.debug("Received unhandled message type");
+                self.logger.debug(|| "Received unhandled message type");
             }
         }
     }
 
+    // why the client at `addr` isn't offered to `requester` as a world to join, None when it is.
+    // the requester is recognized by its player id, not only the address it asked from
+    fn not_joinable(
+        &self,
+        addr: &SocketAddr,
+        player: ServerPlayerID,
+        requester: &SocketAddr
+    ) -> Option<NotJoinable> {
+        if *addr == *requester || self.addr_to_player.get(requester) == Some(&player) {
+            return Some(NotJoinable::Requester);
+        }
+        if self.incompatible_peers.contains(addr) {
+            return Some(NotJoinable::Incompatible);
+        }
+        if self.connections.get(addr).is_some_and(|peers| peers.len() + 1 >= (MAX_PLAYER_COUNT as usize)) {
+            return Some(NotJoinable::LobbyFull);
+        }
+        if !self.same_world_layout(addr, requester) {
+            return Some(NotJoinable::OtherWorldLayout);
+        }
+        None
+    }
+
+    // a client that never said Hello isn't held back, it can't have been offered by layout anyway
+    fn same_world_layout(&self, a: &SocketAddr, b: &SocketAddr) -> bool {
+        match (self.world_layouts.get(a), self.world_layouts.get(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
+
     pub fn handle_clients_ack(&mut self, seq_num: SeqNum, src: &SocketAddr) {
         if let Some(non_inp_pending_messages) = self.non_input_pending_acks.get_mut(src) {
-            if non_inp_pending_messages.remove(&seq_num).is_some() {
+            if let Some(acked) = non_inp_pending_messages.on_ack(&seq_num) {
+                if let Some(link_loss) = self.link_loss.get_mut(src) {
+                    link_loss.reliable.on_ack(seq_num);
+                }
                 self.logger.ack(
-                    format!("Acknowledged message {:?} from client {:?}", seq_num, src)
+                    || format!(
+                        "Acknowledged message {:?} from client {:?} after {} resends",
+                        seq_num,
+                        src,
+                        acked.retries
+                    )
                 );
-            } else {
-                self.handle_player_input_ack(seq_num, src);
             }
         } else {
-            self.logger.error(format!("Received acknowledgment from unknown client {:?}", src));
-            self.logger.debug(format!("Pending acks: {:?}", self.non_input_pending_acks));
+            self.logger.error(|| format!("Received acknowledgment from unknown client {:?}", src));
+            self.logger.debug(|| {
+                let pending: Vec<_> = self.non_input_pending_acks
+                    .iter()
+                    .map(|(addr, pending)| (addr, pending.pending_retries().collect::<Vec<_>>()))
+                    .collect();
+                format!("Pending acks with their resends: {:?}", pending)
+            });
         }
     }
 
@@ -359,51 +1188,78 @@ impl Server {
         );
         match serialized_msg {
             SerializedMessageType::Chunked(_) => {
-                self.logger.error("ACK message shouldn't need to be chunked");
+                self.logger.error(|| "ACK message shouldn't need to be chunked");
                 panic!("Ack msg shouldnt need to be chunked");
             }
             SerializedMessageType::NonChunked(serialized_msg) => {
+                self.record_sent(dst, &serialized_msg.bytes, Some(&NetworkMessage::ServerSideAck(seq_num)));
+                if let Err(e) = self.socket.send_to(&serialized_msg.bytes, dst) {
+                    self.logger.error(|| format!("Failed to send ACK to {:?}: {}", dst, e));
+                }
+            }
+        }
+    }
+
+    // nothing is resent if it gets lost
+    fn send_once(&mut self, msg: NetworkMessage, dst: &SocketAddr) {
+        match msg.serialize(types::NetworkMessageType::SendOnce) {
+            SerializedMessageType::Chunked(_) => {
+                self.logger.error(|| format!("{:?} doesn't fit one datagram, dropped it", msg));
+            }
+            SerializedMessageType::NonChunked(serialized_msg) => {
+                self.record_sent(dst, &serialized_msg.bytes, Some(&msg));
                 if let Err(e) = self.socket.send_to(&serialized_msg.bytes, dst) {
-                    self.logger.error(format!("Failed to send ACK to {:?}: {}", dst, e));
+                    self.logger.error(|| format!("Failed to send {:?} to {:?}: {}", msg, dst, e));
                 }
             }
         }
     }
 
     pub fn send_and_resend_until_ack(&mut self, msg: NetworkMessage, dst: &SocketAddr) {
-        self.logger.debug(format!("Sending message {:?} to client {:?}", msg, dst));
-        let serialized_msg = msg.serialize(
-            types::NetworkMessageType::ResendUntilAck(self.sequence_number.seq_num)
-        );
+        self.logger.debug(|| format!("Sending message {:?} to client {:?}", msg, dst));
+        let serialized_msg = msg.serialize(types::NetworkMessageType::ReliableTransfer {
+            seq_num: self.sequence_number.seq_num,
+            transfer_id: self.next_transfer_id,
+        });
+        let policy = retry_policy(msg.retry_class());
         match serialized_msg {
             SerializedMessageType::Chunked(chunks) => {
-                for msg in chunks.bytes {
+                self.next_transfer_id = self.next_transfer_id.wrapping_add(1);
+                for msg in chunks.chunks {
                     let seq_num = self.sequence_number.get_seq_num();
-                    self.logger.message("Sending chunked message to client");
+                    self.logger.message(|| "Sending chunked message to client");
                     debug_assert!(
-                        u16::from_le_bytes([msg[SEQ_NUM_BYTE_POS], msg[SEQ_NUM_BYTE_POS + 1]]) ==
-                            seq_num.0
+                        u16::from_le_bytes([
+                            msg.bytes[SEQ_NUM_BYTE_POS],
+                            msg.bytes[SEQ_NUM_BYTE_POS + 1],
+                        ]) == seq_num.0
                     );
-                    if let Err(e) = self.socket.send_to(&msg, dst) {
+                    self.record_sent(dst, &msg.bytes, None);
+                    if let Err(e) = self.socket.send_to(&msg.bytes, dst) {
                         self.logger.error(
-                            format!("Failed to send reliable message to {:?}: {}", dst, e)
+                            || format!("Failed to send reliable message to {:?}: {}", dst, e)
                         );
                     }
+                    let now = Instant::now();
                     self.non_input_pending_acks
                         .entry(*dst)
-                        .or_insert_with(HashMap::new)
-                        .insert(seq_num, (Instant::now(), SerializedNetworkMessage { bytes: msg }));
+                        .or_insert_with(new_pending_acks)
+                        .insert_with_policy(now, seq_num, msg, policy);
+                    self.link_loss.entry(*dst).or_default().reliable.on_send(now, seq_num);
                 }
             }
             SerializedMessageType::NonChunked(serialized_msg) => {
                 let seq_num = self.sequence_number.get_seq_num();
+                let now = Instant::now();
                 self.non_input_pending_acks
                     .entry(*dst)
-                    .or_insert_with(HashMap::new)
-                    .insert(seq_num, (Instant::now(), serialized_msg.clone()));
+                    .or_insert_with(new_pending_acks)
+                    .insert_with_policy(now, seq_num, serialized_msg.clone(), policy);
+                self.link_loss.entry(*dst).or_default().reliable.on_send(now, seq_num);
+                self.record_sent(dst, &serialized_msg.bytes, Some(&msg));
                 if let Err(e) = self.socket.send_to(&serialized_msg.bytes, dst) {
                     self.logger.error(
-                        format!("Failed to send reliable message to {:?}: {}", dst, e)
+                        || format!("Failed to send reliable message to {:?}: {}", dst, e)
                     );
                 }
             }
@@ -419,82 +1275,1229 @@ impl Server {
         }
     }
 
-    fn broadcast_inputs(&mut self, inputs: &BufferedNetworkedPlayerInputs, src: &SocketAddr) {
-        let seq_num = self.sequence_number.get_seq_num();
-        if let Some(connections) = self.connections.get(src) {
-            let msg = NetworkMessage::ServerSentPlayerInputs(inputs.clone()).serialize(
-                types::NetworkMessageType::SendOnceButReceiveAck(seq_num)
-            );
+    // the host's world of `frame` is on its way to its peers, they start from it and only need the
+    // host's inputs after it. what piled up for them since the pairing is dropped
+    fn set_relay_floor(&mut self, host_addr: &SocketAddr, frame: u32) {
+        let peers = self.connections.get(host_addr).cloned().unwrap_or_default();
+        for peer in peers {
+            self.input_relay.set_floor(peer, frame);
+            self.logger.player_input(|| format!("Relaying inputs after frame {} to {:?}", frame, peer));
+        }
+    }
 
-            match msg {
-                SerializedMessageType::NonChunked(msg) => {
-                    for target in connections.clone() {
-                        if let Some(inp_buffer) = self.unack_input_buffer.get_mut(&target) {
-                            inp_buffer.bulk_insert_player_input(inputs.clone());
-                            if
-                                let Some(seq_num_to_frame) =
-                                    self.unack_input_seq_nums_to_frame.get_mut(&target)
-                            {
-                                seq_num_to_frame.insert(
-                                    seq_num,
-                                    inp_buffer.buffered_inputs
-                                        .last()
-                                        .expect("If we send sth it shouldnt be empty").frame
-                                );
-
-                                #[cfg(feature = "simulation_mode")]
-                                {
-                                    self.logger.debug("Enqueued player inputs");
-                                    self.network_simulator.enqueue_send_message(
-                                        msg.bytes.clone(),
-                                        target
-                                    );
-                                }
+    fn broadcast_inputs(&mut self, inputs: &BufferedNetworkedPlayerInputs, src: &SocketAddr) {
+        let Some(slot) = self.player_slots.get(src).copied() else {
+            return; // not in a session yet, nobody to forward to
+        };
+        let Some(connections) = self.connections.get(src).cloned() else {
+            return;
+        };
+        for target in connections {
+            let Some(relayed) = self.input_relay.relay_to(&target, inputs) else {
+                continue;
+            };
+            if relayed.dropped > 0 {
+                self.logger.player_input(
+                    || format!(
+                        "Dropped {} unacked input frames for {:?}, oldest kept is {}",
+                        relayed.dropped,
+                        target,
+                        relayed.oldest_unacked
+                    )
+                );
+            }
+            let msg = NetworkMessage::ServerSentPlayerInputs(slot, relayed.inputs);
+            match msg.serialize(types::NetworkMessageType::SendOnce) {
+                SerializedMessageType::NonChunked(serialized_msg) => {
+                    if let Some(link_loss) = self.link_loss.get_mut(&target) {
+                        link_loss.inputs.on_send(Instant::now(), relayed.newest_unacked);
+                    }
+                    self.record_sent(&target, &serialized_msg.bytes, Some(&msg));
 
-                                #[cfg(not(feature = "simulation_mode"))]
-                                {
-                                    if let Err(e) = self.socket.send_to(&msg.bytes, target) {
-                                        self.logger.error(
-                                            format!("Failed to send input message: {}", e)
-                                        );
-                                    }
-                                }
-                            }
-                        }
+                    #[cfg(feature = "simulation_mode")]
+                    {
+                        self.logger.debug(|| "Enqueued player inputs");
+                        self.network_simulator.enqueue_send_message(
+                            Instant::now(),
+                            serialized_msg.bytes.to_vec(),
+                            target
+                        );
                     }
+
+                    // behind whatever is queued for this target, but not behind other clients
+                    #[cfg(not(feature = "simulation_mode"))]
+                    self.outgoing.push(target, None, serialized_msg.bytes.clone());
                 }
                 SerializedMessageType::Chunked(_) => {
-                    self.logger.error("Inputs should never be chunked");
-                    panic!("Inputs should never be chunked");
+                    self.logger.error(|| "Inputs should never be chunked, dropped them");
                 }
             }
         }
     }
 
-    fn handle_player_input_ack(&mut self, seq_num: SeqNum, src: &SocketAddr) {
-        if let Some(inp_buffer) = self.unack_input_buffer.get_mut(src) {
-            if let Some(seq_num_to_frame) = self.unack_input_seq_nums_to_frame.get_mut(src) {
-                if let Some(frame) = seq_num_to_frame.remove(&seq_num) {
-                    inp_buffer.discard_acknowledged_frames(frame);
-                }
-            } else {
+    // frames still waiting for the requester's ack are sent again right away, once any of them was
+    // acked or dropped only the peer has them and is asked to send them again
+    fn answer_input_range(&mut self, src: &SocketAddr, from_frame: u32, to_frame: u32) {
+        // a client asks for at most MAX_REQUESTED_FRAMES, anything longer or reversed isn't a lost datagram
+        let requested = to_frame
+            .checked_sub(from_frame)
+            .and_then(|span| span.checked_add(1))
+            .filter(|frames| (*frames as usize) <= MAX_REQUESTED_FRAMES);
+        let Some(requested) = requested else {
+            self.logger.error(|| {
+                format!("{:?} asked for frames {} to {}, more than a client ever does", src, from_frame, to_frame)
+            });
+            return;
+        };
+        let peers = self.connections.get(src).cloned().unwrap_or_default();
+        let [peer] = peers[..] else {
+            self.logger.error(|| format!("{:?} asked for inputs without a peer to get them from", src));
+            return;
+        };
+        let slot = self.player_slots.get(&peer).copied();
+        let buffered = self.input_relay.unacked_in(src, from_frame, to_frame);
+        match slot {
+            Some(slot) if buffered.len() == (requested as usize) => {
+                self.logger.player_input(
+                    || format!("Resending frames {} to {} to {:?}", from_frame, to_frame, src)
+                );
+                let inputs = BufferedNetworkedPlayerInputs { buffered_inputs: buffered };
+                self.send_once(NetworkMessage::ServerSentPlayerInputs(slot, inputs), src);
+            }
+            _ => {
+                self.logger.player_input(
+                    || format!("Asking {:?} to resend frames {} to {} for {:?}", peer, from_frame, to_frame, src)
+                );
+                self.send_and_resend_until_ack(
+                    NetworkMessage::ServerRequestInputRange { from_frame, to_frame },
+                    &peer
+                );
+            }
+        }
+    }
+
+    // the client got every relayed input up to `frame`, they aren't repeated to it anymore
+    // the paused frames count as received, false if the pause claims frames the stream can't be at yet
+    fn skip_paused_frames(&mut self, src: &SocketAddr, through_frame: u32) -> bool {
+        match self.input_relay.paused_through(Instant::now(), src, through_frame) {
+            Ok(()) => true,
+            Err(rejection) => {
                 self.logger.error(
-                    "BUG: seq_num_to_frame should always exist when inp buffer exists"
+                    || format!(
+                        "Dropped pause from {:?}: {} ({} rejections)",
+                        src,
+                        rejection,
+                        self.input_relay.rejections(src)
+                    )
                 );
+                false
+            }
+        }
+    }
+
+    fn handle_player_inputs_acked_through(&mut self, frame: u32, src: &SocketAddr) {
+        if self.input_relay.on_acked_through(src, frame) {
+            if let Some(link_loss) = self.link_loss.get_mut(src) {
+                link_loss.inputs.on_acked_through(frame);
             }
         } else {
-            self.logger.error("Unack input buffer missing for client, possibly timeout or bug");
+            self.logger.error(|| "Unack input buffer missing for client, possibly timeout or bug");
         }
     }
 }
 
 fn main() -> std::io::Result<()> {
-    let mut server = Server::new();
-    server.logger.message("Server started on 127.0.0.1:8080");
-    loop {
-        #[cfg(feature = "simulation_mode")]
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config_path = Path::new(SERVER_CONFIG_FILE);
+    let server_config: ServerConfig = config::load(config_path, &args).map_err(std::io::Error::other)?;
+    let scan = if server_config.port_scan { PORT_SCAN_RANGE } else { 0 };
+    let mut server = match Server::bind_first_free(&server_config.bind_addr, scan) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("Server failed to start: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    };
+    server.logger = Logger::new(server_config.log);
+    server.message_history = MessageHistory::new(server_config.message_history);
+    if server_config.console {
+        server.console = Some(console::spawn());
+    }
+    signal_hook::flag::register(signal_hook::consts::SIGINT, server.shutdown_flag())?;
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, server.shutdown_flag())?;
+    // printed whatever the log settings, clients have to be pointed at this port
+    let local_addr = server.socket.local_addr()?;
+    let configured_port = server_config.bind_addr.rsplit(':').next().and_then(|port| port.parse::<u16>().ok());
+    if configured_port.is_some_and(|port| port != 0 && port != local_addr.port()) {
+        println!("{} is already in use, took the next free port", server_config.bind_addr);
+    }
+    println!("==== Server listening on {} ====", local_addr);
+    // the simulator's key controls and the console would both read the terminal
+    #[cfg(feature = "simulation_mode")]
+    if server_config.console {
+        server.run();
+    } else {
         server.run_w_attached_tui()?;
-        #[cfg(not(feature = "simulation_mode"))]
+    }
+    #[cfg(not(feature = "simulation_mode"))]
+    server.run();
+    // only a clean shutdown remembers the settings, flags given this time included
+    config::save(&server_config, config_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        DeserializedMessageType,
+        NetworkMessageType,
+        NetworkedPlayerInput,
+        PlayerInput,
+        MAX_UDP_PAYLOAD_DATA_LENGTH,
+    };
+
+    fn test_server() -> Server {
+        #[allow(unused_mut)]
+        let mut server = Server::bind("127.0.0.1:0").expect("Failed to bind test server");
+        #[cfg(feature = "simulation_mode")]
+        {
+            server.network_simulator = NetworkSimulator::new(NETWORK_SIM_SEED, 0, 0, 0.0);
+        }
+        server
+    }
+
+    fn test_client() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        socket
+    }
+
+    fn recv_msg(socket: &UdpSocket) -> Option<NetworkMessage> {
+        let mut buffer = MsgBuffer::default();
+        let amt = socket.recv(&mut buffer.0).ok()?;
+        match buffer.parse_on_client(amt).ok()? {
+            DeserializedMessageType::NonChunked(msg) => Some(msg.msg),
+            DeserializedMessageType::ChunkOfMessage(_) => None,
+        }
+    }
+
+    #[test]
+    fn test_forwarded_inputs_are_stamped_with_sender_slot() {
+        let mut server = test_server();
+        let host = test_client();
+        let joiner = test_client();
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+
+        for (src, frame) in [(joiner_addr, 3), (host_addr, 4)] {
+            let inputs = BufferedNetworkedPlayerInputs {
+                buffered_inputs: vec![NetworkedPlayerInput::new(Vec::new(), frame)],
+            };
+            server.process_message(NetworkMessage::ClientSentPlayerInputs(inputs), &src);
+        }
+        flush_sends(&mut server);
+
+        let forwarded = |socket: &UdpSocket| {
+            std::iter::from_fn(|| recv_msg(socket)).find_map(|msg| {
+                match msg {
+                    NetworkMessage::ServerSentPlayerInputs(slot, inputs) =>
+                        Some((slot, inputs.buffered_inputs[0].frame)),
+                    _ => None,
+                }
+            })
+        };
+        assert_eq!(forwarded(&host), Some((PlayerID::Player2, 3)));
+        assert_eq!(forwarded(&joiner), Some((PlayerID::Player1, 4)));
+    }
+
+    #[cfg(feature = "simulation_mode")]
+    #[test]
+    fn test_a_peer_on_a_poor_link_gets_its_inputs_later() {
+        use unlockrs::network_simulator::LinkConditions;
+
+        let mut server = test_server();
+        let (host_addr, joiner_addr) = (test_client().local_addr().unwrap(), test_client().local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        // the join notifications go out without delay
+        server.network_simulator.get_ready_send_messages(Instant::now());
+        let poor = LinkConditions { latency: 150, jitter: 0, packet_loss: 0.0 };
+        server.network_simulator.set_link(joiner_addr, poor);
+
+        for src in [joiner_addr, host_addr] {
+            let inputs = BufferedNetworkedPlayerInputs {
+                buffered_inputs: vec![NetworkedPlayerInput::new(Vec::new(), 1)],
+            };
+            server.process_message(NetworkMessage::ClientSentPlayerInputs(inputs), &src);
+        }
+        let relayed_to = |server: &mut Server, at: Instant| -> Vec<SocketAddr> {
+            server.network_simulator
+                .get_ready_send_messages(at)
+                .into_iter()
+                .map(|(_, dst)| dst)
+                .collect()
+        };
+        assert_eq!(relayed_to(&mut server, Instant::now()), vec![host_addr]);
+        assert_eq!(relayed_to(&mut server, Instant::now() + Duration::from_millis(150)), vec![joiner_addr]);
+    }
+
+    #[test]
+    fn test_slots_are_assigned_by_the_server_and_sent_to_both_sides() {
+        let mut server = test_server();
+        let (host, joiner, late) = (test_client(), test_client(), test_client());
+        let addrs: Vec<SocketAddr> = [&host, &joiner, &late]
+            .iter()
+            .map(|socket| socket.local_addr().unwrap())
+            .collect();
+        for addr in &addrs {
+            server.create_new_connection(addr);
+        }
+        // a host that already plays in the second slot keeps it, the joiner gets what's left
+        server.player_slots.insert(addrs[0], PlayerID::Player2);
+        server.create_player_conn_from_to_host(addrs[1], addrs[0]);
+        // the lobby is full now
+        server.create_player_conn_from_to_host(addrs[2], addrs[0]);
+        flush_sends(&mut server);
+
+        let assigned = |socket: &UdpSocket| {
+            std::iter::from_fn(|| recv_msg(socket)).find_map(|msg| {
+                match msg {
+                    NetworkMessage::ServerAssignPlayerSlot(slot) => Some(slot),
+                    _ => None,
+                }
+            })
+        };
+        assert_eq!(assigned(&host), Some(PlayerID::Player2));
+        assert_eq!(assigned(&joiner), Some(PlayerID::Player1));
+        assert_eq!(assigned(&late), None);
+        assert!(!server.player_slots.contains_key(&addrs[2]));
+        assert_eq!(server.connections[&addrs[0]], vec![addrs[1]]);
+    }
+
+    #[test]
+    fn test_consensus_frame_is_lowest_contiguous_frame() {
+        let mut server = test_server();
+        let (host_addr, joiner_addr) = (
+            test_client().local_addr().unwrap(),
+            test_client().local_addr().unwrap(),
+        );
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        assert_eq!(server.consensus_frame(&host_addr), None);
+
+        let send = |server: &mut Server, src: &SocketAddr, frames: &[u32]| {
+            let inputs = BufferedNetworkedPlayerInputs {
+                buffered_inputs: frames
+                    .iter()
+                    .map(|frame| NetworkedPlayerInput::new(Vec::new(), *frame))
+                    .collect(),
+            };
+            server.process_message(NetworkMessage::ClientSentPlayerInputs(inputs), src);
+        };
+        send(&mut server, &host_addr, &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        // the joiner hasn't sent anything yet
+        assert_eq!(server.consensus_frame(&host_addr), None);
+        // frame 7 got lost, 8 doesn't count until it arrives
+        send(&mut server, &joiner_addr, &[1, 2, 3, 4, 5, 6, 8]);
+        assert_eq!(server.consensus_frame(&host_addr), Some(6));
+        assert_eq!(server.consensus_frame(&joiner_addr), Some(6));
+        send(&mut server, &joiner_addr, &[7, 8]);
+        assert_eq!(server.consensus_frame(&joiner_addr), Some(8));
+    }
+
+    fn send_reliable(client: &UdpSocket, msg: NetworkMessage, seq_num: u16) {
+        let SerializedMessageType::NonChunked(request) = msg.serialize(
+            NetworkMessageType::ResendUntilAck(SeqNum(seq_num))
+        ) else {
+            panic!("request should not be chunked");
+        };
+        client.send(&request.bytes).unwrap();
+    }
+
+    // everything the server queued goes out, through the simulator or the send queues
+    fn flush_sends(server: &mut Server) {
+        #[cfg(feature = "simulation_mode")]
+        for (data, dst) in server.network_simulator.get_ready_send_messages(Instant::now()) {
+            server.socket.send_to(&data, dst).unwrap();
+        }
+        server.drain_outgoing(usize::MAX);
+    }
+
+    // enough updates to receive a couple of datagrams and send out the replies
+    fn run_updates(server: &mut Server) {
+        std::thread::sleep(Duration::from_millis(20));
+        for _ in 0..4 {
+            server.update();
+        }
+    }
+
+    #[test]
+    fn test_hello_with_other_features_is_rejected() {
+        let mut server = test_server();
+        let client = test_client();
+        client.connect(server.socket.local_addr().unwrap()).unwrap();
+        let client_info = ProtocolInfo {
+            feature_bits: ProtocolInfo::local().feature_bits | (1 << 31),
+            ..ProtocolInfo::local()
+        };
+        send_reliable(&client, NetworkMessage::Hello(client_info, SessionId(1)), 0);
+        run_updates(&mut server);
+        let received: Vec<NetworkMessage> = std::iter::from_fn(|| recv_msg(&client)).collect();
+        assert!(
+            received
+                .iter()
+                .any(|msg| matches!(msg, NetworkMessage::HelloAck(info) if *info == ProtocolInfo::local()))
+        );
+        assert!(server.incompatible_peers.contains(&client.local_addr().unwrap()));
+
+        send_reliable(&client, NetworkMessage::GetServerPlayerIDs, 1);
+        run_updates(&mut server);
+        assert!(
+            !std::iter::from_fn(|| recv_msg(&client)).any(|msg|
+                matches!(msg, NetworkMessage::ServerSentPlayerIDs(..))
+            )
+        );
+    }
+
+    #[test]
+    fn test_hello_with_matching_features_proceeds() {
+        let mut server = test_server();
+        let client = test_client();
+        client.connect(server.socket.local_addr().unwrap()).unwrap();
+        send_reliable(&client, NetworkMessage::Hello(ProtocolInfo::local(), SessionId(1)), 0);
+        send_reliable(&client, NetworkMessage::GetServerPlayerIDs, 1);
+        run_updates(&mut server);
+        let received: Vec<NetworkMessage> = std::iter::from_fn(|| recv_msg(&client)).collect();
+        assert!(received.iter().any(|msg| matches!(msg, NetworkMessage::HelloAck(_))));
+        assert!(received.iter().any(|msg| matches!(msg, NetworkMessage::ServerSentPlayerIDs(..))));
+        assert!(server.incompatible_peers.is_empty());
+    }
+
+    #[test]
+    fn test_host_list_only_offers_joinable_worlds() {
+        let mut server = test_server();
+        let requester = test_client();
+        let requester_addr = requester.local_addr().unwrap();
+        let [host_addr, full_host_addr, full_joiner_addr, incompatible_addr] = [(); 4].map(|_|
+            test_client().local_addr().unwrap()
+        );
+        for addr in [requester_addr, host_addr, full_host_addr, full_joiner_addr, incompatible_addr] {
+            server.create_new_connection(&addr);
+        }
+        server.create_player_conn_from_to_host(full_joiner_addr, full_host_addr);
+        server.incompatible_peers.insert(incompatible_addr);
+        let requester_id = server.addr_to_player[&requester_addr];
+        let host_id = server.addr_to_player[&host_addr];
+
+        assert_eq!(
+            server.not_joinable(&requester_addr, requester_id, &requester_addr),
+            Some(NotJoinable::Requester)
+        );
+        assert_eq!(
+            server.not_joinable(&full_host_addr, server.addr_to_player[&full_host_addr], &requester_addr),
+            Some(NotJoinable::LobbyFull)
+        );
+        assert_eq!(
+            server.not_joinable(&incompatible_addr, server.addr_to_player[&incompatible_addr], &requester_addr),
+            Some(NotJoinable::Incompatible)
+        );
+        assert_eq!(server.not_joinable(&host_addr, host_id, &requester_addr), None);
+
+        server.process_message(NetworkMessage::GetServerPlayerIDs, &requester_addr);
+        flush_sends(&mut server);
+        assert!(
+            std::iter::from_fn(|| recv_msg(&requester)).any(|msg|
+                matches!(msg, NetworkMessage::ServerSentPlayerIDs(ids, 0) if ids == vec![host_id.0])
+            )
+        );
+    }
+
+    #[test]
+    fn test_hosts_with_another_world_layout_are_not_offered() {
+        let mut server = test_server();
+        let host_addr = test_client().local_addr().unwrap();
+        let joiner = test_client();
+        let joiner_addr = joiner.local_addr().unwrap();
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        let host_info = ProtocolInfo {
+            world_layout: ProtocolInfo::local().world_layout ^ 1,
+            ..ProtocolInfo::local()
+        };
+        server.process_message(NetworkMessage::Hello(host_info, SessionId(1)), &host_addr);
+        server.process_message(NetworkMessage::Hello(ProtocolInfo::local(), SessionId(2)), &joiner_addr);
+        // the rest of the handshake matches, only the world wouldn't read back
+        assert!(server.incompatible_peers.is_empty());
+
+        server.process_message(NetworkMessage::GetServerPlayerIDs, &joiner_addr);
+        flush_sends(&mut server);
+        assert!(
+            std::iter::from_fn(|| recv_msg(&joiner)).any(|msg|
+                matches!(msg, NetworkMessage::ServerSentPlayerIDs(ids, 1) if ids.is_empty())
+            )
+        );
+
+        // asking for the host by id anyway doesn't connect the two
+        let host_id = server.addr_to_player[&host_addr];
+        server.process_message(NetworkMessage::ClientConnectToOtherWorld(host_id), &joiner_addr);
+        assert!(!server.connections.contains_key(&joiner_addr));
+    }
+
+    #[test]
+    fn test_a_taken_port_fails_the_start_unless_scanning_for_a_free_one() {
+        let taken = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let taken_addr = taken.local_addr().unwrap();
+        match Server::bind(&taken_addr.to_string()) {
+            Err(ServerStartError::AddrInUse { addr, scanned: 0 }) => assert_eq!(addr, taken_addr),
+            Err(e) => panic!("expected the port to be in use, got {}", e),
+            Ok(_) => panic!("bound a port that is in use"),
+        }
+        let server = Server::bind_first_free(&taken_addr.to_string(), PORT_SCAN_RANGE).unwrap();
+        let port = server.socket.local_addr().unwrap().port();
+        assert!(port > taken_addr.port() && port <= taken_addr.port() + PORT_SCAN_RANGE, "port {}", port);
+
+        assert!(matches!(Server::bind("not an address"), Err(ServerStartError::InvalidAddr(_))));
+    }
+
+    #[test]
+    fn test_server_config_round_trips_and_flags_win() {
+        let config = ServerConfig {
+            bind_addr: "0.0.0.0:9000".to_string(),
+            port_scan: true,
+            console: true,
+            message_history: 64,
+            log: LogConfig { connection: true, error: true, ..LogConfig::default() },
+        };
+        let path = std::env::temp_dir().join(format!("unlockrs-server-{}.cfg", std::process::id()));
+        config::save(&config, &path).unwrap();
+        assert_eq!(config::load::<ServerConfig>(&path, &[]), Ok(config.clone()));
+        let args = ["--bind_addr=127.0.0.1:0".to_string()];
+        assert_eq!(
+            config::load::<ServerConfig>(&path, &args),
+            Ok(ServerConfig { bind_addr: "127.0.0.1:0".to_string(), ..config })
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_resync_request_is_passed_to_the_host() {
+        let mut server = test_server();
+        let host = test_client();
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), test_client().local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        server.process_message(NetworkMessage::ClientRequestResync, &joiner_addr);
+        flush_sends(&mut server);
+        // one for the join, one for the resync
+        let requests = std::iter::from_fn(|| recv_msg(&host))
+            .filter(|msg| matches!(msg, NetworkMessage::ServerRequestHostForWorldData))
+            .count();
+        assert_eq!(requests, 2);
+    }
+
+    #[test]
+    fn test_a_pause_is_passed_on_with_the_slot_of_the_player_that_paused() {
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        server.process_message(NetworkMessage::ClientInputStreamPaused { last_frame: 40 }, &joiner_addr);
+        server.process_message(NetworkMessage::ClientInputStreamPausedThrough { through_frame: 75 }, &joiner_addr);
+        server.process_message(NetworkMessage::ClientInputStreamResumed { from_frame: 90 }, &joiner_addr);
+        flush_sends(&mut server);
+        let slot = server.player_slots[&joiner_addr];
+        let received: Vec<NetworkMessage> = std::iter::from_fn(|| recv_msg(&host)).collect();
+        assert!(
+            received.iter().any(|msg| {
+                matches!(msg, NetworkMessage::ServerInputStreamPausedThrough { slot: paused, through_frame: 75 }
+                    if *paused == slot)
+            })
+        );
+        let markers: Vec<NetworkMessage> = received
+            .into_iter()
+            .filter(|msg| {
+                matches!(
+                    msg,
+                    NetworkMessage::ServerInputStreamPaused { .. } | NetworkMessage::ServerInputStreamResumed { .. }
+                )
+            })
+            .collect();
+        assert!(matches!(markers[..], [
+            NetworkMessage::ServerInputStreamPaused { slot: paused, last_frame: 40 },
+            NetworkMessage::ServerInputStreamResumed { slot: resumed, from_frame: 90 },
+        ] if paused == slot && resumed == slot));
+        // not sent back to the one that paused
+        assert!(
+            !std::iter::from_fn(|| recv_msg(&joiner)).any(|msg|
+                matches!(msg, NetworkMessage::ServerInputStreamPaused { .. })
+            )
+        );
+    }
+
+    #[test]
+    fn test_a_resumed_stream_is_acked_past_its_pause() {
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        let send = |server: &mut Server, src: &SocketAddr, frames: std::ops::RangeInclusive<u32>| {
+            let inputs = BufferedNetworkedPlayerInputs {
+                buffered_inputs: frames.map(|frame| NetworkedPlayerInput::new(Vec::new(), frame)).collect(),
+            };
+            server.process_message(NetworkMessage::ClientSentPlayerInputs(inputs), src);
+        };
+        send(&mut server, &host_addr, 1..=10);
+        send(&mut server, &joiner_addr, 1..=38);
+        server.process_message(NetworkMessage::ClientInputStreamPaused { last_frame: 10 }, &host_addr);
+        server.process_message(NetworkMessage::ClientInputStreamPausedThrough { through_frame: 20 }, &host_addr);
+        assert_eq!(server.consensus_frame(&host_addr), Some(20));
+        server.process_message(NetworkMessage::ClientInputStreamResumed { from_frame: 26 }, &host_addr);
+        // everything before the resume was acked, the first datagram after it only holds new frames
+        send(&mut server, &host_addr, 26..=30);
+        send(&mut server, &host_addr, 26..=38);
+        assert_eq!(server.consensus_frame(&host_addr), Some(38));
+
+        server.send_input_acks();
+        flush_sends(&mut server);
+        let acked = std::iter::from_fn(|| recv_msg(&host))
+            .filter_map(|msg| {
+                match msg {
+                    NetworkMessage::ServerInputsAckedThrough(frame) => Some(frame),
+                    _ => None,
+                }
+            })
+            .last();
+        assert_eq!(acked, Some(38));
+    }
+
+    #[test]
+    fn test_input_range_is_resent_while_unacked_and_asked_of_the_peer_once_gone() {
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        let inputs = BufferedNetworkedPlayerInputs {
+            buffered_inputs: (1..=4).map(|frame| NetworkedPlayerInput::new(vec![PlayerInput::Left], frame)).collect(),
+        };
+        server.process_message(NetworkMessage::ClientSentPlayerInputs(inputs), &host_addr);
+        flush_sends(&mut server);
+        while recv_msg(&joiner).is_some() {}
+        while recv_msg(&host).is_some() {}
+
+        // the joiner never acked frames 2 and 3, the server still has them
+        server.process_message(NetworkMessage::ClientRequestInputRange { from_frame: 2, to_frame: 3 }, &joiner_addr);
+        let resent = std::iter::from_fn(|| recv_msg(&joiner)).find_map(|msg| {
+            match msg {
+                NetworkMessage::ServerSentPlayerInputs(slot, inputs) => Some((slot, inputs)),
+                _ => None,
+            }
+        });
+        let (slot, resent) = resent.expect("the buffered frames should be sent again");
+        assert_eq!(slot, server.player_slots[&host_addr]);
+        assert_eq!(resent.buffered_inputs.iter().map(|inp| inp.frame).collect::<Vec<u32>>(), vec![2, 3]);
+        assert!(recv_msg(&host).is_none());
+
+        // frame 5 never reached the server, only the host can send it again
+        server.process_message(NetworkMessage::ClientRequestInputRange { from_frame: 3, to_frame: 5 }, &joiner_addr);
+        assert!(
+            std::iter::from_fn(|| recv_msg(&host)).any(|msg|
+                matches!(msg, NetworkMessage::ServerRequestInputRange { from_frame: 3, to_frame: 5 })
+            )
+        );
+    }
+
+    #[test]
+    fn test_an_input_range_past_what_a_client_asks_for_is_dropped() {
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        while recv_msg(&joiner).is_some() {}
+        while recv_msg(&host).is_some() {}
+
+        let too_many = MAX_REQUESTED_FRAMES as u32;
+        for (from_frame, to_frame) in [(0, u32::MAX), (5, 4), (1, too_many + 1)] {
+            server.process_message(NetworkMessage::ClientRequestInputRange { from_frame, to_frame }, &joiner_addr);
+        }
+        assert!(recv_msg(&host).is_none());
+        assert!(recv_msg(&joiner).is_none());
+        // the longest a client asks for still reaches the peer
+        server.process_message(
+            NetworkMessage::ClientRequestInputRange { from_frame: 1, to_frame: too_many },
+            &joiner_addr
+        );
+        assert!(
+            std::iter::from_fn(|| recv_msg(&host)).any(|msg| {
+                matches!(msg, NetworkMessage::ServerRequestInputRange { from_frame: 1, to_frame }
+                    if to_frame == too_many)
+            })
+        );
+    }
+
+    #[test]
+    fn test_connecting_the_same_pair_twice_relays_each_input_once() {
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        assert_eq!(server.connections[&host_addr], vec![joiner_addr]);
+        assert_eq!(server.connections[&joiner_addr], vec![host_addr]);
+        assert_eq!(server.validate_connections(), Ok(()));
+
+        let inputs = BufferedNetworkedPlayerInputs {
+            buffered_inputs: vec![NetworkedPlayerInput::new(vec![PlayerInput::Left], 1)],
+        };
+        server.process_message(NetworkMessage::ClientSentPlayerInputs(inputs), &joiner_addr);
+        flush_sends(&mut server);
+        let relayed = std::iter::from_fn(|| recv_msg(&host))
+            .filter(|msg| matches!(msg, NetworkMessage::ServerSentPlayerInputs(..)))
+            .count();
+        assert_eq!(relayed, 1);
+    }
+
+    #[test]
+    fn test_a_joiner_only_catches_up_on_the_frames_its_world_transfer_took() {
+        const SNAPSHOT_FRAME: u32 = 120;
+        const TRANSFER_FRAMES: u32 = 24; // 0.4 s at 60 Hz until the joiner has the world
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+
+        // the host played alone up to the snapshot, its last 60 frames were never acked and every
+        // datagram repeats them
+        let batch = |newest: u32| BufferedNetworkedPlayerInputs {
+            buffered_inputs: (61..=newest).map(|frame| NetworkedPlayerInput::new(Vec::new(), frame)).collect(),
+        };
+        server.process_message(NetworkMessage::ClientSentPlayerInputs(batch(SNAPSHOT_FRAME)), &host_addr);
+        let relayed_frames = |server: &mut Server| {
+            flush_sends(server);
+            let mut frames: Vec<u32> = std::iter::from_fn(|| recv_msg(&joiner))
+                .filter_map(|msg| {
+                    match msg {
+                        NetworkMessage::ServerSentPlayerInputs(_, inputs) => Some(inputs.buffered_inputs),
+                        _ => None,
+                    }
+                })
+                .flatten()
+                .map(|inp| inp.frame)
+                .collect();
+            frames.sort_unstable();
+            frames.dedup();
+            frames
+        };
+        // went out before the host took its snapshot, the joiner's baseline drops them
+        assert_eq!(relayed_frames(&mut server).len(), 60);
+        server.process_message(NetworkMessage::ClientWorldBaseline(SNAPSHOT_FRAME), &host_addr);
+        for newest in SNAPSHOT_FRAME + 1..=SNAPSHOT_FRAME + TRANSFER_FRAMES {
+            server.process_message(NetworkMessage::ClientSentPlayerInputs(batch(newest)), &host_addr);
+        }
+        let after_snapshot: Vec<u32> = (SNAPSHOT_FRAME + 1..=SNAPSHOT_FRAME + TRANSFER_FRAMES).collect();
+        let unacked: Vec<u32> = server.input_relay.unacked(&joiner_addr).unwrap().buffered_inputs
+            .iter()
+            .map(|inp| inp.frame)
+            .collect();
+        assert_eq!(unacked, after_snapshot);
+        // what the joiner steps through after the world is the transfer's frames, none from before it
+        assert_eq!(relayed_frames(&mut server), after_snapshot);
+    }
+
+    #[test]
+    fn test_removing_a_client_takes_it_out_of_its_peers_lists() {
+        let mut server = test_server();
+        let (host_addr, joiner_addr) = (test_client().local_addr().unwrap(), test_client().local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        server.remove_client(&joiner_addr);
+        assert!(server.connections[&host_addr].is_empty());
+        assert_eq!(server.validate_connections(), Ok(()));
+
+        // an edge only one way is caught, the status command shows it
+        server.connections.get_mut(&host_addr).unwrap().push(joiner_addr);
+        assert!(server.validate_connections().unwrap_err().contains("not the other way"));
+        assert!(server.status().starts_with("Broken connection groups"));
+    }
+
+    #[test]
+    fn test_join_and_leave_are_announced_to_the_group() {
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        let (host_id, joiner_id) = (server.addr_to_player[&host_addr], server.addr_to_player[&joiner_addr]);
+
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        flush_sends(&mut server);
+        assert!(
+            std::iter::from_fn(|| recv_msg(&host)).any(|msg|
+                matches!(msg, NetworkMessage::PlayerJoined(id) if id == joiner_id)
+            )
+        );
+        assert!(
+            std::iter::from_fn(|| recv_msg(&joiner)).any(|msg|
+                matches!(msg, NetworkMessage::PlayerJoined(id) if id == host_id)
+            )
+        );
+
+        server.process_message(NetworkMessage::ClientLeaveSession, &joiner_addr);
+        flush_sends(&mut server);
+        assert!(
+            std::iter::from_fn(|| recv_msg(&host)).any(|msg|
+                matches!(msg, NetworkMessage::PlayerLeft(id) if id == joiner_id)
+            )
+        );
+        assert!(!server.connections.contains_key(&joiner_addr));
+        assert!(server.connections[&host_addr].is_empty());
+        // a second leave has no group to leave
+        server.process_message(NetworkMessage::ClientLeaveSession, &joiner_addr);
+    }
+
+    #[test]
+    fn test_a_source_past_the_last_player_id_is_ignored_until_one_is_free() {
+        let mut server = test_server();
+        for port in 0..=u8::MAX as u16 {
+            assert!(server.create_new_connection(&SocketAddr::from(([127, 0, 0, 1], 40000 + port))));
+        }
+        let client = test_client();
+        client.connect(server.socket.local_addr().unwrap()).unwrap();
+        send_reliable(&client, NetworkMessage::Hello(ProtocolInfo::local(), SessionId(1)), 0);
+        // nobody listens at the made up addresses, what the server sent them comes back as errors it reads
+        // one per update, ahead of the Hello
+        run_updates(&mut server);
+        for _ in 0..256 {
+            server.update();
+        }
+        assert!(!server.addr_to_player.contains_key(&client.local_addr().unwrap()));
+
+        server.kick(ServerPlayerID(7));
+        send_reliable(&client, NetworkMessage::Hello(ProtocolInfo::local(), SessionId(1)), 1);
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while server.addr_to_player.get(&client.local_addr().unwrap()) != Some(&ServerPlayerID(7)) {
+            assert!(Instant::now() < deadline, "the freed id wasn't given to the client");
+            run_updates(&mut server);
+        }
+    }
+
+    #[test]
+    fn test_kick_from_the_console_removes_the_player_and_frees_its_id() {
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        let joiner_id = server.addr_to_player[&joiner_addr];
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        flush_sends(&mut server);
+        while recv_msg(&host).is_some() {}
+
+        let (console, commands) = std::sync::mpsc::channel();
+        server.console = Some(commands);
+        console.send(ConsoleCommand::Kick(joiner_id)).unwrap();
+        console.send(ConsoleCommand::Log { key: "log_debug".to_string(), enabled: true }).unwrap();
+        server.update();
+        flush_sends(&mut server);
+        assert!(
+            std::iter::from_fn(|| recv_msg(&host)).any(|msg|
+                matches!(msg, NetworkMessage::PlayerLeft(id) if id == joiner_id)
+            )
+        );
+        assert!(!server.addr_to_player.contains_key(&joiner_addr));
+        assert!(server.connections[&host_addr].is_empty());
+        assert!(server.logger.config.debug);
+
+        let newcomer = test_client().local_addr().unwrap();
+        server.create_new_connection(&newcomer);
+        assert_eq!(server.addr_to_player[&newcomer], joiner_id);
+        // an id nobody has is only reported
+        console.send(ConsoleCommand::Kick(ServerPlayerID(200))).unwrap();
+        server.update();
+        assert_eq!(server.addr_to_player.len(), 2);
+    }
+
+    #[test]
+    fn test_history_keeps_each_clients_datagrams_apart() {
+        let mut server = test_server();
+        let (a, b) = (test_client(), test_client());
+        let server_addr = server.socket.local_addr().unwrap();
+        a.connect(server_addr).unwrap();
+        b.connect(server_addr).unwrap();
+        send_reliable(&a, NetworkMessage::GetServerPlayerIDs, 7);
+        send_reliable(&b, NetworkMessage::ClientRequestResync, 9);
+        run_updates(&mut server);
+
+        let a_id = server.addr_to_player[&a.local_addr().unwrap()];
+        let history = server.history(Some(a_id));
+        let kind = u8::from(&NetworkMessage::GetServerPlayerIDs);
+        assert!(history.contains(&format!("received kind {} seq 7", kind)), "{}", history);
+        assert!(history.contains(&format!("sent kind {}", u8::from(&NetworkMessage::ServerSideAck(SeqNum(7))))));
+        assert!(!history.contains("seq 9"), "{}", history);
+        assert!(server.history(None).contains("seq 9"));
+        assert_eq!(server.history(Some(ServerPlayerID(200))), "No player 200");
+    }
+
+    #[test]
+    fn test_a_relayed_world_has_a_longer_retry_budget_than_lobby_messages() {
+        let mut server = test_server();
+        let client = test_client().local_addr().unwrap();
+        server.create_new_connection(&client);
+        let world = NetworkMessage::ServerSentWorld(vec![5; MAX_UDP_PAYLOAD_DATA_LENGTH * 2]);
+        server.send_and_resend_until_ack(world, &client);
+        server.send_and_resend_until_ack(NetworkMessage::ServerSentPlayerIDs(Vec::new(), 0), &client);
+        let start = Instant::now();
+        let pending = server.non_input_pending_acks.get_mut(&client).unwrap();
+        assert_eq!(pending.len(), 3);
+
+        // nothing is ever acked, ticks come every control timeout
+        let mut resends: HashMap<SeqNum, u32> = HashMap::new();
+        let mut given_up_at: HashMap<SeqNum, Duration> = HashMap::new();
+        let mut elapsed = Duration::ZERO;
+        while !pending.is_empty() {
+            elapsed += CONTROL_RETRY.timeout;
+            for (seq, _) in pending.tick(start + elapsed) {
+                *resends.entry(seq).or_default() += 1;
+            }
+            for (seq, _) in pending.take_exhausted() {
+                given_up_at.insert(seq, elapsed);
+            }
+        }
+        let (chunks, player_ids) = ([SeqNum(0), SeqNum(1)], SeqNum(2));
+        assert_eq!(resends[&player_ids], CONTROL_RETRY.max_retries);
+        assert_eq!(given_up_at[&player_ids], CONTROL_RETRY.budget());
+        for chunk in chunks {
+            assert_eq!(resends[&chunk], BULK_RETRY.max_retries);
+            // the backoff waits don't fall on a tick, each resend can come up to one tick late
+            let late = given_up_at[&chunk] - BULK_RETRY.budget();
+            assert!(late < CONTROL_RETRY.timeout * (BULK_RETRY.max_retries + 1), "{:?}", late);
+        }
+        assert!(BULK_RETRY.budget() > CONTROL_RETRY.budget() * 5);
+    }
+
+    #[test]
+    fn test_one_clients_resend_burst_does_not_hold_up_another_client() {
+        let mut server = test_server();
+        let (slow, other) = (test_client(), test_client());
+        let (slow_addr, other_addr) = (slow.local_addr().unwrap(), other.local_addr().unwrap());
+        server.create_new_connection(&slow_addr);
+        server.create_new_connection(&other_addr);
+        server.send_and_resend_until_ack(
+            NetworkMessage::ServerSentWorld(vec![5; MAX_UDP_PAYLOAD_DATA_LENGTH * 30]),
+            &slow_addr
+        );
+        server.send_and_resend_until_ack(NetworkMessage::ServerSentPlayerIDs(Vec::new(), 0), &other_addr);
+        let chunks = server.non_input_pending_acks[&slow_addr].len();
+        assert!(chunks >= 30);
+        while recv_msg(&other).is_some() {}
+
+        // nothing gets acked, everything is due again at once
+        std::thread::sleep(CONTROL_RETRY.timeout * 2);
         server.update();
+        assert!(matches!(recv_msg(&other), Some(NetworkMessage::ServerSentPlayerIDs(..))));
+        // the rest of the burst waits for the next updates
+        assert_eq!(server.outgoing.len(), chunks + 1 - SEND_BUDGET_PER_UPDATE);
+    }
+
+    #[test]
+    fn test_join_failure_is_passed_to_the_group_and_the_joiner_leaves() {
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        let joiner_id = server.addr_to_player[&joiner_addr];
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+
+        let reason = "Snapshot needs more pages than this build allows".to_string();
+        server.process_message(NetworkMessage::ClientJoinFailed(reason.clone()), &joiner_addr);
+        flush_sends(&mut server);
+        let received: Vec<NetworkMessage> = std::iter::from_fn(|| recv_msg(&host)).collect();
+        let failed_at = received
+            .iter()
+            .position(|msg| {
+                matches!(msg, NetworkMessage::ServerJoinFailed(id, r) if *id == joiner_id && *r == reason)
+            });
+        let left_at = received
+            .iter()
+            .position(|msg| matches!(msg, NetworkMessage::PlayerLeft(id) if *id == joiner_id));
+        assert!(failed_at.is_some() && failed_at < left_at, "{:?}", received);
+        assert!(!server.connections.contains_key(&joiner_addr));
+        assert!(server.connections[&host_addr].is_empty());
+    }
+
+    #[test]
+    fn test_lobby_is_nudged_when_a_client_connects_or_starts_hosting() {
+        let mut server = test_server();
+        let (first, second) = (test_client(), test_client());
+        let (host, joiner) = (test_client(), test_client());
+        for client in [&first, &second, &host, &joiner] {
+            server.create_new_connection(&client.local_addr().unwrap());
+        }
+        server.create_player_conn_from_to_host(joiner.local_addr().unwrap(), host.local_addr().unwrap());
+        let nudged = |client: &UdpSocket| {
+            std::iter::from_fn(|| recv_msg(client)).any(|msg|
+                matches!(msg, NetworkMessage::ServerPlayerListChanged)
+            )
+        };
+        for client in [&first, &second, &host, &joiner] {
+            while recv_msg(client).is_some() {}
+        }
+
+        let third = test_client();
+        let third_addr = third.local_addr().unwrap();
+        server.create_new_connection(&third_addr);
+        assert!(nudged(&first));
+        assert!(nudged(&second));
+        assert!(!nudged(&host));
+        assert!(!nudged(&joiner));
+        // the one that changed the list asks for it anyway
+        assert!(!nudged(&third));
+
+        server.process_message(NetworkMessage::ClientStartedHosting, &third_addr);
+        assert!(nudged(&first));
+        assert!(nudged(&second));
+        assert!(!nudged(&joiner));
+    }
+
+    #[test]
+    fn test_rebound_client_keeps_its_state() {
+        let mut server = test_server();
+        let host = test_client();
+        let (old_addr, host_addr) = (test_client().local_addr().unwrap(), host.local_addr().unwrap());
+        let rebound = test_client();
+        let new_addr = rebound.local_addr().unwrap();
+        let session = SessionId(77);
+
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&old_addr);
+        server.process_message(NetworkMessage::Hello(ProtocolInfo::local(), session), &old_addr);
+        server.create_player_conn_from_to_host(old_addr, host_addr);
+        let inputs = BufferedNetworkedPlayerInputs {
+            buffered_inputs: vec![NetworkedPlayerInput::new(Vec::new(), 5)],
+        };
+        server.process_message(NetworkMessage::ClientSentPlayerInputs(inputs.clone()), &host_addr);
+        server.process_message(NetworkMessage::ClientSentPlayerInputs(inputs.clone()), &old_addr);
+        let old_id = server.addr_to_player[&old_addr];
+        let pending_acks = server.non_input_pending_acks[&old_addr].len();
+        assert!(pending_acks > 0);
+
+        // the first packet from the new address creates a connection before it's handled
+        server.create_new_connection(&new_addr);
+        server.process_message(NetworkMessage::Hello(ProtocolInfo::local(), session), &new_addr);
+
+        assert!(!server.addr_to_player.contains_key(&old_addr));
+        assert_eq!(server.addr_to_player[&new_addr], old_id);
+        assert_eq!(server.player_to_addr[old_id.0 as usize], Some(new_addr));
+        assert_eq!(server.addr_to_player.len(), 2);
+        // +1 for the HelloAck of the second Hello
+        assert_eq!(server.non_input_pending_acks[&new_addr].len(), pending_acks + 1);
+        assert_eq!(server.input_relay.unacked(&new_addr).unwrap().buffered_inputs, inputs.buffered_inputs);
+        assert_eq!(server.input_relay.acks(&new_addr).unwrap().through(), Some(5));
+        assert_eq!(server.player_slots.get(&new_addr), Some(&PlayerID::Player2));
+        assert_eq!(server.connections[&host_addr], vec![new_addr]);
+        assert_eq!(server.connections[&new_addr], vec![host_addr]);
+
+        // the host's inputs now reach the rebound socket
+        server.process_message(
+            NetworkMessage::ClientSentPlayerInputs(BufferedNetworkedPlayerInputs {
+                buffered_inputs: vec![NetworkedPlayerInput::new(Vec::new(), 6)],
+            }),
+            &host_addr
+        );
+        flush_sends(&mut server);
+        assert!(
+            std::iter::from_fn(|| recv_msg(&rebound)).any(|msg|
+                matches!(msg, NetworkMessage::ServerSentPlayerInputs(PlayerID::Player1, _))
+            )
+        );
+    }
+
+    #[test]
+    fn test_inputs_are_acked_by_frame_and_a_later_ack_covers_lost_ones() {
+        let mut server = test_server();
+        let (host, joiner) = (test_client(), test_client());
+        let (host_addr, joiner_addr) = (host.local_addr().unwrap(), joiner.local_addr().unwrap());
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+        while recv_msg(&host).is_some() {}
+
+        let batch = |frames: std::ops::RangeInclusive<u32>| {
+            NetworkMessage::ClientSentPlayerInputs(BufferedNetworkedPlayerInputs {
+                buffered_inputs: frames.map(|frame| NetworkedPlayerInput::new(Vec::new(), frame)).collect(),
+            })
+        };
+        server.process_message(batch(1..=4), &host_addr);
+        server.send_input_acks();
+        // the next report waits for the interval and for newer frames
+        server.send_input_acks();
+        let acks: Vec<u32> = std::iter::from_fn(|| recv_msg(&host))
+            .filter_map(|msg| {
+                match msg {
+                    NetworkMessage::ServerInputsAckedThrough(frame) => Some(frame),
+                    _ => None,
+                }
+            })
+            .collect();
+        assert_eq!(acks, vec![4]);
+
+        // nothing is acked per datagram anymore, only the frames the joiner got are dropped
+        server.process_message(batch(1..=6), &host_addr);
+        let unacked = |server: &Server| {
+            server.input_relay.unacked(&joiner_addr).unwrap().buffered_inputs
+                .iter()
+                .map(|inp| inp.frame)
+                .collect::<Vec<u32>>()
+        };
+        assert_eq!(unacked(&server), (1..=6).collect::<Vec<u32>>());
+        server.process_message(NetworkMessage::ClientInputsAckedThrough(2), &joiner_addr);
+        assert_eq!(unacked(&server), vec![3, 4, 5, 6]);
+        // the ack through 4 was lost, the one through 5 still covers it
+        server.process_message(NetworkMessage::ClientInputsAckedThrough(5), &joiner_addr);
+        assert_eq!(unacked(&server), vec![6]);
+        // a late ack doesn't bring anything back
+        server.process_message(NetworkMessage::ClientInputsAckedThrough(4), &joiner_addr);
+        assert_eq!(unacked(&server), vec![6]);
+    }
+
+    #[test]
+    fn test_unacked_inputs_for_a_silent_peer_stay_bounded() {
+        let mut server = test_server();
+        let host_addr = test_client().local_addr().unwrap();
+        let joiner_addr = test_client().local_addr().unwrap();
+        server.create_new_connection(&host_addr);
+        server.create_new_connection(&joiner_addr);
+        server.create_player_conn_from_to_host(joiner_addr, host_addr);
+
+        // the joiner never acks, the host keeps sending its usual redundant batches
+        let max_frames = BufferedNetworkedPlayerInputs::max_frames_per_packet();
+        let newest = (max_frames as u32) * 4;
+        for frame in 1..=newest {
+            let batch = BufferedNetworkedPlayerInputs {
+                buffered_inputs: (frame.saturating_sub(3).max(1)..=frame)
+                    .map(|frame| NetworkedPlayerInput::new(Vec::new(), frame))
+                    .collect(),
+            };
+            server.broadcast_inputs(&batch, &host_addr);
+            assert!(server.input_relay.unacked(&joiner_addr).unwrap().buffered_inputs.len() <= max_frames);
+        }
+        let kept = &server.input_relay.unacked(&joiner_addr).unwrap().buffered_inputs;
+        assert_eq!(kept.first().unwrap().frame, newest - (max_frames as u32) + 1);
+        assert_eq!(kept.last().unwrap().frame, newest);
+    }
+
+    #[test]
+    fn test_panic_in_update_is_logged_and_serving_continues() {
+        let mut server = test_server();
+        let client = test_client();
+        client.connect(server.socket.local_addr().unwrap()).unwrap();
+        server.inject_panic = true;
+        server.guarded_update();
+        assert!(!server.inject_panic);
+
+        send_reliable(&client, NetworkMessage::Hello(ProtocolInfo::local(), SessionId(1)), 0);
+        std::thread::sleep(Duration::from_millis(20));
+        for _ in 0..4 {
+            server.guarded_update();
+        }
+        assert!(
+            std::iter::from_fn(|| recv_msg(&client)).any(|msg| matches!(msg, NetworkMessage::HelloAck(_)))
+        );
+    }
+
+    #[test]
+    fn test_shutdown_flag_exits_run_after_one_more_iteration() {
+        let mut server = test_server();
+        let client = test_client();
+        client.connect(server.socket.local_addr().unwrap()).unwrap();
+        let SerializedMessageType::NonChunked(request) = NetworkMessage::GetServerPlayerIDs.serialize(
+            NetworkMessageType::ResendUntilAck(SeqNum(0))
+        ) else {
+            panic!("request should not be chunked");
+        };
+        client.send(&request.bytes).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+
+        server.shutdown_flag().store(true, Ordering::Relaxed);
+        server.run();
+
+        // the single remaining update handled the request before run returned
+        let received: Vec<NetworkMessage> = std::iter::from_fn(|| recv_msg(&client)).collect();
+        assert!(received.iter().any(|msg| matches!(msg, NetworkMessage::ServerSideAck(SeqNum(0)))));
+        assert!(received.iter().any(|msg| matches!(msg, NetworkMessage::ServerSentPlayerIDs(..))));
+    }
+
+    #[test]
+    fn test_shutdown_flushes_pending_retransmissions() {
+        let mut server = test_server();
+        let client = test_client();
+        let client_addr = client.local_addr().unwrap();
+        server.create_new_connection(&client_addr);
+        server.send_and_resend_until_ack(NetworkMessage::ServerRequestHostForWorldData, &client_addr);
+        assert!(matches!(recv_msg(&client), Some(NetworkMessage::ServerRequestHostForWorldData)));
+
+        server.shutdown_flag().store(true, Ordering::Relaxed);
+        server.run();
+
+        assert!(matches!(recv_msg(&client), Some(NetworkMessage::ServerRequestHostForWorldData)));
+    }
+
+    #[test]
+    fn test_shutdown_from_other_thread() {
+        let mut server = test_server();
+        let shutdown = server.shutdown_flag();
+        let (done_sender, done_receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            server.run();
+            let _ = done_sender.send(());
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(done_receiver.try_recv().is_err());
+
+        shutdown.store(true, Ordering::Relaxed);
+        assert!(done_receiver.recv_timeout(Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn test_ping_is_echoed_as_pong() {
+        let mut server = test_server();
+        let client = test_client();
+        let ping = NetworkMessage::Ping { nonce: 7, send_time_micros: 123_456_789 };
+        let bytes = match ping.serialize(types::NetworkMessageType::SendOnce) {
+            SerializedMessageType::NonChunked(msg) => msg.bytes,
+            SerializedMessageType::Chunked(_) => panic!("a ping fits one datagram"),
+        };
+        let mut buffer = MsgBuffer::default();
+        buffer.0[..bytes.len()].copy_from_slice(&bytes);
+        let DeserializedMessageType::NonChunked(msg) = buffer.parse_on_server(bytes.len()).unwrap() else {
+            panic!("a ping isn't chunked");
+        };
+        assert!(!msg.reliable);
+        server.process_message(msg.msg, &client.local_addr().unwrap());
+
+        assert!(
+            matches!(
+                recv_msg(&client),
+                Some(NetworkMessage::Pong { nonce: 7, send_time_micros: 123_456_789 })
+            )
+        );
     }
 }
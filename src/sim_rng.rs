@@ -0,0 +1,63 @@
+use std::ops::Range;
+use unlockrs::impl_pod;
+
+// The only randomness the simulation may use. It lives in the simulation's pages like everything else,
+// so the snapshot carries it to a joiner and restoring the prediction from the verified state rolls it
+// back too. Peers stay in step as long as every draw happens inside Simulation::update, in the same
+// order on every peer: the host picks the seed when it starts the session, nobody seeds it again.
+// SplitMix64, integer only so it is the same on every platform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimRng {
+    state: u64,
+}
+impl_pod!(SimRng { state });
+
+impl SimRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+    // all there is to it, new(rng.state()) continues where rng is
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+    // uniform in [start, end), one draw
+    pub fn gen_range(&mut self, range: Range<f32>) -> f32 {
+        let unit = ((self.next_u32() >> 8) as f32) / ((1u32 << 24) as f32); // 24 bits fit an f32 exactly
+        range.start + unit * (range.end - range.start)
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_the_same_seed_draws_the_same_sequence() {
+        let (mut a, mut b) = (SimRng::new(42), SimRng::new(42));
+        let drawn: Vec<u32> = (0..100).map(|_| a.next_u32()).collect();
+        assert_eq!(drawn, (0..100).map(|_| b.next_u32()).collect::<Vec<u32>>());
+        assert_ne!(drawn, (0..100).map(|_| SimRng::new(43).next_u32()).collect::<Vec<u32>>());
+        // a copy of the state continues the same way
+        let mut resumed = SimRng::new(a.state());
+        assert_eq!(resumed.next_u32(), a.next_u32());
+    }
+
+    #[test]
+    fn test_gen_range_stays_in_the_range() {
+        let mut rng = SimRng::new(7);
+        for _ in 0..10_000 {
+            let x = rng.gen_range(40.0..360.0);
+            assert!((40.0..360.0).contains(&x), "{}", x);
+        }
+    }
+}
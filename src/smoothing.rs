@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use macroquad::math::Vec2;
+
+use crate::types::{ MAX_PLAYER_COUNT, PlayerID };
+
+pub const DEFAULT_SMOOTHING_FACTOR: f32 = 0.35; // share of the remaining distance covered per render frame
+pub const SNAP_DISTANCE: f32 = 200.0; // further than this is a teleport or a reused slot, easing would look worse
+
+pub fn ease_toward(rendered: Vec2, target: Vec2, factor: f32) -> Vec2 {
+    if rendered.distance(target) > SNAP_DISTANCE {
+        return target;
+    }
+    rendered + (target - rendered) * factor
+}
+
+// Visual only: remembers where things were drawn last and eases them toward the predicted state,
+// so a burst of verified frames after a catch-up doesn't show up as a jump. The simulation is
+// never written to, callers draw copies with the returned positions.
+#[derive(Debug, Default)]
+pub struct RenderSmoothing {
+    factor: Option<f32>, // None draws the predicted state as is
+    players: [Option<Vec2>; MAX_PLAYER_COUNT as usize],
+    enemies: HashMap<u32, Vec2>,
+}
+
+impl RenderSmoothing {
+    pub fn enabled(&self) -> bool {
+        self.factor.is_some()
+    }
+    pub fn toggle(&mut self) {
+        self.factor = match self.factor {
+            Some(_) => None,
+            None => Some(DEFAULT_SMOOTHING_FACTOR),
+        };
+        self.players = [None; MAX_PLAYER_COUNT as usize];
+        self.enemies.clear();
+    }
+    pub fn player(&mut self, player: PlayerID, target: Vec2) -> Vec2 {
+        Self::ease(self.factor, &mut self.players[player as usize], target)
+    }
+    pub fn enemy(&mut self, id: u32, target: Vec2) -> Vec2 {
+        let mut rendered = self.enemies.get(&id).copied();
+        let position = Self::ease(self.factor, &mut rendered, target);
+        if self.factor.is_some() {
+            self.enemies.insert(id, position);
+        }
+        position
+    }
+    // enemies that weren't drawn this frame are gone, their ids are never reused
+    pub fn retain_enemies(&mut self, active: impl Iterator<Item = u32>) {
+        let active: Vec<u32> = active.collect();
+        self.enemies.retain(|id, _| active.contains(id));
+    }
+    fn ease(factor: Option<f32>, rendered: &mut Option<Vec2>, target: Vec2) -> Vec2 {
+        let Some(factor) = factor else {
+            return target;
+        };
+        let position = match *rendered {
+            Some(rendered) => ease_toward(rendered, target, factor),
+            None => target,
+        };
+        *rendered = Some(position);
+        position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::math::vec2;
+
+    #[test]
+    fn test_ease_toward_covers_a_share_of_the_remaining_distance() {
+        let target = vec2(100.0, 50.0);
+        let mut rendered = vec2(0.0, 50.0);
+        let mut xs = Vec::new();
+        for _ in 0..4 {
+            rendered = ease_toward(rendered, target, 0.5);
+            xs.push(rendered.x);
+        }
+        assert_eq!(xs, vec![50.0, 75.0, 87.5, 93.75]);
+        assert_eq!(rendered.y, 50.0);
+        // a teleport is drawn right away
+        let far = vec2(SNAP_DISTANCE + 1.0, 0.0);
+        assert_eq!(ease_toward(vec2(0.0, 0.0), far, 0.5), far);
+    }
+
+    #[test]
+    fn test_smoothing_is_off_by_default_and_eases_once_enabled() {
+        let mut smoothing = RenderSmoothing::default();
+        assert!(!smoothing.enabled());
+        assert_eq!(smoothing.player(PlayerID::Player1, vec2(10.0, 0.0)), vec2(10.0, 0.0));
+        assert_eq!(smoothing.player(PlayerID::Player1, vec2(90.0, 0.0)), vec2(90.0, 0.0));
+
+        smoothing.toggle();
+        // the first position after enabling is drawn as is, later jumps are eased
+        assert_eq!(smoothing.player(PlayerID::Player1, vec2(10.0, 0.0)), vec2(10.0, 0.0));
+        let eased = smoothing.player(PlayerID::Player1, vec2(110.0, 0.0));
+        assert_eq!(eased.x, 10.0 + 100.0 * DEFAULT_SMOOTHING_FACTOR);
+        // every player and enemy keeps its own rendered position
+        assert_eq!(smoothing.player(PlayerID::Player2, vec2(110.0, 0.0)), vec2(110.0, 0.0));
+        assert_eq!(smoothing.enemy(3, vec2(0.0, 0.0)), vec2(0.0, 0.0));
+        assert_eq!(smoothing.enemy(3, vec2(0.0, 100.0)).y, 100.0 * DEFAULT_SMOOTHING_FACTOR);
+        smoothing.retain_enemies(std::iter::empty());
+        assert_eq!(smoothing.enemy(3, vec2(0.0, 100.0)), vec2(0.0, 100.0));
+    }
+}
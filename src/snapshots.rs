@@ -0,0 +1,44 @@
+use crate::types::VerifiedFrame;
+
+// the host resends its verified world every `interval_frames` verified frames and joiners take it as
+// their new verified baseline, the server only relays it, it doesn't run a simulation of its own
+#[derive(Debug, Default)]
+pub struct SnapshotSchedule {
+    interval_frames: u32, // 0 never sends one
+    last_snapshot_frame: Option<VerifiedFrame>,
+}
+
+impl SnapshotSchedule {
+    pub fn new(interval_frames: u32) -> Self {
+        Self { interval_frames, last_snapshot_frame: None }
+    }
+
+    // called with every verified frame, a frame that is seen twice is only snapshotted once
+    pub fn is_due(&mut self, verified_frame: VerifiedFrame) -> bool {
+        if self.interval_frames == 0 || !verified_frame.0.is_multiple_of(self.interval_frames) {
+            return false;
+        }
+        if self.last_snapshot_frame == Some(verified_frame) {
+            return false;
+        }
+        self.last_snapshot_frame = Some(verified_frame);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_snapshot_every_interval() {
+        const INTERVAL: u32 = 30;
+        let mut schedule = SnapshotSchedule::new(INTERVAL);
+        let due: Vec<u32> = (1..=INTERVAL * 4).filter(|frame| schedule.is_due(VerifiedFrame(*frame))).collect();
+        assert_eq!(due, vec![INTERVAL, INTERVAL * 2, INTERVAL * 3, INTERVAL * 4]);
+        assert!(!schedule.is_due(VerifiedFrame(INTERVAL * 4)));
+
+        let mut off = SnapshotSchedule::new(0);
+        assert!((1..=INTERVAL * 4).all(|frame| !off.is_due(VerifiedFrame(frame))));
+    }
+}
@@ -0,0 +1,400 @@
+use std::{ collections::VecDeque, time::{ Duration, Instant } };
+
+pub const TIMING_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TimingSummary {
+    pub avg: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+    pub samples: usize,
+}
+
+// Durations recorded over the last `window`. Samples that fall out of the window are dropped,
+// and so is the oldest one once `capacity` is reached so a busy loop can't grow it without bound.
+#[derive(Debug, Clone)]
+pub struct RollingWindow {
+    window: Duration,
+    capacity: usize,
+    samples: VecDeque<(Instant, Duration)>,
+}
+
+impl RollingWindow {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        debug_assert!(capacity > 0);
+        Self {
+            window,
+            capacity,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+    pub fn record_at(&mut self, now: Instant, sample: Duration) {
+        self.evict(now);
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, sample));
+    }
+    pub fn summary(&mut self) -> TimingSummary {
+        self.summary_at(Instant::now())
+    }
+    pub fn summary_at(&mut self, now: Instant) -> TimingSummary {
+        self.evict(now);
+        if self.samples.is_empty() {
+            return TimingSummary::default();
+        }
+        let total: Duration = self.samples
+            .iter()
+            .map(|(_, sample)| *sample)
+            .sum();
+        let mut sorted: Vec<Duration> = self.samples
+            .iter()
+            .map(|(_, sample)| *sample)
+            .collect();
+        sorted.sort_unstable();
+        // nearest rank, the smallest sample that at least 95% of the samples don't exceed
+        let p95_rank = (sorted.len() * 95).div_ceil(100);
+        TimingSummary {
+            avg: total / (sorted.len() as u32),
+            p95: sorted[p95_rank - 1],
+            max: sorted[sorted.len() - 1],
+            samples: sorted.len(),
+        }
+    }
+    fn evict(&mut self, now: Instant) {
+        while let Some((recorded_at, _)) = self.samples.front() {
+            if now.duration_since(*recorded_at) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+}
+
+// Events counted over the last `window`, reported per second. Like RollingWindow the oldest
+// record gives way once `capacity` is reached.
+#[derive(Debug, Clone)]
+pub struct RateCounter {
+    window: Duration,
+    capacity: usize,
+    records: VecDeque<(Instant, u32)>,
+}
+
+impl RateCounter {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        debug_assert!(capacity > 0 && !window.is_zero());
+        Self {
+            window,
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+    pub fn record_at(&mut self, now: Instant, events: u32) {
+        self.evict(now);
+        if events == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back((now, events));
+    }
+    pub fn per_second(&mut self) -> f32 {
+        self.per_second_at(Instant::now())
+    }
+    pub fn per_second_at(&mut self, now: Instant) -> f32 {
+        self.evict(now);
+        let events: u32 = self.records
+            .iter()
+            .map(|(_, events)| *events)
+            .sum();
+        (events as f32) / self.window.as_secs_f32()
+    }
+    fn evict(&mut self, now: Instant) {
+        while let Some((recorded_at, _)) = self.records.front() {
+            if now.duration_since(*recorded_at) < self.window {
+                break;
+            }
+            self.records.pop_front();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramePhase {
+    InputSampling,
+    NetworkDrain,
+    VerifiedSteps,
+    PredictedSteps,
+    Draw,
+}
+
+impl FramePhase {
+    pub const ALL: [FramePhase; 5] = [
+        FramePhase::InputSampling,
+        FramePhase::NetworkDrain,
+        FramePhase::VerifiedSteps,
+        FramePhase::PredictedSteps,
+        FramePhase::Draw,
+    ];
+    pub fn label(&self) -> &'static str {
+        match self {
+            FramePhase::InputSampling => "input",
+            FramePhase::NetworkDrain => "net drain",
+            FramePhase::VerifiedSteps => "verified",
+            FramePhase::PredictedSteps => "predicted",
+            FramePhase::Draw => "draw",
+        }
+    }
+}
+
+// per phase timings of the game loop, one rolling window per FramePhase
+#[derive(Debug, Clone)]
+pub struct FrameTimings {
+    phases: [RollingWindow; FramePhase::ALL.len()],
+}
+
+impl FrameTimings {
+    pub fn new() -> Self {
+        Self {
+            phases: std::array::from_fn(|_| RollingWindow::new(TIMING_WINDOW, 1024)),
+        }
+    }
+    // records the time since `started` and returns now so phases can be chained
+    pub fn end_phase(&mut self, phase: FramePhase, started: Instant) -> Instant {
+        let now = Instant::now();
+        self.phases[phase as usize].record_at(now, now.duration_since(started));
+        now
+    }
+    pub fn summary(&mut self, phase: FramePhase) -> TimingSummary {
+        self.phases[phase as usize].summary()
+    }
+}
+
+pub const AHEAD_LIMIT_FRAMES: u32 = 12;
+pub const AHEAD_RELEASE_FRAMES: u32 = 8;
+pub const DILATED_RATE: f32 = 58.0 / 60.0;
+pub const DEFAULT_MAX_CATCH_UP_FRAMES: u32 = 30;
+
+// Hands out fixed physics steps from render frame time. When the local simulation runs more than
+// `ahead_limit` frames past the newest remote input it is slowed to DILATED_RATE, so the other
+// client's clock can catch up, and runs at full speed again once it is at most `release_at` ahead.
+// Render frames faster than the tick rate work off time that piled up, one step each, but never
+// more than `max_catch_up` steps of it: after a stall (an unfocused window, a breakpoint) every
+// step would send an input, the rest is skipped and reported instead.
+#[derive(Debug, Clone)]
+pub struct FixedStepDriver {
+    step: f32,
+    accumulator: f32,
+    ahead_limit: u32,
+    release_at: u32,
+    dilated: bool,
+    max_catch_up: u32,
+    skipped_frames: u32, // since the last take_skipped_frames
+}
+
+impl FixedStepDriver {
+    pub fn new(step: f32, ahead_limit: u32, release_at: u32) -> Self {
+        debug_assert!(release_at <= ahead_limit);
+        Self {
+            step,
+            accumulator: 0.0,
+            ahead_limit,
+            release_at,
+            dilated: false,
+            max_catch_up: DEFAULT_MAX_CATCH_UP_FRAMES,
+            skipped_frames: 0,
+        }
+    }
+    pub fn set_max_catch_up(&mut self, frames: u32) {
+        self.max_catch_up = frames;
+    }
+    // returns whether a physics step is due, at most one per call
+    pub fn advance(&mut self, dt: f32) -> bool {
+        self.accumulator += dt * self.dilation();
+        // the step due now plus the ones to catch up on
+        let cap = self.step * ((self.max_catch_up + 1) as f32);
+        if self.accumulator > cap {
+            let skipped = ((self.accumulator - cap) / self.step).ceil();
+            self.accumulator = (self.accumulator - skipped * self.step).max(0.0);
+            self.skipped_frames = self.skipped_frames.saturating_add(skipped as u32);
+        }
+        if self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            return true;
+        }
+        false
+    }
+    // frames the local simulation is past the newest remote input, None while playing alone
+    pub fn set_frames_ahead(&mut self, frames_ahead: Option<u32>) {
+        self.dilated = match frames_ahead {
+            Some(ahead) if ahead > self.ahead_limit => true,
+            Some(ahead) if ahead <= self.release_at => false,
+            Some(_) => self.dilated,
+            None => false,
+        };
+    }
+    pub fn dilation(&self) -> f32 {
+        if self.dilated { DILATED_RATE } else { 1.0 }
+    }
+    // frames dropped for being too far behind, the simulation won't ever step them
+    pub fn take_skipped_frames(&mut self) -> u32 {
+        std::mem::take(&mut self.skipped_frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_empty_window_summary_is_zero() {
+        let mut window = RollingWindow::new(TIMING_WINDOW, 16);
+        assert_eq!(window.summary(), TimingSummary::default());
+    }
+
+    #[test]
+    fn test_summary_avg_p95_max() {
+        let mut window = RollingWindow::new(TIMING_WINDOW, 128);
+        let now = Instant::now();
+        for millis in 1..=100 {
+            window.record_at(now, ms(millis));
+        }
+        let summary = window.summary_at(now);
+        assert_eq!(summary.samples, 100);
+        assert_eq!(summary.avg, Duration::from_micros(50_500));
+        assert_eq!(summary.p95, ms(95));
+        assert_eq!(summary.max, ms(100));
+    }
+
+    #[test]
+    fn test_rate_is_events_in_the_window_per_second() {
+        let mut rate = RateCounter::new(TIMING_WINDOW, 1024);
+        let start = Instant::now();
+        // 3 predicted steps every 10ms for a whole window
+        for tick in 0..100u64 {
+            rate.record_at(start + ms(tick * 10), 3);
+        }
+        assert_eq!(rate.per_second_at(start + ms(999)), 300.0);
+        // the first half ages out
+        assert_eq!(rate.per_second_at(start + ms(1495)), 150.0);
+        rate.record_at(start + ms(1500), 0);
+        assert_eq!(rate.per_second_at(start + ms(2000)), 0.0);
+
+        let mut half_window = RateCounter::new(TIMING_WINDOW / 2, 1024);
+        for tick in 0..50u64 {
+            half_window.record_at(start + ms(tick * 10), 1);
+        }
+        assert_eq!(half_window.per_second_at(start + ms(499)), 100.0);
+    }
+
+    #[test]
+    fn test_samples_outside_window_are_dropped() {
+        let mut window = RollingWindow::new(TIMING_WINDOW, 16);
+        let start = Instant::now();
+        window.record_at(start, ms(40));
+        window.record_at(start + ms(600), ms(2));
+        assert_eq!(window.summary_at(start + ms(900)).max, ms(40));
+
+        let summary = window.summary_at(start + ms(1200));
+        assert_eq!(summary.samples, 1);
+        assert_eq!(summary.max, ms(2));
+        assert_eq!(window.summary_at(start + ms(1700)).samples, 0);
+    }
+
+    #[test]
+    fn test_capacity_drops_oldest() {
+        let mut window = RollingWindow::new(TIMING_WINDOW, 3);
+        let now = Instant::now();
+        for millis in [30, 1, 2, 3] {
+            window.record_at(now, ms(millis));
+        }
+        let summary = window.summary_at(now);
+        assert_eq!(summary.samples, 3);
+        assert_eq!(summary.max, ms(3));
+    }
+
+    #[test]
+    fn test_frame_timings_are_tracked_per_phase() {
+        let mut timings = FrameTimings::new();
+        let started = Instant::now() - ms(5);
+        timings.end_phase(FramePhase::Draw, started);
+        assert_eq!(timings.summary(FramePhase::Draw).samples, 1);
+        assert!(timings.summary(FramePhase::Draw).max >= ms(5));
+        for phase in FramePhase::ALL.iter().filter(|phase| **phase != FramePhase::Draw) {
+            assert_eq!(timings.summary(*phase).samples, 0);
+        }
+    }
+
+    const STEP: f32 = 1.0 / 60.0;
+
+    fn steps_in(driver: &mut FixedStepDriver, render_frames: usize) -> usize {
+        (0..render_frames).filter(|_| driver.advance(STEP)).count()
+    }
+
+    #[test]
+    fn test_driver_engages_above_limit_and_releases_at_threshold() {
+        let mut driver = FixedStepDriver::new(STEP, AHEAD_LIMIT_FRAMES, AHEAD_RELEASE_FRAMES);
+        driver.set_frames_ahead(Some(AHEAD_LIMIT_FRAMES));
+        assert_eq!(driver.dilation(), 1.0);
+        driver.set_frames_ahead(Some(AHEAD_LIMIT_FRAMES + 1));
+        assert_eq!(driver.dilation(), DILATED_RATE);
+
+        // stays slowed between the two thresholds
+        driver.set_frames_ahead(Some(AHEAD_RELEASE_FRAMES + 1));
+        assert_eq!(driver.dilation(), DILATED_RATE);
+        driver.set_frames_ahead(Some(AHEAD_RELEASE_FRAMES));
+        assert_eq!(driver.dilation(), 1.0);
+        driver.set_frames_ahead(Some(AHEAD_LIMIT_FRAMES));
+        assert_eq!(driver.dilation(), 1.0);
+
+        driver.set_frames_ahead(Some(AHEAD_LIMIT_FRAMES + 5));
+        driver.set_frames_ahead(None);
+        assert_eq!(driver.dilation(), 1.0);
+    }
+
+    #[test]
+    fn test_driver_step_rate_follows_dilation() {
+        // a hair under 1/60 per render frame so float rounding can't add a step
+        let mut driver = FixedStepDriver::new(STEP * 0.999, AHEAD_LIMIT_FRAMES, AHEAD_RELEASE_FRAMES);
+        assert_eq!(steps_in(&mut driver, 600), 600);
+
+        driver.set_frames_ahead(Some(AHEAD_LIMIT_FRAMES + 1));
+        let dilated = steps_in(&mut driver, 600);
+        assert!((579..=581).contains(&dilated), "{} steps", dilated);
+
+        driver.set_frames_ahead(Some(0));
+        assert_eq!(steps_in(&mut driver, 60), 60);
+    }
+
+    #[test]
+    fn test_steps_after_a_stall_are_bounded_by_the_catch_up_cap() {
+        // one second of 144 fps render frames after the window was stuck for `stall` seconds
+        let steps_after_stall = |stall: f32| {
+            let mut driver = FixedStepDriver::new(STEP, AHEAD_LIMIT_FRAMES, AHEAD_RELEASE_FRAMES);
+            driver.set_max_catch_up(10);
+            assert_eq!(steps_in(&mut driver, 60), 60);
+            let steps = (0..=144)
+                .filter(|frame| driver.advance(if *frame == 0 { stall } else { 1.0 / 144.0 }))
+                .count();
+            (steps, driver.take_skipped_frames())
+        };
+        let (after_10s, skipped_10s) = steps_after_stall(10.0);
+        let (after_100s, skipped_100s) = steps_after_stall(100.0);
+        // every step sends an input, it's a second's worth plus the cap whatever the stall
+        assert!((70..=72).contains(&after_10s), "{} steps", after_10s);
+        assert_eq!(after_10s, after_100s);
+        assert!((589..=590).contains(&skipped_10s), "{} skipped", skipped_10s);
+        assert!((5989..=5990).contains(&skipped_100s), "{} skipped", skipped_100s);
+
+        // nothing piles up at the tick rate, nothing is skipped
+        let mut driver = FixedStepDriver::new(STEP, AHEAD_LIMIT_FRAMES, AHEAD_RELEASE_FRAMES);
+        driver.set_max_catch_up(0);
+        steps_in(&mut driver, 600);
+        assert_eq!(driver.take_skipped_frames(), 0);
+    }
+}
@@ -0,0 +1,145 @@
+use std::{ fs, io, path::Path };
+
+use crate::world::GameTuning;
+
+// read by the host when it starts a session, joiners play with the host's numbers
+pub const TUNING_FILE: &str = "tuning.toml";
+
+// everything the host reads from TUNING_FILE, only the tuning travels with the world
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HostSettings {
+    pub tuning: GameTuning,
+    pub snapshot_interval_frames: u32, // verified frames between two world snapshots, 0 sends none
+    pub input_send_rate: u32, // input datagrams per second, read on startup by every client, 0 sends one per frame
+}
+
+impl Default for GameTuning {
+    fn default() -> Self {
+        Self {
+            player_speed: 150.0,
+            reload_time: 0.5,
+            bullet_speed: 500.0,
+            enemy_speed: 100.0,
+            enemy_spawn_interval: 2.0,
+        }
+    }
+}
+
+impl HostSettings {
+    // `key = value` lines, `#` starts a comment, keys that are left out keep their default
+    pub fn parse(text: &str) -> Result<Self, &'static str> {
+        let mut settings = Self::default();
+        let tuning = &mut settings.tuning;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or("Tuning line is not key = value")?;
+            let value = value.trim();
+            match key.trim() {
+                "player_speed" => {
+                    tuning.player_speed = parse_f32(value)?;
+                }
+                "reload_time" => {
+                    tuning.reload_time = parse_f32(value)?;
+                }
+                "bullet_speed" => {
+                    tuning.bullet_speed = parse_f32(value)?;
+                }
+                "enemy_speed" => {
+                    tuning.enemy_speed = parse_f32(value)?;
+                }
+                "enemy_spawn_interval" => {
+                    tuning.enemy_spawn_interval = parse_f32(value)?;
+                }
+                "snapshot_interval_frames" => {
+                    settings.snapshot_interval_frames = parse_u32(value)?;
+                }
+                "input_send_rate" => {
+                    settings.input_send_rate = parse_u32(value)?;
+                }
+                _ => {
+                    return Err("Unknown tuning key");
+                }
+            }
+        }
+        if !settings.tuning.is_usable() {
+            return Err("Tuning values have to be positive");
+        }
+        Ok(settings)
+    }
+
+    // a missing file means the defaults, a broken one is reported so the host doesn't play
+    // with numbers they didn't ask for
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+impl GameTuning {
+    // also checked on snapshots, the values come from another machine there
+    pub fn is_usable(&self) -> bool {
+        [self.player_speed, self.reload_time, self.bullet_speed, self.enemy_speed, self.enemy_spawn_interval]
+            .iter()
+            .all(|value| value.is_finite() && *value > 0.0)
+    }
+}
+
+fn parse_f32(value: &str) -> Result<f32, &'static str> {
+    value.parse().map_err(|_| "Tuning value is not a number")
+}
+
+fn parse_u32(value: &str) -> Result<u32, &'static str> {
+    value.parse().map_err(|_| "Tuning value is not a whole number")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_overrides_only_the_given_keys() {
+        let tuning = HostSettings::parse(
+            "# faster players\nplayer_speed = 300\n\n  reload_time=0.25  # twice the fire rate\n"
+        ).unwrap().tuning;
+        assert_eq!(tuning, GameTuning {
+            player_speed: 300.0,
+            reload_time: 0.25,
+            ..GameTuning::default()
+        });
+        assert_eq!(HostSettings::parse("").unwrap(), HostSettings::default());
+        assert_eq!(HostSettings::parse("snapshot_interval_frames = 60").unwrap(), HostSettings {
+            snapshot_interval_frames: 60,
+            ..HostSettings::default()
+        });
+        assert_eq!(HostSettings::parse("input_send_rate = 30").unwrap(), HostSettings {
+            input_send_rate: 30,
+            ..HostSettings::default()
+        });
+    }
+
+    #[test]
+    fn test_parse_rejects_broken_files() {
+        assert_eq!(HostSettings::parse("player_speed 300"), Err("Tuning line is not key = value"));
+        assert_eq!(HostSettings::parse("player_sped = 300"), Err("Unknown tuning key"));
+        assert_eq!(HostSettings::parse("reload_time = fast"), Err("Tuning value is not a number"));
+        assert_eq!(
+            HostSettings::parse("snapshot_interval_frames = 1.5"),
+            Err("Tuning value is not a whole number")
+        );
+        assert_eq!(HostSettings::parse("enemy_speed = -1"), Err("Tuning values have to be positive"));
+        assert_eq!(HostSettings::parse("reload_time = NaN"), Err("Tuning values have to be positive"));
+        assert_eq!(HostSettings::parse("enemy_spawn_interval = 0"), Err("Tuning values have to be positive"));
+    }
+
+    #[test]
+    fn test_missing_file_means_defaults() {
+        let path = Path::new("this_tuning_file_does_not_exist.toml");
+        assert_eq!(HostSettings::load(path), Ok(HostSettings::default()));
+    }
+}
@@ -0,0 +1,665 @@
+// Two clients and a relay standing in for the server, all in one process and on one simulated clock.
+// The clients run the same step functions as the Playing state, their datagrams go through the
+// wire format and a NetworkSimulator, so a seed pins down every loss and every delivery time.
+// Server lives in the server binary and can't be linked into the game's tests, so this is no gate for
+// Server::process_message: the relay only shares the lib's InputRelay and SendQueues with it, and
+// pairing, slots, pauses and the reliable messages around the handoff are left to server.rs's tests.
+use std::{
+    collections::{ BTreeMap, HashMap },
+    net::SocketAddr,
+    sync::mpsc::{ self, Receiver },
+    time::{ Duration, Instant },
+};
+
+use crate::{
+    client_conn::{ RequestSender, TaggedRequest },
+    frames_ahead,
+    handoff::{ HostUploadGate, JoinHandoff },
+    input_ack::InputAcks,
+    input_buffer::InputBuffer,
+    local_input_frame,
+    memory::PageAllocator,
+    network_simulator::{ LinkConditions, NetworkSimulator },
+    peer_clock::PeerClock,
+    prediction_check::PredictionCheck,
+    restore_prediction,
+    roster::SessionRoster,
+    send_empty_inputs_for_unplayed_frames,
+    session_step_driver,
+    simulation_allocator,
+    step_predicted_frames,
+    step_verified_frame,
+    submit_local_input,
+    timing::{ FixedStepDriver, AHEAD_LIMIT_FRAMES, DEFAULT_MAX_CATCH_UP_FRAMES },
+    types::{
+        BufferedNetworkedPlayerInputs,
+        DeserializedMessage,
+        DeserializedMessageType,
+        GameMessage,
+        GameRequestToNetwork,
+        MsgBuffer,
+        NetworkMessage,
+        NetworkMessageType,
+        PlayerID,
+        PlayerInput,
+        SerializedMessageType,
+        ServerPlayerID,
+        TickRate,
+        VerifiedFrame,
+    },
+    world::{ GameTuning, Simulation },
+    world_format::WorldSerializer,
+    world_format,
+    DEFAULT_ENEMY_CAPACITY,
+};
+use unlockrs::{ input_relay::InputRelay, send_queue::{ SendQueues, SEND_BUDGET_PER_UPDATE } };
+
+const SEED: u64 = 1454;
+const LATENCY_MS: u64 = 80;
+const PACKET_LOSS: f32 = 0.02;
+const RENDER_DT: f32 = (1.0 / 60.0) * 1.001; // a hair longer so float rounding can't drop a step
+const SESSION: Duration = Duration::from_secs(20);
+const JOIN_AT: Duration = Duration::from_secs(2);
+const STALL_FROM: Duration = Duration::from_secs(8);
+const STALL: Duration = Duration::from_millis(1500);
+
+fn datagram(msg: NetworkMessage, msg_type: NetworkMessageType) -> Vec<u8> {
+    match msg.serialize(msg_type) {
+        SerializedMessageType::NonChunked(msg) => msg.bytes.to_vec(),
+        SerializedMessageType::Chunked(_) => panic!("inputs and acks fit one datagram"),
+    }
+}
+
+fn parse(bytes: &[u8], on_server: bool) -> DeserializedMessage {
+    let mut buffer = MsgBuffer::default();
+    buffer.0[..bytes.len()].copy_from_slice(bytes);
+    let parsed = if on_server {
+        buffer.parse_on_server(bytes.len())
+    } else {
+        buffer.parse_on_client(bytes.len())
+    };
+    match parsed.expect("the simulator only delays and drops, it never corrupts") {
+        DeserializedMessageType::NonChunked(msg) => msg,
+        DeserializedMessageType::ChunkOfMessage(_) => panic!("inputs and acks fit one datagram"),
+    }
+}
+
+// what the session script has a player press on a frame
+fn scripted_input(slot: PlayerID, frame: u32) -> Vec<PlayerInput> {
+    let sweep = if (frame / 90).is_multiple_of(2) { PlayerInput::Left } else { PlayerInput::Right };
+    match slot {
+        PlayerID::Player1 => vec![sweep, PlayerInput::Shoot],
+        PlayerID::Player2 if frame % 7 < 3 => vec![PlayerInput::Shoot],
+        PlayerID::Player2 => vec![sweep],
+    }
+}
+
+// the game side of one client plus the input stream bookkeeping ConnectionServer does for it
+struct Client {
+    addr: SocketAddr,
+    slot: PlayerID,
+    roster: SessionRoster,
+    input_buffer: InputBuffer,
+    peer_clock: PeerClock,
+    verif_allocator: PageAllocator,
+    verified: Simulation,
+    pred_allocator: PageAllocator,
+    predicted: Simulation,
+    step_driver: FixedStepDriver,
+    request_sender: RequestSender,
+    requests: Receiver<TaggedRequest>,
+    inbox: Vec<DeserializedMessage>,
+    unack_inputs: BufferedNetworkedPlayerInputs,
+    newest_sent_input: Option<u32>,
+    repeated_input_frames: Vec<u32>,
+    remote_input_acks: InputAcks,
+    verified_hashes: BTreeMap<u32, u64>,
+    prediction_check: PredictionCheck,
+    max_prediction_depth: u32,
+    upload_gate: Option<HostUploadGate>, // only on a host that uploads its world to a joiner
+    world_requested: bool,
+    uploaded_world: Option<(VerifiedFrame, Vec<u8>)>, // taken by the session, it carries the upload
+    inputs_held_by_gate: bool,
+}
+
+impl Client {
+    fn new(addr: SocketAddr, slot: PlayerID, verif_allocator: PageAllocator, verified: Simulation) -> Self {
+        let mut pred_allocator = simulation_allocator();
+        let predicted = world_format(false)
+            .deserialize(world_format(false).serialize(&verified, &verif_allocator), &mut pred_allocator)
+            .unwrap();
+        let (sender, requests) = mpsc::channel();
+        let request_sender = RequestSender::new(sender);
+        let mut input_buffer = InputBuffer::new();
+        let mut roster = SessionRoster::default();
+        roster.on_slot_assigned(slot);
+        input_buffer.on_verified_frame(
+            send_empty_inputs_for_unplayed_frames(request_sender.clone(), slot)
+        );
+        Self {
+            addr,
+            slot,
+            roster,
+            input_buffer,
+            step_driver: session_step_driver(verified.tick_rate(&verif_allocator), DEFAULT_MAX_CATCH_UP_FRAMES),
+            peer_clock: PeerClock::new(verified.tick_rate(&verif_allocator)),
+            verif_allocator,
+            verified,
+            pred_allocator,
+            predicted,
+            request_sender,
+            requests,
+            inbox: Vec::new(),
+            unack_inputs: BufferedNetworkedPlayerInputs::default(),
+            newest_sent_input: None,
+            repeated_input_frames: Vec::new(),
+            remote_input_acks: InputAcks::default(),
+            verified_hashes: BTreeMap::new(),
+            prediction_check: PredictionCheck::default(),
+            max_prediction_depth: 0,
+            upload_gate: None,
+            world_requested: false,
+            uploaded_world: None,
+            inputs_held_by_gate: false,
+        }
+    }
+
+    // NetworkEvent::WorldUploadComplete
+    fn on_upload_complete(&mut self) {
+        if let Some(ref mut gate) = self.upload_gate {
+            for (slot, input) in gate.on_upload_complete() {
+                self.input_buffer.insert_remote_player_inp(slot, input.inputs, input.frame);
+            }
+        }
+    }
+
+    fn verified_frame(&self) -> VerifiedFrame {
+        self.verified.verified_frame(&self.verif_allocator)
+    }
+
+    // what the game does on the PlayerJoined of the other one
+    fn start_session(&mut self, peer: ServerPlayerID) {
+        self.roster.on_player_joined(peer);
+        self.input_buffer.update_player_count(
+            self.roster.local_slot(),
+            self.roster.player_count(),
+            self.verified_frame()
+        );
+        restore_prediction(
+            &self.verified,
+            &self.verif_allocator,
+            &mut self.predicted,
+            &mut self.pred_allocator
+        );
+    }
+
+    // one render frame of the Playing state
+    fn render_frame(&mut self, now: Instant, network: &mut NetworkSimulator) {
+        if !self.step_driver.advance(RENDER_DT) {
+            return;
+        }
+        let playing_alone = self.roster.player_count() == 1;
+        let input_frame = local_input_frame(
+            &self.roster,
+            &self.predicted,
+            &self.pred_allocator,
+            &self.verified,
+            &self.verif_allocator
+        );
+        let request_sender = &self.request_sender;
+        submit_local_input(&mut self.input_buffer, scripted_input(self.slot, input_frame), input_frame, |input| {
+            request_sender.send(GameRequestToNetwork::IndirectRequest(GameMessage::ClientSentPlayerInputs(input)))
+        }).unwrap();
+
+        for msg in std::mem::take(&mut self.inbox) {
+            match msg.msg {
+                NetworkMessage::ServerInputsAckedThrough(frame) => {
+                    self.unack_inputs.discard_acknowledged_frames(frame);
+                }
+                NetworkMessage::ServerSentPlayerInputs(slot, inputs) => {
+                    self.remote_input_acks.record(inputs.buffered_inputs.iter().map(|inp| inp.frame));
+                    let inputs = match self.upload_gate {
+                        Some(ref mut gate) => {
+                            self.inputs_held_by_gate |= gate.is_uploading();
+                            gate.on_remote_inputs(slot, inputs.buffered_inputs)
+                        }
+                        None =>
+                            inputs.buffered_inputs
+                                .into_iter()
+                                .map(|inp| (slot, inp))
+                                .collect(),
+                    };
+                    for (slot, input) in inputs {
+                        self.input_buffer.insert_remote_player_inp(slot, input.inputs, input.frame);
+                    }
+                    if let Some(newest) = self.input_buffer.newest_remote_frame() {
+                        // the round trip to the relay the pings would measure
+                        self.peer_clock.on_remote_frame(newest, Some(Duration::from_millis(2 * LATENCY_MS)));
+                    }
+                }
+                NetworkMessage::PlayerJoined(id) => self.start_session(id),
+                NetworkMessage::ServerRequestHostForWorldData => {
+                    self.world_requested = true;
+                }
+                other => panic!("the relay doesn't send {:?}", other),
+            }
+        }
+        // what the Playing state does once the joiner is counted, the world is taken at the frame it waits on
+        if std::mem::take(&mut self.world_requested) {
+            let snapshot_frame = self.verified_frame();
+            if let Some(ref mut gate) = self.upload_gate {
+                gate.begin_upload(snapshot_frame);
+            }
+            let world = world_format(false).serialize(&self.verified, &self.verif_allocator);
+            self.uploaded_world = Some((snapshot_frame, world));
+        }
+        if let Some(frame) = self.remote_input_acks.due_at(now) {
+            let ack = NetworkMessage::ClientInputsAckedThrough(frame);
+            network.enqueue_rcv_message(now, datagram(ack, NetworkMessageType::SendOnce), self.addr);
+        }
+
+        let mut new_verified_state = false;
+        while
+            let Some(frame) = step_verified_frame(
+                &mut self.input_buffer,
+                &self.verified,
+                &mut self.verif_allocator
+            ).unwrap()
+        {
+            self.verified_hashes.insert(frame.0, self.verified.state_hash(&self.verif_allocator));
+            self.prediction_check.check_verified(frame, &self.verified.digest(&self.verif_allocator));
+            new_verified_state = true;
+        }
+        if new_verified_state && !playing_alone {
+            restore_prediction(
+                &self.verified,
+                &self.verif_allocator,
+                &mut self.predicted,
+                &mut self.pred_allocator
+            );
+        }
+        step_predicted_frames(
+            &self.input_buffer,
+            &self.predicted,
+            &mut self.pred_allocator,
+            &mut self.prediction_check
+        );
+        if !playing_alone {
+            self.step_driver.set_frames_ahead(
+                Some(frames_ahead(&self.peer_clock, &self.predicted, &self.pred_allocator))
+            );
+            let depth = self.predicted
+                .predicted_frame(&self.pred_allocator)
+                .frames_past(self.verified.verified_frame(&self.verif_allocator));
+            self.max_prediction_depth = self.max_prediction_depth.max(depth);
+        }
+    }
+
+    // what ConnectionServer::send_player_inputs does with the requests, every datagram carries all unacked inputs
+    fn flush_requests(&mut self, now: Instant, network: &mut NetworkSimulator) {
+        while let Ok(request) = self.requests.try_recv() {
+            let GameRequestToNetwork::IndirectRequest(GameMessage::ClientSentPlayerInputs(input)) =
+                request.request else {
+                panic!("a running session only sends inputs");
+            };
+            // the sent frames watermark, see ConnectionServer::is_first_send_of
+            if self.newest_sent_input.is_some_and(|newest| input.frame <= newest) {
+                self.repeated_input_frames.push(input.frame);
+                continue;
+            }
+            self.newest_sent_input = Some(input.frame);
+            self.unack_inputs.insert_player_input(input);
+            let msg = NetworkMessage::ClientSentPlayerInputs(self.unack_inputs.clone());
+            network.enqueue_rcv_message(now, datagram(msg, NetworkMessageType::SendOnce), self.addr);
+        }
+    }
+}
+
+// the server's part of the input stream, the InputRelay and send queues Server relays with
+struct Relay {
+    slots: HashMap<SocketAddr, PlayerID>,
+    inputs: InputRelay,
+    outgoing: SendQueues<SocketAddr, u16>,
+}
+
+impl Relay {
+    fn new() -> Self {
+        Self { slots: HashMap::new(), inputs: InputRelay::default(), outgoing: SendQueues::default() }
+    }
+
+    // what Server::create_new_connection does for the relay
+    fn connect(&mut self, addr: SocketAddr, slot: PlayerID) {
+        self.slots.insert(addr, slot);
+        self.inputs.connect(addr);
+    }
+
+    // Server::process_message for what the clients of a running session send
+    fn handle(&mut self, now: Instant, bytes: &[u8], src: SocketAddr) {
+        let msg = parse(bytes, true);
+        match msg.msg {
+            NetworkMessage::ClientSentPlayerInputs(inputs) => {
+                // datagrams repeat unacked frames, a repeat always carries what the frame was sampled with
+                let received = match self.inputs.receive(now, &src, inputs, TickRate::default()) {
+                    Ok(received) => received,
+                    Err(rejection) => panic!("the relay dropped inputs of {}: {}", src, rejection),
+                };
+                if self.slots.len() < 2 {
+                    return; // not in a session yet, nobody to forward to
+                }
+                let targets: Vec<SocketAddr> = self.slots
+                    .keys()
+                    .copied()
+                    .filter(|addr| *addr != src)
+                    .collect();
+                for target in targets {
+                    if let Some(relayed) = self.inputs.relay_to(&target, &received.inputs) {
+                        let forward = NetworkMessage::ServerSentPlayerInputs(self.slots[&src], relayed.inputs);
+                        let bytes = datagram(forward, NetworkMessageType::SendOnce);
+                        self.outgoing.push(target, None, bytes.into());
+                    }
+                }
+            }
+            NetworkMessage::ClientInputsAckedThrough(frame) => {
+                assert!(self.inputs.on_acked_through(&src, frame));
+            }
+            other => panic!("clients in this session don't send {:?}", other),
+        }
+    }
+
+    // the end of Server::update, acks first, then the queued datagrams round-robin
+    fn send(&mut self, now: Instant, network: &mut NetworkSimulator) {
+        for (addr, frame) in self.inputs.due_acks(now) {
+            let ack = NetworkMessage::ServerInputsAckedThrough(frame);
+            network.enqueue_send_message(now, datagram(ack, NetworkMessageType::SendOnce), addr);
+        }
+        self.outgoing.drain(SEND_BUDGET_PER_UPDATE, |dst, bytes| {
+            network.enqueue_send_message(now, bytes.to_vec(), *dst);
+        });
+    }
+}
+
+#[test]
+fn test_two_clients_agree_on_every_verified_frame_over_a_lossy_link() {
+    let start = Instant::now();
+    let render_frame_time = Duration::from_secs_f32(RENDER_DT);
+    let mut network = NetworkSimulator::new(SEED, LATENCY_MS, 0, PACKET_LOSS);
+    let mut relay = Relay::new();
+    let host_addr: SocketAddr = "127.0.0.1:40001".parse().unwrap();
+    let joiner_addr: SocketAddr = "127.0.0.1:40002".parse().unwrap();
+
+    let mut host_allocator = simulation_allocator();
+    let host_world = Simulation::new(
+        &mut host_allocator,
+        DEFAULT_ENEMY_CAPACITY,
+        TickRate::default(),
+        GameTuning::default(),
+        0x5eed
+    );
+    let mut host = Client::new(host_addr, PlayerID::Player1, host_allocator, host_world);
+    relay.connect(host_addr, PlayerID::Player1);
+    let mut joiner: Option<Client> = None;
+
+    let mut now = start;
+    while now - start < SESSION {
+        let elapsed = now - start;
+        if joiner.is_none() && elapsed >= JOIN_AT {
+            // the world upload is reliable, its chunking has its own tests so it is handed over directly
+            let mut joiner_allocator = simulation_allocator();
+            let world = world_format(false)
+                .deserialize(
+                    world_format(false).serialize(&host.verified, &host.verif_allocator),
+                    &mut joiner_allocator
+                )
+                .unwrap();
+            let mut client = Client::new(joiner_addr, PlayerID::Player2, joiner_allocator, world);
+            relay.connect(joiner_addr, PlayerID::Player2);
+            host.start_session(ServerPlayerID(1));
+            client.start_session(ServerPlayerID(0));
+            joiner = Some(client);
+        }
+
+        for (bytes, src) in network.get_ready_receive_messages(now) {
+            relay.handle(now, &bytes, src);
+        }
+        relay.send(now, &mut network);
+        for (bytes, dst) in network.get_ready_send_messages(now) {
+            let msg = parse(&bytes, false);
+            match joiner {
+                Some(ref mut joiner) if dst == joiner_addr => joiner.inbox.push(msg),
+                _ => host.inbox.push(msg),
+            }
+        }
+
+        host.render_frame(now, &mut network);
+        host.flush_requests(now, &mut network);
+        let stalled = elapsed >= STALL_FROM && elapsed < STALL_FROM + STALL;
+        if let Some(ref mut joiner) = joiner {
+            if !stalled {
+                joiner.render_frame(now, &mut network);
+                joiner.flush_requests(now, &mut network);
+            }
+        }
+        now += render_frame_time;
+    }
+
+    let joiner = joiner.unwrap();
+    let snapshot_frame = *joiner.verified_hashes.keys().next().unwrap() - 1;
+    let common: Vec<u32> = joiner.verified_hashes
+        .keys()
+        .copied()
+        .filter(|frame| host.verified_hashes.contains_key(frame))
+        .collect();
+    // the session is far past the stall on both sides, not stuck somewhere before it
+    let tick_rate = TickRate::default();
+    assert!(common.len() as u32 > tick_rate.frames_in(STALL_FROM + STALL + STALL - JOIN_AT));
+    assert_eq!(common.first(), Some(&(snapshot_frame + 1)));
+    for frame in common {
+        assert_eq!(
+            host.verified_hashes[&frame],
+            joiner.verified_hashes[&frame],
+            "verified state diverged at frame {}",
+            frame
+        );
+    }
+    // the stall is the only reason to predict past the limit, plus what's in flight over one round trip
+    let round_trip = tick_rate.frames_in(Duration::from_millis(2 * LATENCY_MS));
+    let bound = tick_rate.frames_in(STALL) + AHEAD_LIMIT_FRAMES + round_trip;
+    for client in [&host, &joiner] {
+        assert!(
+            client.max_prediction_depth <= bound,
+            "{:?} predicted {} frames past its verified state",
+            client.slot,
+            client.max_prediction_depth
+        );
+        // the game asks for each frame's input to be sent once, when it is sampled
+        assert!(client.repeated_input_frames.is_empty(), "{:?} {:?}", client.slot, client.repeated_input_frames);
+        // the scripted inputs change direction, guessing the other player's input can't always be right
+        let check = &client.prediction_check;
+        assert!(check.mispredicted > 0 && check.mispredicted < check.checked, "{:?}", check);
+    }
+}
+
+// how the join handoff is raced, the world itself is handed over directly once the links say it arrived
+struct HandoffRace {
+    host_latency: u64, // ms, each way
+    joiner_latency: u64,
+    upload_ack_delay: Duration, // the server's ack of the last world chunk got lost and was sent again
+    slot_after_world: bool,
+}
+
+struct HandoffOutcome {
+    host: Client,
+    joiner: Client,
+    snapshot_frame: VerifiedFrame,
+    joiner_inputs_held: bool, // the joiner's inputs reached the host before the upload was acked
+    world_before_slot: bool,
+}
+
+fn run_handoff_race(race: HandoffRace) -> HandoffOutcome {
+    let start = Instant::now();
+    let render_frame_time = Duration::from_secs_f32(RENDER_DT);
+    let mut network = NetworkSimulator::new(SEED, 0, 0, 0.0);
+    let mut relay = Relay::new();
+    let host_addr: SocketAddr = "127.0.0.1:40001".parse().unwrap();
+    let joiner_addr: SocketAddr = "127.0.0.1:40002".parse().unwrap();
+    for (addr, latency) in [(host_addr, race.host_latency), (joiner_addr, race.joiner_latency)] {
+        network.set_link(addr, LinkConditions { latency, jitter: 0, packet_loss: 0.0 });
+    }
+    let slot_message = datagram(
+        NetworkMessage::ServerAssignPlayerSlot(PlayerID::Player2),
+        NetworkMessageType::SendOnce
+    );
+
+    let mut host_allocator = simulation_allocator();
+    let host_world = Simulation::new(
+        &mut host_allocator,
+        DEFAULT_ENEMY_CAPACITY,
+        TickRate::default(),
+        GameTuning::default(),
+        0x5eed
+    );
+    let mut host = Client::new(host_addr, PlayerID::Player1, host_allocator, host_world);
+    host.upload_gate = Some(HostUploadGate::new());
+    relay.connect(host_addr, PlayerID::Player1);
+    let mut handoff = JoinHandoff::new();
+    let mut joiner: Option<Client> = None;
+    let mut snapshot_frame: Option<VerifiedFrame> = None;
+    let mut world_at_relay: Option<(Instant, Vec<u8>)> = None;
+    let mut world_at_joiner: Option<(Instant, Vec<u8>)> = None;
+    let mut slot_sent_at: Option<Instant> = None;
+    let mut upload_complete_at: Option<Instant> = None;
+    let mut world_before_slot = None;
+
+    let mut now = start;
+    while now - start < Duration::from_secs(6) {
+        if relay.slots.len() < 2 && now - start >= Duration::from_secs(1) {
+            // what the server does on the joiner's ClientConnect
+            relay.connect(joiner_addr, PlayerID::Player2);
+            let joined = datagram(NetworkMessage::PlayerJoined(ServerPlayerID(1)), NetworkMessageType::SendOnce);
+            network.enqueue_send_message(now, joined, host_addr);
+            let request = datagram(NetworkMessage::ServerRequestHostForWorldData, NetworkMessageType::SendOnce);
+            network.enqueue_send_message(now, request, host_addr);
+            if !race.slot_after_world {
+                network.enqueue_send_message(now, slot_message.clone(), joiner_addr);
+                slot_sent_at = Some(now);
+            }
+        }
+        if let Some((at, world)) = world_at_relay.take_if(|(at, _)| *at <= now) {
+            // the ClientWorldBaseline that goes ahead of the world
+            relay.inputs.set_floor(joiner_addr, snapshot_frame.unwrap().0);
+            world_at_joiner = Some((at + Duration::from_millis(race.joiner_latency), world));
+            upload_complete_at = Some(at + Duration::from_millis(race.host_latency) + race.upload_ack_delay);
+            if race.slot_after_world {
+                network.enqueue_send_message(now, slot_message.clone(), joiner_addr);
+                slot_sent_at = Some(now);
+            }
+        }
+        if upload_complete_at.take_if(|at| *at <= now).is_some() {
+            host.on_upload_complete();
+        }
+        if let Some((_, world)) = world_at_joiner.take_if(|(at, _)| *at <= now) {
+            world_before_slot.get_or_insert(true);
+            handoff.on_world(world);
+        }
+
+        for (bytes, src) in network.get_ready_receive_messages(now) {
+            relay.handle(now, &bytes, src);
+        }
+        relay.send(now, &mut network);
+        for (bytes, dst) in network.get_ready_send_messages(now) {
+            let msg = parse(&bytes, false);
+            match (dst == joiner_addr, joiner.as_mut()) {
+                (false, _) => host.inbox.push(msg),
+                (true, Some(joiner)) => joiner.inbox.push(msg),
+                // what the Joining state does with what comes ahead of the world
+                (true, None) =>
+                    match msg.msg {
+                        NetworkMessage::ServerSentPlayerInputs(slot, inputs) => {
+                            handoff.on_remote_inputs(slot, inputs.buffered_inputs);
+                        }
+                        NetworkMessage::ServerAssignPlayerSlot(slot) => {
+                            world_before_slot.get_or_insert(false);
+                            handoff.on_slot(slot);
+                        }
+                        other => panic!("a joiner doesn't get {:?} before it plays", other),
+                    }
+            }
+        }
+        if let Some(join) = handoff.try_complete() {
+            let mut joiner_allocator = simulation_allocator();
+            let world = world_format(false).deserialize(join.world, &mut joiner_allocator).unwrap();
+            let mut client = Client::new(joiner_addr, join.slot, joiner_allocator, world);
+            client.start_session(ServerPlayerID(0));
+            for (slot, input) in join.inputs {
+                client.input_buffer.insert_remote_player_inp(slot, input.inputs, input.frame);
+            }
+            joiner = Some(client);
+        }
+
+        host.render_frame(now, &mut network);
+        host.flush_requests(now, &mut network);
+        if let Some((frame, world)) = host.uploaded_world.take() {
+            snapshot_frame = Some(frame);
+            world_at_relay = Some((now + Duration::from_millis(race.host_latency), world));
+        }
+        if let Some(ref mut joiner) = joiner {
+            joiner.render_frame(now, &mut network);
+            joiner.flush_requests(now, &mut network);
+        }
+        now += render_frame_time;
+    }
+    assert!(slot_sent_at.is_some());
+    HandoffOutcome {
+        joiner_inputs_held: host.inputs_held_by_gate,
+        host,
+        joiner: joiner.expect("the joiner never got both its world and its slot"),
+        snapshot_frame: snapshot_frame.unwrap(),
+        world_before_slot: world_before_slot.unwrap(),
+    }
+}
+
+// both sides verify the same frames from the snapshot on, with the same state on each
+fn assert_handoff_converged(outcome: &HandoffOutcome) {
+    let after_snapshot = |client: &Client| -> Vec<(u32, u64)> {
+        client.verified_hashes
+            .range(outcome.snapshot_frame.next_input_frame()..)
+            .map(|(frame, hash)| (*frame, *hash))
+            .collect()
+    };
+    let host = after_snapshot(&outcome.host);
+    let joiner = after_snapshot(&outcome.joiner);
+    assert_eq!(
+        outcome.joiner.verified_hashes.keys().next(),
+        Some(&outcome.snapshot_frame.next_input_frame()),
+        "the joiner starts right after the snapshot"
+    );
+    let common = host.len().min(joiner.len());
+    assert!(common as u32 > TickRate::default().frames_in(Duration::from_secs(3)), "stuck after {} frames", common);
+    assert_eq!(host[..common], joiner[..common]);
+}
+
+#[test]
+fn test_handoff_converges_with_the_joiners_inputs_ahead_of_the_upload_ack() {
+    let outcome = run_handoff_race(HandoffRace {
+        host_latency: 80,
+        joiner_latency: 10,
+        upload_ack_delay: Duration::from_millis(300),
+        slot_after_world: false,
+    });
+    assert!(outcome.joiner_inputs_held);
+    assert!(!outcome.world_before_slot);
+    assert_handoff_converged(&outcome);
+}
+
+#[test]
+fn test_handoff_converges_with_the_upload_ack_ahead_of_the_joiners_inputs() {
+    let outcome = run_handoff_race(HandoffRace {
+        host_latency: 20,
+        joiner_latency: 150,
+        upload_ack_delay: Duration::ZERO,
+        slot_after_world: true,
+    });
+    assert!(!outcome.joiner_inputs_held);
+    assert!(outcome.world_before_slot);
+    assert_handoff_converged(&outcome);
+}
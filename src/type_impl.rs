@@ -1,16 +1,22 @@
-use std::{ fmt::Display, fs::OpenOptions, time::Instant };
+use std::{ fmt::Display, fs::OpenOptions, time::{ Duration, Instant } };
+use crate::input_codec;
 
 use crate::types::{
     BufferedNetworkedPlayerInputs,
+    ChunkGroup,
+    ChunkLimits,
     ChunkOfMessage,
     ChunkedMessageCollector,
     ChunkedSerializedNetworkMessage,
     DeserializedMessage,
     DeserializedMessageType,
+    Endpoint,
+    InputGapStats,
     LogConfig,
     Logger,
     MessageHeader,
     MsgBuffer,
+    NetError,
     NetworkLogger,
     NetworkMessage,
     NetworkMessageType,
@@ -18,23 +24,38 @@ use crate::types::{
     PacketParser,
     PlayerID,
     PlayerInput,
+    ProtocolInfo,
+    RetryClass,
     SeqNum,
     SeqNumGenerator,
     SerializedMessageType,
     SerializedNetworkMessage,
     ServerPlayerID,
+    SessionId,
+    TickRate,
+    PredictedFrame,
+    VerifiedFrame,
     AMT_OF_CHUNKS_BYTE_POS,
     AMT_RANDOM_BYTES,
     BASE_CHUNK_SEQ_NUM_BYTE_POS,
     DATA_BIT_START_POS,
+    DEFAULT_TICK_RATE_HZ,
     DISCRIMINANT_BIT_START_POS,
+    FEATURE_BITS,
+    INPUTS_MESSAGE_HEADER_BYTES,
+    MAX_ACKS_PER_DATAGRAM,
+    MAX_JOIN_FAILED_REASON_BYTES,
+    MAX_CHUNKS_PER_MESSAGE,
+    MAX_PLAYER_COUNT,
     MAX_UDP_PAYLOAD_DATA_LENGTH,
     MAX_UDP_PAYLOAD_LEN,
-    PLAYER_MOVE_LEFT_BYTE_POS,
-    PLAYER_MOVE_RIGHT_BYTE_POS,
-    PLAYER_SHOOT_BYTE_POS,
+    PROTOCOL_VERSION,
     RELIABLE_FLAG_BYTE_POS,
     SEQ_NUM_BYTE_POS,
+    SERIALIZED_INPUT_BYTES,
+    SERIALIZED_INPUT_RUN_BYTES,
+    SUPPORTED_TICK_RATES,
+    TRANSFER_ID_BYTE_POS,
     VECTOR_LEN_BYTE_POS,
 };
 impl PacketParser {
@@ -53,6 +74,10 @@ impl PacketParser {
             bytes[BASE_CHUNK_SEQ_NUM_BYTE_POS],
             bytes[BASE_CHUNK_SEQ_NUM_BYTE_POS + 1],
         ]);
+        let transfer_id = u16::from_le_bytes([bytes[TRANSFER_ID_BYTE_POS], bytes[TRANSFER_ID_BYTE_POS + 1]]);
+        if (amt_of_chunks as usize) > MAX_CHUNKS_PER_MESSAGE {
+            return Err("Message claims more chunks than any message can have");
+        }
         let is_chunked = amt_of_chunks > 0;
         let discriminator = bytes[DISCRIMINANT_BIT_START_POS];
         let message = NetworkMessage::try_from(discriminator)?;
@@ -62,6 +87,7 @@ impl PacketParser {
             seq_num,
             amt_of_chunks,
             base_chunk_seq_num,
+            transfer_id,
             is_chunked,
             message,
         })
@@ -70,49 +96,177 @@ impl PacketParser {
         header: &MessageHeader,
         data: &[u8]
     ) -> Result<DeserializedMessage, &'static str> {
-        debug_assert!(data.len() % MAX_UDP_PAYLOAD_DATA_LENGTH == 0, "data.len {}", data.len()); // either its 1 packet or its multiple packets of this size
         // HEADER IS REMOVED from data; ONLY DATA HERE
         let parsed_message = match header.message {
             | NetworkMessage::GetServerPlayerIDs
             | NetworkMessage::GetOwnServerPlayerID
-            | NetworkMessage::ServerRequestHostForWorldData => header.message.clone(),
+            | NetworkMessage::ServerRequestHostForWorldData
+            | NetworkMessage::ClientRequestResync
+            | NetworkMessage::ClientLeaveSession
+            | NetworkMessage::ClientStartedHosting
+            | NetworkMessage::ServerPlayerListChanged => header.message.clone(),
 
             NetworkMessage::ClientSentWorld(_) => NetworkMessage::ClientSentWorld(data.to_vec()),
 
             | NetworkMessage::ClientSentPlayerInputs(_)
-            | NetworkMessage::ServerSentPlayerInputs(_) => {
+            | NetworkMessage::ServerSentPlayerInputs(..) => {
+                // the server stamps the sending player's slot in front, clients don't need to
+                let (slot, data) = match header.message {
+                    NetworkMessage::ServerSentPlayerInputs(..) => {
+                        let slot = *data.first().ok_or("Missing player slot")?;
+                        let slot = PlayerID::from_usize(slot as usize).ok_or(
+                            "Invalid player slot"
+                        )?;
+                        (Some(slot), &data[1..])
+                    }
+                    _ => (None, data),
+                };
+                let input_count = *data.first().ok_or("Missing input count")? as usize;
+                // the count is the sender's word, the entries it claims have to be there
+                let entries = data
+                    .get(1..1 + input_count * SERIALIZED_INPUT_BYTES)
+                    .ok_or("Truncated player inputs")?;
                 let mut buffered_inputs = BufferedNetworkedPlayerInputs::default();
-                let mut offset = 1; // Start after the first byte, which is the length of the Vec
-                let input_count = data[0] as usize;
-                for _ in 0..input_count {
-                    let frame = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
-                    offset += 4;
-                    let player_inputs = parse_player_inputs(data[offset]);
-                    offset += 1;
+                for entry in entries.chunks_exact(SERIALIZED_INPUT_BYTES) {
+                    let frame = u32::from_le_bytes(entry[..4].try_into().unwrap());
+                    let packed_inputs = entry[4];
                     buffered_inputs.buffered_inputs.push(NetworkedPlayerInput {
-                        inputs: player_inputs,
+                        inputs: input_codec::unpack(packed_inputs),
                         frame,
+                        unknown_bits: input_codec::unknown_bits(packed_inputs),
                     });
                 }
                 match header.message {
                     NetworkMessage::ClientSentPlayerInputs(_) => {
                         NetworkMessage::ClientSentPlayerInputs(buffered_inputs)
                     }
-                    NetworkMessage::ServerSentPlayerInputs(_) => {
-                        NetworkMessage::ServerSentPlayerInputs(buffered_inputs)
+                    NetworkMessage::ServerSentPlayerInputs(..) => {
+                        NetworkMessage::ServerSentPlayerInputs(
+                            slot.expect("parsed above"),
+                            buffered_inputs
+                        )
                     }
                     _ => { panic!() }
                 }
             }
+            NetworkMessage::ClientSentPlayerInputRuns(_) => {
+                let run_count = *data.first().ok_or("Missing input run count")? as usize;
+                let runs = data
+                    .get(1..1 + run_count * SERIALIZED_INPUT_RUN_BYTES)
+                    .ok_or("Truncated input runs")?;
+                let mut buffered_inputs = BufferedNetworkedPlayerInputs::default();
+                for run in runs.chunks_exact(SERIALIZED_INPUT_RUN_BYTES) {
+                    let first_frame = u32::from_le_bytes(run[..4].try_into().unwrap());
+                    let (packed_inputs, repeats) = (run[4], run[5]);
+                    let last_frame = first_frame
+                        .checked_add(u32::from(repeats))
+                        .ok_or("Input run past the last frame")?;
+                    for frame in first_frame..=last_frame {
+                        buffered_inputs.buffered_inputs.push(NetworkedPlayerInput {
+                            inputs: input_codec::unpack(packed_inputs),
+                            frame,
+                            unknown_bits: input_codec::unknown_bits(packed_inputs),
+                        });
+                    }
+                }
+                NetworkMessage::ClientSentPlayerInputRuns(buffered_inputs)
+            }
 
             NetworkMessage::ClientConnectToOtherWorld(_) => {
-                NetworkMessage::ClientConnectToOtherWorld(ServerPlayerID(data[0]))
+                let id = *data.first().ok_or("Missing player id")?;
+                NetworkMessage::ClientConnectToOtherWorld(ServerPlayerID(id))
             }
-            NetworkMessage::ServerSideAck(_) | NetworkMessage::ClientSideAck(_) => {
-                if data.len() < std::mem::size_of::<SeqNum>() {
-                    return Err("Insufficient data for Ack message");
+            NetworkMessage::PlayerJoined(_) | NetworkMessage::PlayerLeft(_) => {
+                let id = ServerPlayerID(*data.first().ok_or("Missing player id")?);
+                match header.message {
+                    NetworkMessage::PlayerJoined(_) => NetworkMessage::PlayerJoined(id),
+                    NetworkMessage::PlayerLeft(_) => NetworkMessage::PlayerLeft(id),
+                    _ => unreachable!(),
                 }
-                let seq_num = SeqNum(u16::from_le_bytes([data[0], data[1]])); // Assuming SeqNum is a single byte
+            }
+            NetworkMessage::ServerAssignPlayerSlot(_) => {
+                let slot = *data.first().ok_or("Missing player slot")?;
+                let slot = PlayerID::from_usize(slot as usize).ok_or("Invalid player slot")?;
+                NetworkMessage::ServerAssignPlayerSlot(slot)
+            }
+            NetworkMessage::ClientSetTickRate(_) => {
+                let hz: [u8; 4] = data.try_into().map_err(|_| "Tick rate must be a u32")?;
+                NetworkMessage::ClientSetTickRate(TickRate::new(u32::from_le_bytes(hz))?)
+            }
+            NetworkMessage::ClientRequestInputRange { .. } | NetworkMessage::ServerRequestInputRange { .. } => {
+                let fields: [u8; 8] = data.try_into().map_err(|_| "Input range must be two u32 frames")?;
+                let from_frame = u32::from_le_bytes(fields[..4].try_into().unwrap());
+                let to_frame = u32::from_le_bytes(fields[4..].try_into().unwrap());
+                if from_frame > to_frame {
+                    return Err("Input range ends before it starts");
+                }
+                match header.message {
+                    NetworkMessage::ClientRequestInputRange { .. } =>
+                        NetworkMessage::ClientRequestInputRange { from_frame, to_frame },
+                    NetworkMessage::ServerRequestInputRange { .. } =>
+                        NetworkMessage::ServerRequestInputRange { from_frame, to_frame },
+                    _ => unreachable!(),
+                }
+            }
+            NetworkMessage::ClientInputsAckedThrough(_) | NetworkMessage::ServerInputsAckedThrough(_) => {
+                let frame: [u8; 4] = data.try_into().map_err(|_| "Input ack must carry exactly one frame")?;
+                let frame = u32::from_le_bytes(frame);
+                match header.message {
+                    NetworkMessage::ClientInputsAckedThrough(_) => NetworkMessage::ClientInputsAckedThrough(frame),
+                    NetworkMessage::ServerInputsAckedThrough(_) => NetworkMessage::ServerInputsAckedThrough(frame),
+                    _ => unreachable!(),
+                }
+            }
+            NetworkMessage::ClientWorldBaseline(_) => {
+                let frame: [u8; 4] = data.try_into().map_err(|_| "World baseline must carry exactly one frame")?;
+                NetworkMessage::ClientWorldBaseline(u32::from_le_bytes(frame))
+            }
+            | NetworkMessage::ClientInputStreamPaused { .. }
+            | NetworkMessage::ClientInputStreamResumed { .. }
+            | NetworkMessage::ClientInputStreamPausedThrough { .. } => {
+                let frame: [u8; 4] = data.try_into().map_err(|_| "Input stream marker must carry one frame")?;
+                let frame = u32::from_le_bytes(frame);
+                match header.message {
+                    NetworkMessage::ClientInputStreamPaused { .. } =>
+                        NetworkMessage::ClientInputStreamPaused { last_frame: frame },
+                    NetworkMessage::ClientInputStreamResumed { .. } =>
+                        NetworkMessage::ClientInputStreamResumed { from_frame: frame },
+                    NetworkMessage::ClientInputStreamPausedThrough { .. } =>
+                        NetworkMessage::ClientInputStreamPausedThrough { through_frame: frame },
+                    _ => unreachable!(),
+                }
+            }
+            | NetworkMessage::ServerInputStreamPaused { .. }
+            | NetworkMessage::ServerInputStreamResumed { .. }
+            | NetworkMessage::ServerInputStreamPausedThrough { .. } => {
+                let fields: [u8; 5] = data
+                    .try_into()
+                    .map_err(|_| "Input stream marker must carry a slot and one frame")?;
+                let slot = PlayerID::from_usize(fields[0] as usize).ok_or("Invalid player slot")?;
+                let frame = u32::from_le_bytes(fields[1..].try_into().unwrap());
+                match header.message {
+                    NetworkMessage::ServerInputStreamPaused { .. } =>
+                        NetworkMessage::ServerInputStreamPaused { slot, last_frame: frame },
+                    NetworkMessage::ServerInputStreamResumed { .. } =>
+                        NetworkMessage::ServerInputStreamResumed { slot, from_frame: frame },
+                    NetworkMessage::ServerInputStreamPausedThrough { .. } =>
+                        NetworkMessage::ServerInputStreamPausedThrough { slot, through_frame: frame },
+                    _ => unreachable!(),
+                }
+            }
+            NetworkMessage::Ping { .. } | NetworkMessage::Pong { .. } => {
+                let fields: [u8; 12] = data.try_into().map_err(|_| "Ping must carry a nonce and a send time")?;
+                let nonce = u32::from_le_bytes(fields[..4].try_into().unwrap());
+                let send_time_micros = u64::from_le_bytes(fields[4..].try_into().unwrap());
+                match header.message {
+                    NetworkMessage::Ping { .. } => NetworkMessage::Ping { nonce, send_time_micros },
+                    NetworkMessage::Pong { .. } => NetworkMessage::Pong { nonce, send_time_micros },
+                    _ => unreachable!(),
+                }
+            }
+            NetworkMessage::ServerSideAck(_) | NetworkMessage::ClientSideAck(_) => {
+                let seq_num: [u8; 2] = data.try_into().map_err(|_| "Ack must carry exactly one seq num")?;
+                let seq_num = SeqNum(u16::from_le_bytes(seq_num));
                 match header.message {
                     NetworkMessage::ServerSideAck(_) => NetworkMessage::ServerSideAck(seq_num),
                     NetworkMessage::ClientSideAck(_) => NetworkMessage::ClientSideAck(seq_num),
@@ -120,15 +274,71 @@ impl PacketParser {
                 }
             }
 
-            NetworkMessage::ServerSentPlayerIDs(_) => {
-                let amt = data[0] as usize;
+            NetworkMessage::Hello(..) | NetworkMessage::HelloAck(_) => {
+                if data.len() < 11 {
+                    return Err("Insufficient data for Hello message");
+                }
+                let info = ProtocolInfo {
+                    protocol_version: u16::from_le_bytes([data[0], data[1]]),
+                    max_players: data[2],
+                    feature_bits: u32::from_le_bytes(data[3..7].try_into().unwrap()),
+                    world_layout: u32::from_le_bytes(data[7..11].try_into().unwrap()),
+                };
+                match header.message {
+                    NetworkMessage::Hello(..) => {
+                        let session = data
+                            .get(11..19)
+                            .ok_or("Missing session id")?
+                            .try_into()
+                            .unwrap();
+                        NetworkMessage::Hello(info, SessionId(u64::from_le_bytes(session)))
+                    }
+                    NetworkMessage::HelloAck(_) => NetworkMessage::HelloAck(info),
+                    _ => unreachable!(),
+                }
+            }
+
+            NetworkMessage::ClientSideAcks(_) => {
+                let amt = *data.first().ok_or("Missing ack count")? as usize;
+                if 1 + amt * 2 > data.len() {
+                    return Err("Ack count exceeds data");
+                }
+                let seq_nums = data[1..1 + amt * 2]
+                    .chunks_exact(2)
+                    .map(|seq| SeqNum(u16::from_le_bytes([seq[0], seq[1]])))
+                    .collect();
+                NetworkMessage::ClientSideAcks(seq_nums)
+            }
+
+            NetworkMessage::ServerSentPlayerIDs(..) => {
+                let amt = *data.first().ok_or("Missing player id count")? as usize;
                 println!("server sent player ids amt {}", amt);
                 println!("{:?}", data);
-                debug_assert!(amt + 1 < data.len());
-                NetworkMessage::ServerSentPlayerIDs(data[1..amt + 1].to_vec())
+                if amt + 1 > data.len() {
+                    return Err("Player id count exceeds data");
+                }
+                let other_layout = *data.get(amt + 1).ok_or("Missing hidden host count")?;
+                NetworkMessage::ServerSentPlayerIDs(data[1..amt + 1].to_vec(), other_layout)
             }
 
             NetworkMessage::ServerSentWorld(_) => NetworkMessage::ServerSentWorld(data.to_vec()),
+
+            NetworkMessage::ClientJoinFailed(_) | NetworkMessage::ServerJoinFailed(..) => {
+                // the server puts the joiner's id in front
+                let (joiner, data) = match header.message {
+                    NetworkMessage::ServerJoinFailed(..) => {
+                        (Some(ServerPlayerID(*data.first().ok_or("Missing player id")?)), &data[1..])
+                    }
+                    _ => (None, data),
+                };
+                let len = *data.first().ok_or("Missing reason length")? as usize;
+                let reason = data.get(1..1 + len).ok_or("Reason length exceeds data")?;
+                let reason = String::from_utf8(reason.to_vec()).map_err(|_| "Reason is not UTF-8")?;
+                match joiner {
+                    Some(joiner) => NetworkMessage::ServerJoinFailed(joiner, reason),
+                    None => NetworkMessage::ClientJoinFailed(reason),
+                }
+            }
         };
 
         if header.reliable {
@@ -151,99 +361,118 @@ impl MsgBuffer {
         self.0 = [0; MAX_UDP_PAYLOAD_LEN];
     }
 
-    pub fn parse_on_server(&self) -> Result<DeserializedMessageType, &'static str> {
+    // len is the amount of bytes the datagram actually had, everything after it is stale buffer content
+    pub fn parse_on_server(&self, len: usize) -> Result<DeserializedMessageType, &'static str> {
         let bytes = &self.0;
-        if bytes.is_empty() {
-            return Err("Empty buffer");
+        if len < DATA_BIT_START_POS || len > bytes.len() {
+            return Err("Invalid datagram length");
         }
         let header = PacketParser::parse_header(bytes)?;
 
-        // Debug assert to ensure only client-sent events are received on the server
-        debug_assert!(
-            matches!(
+        // only client-sent events are accepted on the server, the bytes come from anyone who can reach us
+        if
+            !matches!(
                 header.message,
                 NetworkMessage::GetServerPlayerIDs |
                     NetworkMessage::GetOwnServerPlayerID |
                     NetworkMessage::ClientSentWorld(_) |
                     NetworkMessage::ClientSentPlayerInputs(_) |
+                    NetworkMessage::ClientSentPlayerInputRuns(_) |
                     NetworkMessage::ClientSideAck(_) |
-                    NetworkMessage::ClientConnectToOtherWorld(_)
-            ),
-            "Server received an invalid message type: {:?}",
-            header.message
-        );
+                    NetworkMessage::ClientSideAcks(_) |
+                    NetworkMessage::ClientConnectToOtherWorld(_) |
+                    NetworkMessage::ClientSetTickRate(_) |
+                    NetworkMessage::Ping { .. } |
+                    NetworkMessage::ClientRequestResync |
+                    NetworkMessage::ClientLeaveSession |
+                    NetworkMessage::ClientStartedHosting |
+                    NetworkMessage::ClientRequestInputRange { .. } |
+                    NetworkMessage::ClientJoinFailed(_) |
+                    NetworkMessage::ClientInputsAckedThrough(_) |
+                    NetworkMessage::ClientInputStreamPaused { .. } |
+                    NetworkMessage::ClientInputStreamResumed { .. } |
+                    NetworkMessage::ClientInputStreamPausedThrough { .. } |
+                    NetworkMessage::ClientWorldBaseline(_) |
+                    NetworkMessage::Hello(..)
+            )
+        {
+            return Err("Server received a message only the server sends");
+        }
 
         if header.is_chunked {
-            return Ok(
-                DeserializedMessageType::ChunkOfMessage(ChunkOfMessage {
-                    seq_num: header.seq_num.unwrap().0,
-                    base_seq_num: header.base_chunk_seq_num,
-                    amt_of_chunks: header.amt_of_chunks,
-                    data_bytes: *bytes,
-                })
-            );
+            return Ok(DeserializedMessageType::ChunkOfMessage(self.to_chunk(&header, len)?));
         }
-        let parsed_data = PacketParser::parse_data(&header, &bytes[DATA_BIT_START_POS..].to_vec())?;
+        let parsed_data = PacketParser::parse_data(&header, &bytes[DATA_BIT_START_POS..len])?;
 
         Ok(DeserializedMessageType::NonChunked(parsed_data))
     }
 
-    pub fn parse_on_client(&self) -> Result<DeserializedMessageType, &'static str> {
+    pub fn parse_on_client(&self, len: usize) -> Result<DeserializedMessageType, &'static str> {
         let bytes = &self.0;
-
-        if bytes.is_empty() {
-            return Err("Empty buffer");
+        if len < DATA_BIT_START_POS || len > bytes.len() {
+            return Err("Invalid datagram length");
         }
         let header = PacketParser::parse_header(bytes)?;
-        // Debug assert to ensure only server-sent events are received on the client
-        debug_assert!(
-            matches!(
+        // only server-sent events are accepted on the client
+        if
+            !matches!(
                 header.message,
                 NetworkMessage::ServerSideAck(_) |
-                    NetworkMessage::ServerSentPlayerIDs(_) |
-                    NetworkMessage::ServerSentPlayerInputs(_) |
+                    NetworkMessage::ServerSentPlayerIDs(..) |
+                    NetworkMessage::ServerSentPlayerInputs(..) |
                     NetworkMessage::ServerSentWorld(_) |
-                    NetworkMessage::ServerRequestHostForWorldData
-            ),
-            "Client received an invalid message type: {:?}",
-            header.message
-        );
+                    NetworkMessage::ServerRequestHostForWorldData |
+                    NetworkMessage::ServerAssignPlayerSlot(_) |
+                    NetworkMessage::PlayerJoined(_) |
+                    NetworkMessage::PlayerLeft(_) |
+                    NetworkMessage::ServerPlayerListChanged |
+                    NetworkMessage::ServerRequestInputRange { .. } |
+                    NetworkMessage::ServerJoinFailed(..) |
+                    NetworkMessage::ServerInputsAckedThrough(_) |
+                    NetworkMessage::ServerInputStreamPaused { .. } |
+                    NetworkMessage::ServerInputStreamResumed { .. } |
+                    NetworkMessage::ServerInputStreamPausedThrough { .. } |
+                    NetworkMessage::Pong { .. } |
+                    NetworkMessage::HelloAck(_)
+            )
+        {
+            return Err("Client received a message only clients send");
+        }
         if header.is_chunked {
-            return Ok(
-                DeserializedMessageType::ChunkOfMessage(ChunkOfMessage {
-                    seq_num: header.seq_num.unwrap().0,
-                    base_seq_num: header.base_chunk_seq_num,
-                    amt_of_chunks: header.amt_of_chunks,
-                    data_bytes: *bytes,
-                })
-            );
+            return Ok(DeserializedMessageType::ChunkOfMessage(self.to_chunk(&header, len)?));
         }
-        let parsed_data = PacketParser::parse_data(&header, &bytes[DATA_BIT_START_POS..].to_vec())?;
+        let parsed_data = PacketParser::parse_data(&header, &bytes[DATA_BIT_START_POS..len])?;
         Ok(DeserializedMessageType::NonChunked(parsed_data))
     }
-}
-fn parse_player_inputs(byte: u8) -> Vec<PlayerInput> {
-    let mut res = Vec::new();
-    let player_moves_left = (byte >> PLAYER_MOVE_LEFT_BYTE_POS) & 1;
-    let player_moves_right: u8 = (byte >> PLAYER_MOVE_RIGHT_BYTE_POS) & 1;
-    let player_shoots: u8 = (byte >> PLAYER_SHOOT_BYTE_POS) & 1;
-    if player_moves_left > 0 {
-        res.push(PlayerInput::Left);
-    }
-    if player_moves_right > 0 {
-        res.push(PlayerInput::Right);
-    }
-    if player_shoots > 0 {
-        res.push(PlayerInput::Shoot);
+
+    fn to_chunk(&self, header: &MessageHeader, len: usize) -> Result<ChunkOfMessage, &'static str> {
+        let seq_num = header.seq_num.ok_or("Chunked message without seq num")?;
+        Ok(ChunkOfMessage {
+            seq_num: seq_num.0,
+            base_seq_num: header.base_chunk_seq_num,
+            amt_of_chunks: header.amt_of_chunks,
+            transfer_id: header.transfer_id,
+            data_bytes: self.0,
+            data_len: len - DATA_BIT_START_POS,
+        })
     }
-    return res;
 }
+// the message of a caught panic, panic! with a literal carries a &str and with arguments a String
+pub fn panic_reason(payload: &(dyn std::any::Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|reason| reason.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
 impl DeserializedMessage {
     fn from_reliable_msg(msg: NetworkMessage, seq_num: Option<u16>) -> Self {
         DeserializedMessage {
             reliable: true,
             seq_num,
             msg: msg,
+            arrived_as_chunks: None,
         }
     }
     fn from_unreliable_msg(msg: NetworkMessage) -> Self {
@@ -251,6 +480,7 @@ impl DeserializedMessage {
             reliable: false,
             seq_num: None,
             msg: msg,
+            arrived_as_chunks: None,
         }
     }
 }
@@ -264,19 +494,25 @@ impl NetworkMessage {
     ) -> SerializedMessageType {
         let amt_of_chunks =
             (data.len() + MAX_UDP_PAYLOAD_DATA_LENGTH - 1) / MAX_UDP_PAYLOAD_DATA_LENGTH;
-        debug_assert!(amt_of_chunks < (u8::MAX as usize), "{}", amt_of_chunks);
+        debug_assert!(amt_of_chunks <= MAX_CHUNKS_PER_MESSAGE, "{}", amt_of_chunks);
         let mut byte_chunks: Vec<Vec<u8>> = Vec::new();
         let mut rng = rand::thread_rng();
         let random_bytes: Vec<u8> = (0..AMT_RANDOM_BYTES).map(|_| rng.gen()).collect(); // First few random bytes (3 bytes in this example)
         for i in 0..amt_of_chunks {
             let mut msg_bytes = Vec::new();
             match msg_type {
-                NetworkMessageType::ResendUntilAck(seq_num) => {
+                | NetworkMessageType::ResendUntilAck(seq_num)
+                | NetworkMessageType::ReliableTransfer { seq_num, .. } => {
+                    let transfer_id = match msg_type {
+                        NetworkMessageType::ReliableTransfer { transfer_id, .. } => transfer_id,
+                        _ => 0,
+                    };
                     msg_bytes.extend(random_bytes.clone());
                     msg_bytes.push(1); // true
                     msg_bytes.extend_from_slice(&seq_num.0.wrapping_add(i as u16).to_le_bytes());
                     msg_bytes.extend_from_slice(&seq_num.0.to_le_bytes());
                     msg_bytes.extend_from_slice(&(amt_of_chunks as u16).to_le_bytes());
+                    msg_bytes.extend_from_slice(&transfer_id.to_le_bytes());
                     msg_bytes.push(discriminator_byte);
 
                     debug_assert!(msg_bytes[RELIABLE_FLAG_BYTE_POS] == 1);
@@ -300,7 +536,7 @@ impl NetworkMessage {
                     );
                     debug_assert!(msg_bytes[DISCRIMINANT_BIT_START_POS] == discriminator_byte);
                 }
-                NetworkMessageType::SendOnce | NetworkMessageType::SendOnceButReceiveAck(_) => {
+                NetworkMessageType::SendOnce => {
                     panic!("Cannot send chunked message unreliable");
                 }
             }
@@ -320,6 +556,7 @@ impl NetworkMessage {
     pub fn push_non_chunked(bytes: &mut Vec<u8>) {
         bytes.extend_from_slice(&(0 as u16).to_le_bytes());
         bytes.extend_from_slice(&(0 as u16).to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes()); // transfer id
         debug_assert!(
             u16::from_le_bytes([
                 bytes[BASE_CHUNK_SEQ_NUM_BYTE_POS],
@@ -333,12 +570,18 @@ impl NetworkMessage {
             ]) == 0
         );
     }
+    pub fn retry_class(&self) -> RetryClass {
+        match self {
+            NetworkMessage::ClientSentWorld(_) | NetworkMessage::ServerSentWorld(_) => RetryClass::Bulk,
+            _ => RetryClass::Control,
+        }
+    }
     pub fn serialize(&self, msg_type: NetworkMessageType) -> SerializedMessageType {
         let msg = self.may_overflow_udp_packet_serialize(msg_type);
         match &msg {
             SerializedMessageType::Chunked(_) => {}
             SerializedMessageType::NonChunked(msg) =>
-                debug_assert!(msg.bytes.len() < MAX_UDP_PAYLOAD_LEN),
+                debug_assert!(msg.bytes.len() <= MAX_UDP_PAYLOAD_LEN),
         }
         return msg;
     }
@@ -352,7 +595,7 @@ impl NetworkMessage {
         bytes.extend(random_bytes);
         match msg_type {
             | NetworkMessageType::ResendUntilAck(seq_num)
-            | NetworkMessageType::SendOnceButReceiveAck(seq_num) => {
+            | NetworkMessageType::ReliableTransfer { seq_num, .. } => {
                 bytes.push(1); // true
                 bytes.extend_from_slice(&seq_num.0.to_le_bytes());
                 debug_assert!(bytes[RELIABLE_FLAG_BYTE_POS] == 1);
@@ -393,58 +636,62 @@ impl NetworkMessage {
                     Self::push_non_chunked(&mut bytes);
                     bytes.push(discriminator);
                     bytes.extend(sim); // append actual Vec<u8> data
-                    return SerializedMessageType::from_serialized_msg(SerializedNetworkMessage {
-                        bytes,
-                    });
+                    return SerializedMessageType::from_serialized_msg(bytes);
                 }
             }
-            Self::ClientSentPlayerInputs(ref inp) | Self::ServerSentPlayerInputs(ref inp) => {
+            Self::ClientSentPlayerInputs(ref inp) | Self::ServerSentPlayerInputs(_, ref inp) => {
                 Self::push_non_chunked(&mut bytes);
-                let message = match *self {
-                    Self::ClientSentPlayerInputs(_) => {
-                        NetworkMessage::ClientSentPlayerInputs(
-                            BufferedNetworkedPlayerInputs::default()
-                        )
-                    }
-                    Self::ServerSentPlayerInputs(_) => {
-                        NetworkMessage::ServerSentPlayerInputs(
-                            BufferedNetworkedPlayerInputs::default()
-                        )
-                    }
-                    _ => { panic!() }
-                };
-                bytes.push(message.into());
+                bytes.push(self.into());
+                if let Self::ServerSentPlayerInputs(slot, _) = *self {
+                    bytes.push(slot as u8);
+                }
                 bytes.push(inp.buffered_inputs.len() as u8);
                 for input in &inp.buffered_inputs {
-                    let packed_inputs = Self::pack_player_inputs(&input.inputs);
                     bytes.extend_from_slice(&input.frame.to_le_bytes());
-                    bytes.push(packed_inputs);
+                    bytes.push(input.packed());
+                }
+                debug_assert!(bytes.len() <= MAX_UDP_PAYLOAD_LEN, "length {}", bytes.len());
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::ClientSentPlayerInputRuns(ref inp) => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                let runs = inp.runs();
+                bytes.push(runs.len() as u8);
+                for (first, repeats) in runs {
+                    bytes.extend_from_slice(&first.frame.to_le_bytes());
+                    bytes.push(first.packed());
+                    bytes.push(repeats);
                 }
                 debug_assert!(bytes.len() <= MAX_UDP_PAYLOAD_LEN, "length {}", bytes.len());
-                SerializedMessageType::from_serialized_msg(SerializedNetworkMessage {
-                    bytes,
-                })
+                SerializedMessageType::from_serialized_msg(bytes)
             }
 
             Self::ServerSideAck(ref seq_num) => {
                 Self::push_non_chunked(&mut bytes);
                 bytes.push(NetworkMessage::ServerSideAck(SeqNum(0)).into());
                 bytes.extend_from_slice(&seq_num.0.to_le_bytes());
-                SerializedMessageType::from_serialized_msg(SerializedNetworkMessage {
-                    bytes,
-                })
+                SerializedMessageType::from_serialized_msg(bytes)
             }
             Self::ClientSideAck(ref seq_num) => {
                 Self::push_non_chunked(&mut bytes);
                 bytes.push(NetworkMessage::ClientSideAck(SeqNum(0)).into());
                 bytes.extend_from_slice(&seq_num.0.to_le_bytes());
-                SerializedMessageType::from_serialized_msg(SerializedNetworkMessage {
-                    bytes,
-                })
+                SerializedMessageType::from_serialized_msg(bytes)
             }
-            Self::ServerSentPlayerIDs(ref ids) => {
+            Self::ClientSideAcks(ref seq_nums) => {
                 Self::push_non_chunked(&mut bytes);
-                bytes.push(NetworkMessage::ServerSentPlayerIDs(Vec::new()).into());
+                bytes.push(NetworkMessage::ClientSideAcks(Vec::new()).into());
+                debug_assert!(seq_nums.len() <= MAX_ACKS_PER_DATAGRAM);
+                bytes.push(seq_nums.len() as u8);
+                for seq_num in seq_nums {
+                    bytes.extend_from_slice(&seq_num.0.to_le_bytes());
+                }
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::ServerSentPlayerIDs(ref ids, other_layout) => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(NetworkMessage::ServerSentPlayerIDs(Vec::new(), 0).into());
                 debug_assert!(ids.len() <= (u8::MAX as usize));
                 bytes.push(ids.len() as u8);
                 bytes.extend(ids);
@@ -454,44 +701,106 @@ impl NetworkMessage {
                     bytes[VECTOR_LEN_BYTE_POS]
                 );
                 debug_assert!(bytes[VECTOR_LEN_BYTE_POS] == (ids.len() as u8));
-                SerializedMessageType::from_serialized_msg(SerializedNetworkMessage {
-                    bytes,
-                })
+                bytes.push(other_layout);
+                SerializedMessageType::from_serialized_msg(bytes)
             }
             Self::ClientConnectToOtherWorld(ref id) => {
                 Self::push_non_chunked(&mut bytes);
                 bytes.push(NetworkMessage::ClientConnectToOtherWorld(ServerPlayerID(0)).into());
                 bytes.push(id.0);
-                SerializedMessageType::from_serialized_msg(SerializedNetworkMessage {
-                    bytes,
-                })
+                SerializedMessageType::from_serialized_msg(bytes)
             }
-            _ => {
+            Self::Hello(ref info, _) | Self::HelloAck(ref info) => {
                 Self::push_non_chunked(&mut bytes);
                 bytes.push(self.into());
-                SerializedMessageType::from_serialized_msg(SerializedNetworkMessage {
-                    bytes,
-                })
-            }
-        }
-    }
-
-    fn pack_player_inputs(inputs: &Vec<PlayerInput>) -> u8 {
-        let mut res: u8 = 0;
-        for input in inputs {
-            match *input {
-                PlayerInput::Left => {
-                    res = res | (1 << PLAYER_MOVE_LEFT_BYTE_POS);
+                bytes.extend_from_slice(&info.protocol_version.to_le_bytes());
+                bytes.push(info.max_players);
+                bytes.extend_from_slice(&info.feature_bits.to_le_bytes());
+                bytes.extend_from_slice(&info.world_layout.to_le_bytes());
+                if let Self::Hello(_, session) = self {
+                    bytes.extend_from_slice(&session.0.to_le_bytes());
                 }
-                PlayerInput::Right => {
-                    res = res | (1 << PLAYER_MOVE_RIGHT_BYTE_POS);
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::ClientSetTickRate(rate) => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                bytes.extend_from_slice(&rate.hz().to_le_bytes());
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::Ping { nonce, send_time_micros } | Self::Pong { nonce, send_time_micros } => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                bytes.extend_from_slice(&nonce.to_le_bytes());
+                bytes.extend_from_slice(&send_time_micros.to_le_bytes());
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::ClientRequestInputRange { from_frame, to_frame } |
+            Self::ServerRequestInputRange { from_frame, to_frame } => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                bytes.extend_from_slice(&from_frame.to_le_bytes());
+                bytes.extend_from_slice(&to_frame.to_le_bytes());
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::ClientInputsAckedThrough(frame) |
+            Self::ServerInputsAckedThrough(frame) |
+            Self::ClientWorldBaseline(frame) => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                bytes.extend_from_slice(&frame.to_le_bytes());
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::ClientInputStreamPaused { last_frame: frame } |
+            Self::ClientInputStreamResumed { from_frame: frame } |
+            Self::ClientInputStreamPausedThrough { through_frame: frame } => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                bytes.extend_from_slice(&frame.to_le_bytes());
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::ServerInputStreamPaused { slot, last_frame: frame } |
+            Self::ServerInputStreamResumed { slot, from_frame: frame } |
+            Self::ServerInputStreamPausedThrough { slot, through_frame: frame } => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                bytes.push(slot as u8);
+                bytes.extend_from_slice(&frame.to_le_bytes());
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::PlayerJoined(id) | Self::PlayerLeft(id) => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                bytes.push(id.0);
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::ServerAssignPlayerSlot(slot) => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(NetworkMessage::ServerAssignPlayerSlot(PlayerID::Player1).into());
+                bytes.push(slot as u8);
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            Self::ClientJoinFailed(ref reason) | Self::ServerJoinFailed(_, ref reason) => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                if let Self::ServerJoinFailed(joiner, _) = self {
+                    bytes.push(joiner.0);
                 }
-                PlayerInput::Shoot => {
-                    res = res | (1 << PLAYER_SHOOT_BYTE_POS);
+                // cut on a char boundary so the other side still reads UTF-8
+                let mut len = reason.len().min(MAX_JOIN_FAILED_REASON_BYTES);
+                while !reason.is_char_boundary(len) {
+                    len -= 1;
                 }
+                bytes.push(len as u8);
+                bytes.extend_from_slice(&reason.as_bytes()[..len]);
+                SerializedMessageType::from_serialized_msg(bytes)
+            }
+            _ => {
+                Self::push_non_chunked(&mut bytes);
+                bytes.push(self.into());
+                SerializedMessageType::from_serialized_msg(bytes)
             }
         }
-        return res;
     }
 }
 impl From<NetworkMessage> for u8 {
@@ -503,11 +812,38 @@ impl From<NetworkMessage> for u8 {
             NetworkMessage::ClientSentPlayerInputs(_) => 3,
             NetworkMessage::ServerSideAck(_) => 4,
             NetworkMessage::ClientSideAck(_) => 5,
-            NetworkMessage::ServerSentPlayerIDs(_) => 6,
-            NetworkMessage::ServerSentPlayerInputs(_) => 7,
+            NetworkMessage::ServerSentPlayerIDs(..) => 6,
+            NetworkMessage::ServerSentPlayerInputs(..) => 7,
             NetworkMessage::ServerSentWorld(_) => 8,
             NetworkMessage::ClientConnectToOtherWorld(_) => 9,
             NetworkMessage::ServerRequestHostForWorldData => 10,
+            NetworkMessage::ServerAssignPlayerSlot(_) => 11,
+            NetworkMessage::ClientSideAcks(_) => 12,
+            NetworkMessage::Hello(..) => 13,
+            NetworkMessage::HelloAck(_) => 14,
+            NetworkMessage::ClientSetTickRate(_) => 15,
+            NetworkMessage::Ping { .. } => 16,
+            NetworkMessage::Pong { .. } => 17,
+            NetworkMessage::ClientRequestResync => 18,
+            NetworkMessage::PlayerJoined(_) => 19,
+            NetworkMessage::PlayerLeft(_) => 20,
+            NetworkMessage::ClientLeaveSession => 21,
+            NetworkMessage::ClientStartedHosting => 22,
+            NetworkMessage::ServerPlayerListChanged => 23,
+            NetworkMessage::ClientRequestInputRange { .. } => 24,
+            NetworkMessage::ServerRequestInputRange { .. } => 25,
+            NetworkMessage::ClientJoinFailed(_) => 26,
+            NetworkMessage::ServerJoinFailed(..) => 27,
+            NetworkMessage::ClientInputsAckedThrough(_) => 28,
+            NetworkMessage::ServerInputsAckedThrough(_) => 29,
+            NetworkMessage::ClientSentPlayerInputRuns(_) => 30,
+            NetworkMessage::ClientInputStreamPaused { .. } => 31,
+            NetworkMessage::ClientInputStreamResumed { .. } => 32,
+            NetworkMessage::ServerInputStreamPaused { .. } => 33,
+            NetworkMessage::ServerInputStreamResumed { .. } => 34,
+            NetworkMessage::ClientWorldBaseline(_) => 35,
+            NetworkMessage::ClientInputStreamPausedThrough { .. } => 36,
+            NetworkMessage::ServerInputStreamPausedThrough { .. } => 37,
         }
     }
 }
@@ -520,11 +856,38 @@ impl From<&NetworkMessage> for u8 {
             NetworkMessage::ClientSentPlayerInputs(_) => 3,
             NetworkMessage::ServerSideAck(_) => 4,
             NetworkMessage::ClientSideAck(_) => 5,
-            NetworkMessage::ServerSentPlayerIDs(_) => 6,
-            NetworkMessage::ServerSentPlayerInputs(_) => 7,
+            NetworkMessage::ServerSentPlayerIDs(..) => 6,
+            NetworkMessage::ServerSentPlayerInputs(..) => 7,
             NetworkMessage::ServerSentWorld(_) => 8,
             NetworkMessage::ClientConnectToOtherWorld(_) => 9,
             NetworkMessage::ServerRequestHostForWorldData => 10,
+            NetworkMessage::ServerAssignPlayerSlot(_) => 11,
+            NetworkMessage::ClientSideAcks(_) => 12,
+            NetworkMessage::Hello(..) => 13,
+            NetworkMessage::HelloAck(_) => 14,
+            NetworkMessage::ClientSetTickRate(_) => 15,
+            NetworkMessage::Ping { .. } => 16,
+            NetworkMessage::Pong { .. } => 17,
+            NetworkMessage::ClientRequestResync => 18,
+            NetworkMessage::PlayerJoined(_) => 19,
+            NetworkMessage::PlayerLeft(_) => 20,
+            NetworkMessage::ClientLeaveSession => 21,
+            NetworkMessage::ClientStartedHosting => 22,
+            NetworkMessage::ServerPlayerListChanged => 23,
+            NetworkMessage::ClientRequestInputRange { .. } => 24,
+            NetworkMessage::ServerRequestInputRange { .. } => 25,
+            NetworkMessage::ClientJoinFailed(_) => 26,
+            NetworkMessage::ServerJoinFailed(..) => 27,
+            NetworkMessage::ClientInputsAckedThrough(_) => 28,
+            NetworkMessage::ServerInputsAckedThrough(_) => 29,
+            NetworkMessage::ClientSentPlayerInputRuns(_) => 30,
+            NetworkMessage::ClientInputStreamPaused { .. } => 31,
+            NetworkMessage::ClientInputStreamResumed { .. } => 32,
+            NetworkMessage::ServerInputStreamPaused { .. } => 33,
+            NetworkMessage::ServerInputStreamResumed { .. } => 34,
+            NetworkMessage::ClientWorldBaseline(_) => 35,
+            NetworkMessage::ClientInputStreamPausedThrough { .. } => 36,
+            NetworkMessage::ServerInputStreamPausedThrough { .. } => 37,
         }
     }
 }
@@ -546,14 +909,44 @@ impl TryFrom<u8> for NetworkMessage {
             4 => Ok(NetworkMessage::ServerSideAck(SeqNum(0))),
             5 => Ok(NetworkMessage::ClientSideAck(SeqNum(0))),
 
-            6 => Ok(NetworkMessage::ServerSentPlayerIDs(Vec::new())),
+            6 => Ok(NetworkMessage::ServerSentPlayerIDs(Vec::new(), 0)),
             7 =>
                 Ok(
-                    NetworkMessage::ServerSentPlayerInputs(BufferedNetworkedPlayerInputs::default())
+                    NetworkMessage::ServerSentPlayerInputs(
+                        PlayerID::Player1,
+                        BufferedNetworkedPlayerInputs::default()
+                    )
                 ),
             8 => Ok(NetworkMessage::ServerSentWorld(Vec::new())),
             9 => Ok(NetworkMessage::ClientConnectToOtherWorld(ServerPlayerID(0))),
             10 => Ok(NetworkMessage::ServerRequestHostForWorldData),
+            11 => Ok(NetworkMessage::ServerAssignPlayerSlot(PlayerID::Player1)),
+            12 => Ok(NetworkMessage::ClientSideAcks(Vec::new())),
+            13 => Ok(NetworkMessage::Hello(ProtocolInfo::local(), SessionId(0))),
+            14 => Ok(NetworkMessage::HelloAck(ProtocolInfo::local())),
+            15 => Ok(NetworkMessage::ClientSetTickRate(TickRate::default())),
+            16 => Ok(NetworkMessage::Ping { nonce: 0, send_time_micros: 0 }),
+            17 => Ok(NetworkMessage::Pong { nonce: 0, send_time_micros: 0 }),
+            18 => Ok(NetworkMessage::ClientRequestResync),
+            19 => Ok(NetworkMessage::PlayerJoined(ServerPlayerID(0))),
+            20 => Ok(NetworkMessage::PlayerLeft(ServerPlayerID(0))),
+            21 => Ok(NetworkMessage::ClientLeaveSession),
+            22 => Ok(NetworkMessage::ClientStartedHosting),
+            23 => Ok(NetworkMessage::ServerPlayerListChanged),
+            24 => Ok(NetworkMessage::ClientRequestInputRange { from_frame: 0, to_frame: 0 }),
+            25 => Ok(NetworkMessage::ServerRequestInputRange { from_frame: 0, to_frame: 0 }),
+            26 => Ok(NetworkMessage::ClientJoinFailed(String::new())),
+            27 => Ok(NetworkMessage::ServerJoinFailed(ServerPlayerID(0), String::new())),
+            28 => Ok(NetworkMessage::ClientInputsAckedThrough(0)),
+            29 => Ok(NetworkMessage::ServerInputsAckedThrough(0)),
+            30 => Ok(NetworkMessage::ClientSentPlayerInputRuns(BufferedNetworkedPlayerInputs::default())),
+            31 => Ok(NetworkMessage::ClientInputStreamPaused { last_frame: 0 }),
+            32 => Ok(NetworkMessage::ClientInputStreamResumed { from_frame: 0 }),
+            33 => Ok(NetworkMessage::ServerInputStreamPaused { slot: PlayerID::Player1, last_frame: 0 }),
+            34 => Ok(NetworkMessage::ServerInputStreamResumed { slot: PlayerID::Player1, from_frame: 0 }),
+            35 => Ok(NetworkMessage::ClientWorldBaseline(0)),
+            36 => Ok(NetworkMessage::ClientInputStreamPausedThrough { through_frame: 0 }),
+            37 => Ok(NetworkMessage::ServerInputStreamPausedThrough { slot: PlayerID::Player1, through_frame: 0 }),
             _ => {
                 println!("Invalid value : {}", value);
                 Err("Invalid network msg u8 type ^^")
@@ -563,76 +956,148 @@ impl TryFrom<u8> for NetworkMessage {
 }
 
 impl SerializedMessageType {
-    fn from_serialized_msg(msg: SerializedNetworkMessage) -> Self {
-        return SerializedMessageType::NonChunked(msg);
+    fn from_serialized_msg(bytes: Vec<u8>) -> Self {
+        return SerializedMessageType::NonChunked(SerializedNetworkMessage::new(bytes));
     }
     fn from_chunked_msg(msgs: Vec<Vec<u8>>) -> Self {
         return SerializedMessageType::Chunked(ChunkedSerializedNetworkMessage {
-            bytes: msgs,
+            chunks: msgs.into_iter().map(SerializedNetworkMessage::new).collect(),
         });
     }
 }
 
+impl SerializedNetworkMessage {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self { bytes: bytes.into() }
+    }
+    // the message discriminator, without parsing the rest of the datagram
+    pub fn kind(&self) -> u8 {
+        self.bytes[DISCRIMINANT_BIT_START_POS]
+    }
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl Default for ChunkLimits {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: 1 << 20,
+            max_chunks_per_group: MAX_CHUNKS_PER_MESSAGE as u16,
+            max_groups: 4,
+        }
+    }
+}
+
 impl ChunkedMessageCollector {
     pub fn default() -> Self {
-        let mut msgs = Vec::with_capacity(u16::MAX as usize); // TODO THIS is inefficient
-        for _ in 0..u16::MAX {
-            msgs.push(Vec::new());
+        Self::with_limits(ChunkLimits::default())
+    }
+    pub fn with_limits(limits: ChunkLimits) -> Self {
+        ChunkedMessageCollector {
+            groups: Vec::with_capacity(limits.max_groups),
+            limits,
+            dropped_groups: 0,
+            newest_transfer: None,
         }
-        return ChunkedMessageCollector {
-            msgs: msgs,
-        };
     }
-    pub fn collect(&mut self, chunk: ChunkOfMessage) {
-        self.msgs[chunk.base_seq_num as usize].push(chunk);
+    // the chunk's length fields come from the peer, nothing is allocated for what they claim,
+    // a group only ever holds chunks that arrived
+    pub fn collect(&mut self, chunk: ChunkOfMessage) -> Result<(), &'static str> {
+        if chunk.amt_of_chunks == 0 || chunk.amt_of_chunks > self.limits.max_chunks_per_group {
+            return Err("Chunked message claims more chunks than allowed");
+        }
+        if chunk.seq_num.wrapping_sub(chunk.base_seq_num) >= chunk.amt_of_chunks {
+            return Err("Chunk lies outside of its message");
+        }
+        match self.newest_transfer {
+            Some(newest) if newest == chunk.transfer_id => {}
+            // a resend of a transfer the peer replaced, it must not complete after the newer one
+            Some(newest) if (chunk.transfer_id.wrapping_sub(newest) as i16) < 0 => {
+                return Err("Chunk belongs to a superseded transfer");
+            }
+            _ => {
+                // whatever is left of older transfers can't complete anymore
+                let before = self.groups.len();
+                self.groups.retain(|group| group.transfer_id == chunk.transfer_id);
+                self.dropped_groups += (before - self.groups.len()) as u32;
+                self.newest_transfer = Some(chunk.transfer_id);
+            }
+        }
+        let idx = match self.groups.iter().position(|group| group.base_seq_num == chunk.base_seq_num) {
+            Some(idx) => idx,
+            None => {
+                if self.groups.len() >= self.limits.max_groups {
+                    // a message that never completes must not block every later one
+                    self.groups.remove(0);
+                    self.dropped_groups += 1;
+                }
+                self.groups.push(ChunkGroup {
+                    base_seq_num: chunk.base_seq_num,
+                    amt_of_chunks: chunk.amt_of_chunks,
+                    transfer_id: chunk.transfer_id,
+                    data_len: 0,
+                    chunks: Vec::new(),
+                });
+                self.groups.len() - 1
+            }
+        };
+        let group = &mut self.groups[idx];
+        if group.amt_of_chunks != chunk.amt_of_chunks {
+            return Err("Chunk disagrees with its message on the chunk count");
+        }
+        if group.chunks.iter().any(|collected| collected.seq_num == chunk.seq_num) {
+            return Ok(()); // resent because our ack got lost
+        }
+        if group.data_len + chunk.data_len > self.limits.max_message_bytes {
+            self.groups.remove(idx);
+            self.dropped_groups += 1;
+            return Err("Chunked message is larger than allowed, dropped it");
+        }
+        group.data_len += chunk.data_len;
+        group.chunks.push(chunk);
+        Ok(())
     }
     pub fn try_combine(&mut self) -> Option<DeserializedMessage> {
-        for msg in &mut self.msgs {
-            msg.sort_by_key(|chunk| chunk.seq_num); // TODO wrapping around u32 is not handled
-
-            if let Some(last_msg) = msg.last() {
-                if
-                    last_msg.seq_num ==
-                        last_msg.base_seq_num.wrapping_add(last_msg.amt_of_chunks - 1) && // first packet will have base_Seq_num so last packet wioll be amt_ofchunks-1 away
-                    (last_msg.amt_of_chunks as usize) == msg.len()
-                {
-                    let total_data_bytes: Vec<u8> = msg
-                        .iter()
-                        .flat_map(|chunk| chunk.data_bytes[DATA_BIT_START_POS..].to_vec())
-                        .collect();
-                    if msg[0].seq_num != msg[0].base_seq_num {
-                        return None;
-                    }
-                    // debug_assert!(
-                    //     msg[0].seq_num == msg[0].base_seq_num,
-                    //     "msg 0 vs base seq num {:?} {:?}",
-                    //     msg[0].seq_num,
-                    //     msg[0].base_seq_num
-                    // );
-                    debug_assert!(msg[0].seq_num <= last_msg.seq_num);
-                    let header = PacketParser::parse_header(&msg[0].data_bytes);
-                    match header {
-                        Ok(header) => {
-                            let deserialized_message = PacketParser::parse_data(
-                                &header,
-                                &total_data_bytes
-                            );
-                            match deserialized_message {
-                                Ok(deserialized_message) => {
-                                    msg.clear();
-                                    return Some(deserialized_message);
-                                }
-                                Err(e) => eprintln!("Failed to parse data of chunk: {}", e),
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Error when parsing header from chunk: {}", e);
-                        }
+        // collect keeps every chunk inside its group and drops repeats, so a full group is complete
+        let idx = self.groups
+            .iter()
+            .position(|group| group.chunks.len() == (group.amt_of_chunks as usize))?;
+        let mut group = self.groups.remove(idx);
+        group.chunks.sort_by_key(|chunk| chunk.seq_num.wrapping_sub(chunk.base_seq_num)); // offset from base handles seq nums wrapping inside a group
+        let mut total_data_bytes = Vec::with_capacity(group.data_len);
+        for chunk in &group.chunks {
+            total_data_bytes.extend_from_slice(
+                &chunk.data_bytes[DATA_BIT_START_POS..DATA_BIT_START_POS + chunk.data_len]
+            );
+        }
+        // the group is out of `groups` already, a message that doesn't parse can never complete
+        // so it is counted as dropped and its base seq num is free for the next message
+        match PacketParser::parse_header(&group.chunks[0].data_bytes) {
+            Ok(header) => {
+                match PacketParser::parse_data(&header, &total_data_bytes) {
+                    Ok(mut deserialized_message) => {
+                        deserialized_message.arrived_as_chunks = Some(group.amt_of_chunks);
+                        return Some(deserialized_message);
                     }
+                    Err(e) =>
+                        eprintln!(
+                            "Discarded chunked message {}, failed to parse its data: {}",
+                            group.base_seq_num,
+                            e
+                        ),
                 }
             }
+            Err(e) => {
+                eprintln!(
+                    "Discarded chunked message {}, failed to parse the header of its first chunk: {}",
+                    group.base_seq_num,
+                    e
+                );
+            }
         }
-        return None;
+        self.dropped_groups += 1;
+        None
     }
 }
 
@@ -647,11 +1112,17 @@ impl NetworkLogger {
             println!("Received ack from server: {}", ack_num);
         }
     }
-    pub fn log_pending_acks(&self, pending: Vec<SeqNum>) {
+    // with how often each was resent so far
+    pub fn log_pending_acks(&self, pending: Vec<(SeqNum, u32)>) {
         if self.log {
             println!("Currently pending acks: {:?}", pending)
         }
     }
+    pub fn log_acked_after_retries(&self, ack_num: u16, retries: u32) {
+        if self.log && retries > 0 {
+            println!("SeqNum {} was acked after {} resends", ack_num, retries);
+        }
+    }
     pub fn log_sent_retransmission(&self, seq_num: u16) {
         if self.log {
             println!("Sent retransmission for SeqNum: {}", seq_num);
@@ -669,14 +1140,20 @@ impl NetworkedPlayerInput {
         NetworkedPlayerInput {
             inputs,
             frame,
+            unknown_bits: 0,
         }
     }
     pub fn placeholder() -> Self {
         NetworkedPlayerInput {
             inputs: Vec::new(),
             frame: 0,
+            unknown_bits: 0,
         }
     }
+    // the byte it is sent as
+    fn packed(&self) -> u8 {
+        input_codec::pack(&self.inputs) | self.unknown_bits
+    }
 }
 
 impl PlayerID {
@@ -688,46 +1165,81 @@ impl PlayerID {
         }
     }
 }
+// the count is a single byte
+const _: () = assert!(BufferedNetworkedPlayerInputs::max_frames_per_packet() <= (u8::MAX as usize));
 impl BufferedNetworkedPlayerInputs {
+    // inputs that fit one datagram next to the header, with the slot byte the server adds: the header up
+    // to DATA_BIT_START_POS, slot and count, then SERIALIZED_INPUT_BYTES per input. the client's own
+    // message is a byte shorter, both have to fit so the server can relay what it accepted
+    pub const fn max_frames_per_packet() -> usize {
+        (MAX_UDP_PAYLOAD_DATA_LENGTH - INPUTS_MESSAGE_HEADER_BYTES) / SERIALIZED_INPUT_BYTES
+    }
     pub fn default() -> Self {
         BufferedNetworkedPlayerInputs {
             buffered_inputs: Vec::new(),
         }
     }
+    // true if ClientSentPlayerInputRuns is the shorter datagram for these frames, e.g. a held key
+    pub fn runs_are_shorter(&self) -> bool {
+        self.runs().len() * SERIALIZED_INPUT_RUN_BYTES < self.buffered_inputs.len() * SERIALIZED_INPUT_BYTES
+    }
+    // the first input of every run of consecutive frames sent as the same byte, and how many frames
+    // after it repeat it. a gap or a change starts a new run, so does running out of repeats
+    fn runs(&self) -> Vec<(&NetworkedPlayerInput, u8)> {
+        let mut runs: Vec<(&NetworkedPlayerInput, u8)> = Vec::new();
+        for input in &self.buffered_inputs {
+            if let Some((first, repeats)) = runs.last_mut() {
+                let next_frame = first.frame.checked_add(u32::from(*repeats) + 1);
+                if *repeats < u8::MAX && next_frame == Some(input.frame) && first.packed() == input.packed() {
+                    *repeats += 1;
+                    continue;
+                }
+            }
+            runs.push((input, 0));
+        }
+        runs
+    }
+    // merges in the frames we don't have yet, the buffer stays sorted by frame
     pub fn bulk_insert_player_input(&mut self, other: BufferedNetworkedPlayerInputs) {
         for networked_input in other.buffered_inputs {
-            if
-                let None = self.buffered_inputs
-                    .iter_mut()
-                    .find(|i| i.frame == networked_input.frame)
-            {
-                // Insert new NetworkedPlayerInput if frame doesn't exist
-                self.buffered_inputs.push(networked_input);
-            }
+            self.insert_player_input(networked_input);
+        }
+    }
+    // highest frame in the buffer
+    pub fn last_frame(&self) -> Option<u32> {
+        self.buffered_inputs.last().map(|input| input.frame)
+    }
+    // keeps the `max_frames` newest frames in order, returns the older ones it dropped, oldest first
+    pub fn trim_to_fit(&mut self, max_frames: usize) -> Vec<NetworkedPlayerInput> {
+        if self.buffered_inputs.len() <= max_frames {
+            return Vec::new();
+        }
+        self.buffered_inputs.sort_by_key(|input| input.frame);
+        let dropped = self.buffered_inputs.len() - max_frames;
+        self.buffered_inputs.drain(..dropped).collect()
+    }
+    // sorts by frame and drops repeated frames, the first occurrence wins
+    pub fn normalize(&mut self) -> InputGapStats {
+        let received = self.buffered_inputs.len();
+        self.buffered_inputs.sort_by_key(|inp| inp.frame); // stable, keeps the first occurrence first
+        self.buffered_inputs.dedup_by_key(|inp| inp.frame);
+        let missing_frames = self.buffered_inputs
+            .windows(2)
+            .map(|pair| pair[1].frame - pair[0].frame - 1)
+            .sum();
+        InputGapStats {
+            duplicates: (received - self.buffered_inputs.len()) as u32,
+            missing_frames,
         }
-        debug_assert!(
-            self.buffered_inputs.iter().all(|input| {
-                self.buffered_inputs
-                    .iter()
-                    .filter(|other_inp| **other_inp == *input)
-                    .count() == 1
-            })
-        );
     }
     pub fn insert_player_input(&mut self, networked_input: NetworkedPlayerInput) {
-        if let None = self.buffered_inputs.iter_mut().find(|i| i.frame == networked_input.frame) {
+        let index = self.buffered_inputs.partition_point(|i| i.frame < networked_input.frame);
+        if self.buffered_inputs.get(index).is_none_or(|i| i.frame != networked_input.frame) {
             // Insert new NetworkedPlayerInput if frame doesn't exist
-            self.buffered_inputs.push(networked_input);
+            self.buffered_inputs.insert(index, networked_input);
         }
 
-        debug_assert!(
-            self.buffered_inputs.iter().all(|input| {
-                self.buffered_inputs
-                    .iter()
-                    .filter(|other_inp| **other_inp == *input)
-                    .count() == 1
-            })
-        );
+        debug_assert!(self.buffered_inputs.windows(2).all(|pair| pair[0].frame < pair[1].frame));
     }
 
     pub fn discard_acknowledged_frames(&mut self, frame: u32) {
@@ -759,6 +1271,13 @@ impl BufferedNetworkedPlayerInputs {
     }
 }
 
+impl InputGapStats {
+    pub fn add(&mut self, other: InputGapStats) {
+        self.duplicates += other.duplicates;
+        self.missing_frames += other.missing_frames;
+    }
+}
+
 impl Default for LogConfig {
     fn default() -> Self {
         Self {
@@ -773,61 +1292,62 @@ impl Default for LogConfig {
     }
 }
 
+// messages are built lazily, a disabled category costs a bool check and nothing else
 impl Logger {
     pub fn new(config: LogConfig) -> Self {
         Self { config, last_log_time: None }
     }
 
-    pub fn connection<T: Display>(&self, message: T) {
+    pub fn connection<T: Display>(&self, message: impl FnOnce() -> T) {
         if self.config.connection {
-            println!("[CONNECTION] {}", message);
+            println!("[CONNECTION] {}", message());
         }
     }
 
-    pub fn world_state<T: Display>(&self, message: T) {
+    pub fn world_state<T: Display>(&self, message: impl FnOnce() -> T) {
         if self.config.world_state {
-            println!("[WORLD_STATE] {}", message);
+            println!("[WORLD_STATE] {}", message());
         }
     }
 
-    pub fn player_input<T: Display>(&self, message: T) {
+    pub fn player_input<T: Display>(&self, message: impl FnOnce() -> T) {
         if self.config.player_input {
-            println!("[PLAYER_INPUT] {}", message);
+            println!("[PLAYER_INPUT] {}", message());
         }
     }
 
-    pub fn message<T: Display>(&self, message: T) {
+    pub fn message<T: Display>(&self, message: impl FnOnce() -> T) {
         if self.config.message_handling {
-            println!("[MESSAGE] {}", message);
+            println!("[MESSAGE] {}", message());
         }
     }
 
-    pub fn ack<T: Display>(&self, message: T) {
+    pub fn ack<T: Display>(&self, message: impl FnOnce() -> T) {
         if self.config.ack {
-            println!("[ACK] {}", message);
+            println!("[ACK] {}", message());
         }
     }
 
-    pub fn error<T: Display>(&self, message: T) {
+    pub fn error<T: Display>(&self, message: impl FnOnce() -> T) {
         if self.config.error {
-            eprintln!("[ERROR] {}", message);
+            eprintln!("[ERROR] {}", message());
         }
     }
 
-    pub fn debug<T: Display>(&self, message: T) {
+    pub fn debug<T: Display>(&self, message: impl FnOnce() -> T) {
         if self.config.debug {
-            println!("[DEBUG] {}", message);
+            println!("[DEBUG] {}", message());
         }
     }
-    pub fn debug_log_time<T: Display>(&mut self, message: T) {
+    pub fn debug_log_time<T: Display>(&mut self, message: impl FnOnce() -> T) {
         if self.config.debug {
             let now = Instant::now();
 
             if let Some(last_time) = self.last_log_time {
                 let delta = now.duration_since(last_time);
-                println!("[DEBUG] {} | Time: {:?} | Delta: {:?}", message, now, delta);
+                println!("[DEBUG] {} | Time: {:?} | Delta: {:?}", message(), now, delta);
             } else {
-                println!("[DEBUG] {}", message);
+                println!("[DEBUG] {}", message());
             }
 
             // Update the last log time
@@ -836,6 +1356,132 @@ impl Logger {
     }
 }
 
+impl From<std::io::Error> for NetError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::NotConnected => {
+                NetError::NotConnected
+            }
+            _ => NetError::Io(e),
+        }
+    }
+}
+
+impl Display for NetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetError::Io(e) => write!(f, "io error: {}", e),
+            NetError::Disconnected => write!(f, "couldn't reach other player"),
+            NetError::PayloadTooLarge { len } => write!(f, "payload of {} bytes is too large to send", len),
+            NetError::NotConnected => write!(f, "server is not reachable"),
+            NetError::ShuttingDown => write!(f, "connection is shutting down"),
+            NetError::IncompatiblePeer { ours, theirs } =>
+                write!(
+                    f,
+                    "incompatible peer, {} (ours: version {}, {} players, features {:#x}; theirs: version {}, {} players, features {:#x})",
+                    ours.incompatibility(theirs).unwrap_or("no difference"),
+                    ours.protocol_version,
+                    ours.max_players,
+                    ours.feature_bits,
+                    theirs.protocol_version,
+                    theirs.max_players,
+                    theirs.feature_bits
+                ),
+        }
+    }
+}
+
+impl std::error::Error for NetError {}
+
+impl TickRate {
+    pub fn new(hz: u32) -> Result<Self, &'static str> {
+        if !SUPPORTED_TICK_RATES.contains(&hz) {
+            return Err("Unsupported tick rate");
+        }
+        Ok(Self(hz))
+    }
+    pub fn hz(&self) -> u32 {
+        self.0
+    }
+    pub fn frame_time(&self) -> f32 {
+        1.0 / (self.0 as f32)
+    }
+    // how many frames pass in `duration` at this rate
+    pub fn frames_in(&self, duration: Duration) -> u32 {
+        (duration.as_secs_f32() * (self.0 as f32)).round() as u32
+    }
+    // the next supported rate, wrapping around, for picking one on the menu
+    pub fn next(&self) -> Self {
+        let idx = SUPPORTED_TICK_RATES.iter().position(|hz| *hz == self.0).unwrap_or(0);
+        Self(SUPPORTED_TICK_RATES[(idx + 1) % SUPPORTED_TICK_RATES.len()])
+    }
+}
+impl Default for TickRate {
+    fn default() -> Self {
+        Self(DEFAULT_TICK_RATE_HZ)
+    }
+}
+
+impl VerifiedFrame {
+    // the first input frame the verified simulation still waits for
+    pub fn next_input_frame(self) -> u32 {
+        self.0 + 1
+    }
+    // a rollback restarts the prediction from the verified state
+    pub fn as_predicted(self) -> PredictedFrame {
+        PredictedFrame(self.0)
+    }
+}
+impl PredictedFrame {
+    // the input sampled now is played on the frame after the one shown
+    pub fn next_input_frame(self) -> u32 {
+        self.0 + 1
+    }
+    // how many frames are still only predicted, 0 right after a rollback
+    pub fn frames_past(self, verified: VerifiedFrame) -> u32 {
+        self.0.saturating_sub(verified.0)
+    }
+}
+
+impl ProtocolInfo {
+    // the world layout is the game's to fill in, the server and other tools don't have a world
+    pub fn local() -> Self {
+        ProtocolInfo {
+            protocol_version: PROTOCOL_VERSION,
+            max_players: MAX_PLAYER_COUNT,
+            feature_bits: FEATURE_BITS,
+            world_layout: 0,
+        }
+    }
+    // the first thing that doesn't match, None if the two builds can talk
+    pub fn incompatibility(&self, peer: &ProtocolInfo) -> Option<&'static str> {
+        if self.protocol_version != peer.protocol_version {
+            return Some("protocol versions differ");
+        }
+        if self.max_players != peer.max_players {
+            return Some("max player counts differ");
+        }
+        if self.feature_bits != peer.feature_bits {
+            return Some("enabled features differ");
+        }
+        // world_layout isn't checked, the server relays any world, the joiner's host has to match it
+        None
+    }
+    pub fn check_compatible(&self, peer: &ProtocolInfo) -> Result<(), NetError> {
+        match self.incompatibility(peer) {
+            Some(_) => Err(NetError::IncompatiblePeer { ours: *self, theirs: *peer }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl SessionId {
+    // two clients must not collide, 64 random bits are plenty
+    pub fn random() -> Self {
+        SessionId(rand::thread_rng().gen())
+    }
+}
+
 impl SeqNumGenerator {
     pub fn get_seq_num(&mut self) -> SeqNum {
         let num = self.seq_num;
@@ -843,3 +1489,872 @@ impl SeqNumGenerator {
         return num;
     }
 }
+
+// one received datagram as `receiver` reads it, for tools that don't keep a MsgBuffer around
+pub fn parse_datagram(bytes: &[u8], receiver: Endpoint) -> Result<DeserializedMessageType, &'static str> {
+    let mut buffer = MsgBuffer::default();
+    buffer.0
+        .get_mut(..bytes.len())
+        .ok_or("Invalid datagram length")?
+        .copy_from_slice(bytes);
+    match receiver {
+        Endpoint::Server => buffer.parse_on_server(bytes.len()),
+        Endpoint::Client => buffer.parse_on_client(bytes.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(bytes: &[u8]) -> MsgBuffer {
+        let mut buffer = MsgBuffer([0xAA; MAX_UDP_PAYLOAD_LEN]); // stale content after the datagram
+        buffer.0[..bytes.len()].copy_from_slice(bytes);
+        buffer
+    }
+
+    #[test]
+    fn test_reassembly_excludes_padding_of_short_final_chunk() {
+        let world: Vec<u8> = (0..(MAX_UDP_PAYLOAD_DATA_LENGTH * 2 + 12)).map(|i| i as u8).collect();
+        let SerializedMessageType::Chunked(chunks) = NetworkMessage::ClientSentWorld(
+            world.clone()
+        ).serialize(NetworkMessageType::ResendUntilAck(SeqNum(7))) else {
+            panic!("world should be chunked");
+        };
+        assert_eq!(chunks.chunks.len(), 3);
+        assert_eq!(chunks.chunks[2].len(), DATA_BIT_START_POS + 12);
+
+        let mut collector = ChunkedMessageCollector::default();
+        for chunk in &chunks.chunks {
+            match receive(&chunk.bytes).parse_on_server(chunk.len()).unwrap() {
+                DeserializedMessageType::ChunkOfMessage(chunk) => collector.collect(chunk).unwrap(),
+                DeserializedMessageType::NonChunked(_) => panic!("expected a chunk"),
+            }
+        }
+        let combined = collector.try_combine().expect("all chunks arrived");
+        match combined.msg {
+            NetworkMessage::ClientSentWorld(data) => assert_eq!(data, world),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_chunk_records_real_data_length() {
+        let mut bytes = vec![0; DATA_BIT_START_POS];
+        bytes[RELIABLE_FLAG_BYTE_POS] = 1;
+        bytes[AMT_OF_CHUNKS_BYTE_POS] = 2;
+        bytes[DISCRIMINANT_BIT_START_POS] = NetworkMessage::ClientSentWorld(Vec::new()).into();
+        bytes.extend_from_slice(&[1, 2, 3]);
+        match receive(&bytes).parse_on_server(bytes.len()).unwrap() {
+            DeserializedMessageType::ChunkOfMessage(chunk) => assert_eq!(chunk.data_len, 3),
+            DeserializedMessageType::NonChunked(_) => panic!("expected a chunk"),
+        }
+    }
+
+    fn chunk(seq_num: u16, base_seq_num: u16, amt_of_chunks: u16) -> ChunkOfMessage {
+        ChunkOfMessage {
+            seq_num,
+            base_seq_num,
+            amt_of_chunks,
+            transfer_id: 0,
+            data_bytes: [0; MAX_UDP_PAYLOAD_LEN],
+            data_len: MAX_UDP_PAYLOAD_DATA_LENGTH,
+        }
+    }
+
+    fn world_chunks(fill: u8, seq_num: u16, transfer_id: u16) -> Vec<ChunkOfMessage> {
+        let SerializedMessageType::Chunked(chunks) = NetworkMessage::ClientSentWorld(
+            vec![fill; MAX_UDP_PAYLOAD_DATA_LENGTH * 3]
+        ).serialize(NetworkMessageType::ReliableTransfer { seq_num: SeqNum(seq_num), transfer_id }) else {
+            panic!("world should be chunked");
+        };
+        chunks.chunks
+            .iter()
+            .map(|chunk| {
+                match receive(&chunk.bytes).parse_on_server(chunk.len()).unwrap() {
+                    DeserializedMessageType::ChunkOfMessage(chunk) => chunk,
+                    DeserializedMessageType::NonChunked(_) => panic!("expected a chunk"),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_of_a_superseded_transfer_are_ignored() {
+        let mut collector = ChunkedMessageCollector::default();
+        let mut old = world_chunks(1, 10, u16::MAX).into_iter();
+        collector.collect(old.next().unwrap()).unwrap();
+        // the newer transfer wraps around, it is still newer
+        for chunk in world_chunks(2, 13, 0) {
+            assert_eq!(chunk.transfer_id, 0);
+            collector.collect(chunk).unwrap();
+        }
+        assert_eq!(collector.dropped_groups, 1);
+        // the rest of the old world arrives late, it must not be assembled after the new one
+        for chunk in old {
+            assert_eq!(collector.collect(chunk), Err("Chunk belongs to a superseded transfer"));
+        }
+        match collector.try_combine().map(|combined| combined.msg) {
+            Some(NetworkMessage::ClientSentWorld(data)) => assert!(data.iter().all(|byte| *byte == 2)),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+        assert!(collector.try_combine().is_none());
+        assert!(collector.groups.is_empty());
+    }
+
+    #[test]
+    fn test_wire_fields_are_little_endian() {
+        let inputs = BufferedNetworkedPlayerInputs {
+            buffered_inputs: vec![NetworkedPlayerInput::new(vec![PlayerInput::Left], 0x0a0b_0c0d)],
+        };
+        let SerializedMessageType::NonChunked(msg) = NetworkMessage::ClientSentPlayerInputs(
+            inputs
+        ).serialize(NetworkMessageType::SendOnce) else {
+            panic!("inputs should not be chunked");
+        };
+        // inputs are acked by frame, the datagram carries no seq num
+        assert_eq!(&msg.bytes[RELIABLE_FLAG_BYTE_POS..SEQ_NUM_BYTE_POS + 2], &[0, 0, 0]);
+        // after the discriminator comes the input count, then the frame of the first input
+        let frame = DATA_BIT_START_POS + 1;
+        assert_eq!(&msg.bytes[frame..frame + 4], &[0x0d, 0x0c, 0x0b, 0x0a]);
+
+        let world = vec![0; MAX_UDP_PAYLOAD_DATA_LENGTH * 2 + 1];
+        let SerializedMessageType::Chunked(chunks) = NetworkMessage::ClientSentWorld(world).serialize(
+            NetworkMessageType::ResendUntilAck(SeqNum(0xfeff))
+        ) else {
+            panic!("world should be chunked");
+        };
+        let second = &chunks.chunks[1].bytes;
+        assert_eq!(&second[SEQ_NUM_BYTE_POS..SEQ_NUM_BYTE_POS + 2], &[0x00, 0xff]);
+        assert_eq!(&second[BASE_CHUNK_SEQ_NUM_BYTE_POS..BASE_CHUNK_SEQ_NUM_BYTE_POS + 2], &[0xff, 0xfe]);
+        assert_eq!(&second[AMT_OF_CHUNKS_BYTE_POS..AMT_OF_CHUNKS_BYTE_POS + 2], &[3, 0]);
+    }
+
+    #[test]
+    fn test_join_failure_reason_survives_the_wire_and_is_cut_on_a_char_boundary() {
+        let parse = |msg: NetworkMessage, on_server: bool| {
+            let SerializedMessageType::NonChunked(bytes) = msg.serialize(
+                NetworkMessageType::ResendUntilAck(SeqNum(1))
+            ) else {
+                panic!("a join failure fits one datagram");
+            };
+            let buffer = receive(&bytes.bytes);
+            let parsed = if on_server {
+                buffer.parse_on_server(bytes.len())
+            } else {
+                buffer.parse_on_client(bytes.len())
+            };
+            match parsed.unwrap() {
+                DeserializedMessageType::NonChunked(parsed) => parsed.msg,
+                DeserializedMessageType::ChunkOfMessage(_) => panic!("expected a whole message"),
+            }
+        };
+        let reason = "Snapshot needs more pages than this build allows".to_string();
+        assert!(
+            matches!(parse(NetworkMessage::ClientJoinFailed(reason.clone()), true),
+                NetworkMessage::ClientJoinFailed(parsed) if parsed == reason)
+        );
+        assert!(
+            matches!(parse(NetworkMessage::ServerJoinFailed(ServerPlayerID(4), reason.clone()), false),
+                NetworkMessage::ServerJoinFailed(ServerPlayerID(4), parsed) if parsed == reason)
+        );
+
+        // 'é' is two bytes, the limit falls in the middle of one
+        let long = "é".repeat(MAX_JOIN_FAILED_REASON_BYTES);
+        let NetworkMessage::ClientJoinFailed(cut) = parse(NetworkMessage::ClientJoinFailed(long), true) else {
+            panic!("expected a join failure");
+        };
+        assert_eq!(cut, "é".repeat(MAX_JOIN_FAILED_REASON_BYTES / 2));
+        let odd = format!("x{}", "é".repeat(MAX_JOIN_FAILED_REASON_BYTES));
+        let NetworkMessage::ClientJoinFailed(cut) = parse(NetworkMessage::ClientJoinFailed(odd), true) else {
+            panic!("expected a join failure");
+        };
+        assert_eq!(cut.len(), MAX_JOIN_FAILED_REASON_BYTES - 1);
+    }
+
+    #[test]
+    fn test_input_acks_carry_one_frame_each_way() {
+        let SerializedMessageType::NonChunked(to_server) = NetworkMessage::ClientInputsAckedThrough(
+            0x0102_0304
+        ).serialize(NetworkMessageType::SendOnce) else {
+            panic!("an input ack fits one datagram");
+        };
+        assert_eq!(to_server.len(), DATA_BIT_START_POS + 4);
+        assert!(
+            matches!(
+                receive(&to_server.bytes).parse_on_server(to_server.len()),
+                Ok(DeserializedMessageType::NonChunked(DeserializedMessage {
+                    reliable: false,
+                    msg: NetworkMessage::ClientInputsAckedThrough(0x0102_0304),
+                    ..
+                }))
+            )
+        );
+        // only the server acks the client's own inputs
+        assert!(receive(&to_server.bytes).parse_on_client(to_server.len()).is_err());
+
+        let SerializedMessageType::NonChunked(to_client) = NetworkMessage::ServerInputsAckedThrough(
+            7
+        ).serialize(NetworkMessageType::SendOnce) else {
+            panic!("an input ack fits one datagram");
+        };
+        assert!(
+            matches!(
+                receive(&to_client.bytes).parse_on_client(to_client.len()),
+                Ok(DeserializedMessageType::NonChunked(DeserializedMessage {
+                    msg: NetworkMessage::ServerInputsAckedThrough(7),
+                    ..
+                }))
+            )
+        );
+        assert!(receive(&to_client.bytes).parse_on_client(to_client.len() - 1).is_err());
+    }
+
+    #[test]
+    fn test_input_stream_markers_survive_the_relay() {
+        let parse = |msg: NetworkMessage, on_server: bool| {
+            let SerializedMessageType::NonChunked(bytes) = msg.serialize(
+                NetworkMessageType::ResendUntilAck(SeqNum(2))
+            ) else {
+                panic!("a marker fits one datagram");
+            };
+            let buffer = receive(&bytes.bytes);
+            let parsed = if on_server {
+                buffer.parse_on_server(bytes.len())
+            } else {
+                buffer.parse_on_client(bytes.len())
+            };
+            match parsed {
+                Ok(DeserializedMessageType::NonChunked(parsed)) => Ok(parsed.msg),
+                Ok(DeserializedMessageType::ChunkOfMessage(_)) => panic!("expected a whole message"),
+                Err(e) => Err(e),
+            }
+        };
+        let paused = NetworkMessage::ClientInputStreamPaused { last_frame: 0x0102_0304 };
+        assert!(
+            matches!(
+                parse(paused.clone(), true),
+                Ok(NetworkMessage::ClientInputStreamPaused { last_frame: 0x0102_0304 })
+            )
+        );
+        assert!(parse(paused, false).is_err());
+        assert!(
+            matches!(
+                parse(NetworkMessage::ClientInputStreamResumed { from_frame: 90 }, true),
+                Ok(NetworkMessage::ClientInputStreamResumed { from_frame: 90 })
+            )
+        );
+
+        let relayed = NetworkMessage::ServerInputStreamPaused { slot: PlayerID::Player2, last_frame: 60 };
+        assert!(parse(relayed.clone(), true).is_err());
+        assert!(
+            matches!(
+                parse(relayed, false),
+                Ok(NetworkMessage::ServerInputStreamPaused { slot: PlayerID::Player2, last_frame: 60 })
+            )
+        );
+        assert!(
+            matches!(
+                parse(NetworkMessage::ServerInputStreamResumed { slot: PlayerID::Player1, from_frame: 90 }, false),
+                Ok(NetworkMessage::ServerInputStreamResumed { slot: PlayerID::Player1, from_frame: 90 })
+            )
+        );
+        assert!(
+            matches!(
+                parse(NetworkMessage::ClientInputStreamPausedThrough { through_frame: 75 }, true),
+                Ok(NetworkMessage::ClientInputStreamPausedThrough { through_frame: 75 })
+            )
+        );
+        assert!(
+            matches!(
+                parse(
+                    NetworkMessage::ServerInputStreamPausedThrough { slot: PlayerID::Player2, through_frame: 75 },
+                    false
+                ),
+                Ok(NetworkMessage::ServerInputStreamPausedThrough { slot: PlayerID::Player2, through_frame: 75 })
+            )
+        );
+    }
+
+    #[test]
+    fn test_world_layout_and_hidden_hosts_survive_the_wire() {
+        let info = ProtocolInfo { world_layout: 0xdead_beef, ..ProtocolInfo::local() };
+        for (msg, on_server) in [
+            (NetworkMessage::Hello(info, SessionId(9)), true),
+            (NetworkMessage::ServerSentPlayerIDs(vec![3, 5], 2), false),
+        ] {
+            let SerializedMessageType::NonChunked(bytes) = msg.serialize(
+                NetworkMessageType::ResendUntilAck(SeqNum(1))
+            ) else {
+                panic!("handshake messages fit one datagram");
+            };
+            let buffer = receive(&bytes.bytes);
+            let parsed = if on_server {
+                buffer.parse_on_server(bytes.len())
+            } else {
+                buffer.parse_on_client(bytes.len())
+            };
+            let DeserializedMessageType::NonChunked(parsed) = parsed.unwrap() else {
+                panic!("expected a whole message");
+            };
+            match parsed.msg {
+                NetworkMessage::Hello(parsed, session) => {
+                    assert_eq!(parsed, info);
+                    assert_eq!(session, SessionId(9));
+                }
+                NetworkMessage::ServerSentPlayerIDs(ids, other_layout) => {
+                    assert_eq!(ids, vec![3, 5]);
+                    assert_eq!(other_layout, 2);
+                }
+                msg => panic!("unexpected message {:?}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_frames_per_packet_is_the_largest_buffer_that_fits() {
+        let max = BufferedNetworkedPlayerInputs::max_frames_per_packet();
+        let inputs = BufferedNetworkedPlayerInputs {
+            buffered_inputs: (1..=max as u32)
+                .map(|frame| NetworkedPlayerInput::new(vec![PlayerInput::Left, PlayerInput::Shoot], frame))
+                .collect(),
+        };
+        for msg in [
+            NetworkMessage::ClientSentPlayerInputs(inputs.clone()),
+            NetworkMessage::ServerSentPlayerInputs(PlayerID::Player2, inputs.clone()),
+        ] {
+            let SerializedMessageType::NonChunked(bytes) = msg.serialize(
+                NetworkMessageType::SendOnce
+            ) else {
+                panic!("inputs are never chunked");
+            };
+            assert!(bytes.len() <= MAX_UDP_PAYLOAD_LEN);
+            // one more input wouldn't fit either message
+            assert!(bytes.len() + SERIALIZED_INPUT_BYTES > MAX_UDP_PAYLOAD_LEN, "{} bytes", bytes.len());
+            let count_and_slot = if matches!(msg, NetworkMessage::ServerSentPlayerInputs(..)) { 2 } else { 1 };
+            assert_eq!(bytes.len(), DATA_BIT_START_POS + count_and_slot + max * SERIALIZED_INPUT_BYTES);
+        }
+    }
+
+    #[test]
+    fn test_header_claiming_too_many_chunks_is_rejected() {
+        let mut bytes = vec![0; DATA_BIT_START_POS];
+        bytes[RELIABLE_FLAG_BYTE_POS] = 1;
+        bytes[AMT_OF_CHUNKS_BYTE_POS..AMT_OF_CHUNKS_BYTE_POS + 2].copy_from_slice(
+            &u16::MAX.to_le_bytes()
+        );
+        bytes[DISCRIMINANT_BIT_START_POS] = NetworkMessage::ClientSentWorld(Vec::new()).into();
+        assert!(receive(&bytes).parse_on_server(bytes.len()).is_err());
+        assert!(PacketParser::parse_header(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_collector_refuses_chunks_with_absurd_counts() {
+        let mut collector = ChunkedMessageCollector::default();
+        assert!(collector.collect(chunk(0, 0, u16::MAX)).is_err());
+        assert!(collector.collect(chunk(0, 0, 0)).is_err());
+        assert!(collector.collect(chunk(5, 0, 3)).is_err()); // past the last chunk of its group
+        assert!(collector.groups.is_empty());
+        assert!(collector.groups.capacity() <= ChunkLimits::default().max_groups);
+    }
+
+    #[test]
+    fn test_message_growing_past_the_byte_limit_is_dropped() {
+        let mut collector = ChunkedMessageCollector::with_limits(ChunkLimits {
+            max_message_bytes: 2 * MAX_UDP_PAYLOAD_DATA_LENGTH,
+            ..ChunkLimits::default()
+        });
+        collector.collect(chunk(0, 0, 3)).unwrap();
+        collector.collect(chunk(1, 0, 3)).unwrap();
+        assert!(collector.collect(chunk(2, 0, 3)).is_err());
+        assert!(collector.groups.is_empty());
+        assert_eq!(collector.dropped_groups, 1);
+    }
+
+    #[test]
+    fn test_oldest_group_makes_room_and_repeats_are_ignored() {
+        let mut collector = ChunkedMessageCollector::with_limits(ChunkLimits {
+            max_groups: 2,
+            ..ChunkLimits::default()
+        });
+        for base in [0, 10, 20] {
+            collector.collect(chunk(base, base, 2)).unwrap();
+            collector.collect(chunk(base, base, 2)).unwrap();
+        }
+        let bases: Vec<u16> = collector.groups.iter().map(|group| group.base_seq_num).collect();
+        assert_eq!(bases, vec![10, 20]);
+        assert!(collector.groups.iter().all(|group| group.chunks.len() == 1));
+        assert_eq!(collector.dropped_groups, 1);
+    }
+
+    #[test]
+    fn test_group_with_unparseable_header_is_dropped() {
+        let mut collector = ChunkedMessageCollector::default();
+        for seq_num in 0..2 {
+            let mut corrupt = chunk(seq_num, 0, 2);
+            corrupt.data_bytes[DISCRIMINANT_BIT_START_POS] = u8::MAX;
+            collector.collect(corrupt).unwrap();
+        }
+        assert!(collector.try_combine().is_none());
+        assert!(collector.groups.is_empty());
+        assert_eq!(collector.dropped_groups, 1);
+
+        // the next message with the same base seq num isn't blocked by it
+        let SerializedMessageType::Chunked(chunks) = NetworkMessage::ClientSentWorld(
+            vec![1; MAX_UDP_PAYLOAD_DATA_LENGTH + 1]
+        ).serialize(NetworkMessageType::ResendUntilAck(SeqNum(0))) else {
+            panic!("world should be chunked");
+        };
+        for chunk in &chunks.chunks {
+            if let DeserializedMessageType::ChunkOfMessage(chunk) = receive(&chunk.bytes)
+                .parse_on_server(chunk.len())
+                .unwrap()
+            {
+                collector.collect(chunk).unwrap();
+            }
+        }
+        assert!(collector.try_combine().is_some());
+        assert!(collector.groups.is_empty());
+    }
+
+    #[test]
+    fn test_chunk_with_highest_base_seq_num_is_collected() {
+        let world = vec![1; MAX_UDP_PAYLOAD_DATA_LENGTH + 1];
+        let SerializedMessageType::Chunked(chunks) = NetworkMessage::ClientSentWorld(
+            world.clone()
+        ).serialize(NetworkMessageType::ResendUntilAck(SeqNum(u16::MAX))) else {
+            panic!("world should be chunked");
+        };
+        let mut collector = ChunkedMessageCollector::default();
+        for chunk in &chunks.chunks {
+            if let DeserializedMessageType::ChunkOfMessage(chunk) = receive(&chunk.bytes)
+                .parse_on_server(chunk.len())
+                .unwrap()
+            {
+                collector.collect(chunk).unwrap();
+            }
+        }
+        assert!(collector.try_combine().is_some());
+    }
+
+    fn inputs(frames: &[(u32, Vec<PlayerInput>)]) -> BufferedNetworkedPlayerInputs {
+        BufferedNetworkedPlayerInputs {
+            buffered_inputs: frames
+                .iter()
+                .map(|(frame, inp)| NetworkedPlayerInput::new(inp.clone(), *frame))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_server_sent_inputs_round_trip_with_slot() {
+        let sent = inputs(&[(4, vec![PlayerInput::Left, PlayerInput::Shoot]), (5, Vec::new())]);
+        for slot in [PlayerID::Player1, PlayerID::Player2] {
+            let SerializedMessageType::NonChunked(msg) = NetworkMessage::ServerSentPlayerInputs(
+                slot,
+                sent.clone()
+            ).serialize(NetworkMessageType::SendOnce) else {
+                panic!("inputs should not be chunked");
+            };
+            assert_eq!(msg.bytes[DATA_BIT_START_POS], slot as u8);
+            match receive(&msg.bytes).parse_on_client(msg.bytes.len()).unwrap() {
+                DeserializedMessageType::NonChunked(parsed) =>
+                    match parsed.msg {
+                        NetworkMessage::ServerSentPlayerInputs(parsed_slot, parsed_inputs) => {
+                            assert_eq!(parsed_slot, slot);
+                            assert_eq!(parsed_inputs.buffered_inputs, sent.buffered_inputs);
+                        }
+                        msg => panic!("unexpected message {:?}", msg),
+                    }
+                DeserializedMessageType::ChunkOfMessage(_) => panic!("expected a whole message"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_client_sent_inputs_round_trip_without_slot() {
+        let sent = inputs(&[(9, vec![PlayerInput::Right])]);
+        let SerializedMessageType::NonChunked(msg) = NetworkMessage::ClientSentPlayerInputs(
+            sent.clone()
+        ).serialize(NetworkMessageType::SendOnce) else {
+            panic!("inputs should not be chunked");
+        };
+        // count byte, then 4 frame bytes and 1 packed input byte per entry
+        assert_eq!(msg.bytes.len(), DATA_BIT_START_POS + 1 + 5);
+        match receive(&msg.bytes).parse_on_server(msg.bytes.len()).unwrap() {
+            DeserializedMessageType::NonChunked(parsed) =>
+                match parsed.msg {
+                    NetworkMessage::ClientSentPlayerInputs(parsed_inputs) =>
+                        assert_eq!(parsed_inputs.buffered_inputs, sent.buffered_inputs),
+                    msg => panic!("unexpected message {:?}", msg),
+                }
+            DeserializedMessageType::ChunkOfMessage(_) => panic!("expected a whole message"),
+        }
+    }
+
+    fn parse_client_inputs(msg: NetworkMessage) -> (usize, NetworkMessage) {
+        let SerializedMessageType::NonChunked(msg) = msg.serialize(NetworkMessageType::SendOnce) else {
+            panic!("inputs should not be chunked");
+        };
+        let Ok(DeserializedMessageType::NonChunked(parsed)) = receive(&msg.bytes).parse_on_server(
+            msg.bytes.len()
+        ) else {
+            panic!("expected a whole message");
+        };
+        (msg.bytes.len(), parsed.msg)
+    }
+
+    #[test]
+    fn test_held_inputs_go_out_as_one_run_and_expand_to_every_frame() {
+        let held: Vec<(u32, Vec<PlayerInput>)> = (100..130)
+            .map(|frame| (frame, vec![PlayerInput::Left, PlayerInput::Shoot]))
+            .collect();
+        let sent = inputs(&held);
+        assert!(sent.runs_are_shorter());
+        let (frame_by_frame_len, _) = parse_client_inputs(NetworkMessage::ClientSentPlayerInputs(sent.clone()));
+        let (runs_len, parsed) = parse_client_inputs(NetworkMessage::ClientSentPlayerInputRuns(sent.clone()));
+        assert_eq!(frame_by_frame_len, DATA_BIT_START_POS + 1 + 30 * SERIALIZED_INPUT_BYTES);
+        assert_eq!(runs_len, DATA_BIT_START_POS + 1 + SERIALIZED_INPUT_RUN_BYTES);
+        let NetworkMessage::ClientSentPlayerInputRuns(received) = parsed else {
+            panic!("unexpected message {:?}", parsed);
+        };
+        assert_eq!(received.buffered_inputs.len(), 30);
+        assert_eq!(received.buffered_inputs, sent.buffered_inputs);
+    }
+
+    #[test]
+    fn test_runs_break_at_gaps_changes_and_unknown_flags() {
+        let mut sent = inputs(&[
+            (1, vec![PlayerInput::Right]),
+            (2, vec![PlayerInput::Right]),
+            (4, vec![PlayerInput::Right]), // the sender never had 3
+            (5, vec![PlayerInput::Right]),
+            (6, Vec::new()),
+            (7, Vec::new()),
+        ]);
+        sent.buffered_inputs[5].unknown_bits = 0b1000_0000;
+        assert_eq!(sent.runs().len(), 4);
+        let (_, parsed) = parse_client_inputs(NetworkMessage::ClientSentPlayerInputRuns(sent.clone()));
+        let NetworkMessage::ClientSentPlayerInputRuns(received) = parsed else {
+            panic!("unexpected message {:?}", parsed);
+        };
+        assert_eq!(received.buffered_inputs, sent.buffered_inputs);
+
+        // inputs that change every frame are shorter one by one
+        let alternating: Vec<(u32, Vec<PlayerInput>)> = (0..10)
+            .map(|frame| (frame, if frame % 2 == 0 { vec![PlayerInput::Shoot] } else { Vec::new() }))
+            .collect();
+        assert!(!inputs(&alternating).runs_are_shorter());
+    }
+
+    #[test]
+    fn test_a_run_past_the_last_frame_is_rejected() {
+        let sent = inputs(&[(u32::MAX - 1, Vec::new()), (u32::MAX, Vec::new())]);
+        let SerializedMessageType::NonChunked(msg) = NetworkMessage::ClientSentPlayerInputRuns(
+            sent
+        ).serialize(NetworkMessageType::SendOnce) else {
+            panic!("inputs should not be chunked");
+        };
+        let mut bytes = msg.bytes.to_vec();
+        *bytes.last_mut().unwrap() = 2; // repeats
+        assert_eq!(receive(&bytes).parse_on_server(bytes.len()).err(), Some("Input run past the last frame"));
+        bytes.pop();
+        assert_eq!(receive(&bytes).parse_on_server(bytes.len()).err(), Some("Truncated input runs"));
+    }
+
+    #[test]
+    fn test_forwarded_inputs_keep_flags_this_build_does_not_know() {
+        let mut sent = inputs(&[(12, vec![PlayerInput::Shoot])]);
+        sent.buffered_inputs[0].unknown_bits = 0b1000_0001;
+        let SerializedMessageType::NonChunked(msg) = NetworkMessage::ClientSentPlayerInputs(
+            sent.clone()
+        ).serialize(NetworkMessageType::SendOnce) else {
+            panic!("inputs should not be chunked");
+        };
+        let Ok(DeserializedMessageType::NonChunked(parsed)) = receive(&msg.bytes).parse_on_server(
+            msg.bytes.len()
+        ) else {
+            panic!("expected a whole message");
+        };
+        let NetworkMessage::ClientSentPlayerInputs(received) = parsed.msg else {
+            panic!("unexpected message {:?}", parsed.msg);
+        };
+        assert_eq!(received.buffered_inputs, sent.buffered_inputs);
+        // what the server forwards carries the same byte
+        let SerializedMessageType::NonChunked(forwarded) = NetworkMessage::ServerSentPlayerInputs(
+            PlayerID::Player1,
+            received
+        ).serialize(NetworkMessageType::SendOnce) else {
+            panic!("inputs should not be chunked");
+        };
+        assert_eq!(forwarded.bytes.last(), msg.bytes.last());
+        assert_eq!(*msg.bytes.last().unwrap(), 0b1000_0001 | input_codec::pack(&[PlayerInput::Shoot]));
+    }
+
+    #[test]
+    fn test_input_count_past_the_payload_is_rejected() {
+        // a count of 200 with no entries behind it, to the server and to a client
+        for (msg, endpoint) in [
+            (NetworkMessage::ClientSentPlayerInputs(inputs(&[])), Endpoint::Server),
+            (NetworkMessage::ServerSentPlayerInputs(PlayerID::Player1, inputs(&[])), Endpoint::Client),
+        ] {
+            let SerializedMessageType::NonChunked(msg) = msg.serialize(NetworkMessageType::SendOnce) else {
+                panic!("inputs should not be chunked");
+            };
+            let mut bytes = msg.bytes.to_vec();
+            *bytes.last_mut().unwrap() = 200;
+            assert_eq!(parse_datagram(&bytes, endpoint).err(), Some("Truncated player inputs"));
+        }
+    }
+
+    #[test]
+    fn test_server_sent_inputs_with_invalid_slot_is_rejected() {
+        let SerializedMessageType::NonChunked(msg) = NetworkMessage::ServerSentPlayerInputs(
+            PlayerID::Player2,
+            inputs(&[(1, Vec::new())])
+        ).serialize(NetworkMessageType::SendOnce) else {
+            panic!("inputs should not be chunked");
+        };
+        let mut bytes = msg.bytes.to_vec();
+        bytes[DATA_BIT_START_POS] = 7;
+        assert!(receive(&bytes).parse_on_client(bytes.len()).is_err());
+    }
+
+    #[test]
+    fn test_reassembled_message_reports_chunk_count() {
+        let world: Vec<u8> = vec![3; MAX_UDP_PAYLOAD_DATA_LENGTH * 3 + 1];
+        let SerializedMessageType::Chunked(chunks) = NetworkMessage::ClientSentWorld(
+            world
+        ).serialize(NetworkMessageType::ResendUntilAck(SeqNum(40))) else {
+            panic!("world should be chunked");
+        };
+        let mut collector = ChunkedMessageCollector::default();
+        for chunk in &chunks.chunks {
+            if let DeserializedMessageType::ChunkOfMessage(chunk) = receive(&chunk.bytes)
+                .parse_on_server(chunk.len())
+                .unwrap()
+            {
+                collector.collect(chunk).unwrap();
+            }
+        }
+        assert_eq!(collector.try_combine().unwrap().arrived_as_chunks, Some(4));
+
+        let SerializedMessageType::NonChunked(direct) = NetworkMessage::GetServerPlayerIDs.serialize(
+            NetworkMessageType::ResendUntilAck(SeqNum(2))
+        ) else {
+            panic!("request should not be chunked");
+        };
+        match receive(&direct.bytes).parse_on_server(direct.bytes.len()).unwrap() {
+            DeserializedMessageType::NonChunked(msg) => assert_eq!(msg.arrived_as_chunks, None),
+            DeserializedMessageType::ChunkOfMessage(_) => panic!("expected a whole message"),
+        }
+    }
+
+    #[test]
+    fn test_datagram_shorter_than_header_is_rejected() {
+        let buffer = receive(&[0; 3]);
+        assert!(buffer.parse_on_server(3).is_err());
+        assert!(buffer.parse_on_client(3).is_err());
+    }
+
+    #[test]
+    fn test_parse_datagram_reads_what_the_receiving_end_accepts() {
+        let SerializedMessageType::NonChunked(request) = NetworkMessage::GetServerPlayerIDs.serialize(
+            NetworkMessageType::ResendUntilAck(SeqNum(3))
+        ) else {
+            panic!("a request fits one datagram");
+        };
+        assert!(
+            matches!(
+                parse_datagram(&request.bytes, Endpoint::Server),
+                Ok(DeserializedMessageType::NonChunked(DeserializedMessage {
+                    reliable: true,
+                    seq_num: Some(3),
+                    msg: NetworkMessage::GetServerPlayerIDs,
+                    ..
+                }))
+            )
+        );
+        assert!(parse_datagram(&request.bytes, Endpoint::Client).is_err());
+        assert!(parse_datagram(&[0; MAX_UDP_PAYLOAD_LEN + 1], Endpoint::Server).is_err());
+    }
+
+    #[test]
+    fn test_disabled_log_categories_never_build_the_message() {
+        let built = std::cell::Cell::new(0);
+        let message = || {
+            built.set(built.get() + 1);
+            format!("inputs for frame {}", 7)
+        };
+        let mut logger = Logger::new(LogConfig {
+            connection: false,
+            world_state: false,
+            player_input: false,
+            message_handling: false,
+            ack: false,
+            error: false,
+            debug: false,
+        });
+        logger.connection(message);
+        logger.world_state(message);
+        logger.player_input(message);
+        logger.message(message);
+        logger.ack(message);
+        logger.error(message);
+        logger.debug(message);
+        logger.debug_log_time(message);
+        assert_eq!(built.get(), 0);
+
+        logger.config.player_input = true;
+        logger.player_input(message);
+        assert_eq!(built.get(), 1);
+    }
+
+    #[test]
+    fn test_ack_with_wrong_payload_length_is_rejected() {
+        let SerializedMessageType::NonChunked(ack) = NetworkMessage::ClientSideAck(
+            SeqNum(513)
+        ).serialize(NetworkMessageType::SendOnce) else {
+            panic!("ack should not be chunked");
+        };
+        let len = ack.bytes.len();
+        match receive(&ack.bytes).parse_on_server(len).unwrap() {
+            DeserializedMessageType::NonChunked(msg) => {
+                assert!(matches!(msg.msg, NetworkMessage::ClientSideAck(SeqNum(513))));
+            }
+            DeserializedMessageType::ChunkOfMessage(_) => panic!("ack should not be chunked"),
+        }
+        // the receive buffer is stale past the datagram, only its real length counts
+        assert!(receive(&ack.bytes).parse_on_server(len + 1).is_err());
+        assert!(receive(&ack.bytes).parse_on_server(len - 1).is_err());
+    }
+
+    fn frames(inputs: &BufferedNetworkedPlayerInputs) -> Vec<u32> {
+        inputs.buffered_inputs.iter().map(|inp| inp.frame).collect()
+    }
+
+    #[test]
+    fn test_merging_out_of_order_inputs_keeps_frames_sorted() {
+        let mut merged = inputs(&[(3, Vec::new()), (7, vec![PlayerInput::Left])]);
+        merged.bulk_insert_player_input(
+            inputs(&[(9, Vec::new()), (1, Vec::new()), (7, vec![PlayerInput::Right]), (5, Vec::new())])
+        );
+        assert_eq!(frames(&merged), vec![1, 3, 5, 7, 9]);
+        assert_eq!(merged.last_frame(), Some(9));
+        // a frame we already had keeps its inputs
+        assert_eq!(merged.buffered_inputs[3].inputs, vec![PlayerInput::Left]);
+        assert_eq!(BufferedNetworkedPlayerInputs::default().last_frame(), None);
+    }
+
+    #[test]
+    fn test_trimming_keeps_the_newest_frames_and_returns_the_dropped_ones() {
+        let mut buffer = inputs(
+            &[(4, Vec::new()), (5, vec![PlayerInput::Shoot]), (6, Vec::new()), (7, Vec::new())]
+        );
+        assert!(buffer.trim_to_fit(4).is_empty());
+        let dropped = buffer.trim_to_fit(2);
+        assert_eq!(frames(&BufferedNetworkedPlayerInputs { buffered_inputs: dropped.clone() }), vec![4, 5]);
+        assert_eq!(dropped[1].inputs, vec![PlayerInput::Shoot]);
+        assert_eq!(frames(&buffer), vec![6, 7]);
+        assert_eq!(buffer.last_frame(), Some(7));
+    }
+
+    #[test]
+    fn test_normalize_sorts_shuffled_frames_and_counts_gaps() {
+        let mut inputs = BufferedNetworkedPlayerInputs {
+            buffered_inputs: [7, 3, 5, 4, 10]
+                .into_iter()
+                .map(|frame| NetworkedPlayerInput::new(Vec::new(), frame))
+                .collect(),
+        };
+        let stats = inputs.normalize();
+        assert_eq!(frames(&inputs), vec![3, 4, 5, 7, 10]);
+        assert_eq!(stats, InputGapStats { duplicates: 0, missing_frames: 3 });
+    }
+
+    #[test]
+    fn test_normalize_keeps_first_of_duplicated_frames() {
+        let mut inputs = BufferedNetworkedPlayerInputs {
+            buffered_inputs: vec![
+                NetworkedPlayerInput::new(vec![PlayerInput::Left], 2),
+                NetworkedPlayerInput::new(Vec::new(), 1),
+                NetworkedPlayerInput::new(vec![PlayerInput::Right], 2),
+                NetworkedPlayerInput::new(Vec::new(), 1),
+                NetworkedPlayerInput::new(Vec::new(), 3)
+            ],
+        };
+        let mut total = InputGapStats::default();
+        total.add(inputs.normalize());
+        assert_eq!(frames(&inputs), vec![1, 2, 3]);
+        assert_eq!(inputs.buffered_inputs[1].inputs, vec![PlayerInput::Left]);
+        assert_eq!(total, InputGapStats { duplicates: 2, missing_frames: 0 });
+        // an already normalized batch changes nothing
+        total.add(inputs.normalize());
+        assert_eq!(total, InputGapStats { duplicates: 2, missing_frames: 0 });
+        assert_eq!(
+            BufferedNetworkedPlayerInputs::default().normalize(),
+            InputGapStats::default()
+        );
+    }
+
+    #[test]
+    fn test_frame_kinds_only_convert_where_it_is_spelled_out() {
+        let verified = VerifiedFrame(40);
+        assert_eq!(verified.next_input_frame(), 41);
+        // a rollback is the one way from verified to predicted
+        let predicted = verified.as_predicted();
+        assert_eq!(predicted, PredictedFrame(40));
+        assert_eq!(predicted.frames_past(verified), 0);
+        assert_eq!(PredictedFrame(46).frames_past(verified), 6);
+        assert_eq!(PredictedFrame(46).next_input_frame(), 47);
+        // a prediction behind a fresh snapshot has nothing of its own
+        assert_eq!(PredictedFrame(30).frames_past(verified), 0);
+        assert!(VerifiedFrame(3) < VerifiedFrame(4));
+    }
+
+    // arbitrary datagrams from a seeded rng, a failure names the seed and the iteration so the
+    // buffer can be rebuilt by running the same loop
+    const FUZZ_SEED: u64 = 1468;
+    const FUZZ_ITERATIONS: usize = 20_000;
+
+    #[test]
+    fn test_random_datagrams_never_panic_the_parser() {
+        use rand::{ rngs::StdRng, Rng, SeedableRng };
+        let mut rng = StdRng::seed_from_u64(FUZZ_SEED);
+        let max_discriminant: u8 = NetworkMessage::ServerInputStreamPausedThrough {
+            slot: PlayerID::Player1,
+            through_frame: 0,
+        }.into();
+        for iteration in 0..FUZZ_ITERATIONS {
+            let mut buffer = MsgBuffer::default();
+            let len = rng.gen_range(0..=MAX_UDP_PAYLOAD_LEN);
+            rng.fill(&mut buffer.0[..len]);
+            if len > DISCRIMINANT_BIT_START_POS && rng.gen_bool(0.5) {
+                // most random bytes aren't a message at all, half the buffers get a known one
+                buffer.0[DISCRIMINANT_BIT_START_POS] = rng.gen_range(0..=max_discriminant);
+            }
+            if len > DISCRIMINANT_BIT_START_POS && rng.gen_bool(0.5) {
+                // random chunk fields make nearly every datagram a chunk, half of them reach parse_data whole
+                buffer.0[BASE_CHUNK_SEQ_NUM_BYTE_POS..TRANSFER_ID_BYTE_POS + 2].fill(0);
+            }
+            for on_server in [true, false] {
+                let parsed = std::panic::catch_unwind(|| {
+                    if on_server {
+                        let _ = buffer.parse_on_server(len);
+                    } else {
+                        let _ = buffer.parse_on_client(len);
+                    }
+                });
+                assert!(
+                    parsed.is_ok(),
+                    "seed {} iteration {} ({} bytes, on server: {}) panicked: {:02x?}",
+                    FUZZ_SEED,
+                    iteration,
+                    len,
+                    on_server,
+                    &buffer.0[..len]
+                );
+            }
+        }
+    }
+}
@@ -1,71 +1,73 @@
-use std::time::Instant;
+use std::{ sync::Arc, time::Instant };
 
-use macroquad::{ color::Color, math::Vec2 };
-use crate::memory::FixedDataPtr;
 pub const MAX_UDP_PAYLOAD_LEN: usize = 508; // https://stackoverflow.com/questions/1098897/what-is-the-largest-safe-udp-packet-size-on-the-internet
 pub const MAX_UDP_PAYLOAD_DATA_LENGTH: usize = MAX_UDP_PAYLOAD_LEN - DATA_BIT_START_POS;
-pub const MAX_BULLETS: usize = 5;
-pub const MAX_ENEMIES: usize = 20;
-pub const RELOAD_TIME: f32 = 0.5;
-pub const BULLET_SIZE: f32 = 5.0;
-pub const ENEMY_SIZE: f32 = 40.0;
-pub const AMT_RANDOM_BYTES: usize = 1;
-pub const RELIABLE_FLAG_BYTE_POS: usize = AMT_RANDOM_BYTES; // AMT random bytes starts with bit 0 so bit AMT_RANDOM_BYTES - 1 is last bit of it, and AMT_RANDOM_BYTES IS FREE
-pub const SEQ_NUM_BYTE_POS: usize = RELIABLE_FLAG_BYTE_POS + 1;
-
-pub const BASE_CHUNK_SEQ_NUM_BYTE_POS: usize = SEQ_NUM_BYTE_POS + 2; // u16
-pub const AMT_OF_CHUNKS_BYTE_POS: usize = BASE_CHUNK_SEQ_NUM_BYTE_POS + 2; // u16
-pub const DISCRIMINANT_BIT_START_POS: usize = AMT_OF_CHUNKS_BYTE_POS + 2; // u16
-pub const DATA_BIT_START_POS: usize = DISCRIMINANT_BIT_START_POS + 1;
+// datagram header, every multi-byte field here and in the payloads is little endian so hosts of
+// either byte order can play together. the one exception is a world sent as RawPages, its pages are
+// copied in the sender's native byte order, see world_format. the positions come from the header
+// struct in packet_layout
+pub use crate::packet_layout::{
+    AMT_RANDOM_BYTES,
+    RELIABLE_FLAG_BYTE_POS,
+    SEQ_NUM_BYTE_POS,
+    BASE_CHUNK_SEQ_NUM_BYTE_POS,
+    AMT_OF_CHUNKS_BYTE_POS,
+    TRANSFER_ID_BYTE_POS,
+    DISCRIMINANT_BIT_START_POS,
+    DATA_BIT_START_POS,
+};
 pub const PLAYER_MOVE_LEFT_BYTE_POS: usize = 1;
 pub const PLAYER_MOVE_RIGHT_BYTE_POS: usize = 2;
 pub const PLAYER_SHOOT_BYTE_POS: usize = 3;
 pub const VECTOR_LEN_BYTE_POS: usize = DATA_BIT_START_POS;
+pub const MAX_CHUNKS_PER_MESSAGE: usize = (u8::MAX as usize) - 1;
+pub const MAX_PLAYER_COUNT: u8 = 2;
+// 2: Hello carries the world layout, ServerSentPlayerIDs the hidden host count. 3: chunks carry a transfer id.
+// 4: ClientSentWorld starts with the format of the world. 5: ClientStartedHosting carries no flag.
+// 6: the enemy spawn interval in a world is an f32 of seconds
+pub const PROTOCOL_VERSION: u16 = 6;
+pub const FEATURE_SLOT_TAGGED_INPUTS: u32 = 1 << 0; // ServerSentPlayerInputs carries the sender's slot
+pub const FEATURE_SESSION_TICK_RATE: u32 = 1 << 1; // hosts announce their tick rate with ClientSetTickRate
+// 1 << 2 told builds with field by field worlds apart, since version 4 a world says its format
+pub const FEATURE_PING: u32 = 1 << 3; // the server answers a Ping with a Pong
+pub const FEATURE_RESYNC: u32 = 1 << 4; // the server forwards ClientRequestResync to the host
+pub const FEATURE_LOBBY_EVENTS: u32 = 1 << 5; // the server announces PlayerJoined and PlayerLeft
+pub const FEATURE_HOST_LIST_PUSH: u32 = 1 << 6; // the server nudges the lobby with ServerPlayerListChanged
+pub const FEATURE_INPUT_RANGE: u32 = 1 << 7; // ClientRequestInputRange is answered or passed on to the peer
+pub const FEATURE_JOIN_FAILED: u32 = 1 << 8; // the server tells the group why a joiner gave up, ServerJoinFailed
+pub const FEATURE_INPUT_FRAME_ACKS: u32 = 1 << 9; // inputs are acked by frame, not per datagram
+pub const FEATURE_INPUT_RUNS: u32 = 1 << 10; // clients may send held inputs as ClientSentPlayerInputRuns
+pub const FEATURE_INPUT_STREAM_PAUSE: u32 = 1 << 11; // a pause is announced, peers fill it with empty inputs
+pub const FEATURE_WORLD_BASELINE: u32 = 1 << 12; // hosts send ClientWorldBaseline, older inputs aren't relayed
+pub const FEATURE_PAUSED_THROUGH: u32 = 1 << 13; // a pause is filled up to its last ClientInputStreamPausedThrough
+pub const FEATURE_BITS: u32 =
+    FEATURE_SLOT_TAGGED_INPUTS |
+    FEATURE_SESSION_TICK_RATE |
+    FEATURE_PING |
+    FEATURE_RESYNC |
+    FEATURE_LOBBY_EVENTS |
+    FEATURE_HOST_LIST_PUSH |
+    FEATURE_INPUT_RANGE |
+    FEATURE_JOIN_FAILED |
+    FEATURE_INPUT_FRAME_ACKS |
+    FEATURE_INPUT_RUNS |
+    FEATURE_INPUT_STREAM_PAUSE |
+    FEATURE_WORLD_BASELINE |
+    FEATURE_PAUSED_THROUGH;
+pub const SUPPORTED_TICK_RATES: [u32; 3] = [30, 60, 120];
+pub const DEFAULT_TICK_RATE_HZ: u32 = 60;
+pub const MAX_ACKS_PER_DATAGRAM: usize = (MAX_UDP_PAYLOAD_DATA_LENGTH - 2) / 2; // 1 byte count, 2 bytes per seq num, stays below MAX_UDP_PAYLOAD_LEN
+pub const INPUTS_MESSAGE_HEADER_BYTES: usize = 2; // 1 byte slot (ServerSentPlayerInputs only), 1 byte count
+pub const MAX_JOIN_FAILED_REASON_BYTES: usize = 200; // longer reasons are cut, they have to fit one datagram
+pub const SERIALIZED_INPUT_BYTES: usize = std::mem::size_of::<u32>() + 1; // frame, packed inputs
+pub const MAX_REQUESTED_FRAMES: usize = 8; // more missing than this is a stalled peer, not a lost datagram
+pub const SERIALIZED_INPUT_RUN_BYTES: usize = SERIALIZED_INPUT_BYTES + 1; // first frame, packed inputs, repeats
 
-#[derive(Copy, Clone)]
-pub struct Player {
-    pub position: Vec2,
-    pub speed: f32,
-    pub color: Color,
-    pub bullets: [Bullet; MAX_BULLETS],
-    pub movement_input: f32,
-    pub shoot_input: bool,
-    pub curr_reload_time: f32,
-}
-#[derive(Copy, Clone)]
-pub struct Bullet {
-    pub position: Vec2,
-    pub velocity: Vec2,
-}
-#[derive(Copy, Clone)]
-pub struct Enemy {
-    pub position: Vec2,
-}
-#[derive(Copy, Clone)]
-pub struct Simulation {
-    pub player1: FixedDataPtr<Player>,
-    pub player2: FixedDataPtr<Player>,
-    pub enemies: FixedDataPtr<[Enemy; MAX_ENEMIES]>,
-    pub frame: FixedDataPtr<u32>,
-}
-pub struct SimulationDataMut<'a> {
-    pub player1: &'a mut Player,
-    pub player2: &'a mut Player,
-    pub enemies: &'a mut [Enemy; MAX_ENEMIES],
-    pub spawn_timer: &'a mut f64,
-}
-
-pub struct SimulationDataRef<'a> {
-    player1: &'a Player,
-    player2: &'a Player,
-    enemies: &'a [Enemy; MAX_ENEMIES],
-    spawn_timer: &'a f64,
-}
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PlayerInput {
     Left,
     Right,
-    Shoot,
+    Shoot, // shoot intent this frame, level triggered: set on every frame the key is held
 }
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum PlayerID {
@@ -73,18 +75,25 @@ pub enum PlayerID {
     Player2,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ServerPlayerID(pub u8);
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct NetworkedPlayerInput {
     pub inputs: Vec<PlayerInput>,
     pub frame: u32,
+    pub unknown_bits: u8, // input flags from a newer build, passed along untouched
 }
 #[derive(Debug, Clone)]
 pub struct BufferedNetworkedPlayerInputs {
     pub buffered_inputs: Vec<NetworkedPlayerInput>,
 }
+// what normalizing received input batches found, both point at loss or reordering upstream
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct InputGapStats {
+    pub duplicates: u32, // frames that were in a batch more than once
+    pub missing_frames: u32, // frames missing between the first and last frame of a batch
+}
 
 #[repr(u8)]
 #[derive(Debug, Clone)]
@@ -98,19 +107,125 @@ pub enum NetworkMessage {
     ServerSideAck(SeqNum) = 4,
     ClientSideAck(SeqNum) = 5,
 
-    ServerSentPlayerIDs(Vec<u8>) = 6,
-    ServerSentPlayerInputs(BufferedNetworkedPlayerInputs) = 7,
+    ServerSentPlayerIDs(Vec<u8>, u8) = 6, // hosts we can join, and how many were left out for another world layout
+    ServerSentPlayerInputs(PlayerID, BufferedNetworkedPlayerInputs) = 7, // slot of the player the inputs came from
     ServerSentWorld(Vec<u8>) = 8,
 
     ClientConnectToOtherWorld(ServerPlayerID) = 9,
     ServerRequestHostForWorldData = 10,
+    ServerAssignPlayerSlot(PlayerID) = 11,
+    ClientSideAcks(Vec<SeqNum>) = 12,
+    Hello(ProtocolInfo, SessionId) = 13, // first message of every client, repeated from the new address after a rebind
+    HelloAck(ProtocolInfo) = 14, // the server's side, both ends check compatibility
+    ClientSetTickRate(TickRate) = 15, // sent when starting to host, joiners play at their host's rate
+    // unreliable, only the client reads send_time_micros, it is relative to a clock of its own
+    Ping {
+        nonce: u32,
+        send_time_micros: u64,
+    } = 16,
+    Pong {
+        nonce: u32,
+        send_time_micros: u64,
+    } = 17, // the server's echo of a Ping, unchanged
+    ClientRequestResync = 18, // a joiner's verified frames can't continue, the server asks its host for the world again
+    PlayerJoined(ServerPlayerID) = 19, // sent to everyone in a connection group, the joiner hears of everyone already in it
+    PlayerLeft(ServerPlayerID) = 20, // sent to the members that are left
+    ClientLeaveSession = 21, // the game ended its session, the server takes us out of the connection group
+    ClientStartedHosting = 22, // the lobby is told its host list changed, hosting only ends with the client
+    ServerPlayerListChanged = 23, // unreliable, clients not in a session ask for the host list again
+    // the verified frames stall on these remote frames, the server resends them or asks the peer to
+    ClientRequestInputRange {
+        from_frame: u32,
+        to_frame: u32,
+    } = 24,
+    ServerRequestInputRange {
+        from_frame: u32,
+        to_frame: u32,
+    } = 25, // the server no longer has the frames, the peer sends its inputs for them again
+    ClientJoinFailed(String) = 26, // the joiner couldn't load the host's world and leaves, why in a few words
+    ServerJoinFailed(ServerPlayerID, String) = 27, // passed on to the rest of the joiner's group
+    // unreliable, every relayed input up to this frame arrived, sent every INPUT_ACK_INTERVAL while it moves
+    ClientInputsAckedThrough(u32) = 28,
+    ServerInputsAckedThrough(u32) = 29, // the same for the inputs the client sent
+    // the same frames as ClientSentPlayerInputs, a run of equal inputs on consecutive frames is sent once
+    // with how many frames repeat it. only the wire format differs, parsing expands every frame again
+    ClientSentPlayerInputRuns(BufferedNetworkedPlayerInputs) = 30,
+    // the player paused on purpose, no inputs follow `last_frame` until a Resumed. the peers play
+    // empty inputs for the frames in between instead of waiting for them
+    ClientInputStreamPaused {
+        last_frame: u32,
+    } = 31,
+    ClientInputStreamResumed {
+        from_frame: u32,
+    } = 32, // inputs count again from `from_frame` on, the frames before it are empty
+    ServerInputStreamPaused {
+        slot: PlayerID,
+        last_frame: u32,
+    } = 33, // passed on to the rest of the group with the slot of the player that paused
+    ServerInputStreamResumed {
+        slot: PlayerID,
+        from_frame: u32,
+    } = 34,
+    // the world the host uploads next was taken at this frame, its peers get its inputs from the next one on
+    ClientWorldBaseline(u32) = 35,
+    // unreliable, sent for every frame a pause covers: the player played `through_frame` with an empty
+    // input. the peers fill a pause only this far, a late Resumed can't have them verify a played frame
+    ClientInputStreamPausedThrough {
+        through_frame: u32,
+    } = 36,
+    ServerInputStreamPausedThrough {
+        slot: PlayerID,
+        through_frame: u32,
+    } = 37,
+}
+// physics steps per second, picked by the host and fixed for the whole session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickRate(pub(crate) u32); // only supported rates, build it with TickRate::new
+crate::impl_pod!(TickRate { 0 });
+// The last frame the verified simulation stepped to, final on every peer, and the last frame the
+// prediction stepped to, which a rollback takes back. Input frames stay plain u32s: the input for
+// frame n is what takes a simulation from n - 1 to n. Neither converts into the other implicitly:
+/// ```compile_fail
+/// use unlockrs::types::{ PredictedFrame, VerifiedFrame };
+/// fn rollback_to(_: VerifiedFrame) {}
+/// rollback_to(PredictedFrame(3));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct VerifiedFrame(pub u32);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct PredictedFrame(pub u32);
+// picked by the client once per run, lets the server recognize it behind a new source address.
+// It identifies, it doesn't authenticate: every Hello carries it in the clear, and whoever repeats it
+// takes over the session's slot and state. Random only so two clients don't pick the same one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionId(pub u64);
+// what a build speaks, peers only talk when all of it matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolInfo {
+    pub protocol_version: u16,
+    pub max_players: u8,
+    pub feature_bits: u32,
+    pub world_layout: u32, // the game's WORLD_LAYOUT_HASH, only hosts and joiners have to agree on it
+}
+// everything the network thread hands to the game, wire messages and connection level notifications
+#[derive(Debug)]
+pub enum NetworkEvent {
+    Message(NetworkMessage),
+    WorldUploadComplete, // every chunk of the last ClientSentWorld was acked by the server
+    Failed(NetError), // a request from the game couldn't be carried out
+    Fatal(String), // a network thread panicked, nothing more will arrive
+    PoorConnection, // the estimated loss stayed high for a while, sent once per connection
 }
 pub enum GameMessage {
-    ClientSentPlayerInputs(NetworkedPlayerInput),
+    ClientSentPlayerInputs(NetworkedPlayerInput), // a frame's input as sampled, each frame goes out once
+    ClientResentPlayerInputs(NetworkedPlayerInput), // a frame the server asked for again
 }
 pub enum GameRequestToNetwork {
     DirectRequest(NetworkMessage),
     IndirectRequest(GameMessage),
+    Reconnect, // rebind the socket, the server keeps the session
+    BeginSession, // requests from older sessions are dropped from here on
+    EndSession, // this session's requests are dropped from here on, including the queued ones
 }
 #[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
 pub struct SeqNum(pub u16);
@@ -120,21 +235,34 @@ pub struct SeqNumGenerator {
 }
 pub enum NetworkMessageType {
     ResendUntilAck(SeqNum),
+    // reliable too, if the message gets chunked its receiver only assembles the newest transfer_id
+    ReliableTransfer {
+        seq_num: SeqNum,
+        transfer_id: u16,
+    },
     SendOnce,
-    SendOnceButReceiveAck(SeqNum),
+}
+// which retry policy a reliable message is resent with, each side picks the policies for its link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryClass {
+    Control, // lobby and session messages, lost ones are resent quickly and given up on soon
+    Bulk, // worlds, dozens of chunks that may take a while on a poor link
 }
 #[derive(Debug)]
 pub struct DeserializedMessage {
     pub reliable: bool,
     pub seq_num: Option<u16>,
     pub msg: NetworkMessage,
+    pub arrived_as_chunks: Option<u16>, // set when reassembled from chunks, for metrics
 }
 #[derive(Debug)]
 pub struct ChunkOfMessage {
     pub seq_num: u16,
     pub base_seq_num: u16,
     pub amt_of_chunks: u16,
+    pub transfer_id: u16,
     pub data_bytes: [u8; MAX_UDP_PAYLOAD_LEN],
+    pub data_len: usize, // payload bytes after DATA_BIT_START_POS that were actually received
 }
 
 pub enum DeserializedMessageType {
@@ -142,13 +270,14 @@ pub enum DeserializedMessageType {
     ChunkOfMessage(ChunkOfMessage),
 }
 
+// only ever read after serializing, so pending-ack tables share the datagram instead of copying it
 #[derive(Clone, Debug)]
 pub struct SerializedNetworkMessage {
-    pub bytes: Vec<u8>,
+    pub bytes: Arc<[u8]>,
 }
 #[derive(Clone, Debug)]
 pub struct ChunkedSerializedNetworkMessage {
-    pub bytes: Vec<Vec<u8>>,
+    pub chunks: Vec<SerializedNetworkMessage>,
 }
 #[derive(Debug)]
 pub enum SerializedMessageType {
@@ -156,16 +285,40 @@ pub enum SerializedMessageType {
     Chunked(ChunkedSerializedNetworkMessage),
 }
 pub struct MsgBuffer(pub [u8; MAX_UDP_PAYLOAD_LEN]);
+// the end a datagram arrived at, each one only accepts what the other end sends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Endpoint {
+    Server,
+    Client,
+}
 
 pub enum GameState {
     ChooseMode,
     WaitingForPlayerList,
     ChoosePlayer,
     Playing,
+    NetworkLost(String), // the network threads died, offer to go back to the menu
 }
 
+// what one peer can make us hold on to while its chunked messages come in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkLimits {
+    pub max_message_bytes: usize, // payload of one reassembled message
+    pub max_chunks_per_group: u16,
+    pub max_groups: usize, // messages being reassembled at the same time, the oldest gives way
+}
+pub struct ChunkGroup {
+    pub base_seq_num: u16,
+    pub amt_of_chunks: u16,
+    pub transfer_id: u16,
+    pub data_len: usize, // payload bytes of the chunks collected so far
+    pub chunks: Vec<ChunkOfMessage>,
+}
 pub struct ChunkedMessageCollector {
-    pub msgs: Vec<Vec<ChunkOfMessage>>,
+    pub groups: Vec<ChunkGroup>, // oldest first
+    pub limits: ChunkLimits,
+    pub dropped_groups: u32, // given up on for breaking a limit or to make room for a newer one
+    pub newest_transfer: Option<u16>, // chunks of older transfers are refused, they can only be stale
 }
 #[derive(Debug)]
 pub struct MessageHeader {
@@ -173,6 +326,7 @@ pub struct MessageHeader {
     pub seq_num: Option<SeqNum>,
     pub amt_of_chunks: u16,
     pub base_chunk_seq_num: u16,
+    pub transfer_id: u16,
     pub is_chunked: bool,
     pub message: NetworkMessage,
 }
@@ -183,12 +337,22 @@ pub struct NetworkLogger {
     pub log: bool,
 }
 
-pub enum SendInputsError {
-    Disconnected,
-    IO(std::io::Error),
+#[derive(Debug)]
+pub enum NetError {
+    Io(std::io::Error),
+    Disconnected, // the other side stopped acking, we gave up on it
+    PayloadTooLarge {
+        len: usize,
+    },
+    NotConnected, // nothing is listening at the server address
+    ShuttingDown, // the game side of the connection is gone
+    IncompatiblePeer {
+        ours: ProtocolInfo,
+        theirs: ProtocolInfo,
+    },
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct LogConfig {
     pub connection: bool,
     pub world_state: bool,
@@ -0,0 +1,111 @@
+use macroquad::{ color::Color, math::Vec2 };
+use unlockrs::impl_pod;
+use crate::{ memory::{ ArrayDataPtr, FixedDataPtr }, sim_rng::SimRng, types::TickRate };
+
+// the game's state as it lives in the allocator pages, the server never sees more of it than bytes
+
+pub const MAX_BULLETS: usize = 5;
+pub const MAX_ENEMIES: usize = 40; // upper bound for the enemy capacity a host can pick, has to fit one page
+pub const BULLET_SIZE: f32 = 5.0;
+pub const ENEMY_SIZE: f32 = 40.0;
+// a RawPages snapshot is the simulation's pages as they are in memory, it only reads back in a build
+// that allocates the same sizes in the same order, listed in Simulation::new's order
+const WORLD_LAYOUT: [usize; 11] = [
+    std::mem::size_of::<Player>(),
+    std::mem::size_of::<Player>(),
+    std::mem::size_of::<EnemyPool>(),
+    std::mem::size_of::<Enemy>(),
+    std::mem::size_of::<u32>(),
+    std::mem::size_of::<TickRate>(),
+    std::mem::size_of::<GameTuning>(),
+    std::mem::size_of::<SimRng>(),
+    std::mem::size_of::<Bullet>(),
+    MAX_BULLETS,
+    MAX_ENEMIES,
+];
+pub const WORLD_LAYOUT_HASH: u32 = world_layout_hash(&WORLD_LAYOUT);
+
+// FNV-1a over the little endian bytes of every entry
+pub const fn world_layout_hash(layout: &[usize]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    let mut i = 0;
+    while i < layout.len() {
+        let bytes = (layout[i] as u64).to_le_bytes();
+        let mut b = 0;
+        while b < bytes.len() {
+            hash ^= bytes[b] as u32;
+            hash = hash.wrapping_mul(0x01000193);
+            b += 1;
+        }
+        i += 1;
+    }
+    hash
+}
+
+// every struct in the pages is Pod, see impl_pod! below them
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct Player {
+    pub position: Vec2,
+    pub color: Color,
+    pub bullets: [Bullet; MAX_BULLETS],
+    pub movement_input: f32,
+    pub shoot_input: u8, // anything but 0 shoots, a u8 so any byte a snapshot brings is a valid value
+    pub _padding: [u8; 3], // always 0, the u8 would leave 3 uninitialized bytes before the next f32
+    pub curr_reload_time: f32,
+}
+#[derive(Copy, Clone)]
+pub struct Bullet {
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+#[derive(Copy, Clone)]
+pub struct Enemy {
+    pub position: Vec2,
+    pub id: u32,
+}
+// bookkeeping for the enemy slots: slots[..active_count] are active, the rest are free;
+// removal swaps with the last active enemy
+#[derive(Copy, Clone)]
+pub struct EnemyPool {
+    pub active_count: u32,
+    pub next_id: u32,
+}
+#[derive(Copy, Clone)]
+pub struct Simulation {
+    pub player1: FixedDataPtr<Player>,
+    pub player2: FixedDataPtr<Player>,
+    pub enemies: FixedDataPtr<EnemyPool>,
+    pub enemy_slots: ArrayDataPtr<Enemy>, // length is the session's enemy capacity
+    pub frame: FixedDataPtr<u32>,
+    pub tick_rate: FixedDataPtr<TickRate>, // in the pages so it rides along in the snapshot
+    pub tuning: FixedDataPtr<GameTuning>, // same
+    pub rng: FixedDataPtr<SimRng>, // every random draw of the simulation, seeded by the host
+}
+// gameplay numbers picked by the host for the whole session, joiners get them with the world
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameTuning {
+    pub player_speed: f32, // px per second
+    pub reload_time: f32, // seconds between two shots
+    pub bullet_speed: f32, // px per second
+    pub enemy_speed: f32, // px per second
+    pub enemy_spawn_interval: f32, // seconds between two spawns, a whole number of frames at the tick rate
+}
+impl_pod!(Player { position, color, bullets, movement_input, shoot_input, _padding, curr_reload_time });
+impl_pod!(Bullet { position, velocity });
+impl_pod!(Enemy { position, id });
+impl_pod!(EnemyPool { active_count, next_id });
+impl_pod!(GameTuning { player_speed, reload_time, bullet_speed, enemy_speed, enemy_spawn_interval });
+pub struct SimulationDataMut<'a> {
+    pub player1: &'a mut Player,
+    pub player2: &'a mut Player,
+    pub enemies: &'a mut EnemyPool,
+    pub spawn_timer: &'a mut f64,
+}
+
+pub struct SimulationDataRef<'a> {
+    player1: &'a Player,
+    player2: &'a Player,
+    enemies: &'a EnemyPool,
+    spawn_timer: &'a f64,
+}
@@ -0,0 +1,347 @@
+use macroquad::{ color::Color, math::{ vec2, Vec2 } };
+
+use crate::{
+    memory::PageAllocator,
+    sim_rng::SimRng,
+    types::TickRate,
+    world::{ Bullet, Enemy, EnemyPool, GameTuning, Player, Simulation, MAX_ENEMIES },
+};
+
+// how world state goes into ClientSentWorld and comes back out on the joining side,
+// both peers have to use the same one
+pub trait WorldSerializer {
+    fn serialize(&self, sim: &Simulation, alloc: &PageAllocator) -> Vec<u8>;
+    // `alloc` has to be fresh, the simulation is laid out in it from scratch
+    fn deserialize(
+        &self,
+        bytes: Vec<u8>,
+        alloc: &mut PageAllocator
+    ) -> Result<Simulation, &'static str>;
+}
+
+// the pages as they are in memory, cheap but only readable by a build with the same layout
+// on a machine with the same endianness
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RawPages;
+
+impl WorldSerializer for RawPages {
+    fn serialize(&self, sim: &Simulation, alloc: &PageAllocator) -> Vec<u8> {
+        sim.serialize(alloc)
+    }
+    fn deserialize(
+        &self,
+        bytes: Vec<u8>,
+        alloc: &mut PageAllocator
+    ) -> Result<Simulation, &'static str> {
+        Simulation::new_from_serialized(bytes, alloc)
+    }
+}
+
+// every gameplay field on its own in little endian, no padding and no pointers:
+// enemy capacity, tick rate, tuning, frame, both players, the pool, all enemy slots, then the rng state
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LittleEndianFields;
+
+impl WorldSerializer for LittleEndianFields {
+    fn serialize(&self, sim: &Simulation, alloc: &PageAllocator) -> Vec<u8> {
+        let mut out = FieldWriter::default();
+        out.u32(sim.enemy_slots.len() as u32);
+        out.u32(sim.tick_rate(alloc).hz());
+        let tuning = sim.tuning(alloc);
+        for value in [
+            tuning.player_speed,
+            tuning.reload_time,
+            tuning.bullet_speed,
+            tuning.enemy_speed,
+            tuning.enemy_spawn_interval,
+        ] {
+            out.f32(value);
+        }
+        out.u32(alloc.read_fixed(&sim.frame));
+        for player in [alloc.read_fixed(&sim.player1), alloc.read_fixed(&sim.player2)] {
+            out.vec2(player.position);
+            for channel in [player.color.r, player.color.g, player.color.b, player.color.a] {
+                out.f32(channel);
+            }
+            for bullet in player.bullets {
+                out.vec2(bullet.position);
+                out.vec2(bullet.velocity);
+            }
+            out.f32(player.movement_input);
+            out.bytes.push(u8::from(player.shoot_input != 0));
+            out.f32(player.curr_reload_time);
+        }
+        let pool = alloc.read_fixed(&sim.enemies);
+        out.u32(pool.active_count);
+        out.u32(pool.next_id);
+        for enemy in alloc.read_array(&sim.enemy_slots) {
+            out.vec2(enemy.position);
+            out.u32(enemy.id);
+        }
+        out.u64(alloc.read_fixed(&sim.rng).state());
+        out.bytes
+    }
+    fn deserialize(
+        &self,
+        bytes: Vec<u8>,
+        alloc: &mut PageAllocator
+    ) -> Result<Simulation, &'static str> {
+        let mut input = FieldReader { bytes: &bytes };
+        let enemy_capacity = input.u32()?;
+        if enemy_capacity == 0 || (enemy_capacity as usize) > MAX_ENEMIES {
+            return Err("Snapshot enemy capacity is not supported by this build");
+        }
+        let tick_rate = TickRate::new(input.u32()?).map_err(
+            |_| "Snapshot tick rate is not supported by this build"
+        )?;
+        let tuning = GameTuning {
+            player_speed: input.f32()?,
+            reload_time: input.f32()?,
+            bullet_speed: input.f32()?,
+            enemy_speed: input.f32()?,
+            enemy_spawn_interval: input.f32()?,
+        };
+        if !tuning.is_usable() {
+            return Err("Snapshot tuning is not usable");
+        }
+        let frame = input.u32()?;
+        let mut players = [blank_player(), blank_player()];
+        for player in players.iter_mut() {
+            player.position = input.vec2()?;
+            player.color = Color::new(input.f32()?, input.f32()?, input.f32()?, input.f32()?);
+            for bullet in player.bullets.iter_mut() {
+                *bullet = Bullet { position: input.vec2()?, velocity: input.vec2()? };
+            }
+            player.movement_input = input.f32()?;
+            player.shoot_input = u8::from(input.u8()? != 0);
+            player.curr_reload_time = input.f32()?;
+        }
+        let pool = EnemyPool { active_count: input.u32()?, next_id: input.u32()? };
+        if pool.active_count > enemy_capacity {
+            return Err("Snapshot has more active enemies than slots");
+        }
+        let mut slots = Vec::with_capacity(enemy_capacity as usize);
+        for _ in 0..enemy_capacity {
+            slots.push(Enemy { position: input.vec2()?, id: input.u32()? });
+        }
+        let rng = SimRng::new(input.u64()?);
+        if !input.bytes.is_empty() {
+            return Err("Snapshot has trailing bytes");
+        }
+
+        let sim = Simulation::new(alloc, enemy_capacity, tick_rate, tuning, rng.state());
+        alloc.write_fixed_to_memory(&sim.frame, &frame);
+        alloc.write_fixed_to_memory(&sim.player1, &players[0]);
+        alloc.write_fixed_to_memory(&sim.player2, &players[1]);
+        alloc.write_fixed_to_memory(&sim.enemies, &pool);
+        alloc.mut_read_array(&sim.enemy_slots).copy_from_slice(&slots);
+        Ok(sim)
+    }
+}
+
+// which serializer wrote a snapshot, the first byte of every ClientSentWorld
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WorldFormat {
+    #[default]
+    RawPages = 0,
+    LittleEndianFields = 1,
+}
+
+impl WorldFormat {
+    fn serializer(self) -> &'static dyn WorldSerializer {
+        match self {
+            WorldFormat::RawPages => &RawPages,
+            WorldFormat::LittleEndianFields => &LittleEndianFields,
+        }
+    }
+}
+
+// writes its format behind the tag and reads whichever format a snapshot is tagged with,
+// a joiner loads the host's world no matter which one the host picked
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tagged(pub WorldFormat);
+
+impl WorldSerializer for Tagged {
+    fn serialize(&self, sim: &Simulation, alloc: &PageAllocator) -> Vec<u8> {
+        let mut bytes = vec![self.0 as u8];
+        bytes.extend(self.0.serializer().serialize(sim, alloc));
+        bytes
+    }
+    fn deserialize(
+        &self,
+        mut bytes: Vec<u8>,
+        alloc: &mut PageAllocator
+    ) -> Result<Simulation, &'static str> {
+        if bytes.is_empty() {
+            return Err("Snapshot is truncated");
+        }
+        let format = match bytes.remove(0) {
+            0 => WorldFormat::RawPages,
+            1 => WorldFormat::LittleEndianFields,
+            _ => {
+                return Err("Snapshot format is not supported by this build");
+            }
+        };
+        format.serializer().deserialize(bytes, alloc)
+    }
+}
+
+// every field is overwritten, this only gives the arrays something to start from
+fn blank_player() -> Player {
+    Player::new(0.0, Color::new(0.0, 0.0, 0.0, 0.0))
+}
+
+#[derive(Default)]
+struct FieldWriter {
+    bytes: Vec<u8>,
+}
+
+impl FieldWriter {
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn vec2(&mut self, value: Vec2) {
+        self.f32(value.x);
+        self.f32(value.y);
+    }
+}
+
+struct FieldReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl FieldReader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
+        let (field, rest) = self.bytes.split_first_chunk::<N>().ok_or("Snapshot is truncated")?;
+        self.bytes = rest;
+        Ok(*field)
+    }
+    fn u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.take::<1>()?[0])
+    }
+    fn u32(&mut self) -> Result<u32, &'static str> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+    fn u64(&mut self) -> Result<u64, &'static str> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+    fn f32(&mut self) -> Result<f32, &'static str> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+    fn vec2(&mut self) -> Result<Vec2, &'static str> {
+        Ok(vec2(self.f32()?, self.f32()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ simulation_allocator, DEFAULT_ENEMY_CAPACITY };
+
+    fn busy_simulation() -> (PageAllocator, Simulation) {
+        let mut alloc = simulation_allocator();
+        let tuning = GameTuning { reload_time: 0.75, ..GameTuning::default() };
+        let sim = Simulation::new(
+            &mut alloc,
+            DEFAULT_ENEMY_CAPACITY,
+            TickRate::new(30).unwrap(),
+            tuning,
+            0x0807_0605_0403_0201
+        );
+        alloc.write_fixed_to_memory(&sim.frame, &0x0102_0304u32);
+        let player = alloc.mut_read_fixed(&sim.player2);
+        player.position = vec2(321.5, -7.25);
+        player.bullets[3] = Bullet { position: vec2(1.0, 2.0), velocity: vec2(0.0, -300.0) };
+        player.shoot_input = 1;
+        player.curr_reload_time = 0.125;
+        let mut pool = alloc.read_fixed(&sim.enemies);
+        let slots = alloc.mut_read_array(&sim.enemy_slots);
+        for i in 0..7 {
+            pool.spawn(slots, Enemy::new(30.0 * (i as f32), 12.0));
+        }
+        pool.remove(slots, 2);
+        alloc.write_fixed_to_memory(&sim.enemies, &pool);
+        (alloc, sim)
+    }
+
+    #[test]
+    fn test_structured_format_round_trips_a_simulation() {
+        let (alloc, sim) = busy_simulation();
+        let bytes = LittleEndianFields.serialize(&sim, &alloc);
+
+        let mut joiner_alloc = simulation_allocator();
+        let joined = LittleEndianFields.deserialize(bytes.clone(), &mut joiner_alloc).unwrap();
+        assert_eq!(joined.state_hash(&joiner_alloc), sim.state_hash(&alloc));
+        assert_eq!(joined.tick_rate(&joiner_alloc), sim.tick_rate(&alloc));
+        assert_eq!(joined.tuning(&joiner_alloc), sim.tuning(&alloc));
+        assert_eq!(LittleEndianFields.serialize(&joined, &joiner_alloc), bytes);
+
+        // the raw pages agree with the structured format on the contents
+        let mut raw_alloc = simulation_allocator();
+        let raw = RawPages.deserialize(RawPages.serialize(&sim, &alloc), &mut raw_alloc).unwrap();
+        assert_eq!(LittleEndianFields.serialize(&raw, &raw_alloc), bytes);
+    }
+
+    #[test]
+    fn test_structured_format_is_little_endian_field_by_field() {
+        let (alloc, sim) = busy_simulation();
+        let bytes = LittleEndianFields.serialize(&sim, &alloc);
+        assert_eq!(&bytes[..4], &[20, 0, 0, 0]); // enemy capacity
+        assert_eq!(&bytes[4..8], &[30, 0, 0, 0]); // tick rate
+        assert_eq!(&bytes[12..16], &0.75f32.to_le_bytes()); // reload time
+        assert_eq!(&bytes[24..28], &2.0f32.to_le_bytes()); // enemy spawn interval
+        assert_eq!(&bytes[28..32], &[4, 3, 2, 1]); // frame
+        // a player is 2 + 4 + 5 * 4 + 1 + 1 floats and the shoot flag
+        let player2 = 32 + 28 * 4 + 1;
+        assert_eq!(&bytes[player2..player2 + 4], &321.5f32.to_le_bytes());
+        let pool = 32 + 2 * (28 * 4 + 1);
+        assert_eq!(&bytes[pool..pool + 8], &[6, 0, 0, 0, 7, 0, 0, 0]);
+        let rng = pool + 8 + (DEFAULT_ENEMY_CAPACITY as usize) * 12;
+        assert_eq!(&bytes[rng..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_a_tagged_snapshot_is_read_in_the_format_it_was_written_in() {
+        let (alloc, sim) = busy_simulation();
+        let expected = LittleEndianFields.serialize(&sim, &alloc);
+        // the reader's own format doesn't matter
+        let reader = Tagged(WorldFormat::RawPages);
+        for format in [WorldFormat::RawPages, WorldFormat::LittleEndianFields] {
+            let bytes = Tagged(format).serialize(&sim, &alloc);
+            assert_eq!(bytes[0], format as u8);
+            let mut joiner_alloc = simulation_allocator();
+            let joined = reader.deserialize(bytes, &mut joiner_alloc).unwrap();
+            assert_eq!(LittleEndianFields.serialize(&joined, &joiner_alloc), expected);
+        }
+        let deserialize = |bytes: Vec<u8>| reader.deserialize(bytes, &mut simulation_allocator()).err();
+        assert_eq!(deserialize(Vec::new()), Some("Snapshot is truncated"));
+        let mut unknown = Tagged(WorldFormat::LittleEndianFields).serialize(&sim, &alloc);
+        unknown[0] = 7;
+        assert_eq!(deserialize(unknown), Some("Snapshot format is not supported by this build"));
+    }
+
+    #[test]
+    fn test_structured_format_rejects_broken_snapshots() {
+        let (alloc, sim) = busy_simulation();
+        let bytes = LittleEndianFields.serialize(&sim, &alloc);
+        let deserialize = |bytes: Vec<u8>| {
+            LittleEndianFields.deserialize(bytes, &mut simulation_allocator()).err()
+        };
+        assert_eq!(deserialize(bytes[..bytes.len() - 1].to_vec()), Some("Snapshot is truncated"));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(deserialize(trailing), Some("Snapshot has trailing bytes"));
+        let mut tick_rate = bytes.clone();
+        tick_rate[4] = 45;
+        assert_eq!(deserialize(tick_rate), Some("Snapshot tick rate is not supported by this build"));
+        let mut tuning = bytes.clone();
+        tuning[24..28].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(deserialize(tuning), Some("Snapshot tuning is not usable"));
+    }
+}